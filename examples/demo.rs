@@ -75,7 +75,7 @@ fn phase2_demo() {
 
     // Create P2PKH address
     let pubkey_hash = bit_coin::core::hash160(&pubkey_bytes);
-    println!("  Pubkey hash: {}", hex::encode(&pubkey_hash));
+    println!("  Pubkey hash: {}", hex::encode(pubkey_hash));
 
     // Create scripts
     let script_pubkey = Script::p2pkh_script_pubkey(&pubkey_hash);
@@ -131,7 +131,7 @@ fn phase3_demo() {
     let genesis = Block::genesis();
 
     // Store genesis block
-    storage.blockchain.store_block(&genesis).unwrap();
+    storage.blockchain.store_block(&genesis, 0).unwrap();
     storage.blockchain.store_height(0, &genesis.hash()).unwrap();
     storage.blockchain.store_tip(&genesis.hash()).unwrap();
     storage.blockchain.store_chain_height(1).unwrap();
@@ -277,7 +277,7 @@ fn phase4_demo() {
     // Verack
     let verack = NetMessage::Verack;
     let verack_ser = verack.serialize();
-    let verack_deser = NetMessage::deserialize(&verack_ser).unwrap();
+    let _verack_deser = NetMessage::deserialize(&verack_ser).unwrap();
     println!("\n✓ Verack message serialization verified");
 
     // 2. Inventory Messages
@@ -286,7 +286,7 @@ fn phase4_demo() {
 
     let genesis = Block::genesis();
     let inv = InvMessage::new(InvType::Block, vec![genesis.hash()]);
-    let inv_msg = NetMessage::Inv(inv.clone());
+    let _inv_msg = NetMessage::Inv(inv.clone());
 
     println!("✓ Inventory message created");
     println!("  Type: Block");
@@ -0,0 +1,30 @@
+// Compare the naive CPU miner with the SHA256 midstate path
+//
+// Usage: cargo run --release --example midstate_bench [nonces]
+
+use bit_coin::{Block, Miner};
+
+fn main() {
+    let nonces: u32 = std::env::args()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(2_000_000);
+    if nonces == 0 {
+        eprintln!("The nonce count must be at least 1");
+        std::process::exit(1);
+    }
+
+    // Unreachable target, so both miners scan the full range
+    let miner = Miner::new(0x03000001);
+    let mut header = Block::genesis().header;
+
+    println!("Hashing {} nonces per implementation...\n", nonces);
+
+    let naive = miner.mine_range(&mut header, 0..=nonces - 1);
+    let midstate = miner.mine_midstate_range(&mut header, 0..=nonces - 1);
+
+    println!("Full header hash: {:>10.1} KH/s ({:?})", naive.hash_rate() / 1000.0, naive.duration);
+    println!("Midstate:         {:>10.1} KH/s ({:?})", midstate.hash_rate() / 1000.0, midstate.duration);
+    println!();
+    println!("Speedup: {:.2}x", midstate.hash_rate() / naive.hash_rate());
+}
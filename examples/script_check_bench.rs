@@ -0,0 +1,86 @@
+// Compare sequential, parallel and cached input script verification
+//
+// Usage: cargo run --release --example script_check_bench [inputs]
+
+use bit_coin::consensus::{ScriptCheck, ScriptCheckQueue, SignatureCache};
+use bit_coin::core::{Hash256, Script};
+use bit_coin::core::script::SCRIPT_VERIFY_NONE;
+use bit_coin::wallet::KeyPair;
+use secp256k1::{Message, Secp256k1};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn main() {
+    let count: usize = std::env::args()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(4000);
+
+    println!("Building {} signed inputs...", count);
+
+    let secp = Secp256k1::new();
+    let keypair = KeyPair::generate();
+    let pubkey = keypair.pubkey_bytes();
+    let script_pubkey = keypair.script_pubkey();
+
+    let checks: Vec<ScriptCheck> = (0..count)
+        .map(|i| {
+            let mut digest = [0u8; 32];
+            digest[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            let sighash = Hash256::new(digest);
+
+            let message = Message::from_digest_slice(sighash.as_bytes()).unwrap();
+            let signature = secp.sign_ecdsa(&message, &keypair.secret_key);
+
+            ScriptCheck {
+                tx_index: i + 1,
+                input_index: 0,
+                txid: sighash,
+                script_sig: Script::p2pkh_script_sig(&signature.serialize_der(), &pubkey),
+                script_pubkey: script_pubkey.clone(),
+                sighash,
+                flags: SCRIPT_VERIFY_NONE,
+            }
+        })
+        .collect();
+
+    // Baseline: one fresh context per signature, as `Script::verify_p2pkh` does
+    let start = Instant::now();
+    for check in &checks {
+        assert!(Script::verify_p2pkh(&check.script_sig, &check.script_pubkey, check.sighash.as_bytes()).unwrap());
+    }
+    let baseline = start.elapsed();
+
+    let queue = ScriptCheckQueue::default();
+
+    let start = Instant::now();
+    queue.run_sequential(&checks).unwrap();
+    let sequential = start.elapsed();
+
+    let start = Instant::now();
+    queue.run(&checks).unwrap();
+    let parallel = start.elapsed();
+
+    // Signature cache: first pass fills it (mempool), second pass hits it (block)
+    let cache = Arc::new(SignatureCache::new(count));
+    let cached_queue = ScriptCheckQueue::with_signature_cache(0, cache.clone());
+
+    let start = Instant::now();
+    cached_queue.run(&checks).unwrap();
+    let cold = start.elapsed();
+
+    let start = Instant::now();
+    cached_queue.run(&checks).unwrap();
+    let warm = start.elapsed();
+
+    println!();
+    println!("Per-call context:      {:>10.2?}", baseline);
+    println!("Shared context:        {:>10.2?}", sequential);
+    println!("Parallel ({} workers): {:>10.2?}", queue.workers(), parallel);
+    println!();
+    println!("Cached (cold):         {:>10.2?}", cold);
+    println!("Cached (warm):         {:>10.2?}", warm);
+    println!();
+    println!("Speedup vs per-call:   {:.1}x", baseline.as_secs_f64() / parallel.as_secs_f64());
+    println!("Signature cache:       {}", cache.stats());
+}
@@ -2,11 +2,19 @@
 
 use clap::{Parser, Subcommand};
 use crate::{Storage, Block};
-use crate::core::{BlockHeader, Transaction, TxOutput};
-use crate::consensus::pow::Miner;
-use crate::consensus::gpu_pow::GpuMiner;
-use crate::storage::{OutPoint, Utxo};
+use crate::consensus::backend::{available_backends, backend_by_name};
+use crate::consensus::block_assembly::{block_subsidy, BlockAssembler, BlockTemplate, COIN};
+use crate::consensus::mempool::Mempool;
+use crate::consensus::params::ChainParams;
+use crate::consensus::pow::Target;
+use crate::consensus::validation::{BlockValidator, ScriptValidation};
+use crate::consensus::verify_db::{verify_chain, DEFAULT_CHECK_DEPTH, DEFAULT_CHECK_LEVEL};
+use crate::network::stratum::{StratumClient, StratumConfig, StratumJob, StratumServer};
+use crate::consensus::pow::CancelToken;
+use crate::storage::{BootstrapReader, BootstrapWriter};
 use crate::wallet::{Keystore, TransactionBuilder};
+use std::io::{BufReader, BufWriter};
+use std::thread::JoinHandle;
 
 #[derive(Parser)]
 #[command(name = "bitcoin-edu")]
@@ -14,6 +22,41 @@ use crate::wallet::{Keystore, TransactionBuilder};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Extra checkpoint as HEIGHT:HASH; blocks must match it (repeatable)
+    #[arg(long = "checkpoint", global = true, value_name = "HEIGHT:HASH")]
+    pub checkpoints: Vec<String>,
+
+    /// Skip script checks for this block and its ancestors (its header must be indexed)
+    #[arg(long, global = true, value_name = "HASH")]
+    pub assume_valid: Option<String>,
+
+    /// Maintain the transaction index (existing blocks are indexed in the background)
+    #[arg(long, global = true, default_value = "false")]
+    pub txindex: bool,
+
+    /// Coins cache size in MiB; UTXO changes are written back when it fills (default: 64)
+    #[arg(long, global = true, value_name = "MIB")]
+    pub dbcache: Option<usize>,
+
+    /// Delete the oldest block files to keep them under this many MiB (headers and UTXOs are kept)
+    #[arg(long, global = true, value_name = "MIB")]
+    pub prune: Option<u64>,
+}
+
+impl Cli {
+    /// Chain parameters from the built-in defaults plus command-line overrides
+    pub fn chain_params(&self) -> Result<ChainParams, String> {
+        let mut params = ChainParams::new(MINING_BITS);
+        for arg in &self.checkpoints {
+            let (height, hash) = ChainParams::parse_checkpoint(arg)?;
+            params = params.with_checkpoint(height, hash);
+        }
+        if let Some(hash) = &self.assume_valid {
+            params = params.with_assume_valid(Some(crate::core::Hash256::from_hex(hash)?));
+        }
+        Ok(params)
+    }
 }
 
 #[derive(Subcommand)]
@@ -33,17 +76,112 @@ pub enum Commands {
         /// Address to receive the block reward (uses default wallet address if not specified)
         #[arg(short, long)]
         address: Option<String>,
-        /// Use GPU (wgpu compute shader) for mining; falls back to CPU if no GPU is found
-        #[arg(long, default_value = "false")]
+        /// Mining backend: cpu, gpu or instant (see 'backends')
+        #[arg(short, long, default_value = "cpu")]
+        backend: String,
+        /// Shorthand for '--backend gpu'; falls back to CPU if no GPU is found
+        #[arg(long, default_value = "false", conflicts_with = "backend")]
         gpu: bool,
         /// Number of blocks to mine (default: 1, use 0 for unlimited)
         #[arg(short, long, default_value = "1")]
         count: u32,
+        /// CPU mining threads (default: 0 = one per core)
+        #[arg(short, long, default_value = "0")]
+        threads: usize,
+        /// Do not set version bits for pending soft-fork deployments
+        #[arg(long, default_value = "false")]
+        no_signal: bool,
+    },
+
+    /// List mining backends available on this machine
+    Backends,
+
+    /// Print a block template for external miners (getblocktemplate JSON)
+    GetBlockTemplate,
+
+    /// Print UTXO set statistics and commitment hash (gettxoutsetinfo)
+    GetTxOutSetInfo {
+        /// Recompute the statistics from every UTXO and compare
+        #[arg(long, default_value = "false")]
+        verify: bool,
+    },
+
+    /// Rebuild the block index from the block files, then revalidate every block from genesis
+    Reindex,
+
+    /// Rebuild the UTXO set and indexes by revalidating every stored block from genesis
+    ReindexChainstate,
+
+    /// Write the active chain to a bootstrap file (length-prefixed blocks, like bootstrap.dat)
+    ExportBlocks {
+        /// File to create
+        file: String,
+    },
+
+    /// Validate and connect the blocks of a bootstrap file on top of the chain
+    ImportBlocks {
+        /// Bootstrap file (a blk*.dat file works too)
+        file: String,
+    },
+
+    /// Audit the stored chain from the tip: links, proof of work, merkle roots, undo data, UTXO replay
+    Verifychain {
+        /// Checks to run, 0 (links and proof of work) to 4 (replay and compare the UTXO set)
+        #[arg(long, default_value_t = DEFAULT_CHECK_LEVEL)]
+        level: u32,
+        /// Blocks to check below the tip (0 = every block)
+        #[arg(long, default_value_t = DEFAULT_CHECK_DEPTH)]
+        depth: u32,
+    },
+
+    /// Serve mining jobs to external miners over Stratum v1
+    Stratum {
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:3333")]
+        listen: String,
+        /// Address to receive block rewards (uses default wallet address if not specified)
+        #[arg(short, long)]
+        address: Option<String>,
+        /// Default share difficulty (default: 1/16 of the block difficulty)
+        #[arg(short, long)]
+        difficulty: Option<f64>,
+        /// Compact block target for mined blocks, e.g. 1f00ffff (default: 20ffffff)
+        #[arg(short, long)]
+        bits: Option<String>,
+        /// Do not set version bits for pending soft-fork deployments
+        #[arg(long, default_value = "false")]
+        no_signal: bool,
+    },
+
+    /// Mine shares for a Stratum server (does not open the local database)
+    StratumMine {
+        /// Stratum server address
+        #[arg(short, long, default_value = "127.0.0.1:3333")]
+        server: String,
+        /// Worker name
+        #[arg(short, long, default_value = "worker")]
+        worker: String,
+        /// Worker password; "d=<difficulty>" requests a share difficulty
+        #[arg(short, long, default_value = "x")]
+        password: String,
+        /// CPU mining threads (default: 0 = one per core)
+        #[arg(short, long, default_value = "0")]
+        threads: usize,
     },
 
     /// Block commands
     #[command(subcommand)]
     Block(BlockCommands),
+
+    /// Transaction commands
+    #[command(subcommand)]
+    Tx(TxCommands),
+
+    /// Show the balance and transaction history of any address
+    Address {
+        /// Address (hex pubkey hash)
+        address: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -87,11 +225,57 @@ pub enum BlockCommands {
     BestBlock,
 }
 
+#[derive(Subcommand)]
+pub enum TxCommands {
+    /// Get a transaction by txid (confirmed ones need --txindex)
+    Get {
+        /// Transaction id
+        txid: String,
+    },
+}
+
+/// Run commands that do not need the local data directory
+/// Returns `None` for commands that must go through `CliHandler`.
+pub fn handle_stateless(cli: &Cli) -> Option<Result<(), String>> {
+    match &cli.command {
+        Commands::StratumMine { server, worker, password, threads } => {
+            Some(stratum_mine(server, worker, password, *threads))
+        }
+        _ => None,
+    }
+}
+
+/// Mine shares for a Stratum server until the connection closes
+fn stratum_mine(server: &str, worker: &str, password: &str, threads: usize) -> Result<(), String> {
+    let client = StratumClient::connect(server, worker, password)?;
+    println!("Connected to {} as '{}'", server, worker);
+
+    let stop = CancelToken::new();
+    let stats = client.mine(threads, &stop, |share| {
+        let stats = client.stats();
+        println!(
+            "  Share for job {} (nonce {}, {} attempts) - {} accepted, {} rejected",
+            share.job_id, share.nonce, share.attempts, stats.accepted, stats.rejected
+        );
+    });
+
+    let stats = stats?;
+    println!("Submitted {} shares ({} accepted)", stats.submitted, stats.accepted);
+    Ok(())
+}
+
+/// Difficulty used for blocks mined from the CLI (educational, easy)
+const MINING_BITS: u32 = 0x20ffffff;
+
 /// CLI handler
 pub struct CliHandler {
     storage: Storage,
     keystore: Keystore,
     keystore_path: String,
+    mempool: Mempool,
+    mempool_path: String,
+    validator: BlockValidator,
+    txindex_build: Option<JoinHandle<Result<u32, String>>>,
 }
 
 impl CliHandler {
@@ -109,10 +293,22 @@ impl CliHandler {
             Keystore::new()
         };
 
+        // Load pending transactions (dropping any that no longer apply)
+        let mempool_path = format!("{}/mempool.json", data_dir);
+        let mempool = if std::path::Path::new(&mempool_path).exists() {
+            Mempool::load(&mempool_path, &storage)?
+        } else {
+            Mempool::new()
+        };
+
         Ok(Self {
             storage,
             keystore,
             keystore_path,
+            mempool,
+            mempool_path,
+            validator: BlockValidator::with_params(ChainParams::new(MINING_BITS)),
+            txindex_build: None,
         })
     }
 
@@ -121,37 +317,69 @@ impl CliHandler {
         self.keystore.save(&self.keystore_path)
     }
 
+    /// Save mempool to disk
+    fn save_mempool(&self) -> Result<(), String> {
+        self.mempool.save(&self.mempool_path)
+    }
+
     /// Handle CLI command
     pub fn handle(&mut self, cli: Cli) -> Result<(), String> {
-        match cli.command {
+        let params = cli.chain_params()?;
+        if &params != self.validator.params() {
+            self.validator = BlockValidator::with_params(params);
+        }
+        if cli.txindex && (cli.prune.is_some() || self.storage.blockchain.get_prune_height()?.is_some()) {
+            return Err("The transaction index needs every block; it cannot be used with pruning".to_string());
+        }
+        if let Some(mib) = cli.prune {
+            self.storage.set_prune_target(Some(mib << 20));
+        }
+        if cli.txindex && !self.storage.txindex_enabled() {
+            self.storage.set_txindex(true);
+            self.txindex_build = Some(self.storage.build_txindex());
+        }
+        if let Some(mib) = cli.dbcache {
+            self.storage.set_coins_cache_size(mib << 20);
+        }
+
+        let result = match cli.command {
             Commands::Init => self.init(),
             Commands::Info => self.info(),
-            Commands::Mine { address, gpu, count } => self.mine(address, gpu, count),
+            Commands::Mine { address, backend, gpu, count, threads, no_signal } => {
+                let backend = if gpu { "gpu".to_string() } else { backend };
+                self.mine(address, &backend, count, threads, !no_signal)
+            }
+            Commands::Backends => self.backends(),
+            Commands::GetBlockTemplate => self.get_block_template(),
+            Commands::GetTxOutSetInfo { verify } => self.get_tx_out_set_info(verify),
+            Commands::Reindex => self.reindex(true),
+            Commands::ReindexChainstate => self.reindex(false),
+            Commands::ExportBlocks { file } => self.export_blocks(&file),
+            Commands::ImportBlocks { file } => self.import_blocks(&file),
+            Commands::Verifychain { level, depth } => self.verify_chain(level, depth),
+            Commands::Stratum { listen, address, difficulty, bits, no_signal } => {
+                self.stratum(&listen, address, difficulty, bits, !no_signal)
+            }
+            Commands::StratumMine { server, worker, password, threads } => stratum_mine(&server, &worker, &password, threads),
             Commands::Wallet(cmd) => self.handle_wallet(cmd),
             Commands::Block(cmd) => self.handle_block(cmd),
-        }
+            Commands::Tx(cmd) => self.handle_tx(cmd),
+            Commands::Address { address } => self.address(&address),
+        };
+
+        // Blocks connected by the command may still be in the coins cache
+        let flushed = self.storage.flush();
+        result.and(flushed)
     }
 
     /// Initialize blockchain
     fn init(&mut self) -> Result<(), String> {
         println!("Initializing blockchain...");
 
-        // Store genesis block
+        // Store genesis block and its coinbase UTXO in one transaction
         let genesis = Block::genesis();
-        self.storage.blockchain.store_block(&genesis)?;
-        self.storage.blockchain.store_height(0, &genesis.hash())?;
-        self.storage.blockchain.store_tip(&genesis.hash())?;
-        self.storage.blockchain.store_chain_height(1)?;
-
-        // Add genesis coinbase UTXO
-        let coinbase_tx = &genesis.transactions[0];
-        let outpoint = crate::storage::OutPoint::new(coinbase_tx.txid(), 0);
-        let utxo = crate::storage::Utxo::new(
-            coinbase_tx.outputs[0].clone(),
-            0,
-            true,
-        );
-        self.storage.utxo_set.add_utxo(&outpoint, &utxo)?;
+        self.storage.connect_block(&genesis, 0)?;
+        self.storage.flush()?;
 
         println!("✓ Genesis block created");
         println!("  Hash: {}", genesis.hash());
@@ -172,12 +400,69 @@ impl CliHandler {
             println!("  Best block: {}", hash);
         }
         println!("  UTXO count: {}", utxo_count);
+        let mut files = (0, 0, 0);
+        for file in self.storage.blockchain.files().block_file_numbers()? {
+            if let Some(info) = self.storage.blockchain.get_file_info(file)? {
+                files = (files.0 + 1, files.1 + info.size, files.2 + info.undo_size);
+            }
+        }
+        println!("  Block files: {} ({} bytes of blocks, {} bytes of undo data)", files.0, files.1, files.2);
+        println!("  Database schema: version {}", self.storage.schema_version()?);
+        match (self.storage.prune_target(), self.storage.blockchain.get_prune_height()?) {
+            (Some(target), Some(pruned)) => {
+                println!("  Pruning: target {} MiB, blocks up to height {} pruned", target >> 20, pruned)
+            }
+            (Some(target), None) => println!("  Pruning: target {} MiB, nothing pruned yet", target >> 20),
+            (None, Some(pruned)) => println!("  Pruning: off, blocks up to height {} were pruned", pruned),
+            (None, None) => println!("  Pruning: off (use --prune)"),
+        }
+        println!("  Mempool: {} transactions ({} satoshis in fees)", self.mempool.len(), self.mempool.total_fees());
+        match self.storage.txindex.best_block()? {
+            _ if !self.storage.txindex_enabled() => println!("  Tx index: disabled (use --txindex)"),
+            Some((_, indexed)) => println!("  Tx index: indexed up to height {}", indexed),
+            None => println!("  Tx index: building"),
+        }
+
+        let params = self.validator.params();
+        match params.last_checkpoint_height() {
+            Some(last) => println!("  Checkpoints: {} (last at height {})", params.checkpoints.len(), last),
+            None => println!("  Checkpoints: none"),
+        }
+        match &params.assume_valid {
+            Some(hash) => println!("  Assume-valid: {}", hash),
+            None => println!("  Assume-valid: none"),
+        }
+        match self.storage.blockchain.get_assumed_valid()? {
+            Some((count, highest)) => println!(
+                "  Script checks: skipped for {} blocks up to height {} (assume-valid)",
+                count, highest
+            ),
+            None => println!("  Script checks: verified for every block"),
+        }
+
+        println!(
+            "  Soft forks (window {}, threshold {}):",
+            params.version_bits_window, params.version_bits_threshold
+        );
+        for (deployment, stats) in self.validator.deployment_stats(height, &self.storage.blockchain) {
+            println!(
+                "    {:<12} bit {:<2} {:<9} ({}/{} blocks signalled in this window)",
+                deployment.name, deployment.bit, stats.state.to_string(), stats.count, stats.elapsed
+            );
+        }
 
         Ok(())
     }
 
     /// Mine blocks (count=0 means unlimited)
-    fn mine(&mut self, address: Option<String>, use_gpu: bool, count: u32) -> Result<(), String> {
+    fn mine(
+        &mut self,
+        address: Option<String>,
+        backend: &str,
+        count: u32,
+        threads: usize,
+        signal: bool,
+    ) -> Result<(), String> {
         // Resolve the reward address once
         let reward_addr = match address {
             Some(a) => crate::wallet::Address(a),
@@ -188,13 +473,11 @@ impl CliHandler {
                 .clone(),
         };
 
-        let bits: u32 = 0x20ffffff;
-        const BLOCK_REWARD: u64 = 50 * 100_000_000;
         let unlimited = count == 0;
         let mut mined = 0u32;
 
-        // Pre-create miner (GPU miner initialises wgpu once and reuses)
-        let mode = if use_gpu { "GPU" } else { "CPU" };
+        let backend = backend_by_name(backend, threads)?;
+        let info = backend.info();
 
         loop {
             if !unlimited && mined >= count {
@@ -205,37 +488,21 @@ impl CliHandler {
             let pubkey_hash = reward_addr.to_pubkey_hash()?;
             let reward_script = crate::core::Script::p2pkh_script_pubkey(&pubkey_hash);
 
-            // Determine the current chain tip and height
-            let prev_hash = self
-                .storage
-                .blockchain
-                .get_tip()?
-                .ok_or("Blockchain not initialized. Run 'init' first.")?;
-            let current_height = self.storage.blockchain.get_chain_height()?;
-            let new_height = current_height;
-
-            // Create coinbase transaction
-            let coinbase_script = format!("Block {}", new_height).into_bytes();
-            let coinbase_output = TxOutput::new(BLOCK_REWARD, reward_script);
-            let coinbase_tx = Transaction::coinbase(coinbase_script, coinbase_output, new_height);
-
-            // Build block header
-            let merkle_root = Block::calculate_merkle_root(&[coinbase_tx.clone()]);
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| format!("System time error: {}", e))?
-                .as_secs() as u32;
-            let mut header = BlockHeader::new(1, prev_hash, merkle_root, timestamp, bits, 0);
-
-            println!("Mining block {} on {}...", new_height, mode);
-
-            let result = if use_gpu {
-                let gpu_miner = GpuMiner::new(bits);
-                gpu_miner.mine(&mut header)
-            } else {
-                let cpu_miner = Miner::new(bits);
-                cpu_miner.mine(&mut header)
-            };
+            // Assemble the block from the mempool (coinbase pays subsidy + fees)
+            let template = self.block_template(signal)?;
+            let new_height = template.height;
+            let mut work = template.to_work(reward_script)?;
+            let cancel = CancelToken::new();
+
+            println!("Mining block {} on {} ({})...", new_height, info.name, info.description);
+
+            let result = backend.mine_work(&mut work, &cancel, &|progress| {
+                println!(
+                    "  ... {} attempts ({:.1} KH/s)",
+                    progress.attempts,
+                    progress.hash_rate() / 1000.0
+                );
+            });
 
             if !result.success {
                 return Err(format!("Mining failed at height {}: could not find valid nonce", new_height));
@@ -249,27 +516,17 @@ impl CliHandler {
             );
 
             // Assemble and store the block
-            let block = Block::new(header, vec![coinbase_tx.clone()]);
+            let block = work.into_block();
             let block_hash = block.hash();
 
-            self.storage.blockchain.store_block(&block)?;
-            self.storage.blockchain.store_height(new_height, &block_hash)?;
-            self.storage.blockchain.store_tip(&block_hash)?;
-            self.storage.blockchain.store_chain_height(new_height + 1)?;
-
-            // Register the coinbase output in the UTXO set
-            let outpoint = OutPoint::new(coinbase_tx.txid(), 0);
-            let utxo = Utxo::new(coinbase_tx.outputs[0].clone(), new_height, true);
-            self.storage.utxo_set.add_utxo(&outpoint, &utxo)?;
-
-            // Flush both databases
-            self.storage.blockchain.flush()?;
-            self.storage.utxo_set.flush()?;
+            self.connect_block(&block, new_height)?;
 
             println!("Block mined successfully!");
             println!("  Height:  {}", new_height);
             println!("  Hash:    {}", block_hash);
-            println!("  Reward:  {} satoshis ({} BTC) -> {}", BLOCK_REWARD, BLOCK_REWARD as f64 / 1e8, reward_addr);
+            println!("  Version: {:#010x}", block.header.version);
+            println!("  Txs:     {} ({} satoshis in fees)", block.transactions.len(), template.total_fees);
+            println!("  Reward:  {} satoshis ({} BTC) -> {}", template.coinbase_value, template.coinbase_value as f64 / 1e8, reward_addr);
             println!();
 
             mined += 1;
@@ -282,6 +539,419 @@ impl CliHandler {
         Ok(())
     }
 
+    /// Validate and store a new tip block and apply it to the UTXO set and mempool
+    fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), String> {
+        self.validate_and_connect(block, height)?;
+
+        // Confirmed transactions leave the mempool
+        self.mempool.remove_for_block(block);
+        self.save_mempool()?;
+
+        Ok(())
+    }
+
+    // Helper: run every block check and connect `block` at `height`
+    fn validate_and_connect(&self, block: &Block, height: u32) -> Result<(), String> {
+        let block_hash = block.hash();
+
+        // Validate in a child view of the coins cache; a rejected block drops it
+        let view = self.storage.coins_view();
+        let scripts = self
+            .validator
+            .validate_for_connection(block, height, &view, &self.storage.blockchain)
+            .map_err(|e| format!("Block {} rejected ({}: {}): {}", block_hash, e.class(), e.code(), e))?;
+        // Block, tip, undo data and the assume-valid count commit together; UTXO changes go to the cache
+        self.storage
+            .connect_block_with(view, block, height, scripts == ScriptValidation::AssumedValid)
+    }
+
+    /// Wipe the chain state and revalidate every stored block from genesis
+    /// With `full`, the block index is first rebuilt from the block files.
+    fn reindex(&mut self, full: bool) -> Result<(), String> {
+        // The background index build reads the chain being wiped
+        self.wait_for_txindex()?;
+
+        let chain = if full {
+            println!("Rebuilding the block index from the block files...");
+            self.storage.reindex_blocks()?
+        } else {
+            self.storage.reset_chainstate()?
+        };
+        if chain.is_empty() {
+            return Err("No stored blocks to reindex. Run 'init' first.".to_string());
+        }
+        println!("Revalidating {} blocks from genesis...", chain.len());
+
+        let started = std::time::Instant::now();
+        let step = (chain.len() / 10).max(1);
+        for (height, hash) in chain.iter().enumerate() {
+            let result = self
+                .storage
+                .blockchain
+                .get_block(hash)?
+                .ok_or_else(|| format!("Block {} is missing from the block files", hash))
+                .and_then(|block| self.validate_and_connect(&block, height as u32));
+            if let Err(e) = result {
+                // Blocks above the failure must not be re-applied on the next start
+                self.storage.truncate_height_index(height as u32)?;
+                return Err(format!("Reindex stopped at height {}: {}", height, e));
+            }
+
+            let done = height + 1;
+            if done % step == 0 || done == chain.len() {
+                println!("  ... {}/{} blocks ({}%)", done, chain.len(), done * 100 / chain.len());
+            }
+        }
+        self.storage.flush()?;
+
+        println!("✓ Chain state rebuilt in {:.1}s", started.elapsed().as_secs_f64());
+        println!("  Height: {}", chain.len() - 1);
+        println!("  Best block: {}", chain[chain.len() - 1]);
+        println!("  UTXO count: {}", self.storage.utxo_set.count()?);
+        Ok(())
+    }
+
+    /// Build a block template on the current tip from the mempool
+    /// With `signal`, the version signals every pending soft-fork deployment.
+    fn block_template(&self, signal: bool) -> Result<BlockTemplate, String> {
+        let prev_hash = self
+            .storage
+            .blockchain
+            .get_tip()?
+            .ok_or("Blockchain not initialized. Run 'init' first.")?;
+        let height = self.storage.blockchain.get_chain_height()?;
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("System time error: {}", e))?
+            .as_secs() as u32;
+
+        let version = self.validator.block_version(height, &self.storage.blockchain, signal);
+        let bits = self.validator.params().bits;
+
+        Ok(BlockAssembler::new()
+            .with_version(version)
+            .create_template(&self.mempool, prev_hash, height, bits, timestamp))
+    }
+
+    /// Print a getblocktemplate-style template
+    fn get_block_template(&self) -> Result<(), String> {
+        let template = self.block_template(true)?;
+        let json = serde_json::to_string_pretty(&template.to_json())
+            .map_err(|e| format!("Failed to serialize template: {}", e))?;
+        println!("{}", json);
+        Ok(())
+    }
+
+    /// Print UTXO set statistics; with `verify`, recompute them from scratch
+    fn get_tx_out_set_info(&self, verify: bool) -> Result<(), String> {
+        let height = self.storage.blockchain.get_chain_height()?;
+        let tip = self
+            .storage
+            .blockchain
+            .get_tip()?
+            .ok_or("Blockchain not initialized. Run 'init' first.")?;
+        let stats = self.storage.utxo_set.stats()?;
+        let issued: u64 = (0..height).map(block_subsidy).sum();
+
+        println!("UTXO set:");
+        println!("  Height: {}", height - 1);
+        println!("  Best block: {}", tip);
+        println!("  Transaction outputs: {}", stats.count);
+        println!("  Total amount: {} satoshis ({} BTC)", stats.total_amount, stats.total_amount as f64 / COIN as f64);
+        println!("  Subsidy issued: {} satoshis", issued);
+        println!("  Serialized size: {} bytes", stats.serialized_size);
+        println!("  Hash: {}", stats.hash());
+
+        if stats.total_amount > issued {
+            return Err(format!(
+                "UTXO set holds {} satoshis but only {} were issued",
+                stats.total_amount, issued
+            ));
+        }
+
+        if verify {
+            let computed = self.storage.utxo_set.compute_stats()?;
+            if computed != stats {
+                return Err(format!(
+                    "UTXO statistics do not match the UTXO set (recomputed {} outputs, {} satoshis, hash {})",
+                    computed.count,
+                    computed.total_amount,
+                    computed.hash()
+                ));
+            }
+            println!("  Verified: statistics match a full scan");
+        }
+
+        Ok(())
+    }
+
+    /// Write every block of the active chain, genesis first, to a bootstrap file
+    fn export_blocks(&self, path: &str) -> Result<(), String> {
+        let height = self.storage.blockchain.get_chain_height()?;
+        if height == 0 {
+            return Err("Blockchain not initialized. Run 'init' first.".to_string());
+        }
+
+        let file = std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+        let mut writer = BootstrapWriter::new(BufWriter::new(file));
+        for h in 0..height {
+            let Some(block) = self.storage.blockchain.get_block_by_height(h)? else {
+                if let Some(pruned) = self.storage.blockchain.get_prune_height()?
+                    && h <= pruned
+                {
+                    return Err(format!("Block at height {} was pruned; exporting needs every block", h));
+                }
+                return Err(format!("Block at height {} is missing from storage", h));
+            };
+            writer.write_block(&block)?;
+        }
+        let (blocks, bytes) = writer.finish()?;
+
+        println!("✓ Exported {} blocks ({} bytes) to {}", blocks, bytes, path);
+        println!("  Height: {}", height - 1);
+        if let Some(tip) = self.storage.blockchain.get_tip()? {
+            println!("  Best block: {}", tip);
+        }
+        Ok(())
+    }
+
+    // Helper: index the headers of a bootstrap file up to the assume-valid block
+    // Blocks connect in order, so without its header no block could be placed
+    // below the assume-valid block and every script would be verified.
+    fn presync_headers(&self, path: &str) -> Result<(), String> {
+        let Some(assume_valid) = self.validator.params().assume_valid else {
+            return Ok(());
+        };
+        if self.storage.blockchain.get_index_entry(&assume_valid)?.is_some() {
+            return Ok(());
+        }
+
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        let mut last: Option<(crate::core::Hash256, u32)> = None;
+        for block in BootstrapReader::new(BufReader::new(file)) {
+            let header = block?.header;
+            let prev = header.prev_block_hash;
+            let height = match last {
+                _ if prev == crate::core::Hash256::zero() => 0,
+                Some((hash, height)) if hash == prev => height + 1,
+                _ => match self.storage.blockchain.get_index_entry(&prev)? {
+                    Some(entry) => entry.height + 1,
+                    // The import itself reports a block that does not link
+                    None => break,
+                },
+            };
+            self.storage.blockchain.store_header(&header, height)?;
+
+            let hash = header.hash();
+            if hash == assume_valid {
+                println!("Assume-valid block found at height {}; script checks up to it will be skipped", height);
+                return Ok(());
+            }
+            last = Some((hash, height));
+        }
+        println!("Assume-valid block {} is not in {}; verifying every script", assume_valid, path);
+        Ok(())
+    }
+
+    /// Connect the blocks of a bootstrap file with full validation
+    /// Blocks already on the active chain are skipped; every other block must
+    /// extend the tip, so the file has to be in height order.
+    fn import_blocks(&mut self, path: &str) -> Result<(), String> {
+        self.presync_headers(path)?;
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        println!("Importing blocks from {}...", path);
+
+        let (mut imported, mut skipped) = (0u32, 0u32);
+        for block in BootstrapReader::new(BufReader::new(file)) {
+            let block = block?;
+            let hash = block.hash();
+            if let Some(entry) = self.storage.blockchain.get_index_entry(&hash)?
+                && self.storage.blockchain.get_hash_by_height(entry.height)? == Some(hash)
+            {
+                skipped += 1;
+                continue;
+            }
+
+            let height = self.storage.blockchain.get_chain_height()?;
+            match self.storage.blockchain.get_tip()? {
+                Some(tip) if block.header.prev_block_hash != tip => {
+                    return Err(format!(
+                        "Block {} does not extend the chain: its parent is {}, the tip is {}",
+                        hash, block.header.prev_block_hash, tip
+                    ));
+                }
+                None if block.header.prev_block_hash != crate::core::Hash256::zero() => {
+                    return Err(format!("Block {} is not a genesis block and the chain is empty", hash));
+                }
+                _ => {}
+            }
+            self.validate_and_connect(&block, height)
+                .map_err(|e| format!("Import stopped at height {}: {}", height, e))?;
+            self.mempool.remove_for_block(&block);
+
+            imported += 1;
+            if imported % 1000 == 0 {
+                println!("  ... height {}", height);
+            }
+        }
+        self.save_mempool()?;
+
+        println!("✓ Imported {} blocks ({} already in the chain)", imported, skipped);
+        if let Some(tip) = self.storage.blockchain.get_tip()? {
+            println!("  Height: {}", self.storage.blockchain.get_chain_height()? - 1);
+            println!("  Best block: {}", tip);
+        }
+        Ok(())
+    }
+
+    /// Check the last `depth` blocks (0 = all) up to check `level` and list every problem
+    fn verify_chain(&self, level: u32, depth: u32) -> Result<(), String> {
+        match depth {
+            0 => println!("Verifying every block at level {}...", level),
+            _ => println!("Verifying the last {} blocks at level {}...", depth, level),
+        }
+        let report = verify_chain(&self.validator, &self.storage, level, depth)?;
+
+        for problem in &report.problems {
+            println!("  {}", problem);
+        }
+        if report.reached_pruned {
+            println!("  Blocks below height {} were pruned and not checked", report.first_height);
+        }
+        if !report.problems.is_empty() {
+            return Err(format!(
+                "Found {} problems in {} blocks (heights {} to {})",
+                report.problems.len(),
+                report.blocks,
+                report.first_height,
+                report.tip_height
+            ));
+        }
+
+        println!(
+            "✓ No problems in {} blocks (heights {} to {})",
+            report.blocks, report.first_height, report.tip_height
+        );
+        Ok(())
+    }
+
+    /// Run a Stratum server until Ctrl-C, storing the blocks its miners find
+    fn stratum(
+        &mut self,
+        listen: &str,
+        address: Option<String>,
+        difficulty: Option<f64>,
+        bits: Option<String>,
+        signal: bool,
+    ) -> Result<(), String> {
+        let reward_addr = match address {
+            Some(a) => crate::wallet::Address(a),
+            None => self
+                .keystore
+                .default_address()
+                .ok_or("No default address. Create one with 'wallet new-address'")?
+                .clone(),
+        };
+        let reward_script = crate::core::Script::p2pkh_script_pubkey(&reward_addr.to_pubkey_hash()?);
+
+        let bits = match bits {
+            Some(bits) => u32::from_str_radix(bits.trim_start_matches("0x"), 16)
+                .map_err(|e| format!("Invalid bits '{}': {}", bits, e))?,
+            None => self.validator.params().bits,
+        };
+        if bits != self.validator.params().bits {
+            self.validator = BlockValidator::with_params(ChainParams {
+                bits,
+                ..self.validator.params().clone()
+            });
+        }
+        let config = StratumConfig {
+            listen: listen.parse().map_err(|e| format!("Invalid listen address '{}': {}", listen, e))?,
+            share_difficulty: difficulty.unwrap_or(Target::from_bits(bits).difficulty() / 16.0),
+        };
+
+        let runtime = tokio::runtime::Runtime::new().map_err(|e| format!("Failed to start runtime: {}", e))?;
+        runtime.block_on(async {
+            let share_difficulty = config.share_difficulty;
+            let (server, mut found) = StratumServer::bind(config).await?;
+            let mut job_id = 0u64;
+            self.push_stratum_job(&server, &reward_script, signal, &mut job_id)?;
+
+            println!("Stratum server listening on {}", server.local_addr()?);
+            println!("  Block bits:       {:08x}", bits);
+            println!("  Share difficulty: {}", share_difficulty);
+            println!("  Reward address:   {}", reward_addr);
+            println!("Press Ctrl-C to stop.");
+            println!();
+
+            let run = server.run();
+            tokio::pin!(run);
+
+            loop {
+                tokio::select! {
+                    result = &mut run => return result,
+                    _ = tokio::signal::ctrl_c() => break,
+                    Some(found) = found.recv() => {
+                        let tip = self.storage.blockchain.get_tip()?;
+                        if tip != Some(found.block.header.prev_block_hash) {
+                            println!("Ignoring stale block from '{}'", found.worker);
+                            continue;
+                        }
+
+                        let height = self.storage.blockchain.get_chain_height()?;
+                        self.connect_block(&found.block, height)?;
+                        println!(
+                            "Block {} found by '{}' ({} txs): {}",
+                            height,
+                            found.worker,
+                            found.block.transactions.len(),
+                            found.block.hash()
+                        );
+
+                        // Move every miner to the new tip
+                        self.push_stratum_job(&server, &reward_script, signal, &mut job_id)?;
+                    }
+                }
+            }
+
+            println!();
+            println!("Worker statistics:");
+            for stats in server.worker_stats() {
+                println!(
+                    "  {:<12} accepted {:>6}  rejected {:>4}  blocks {:>4}  (difficulty {})",
+                    stats.name, stats.accepted, stats.rejected, stats.blocks, stats.difficulty
+                );
+            }
+            Ok(())
+        })
+    }
+
+    // Helper: publish a fresh job on the current tip to all Stratum miners
+    fn push_stratum_job(
+        &self,
+        server: &StratumServer,
+        reward_script: &[u8],
+        signal: bool,
+        job_id: &mut u64,
+    ) -> Result<(), String> {
+        let template = self.block_template(signal)?;
+        *job_id += 1;
+
+        let job = StratumJob::from_template(format!("{:x}", job_id), &template, reward_script.to_vec())?;
+        server.set_job(job, true);
+        Ok(())
+    }
+
+    /// List mining backends
+    fn backends(&self) -> Result<(), String> {
+        println!("Mining backends:");
+        for info in available_backends(0) {
+            let status = if info.available { "available" } else { "fallback" };
+            println!("  {:<8} [{}] {}", info.name, status, info.description);
+        }
+        Ok(())
+    }
+
     /// Handle wallet commands
     fn handle_wallet(&mut self, cmd: WalletCommands) -> Result<(), String> {
         match cmd {
@@ -323,14 +993,23 @@ impl CliHandler {
 
                 let to_addr = crate::wallet::Address(to);
 
-                let builder = TransactionBuilder::new(&self.keystore, &self.storage.utxo_set);
+                // Coins spent by earlier sends still waiting in the mempool are skipped
+                let builder = TransactionBuilder::new(&self.keystore, &self.storage.utxo_set).with_mempool(&self.mempool);
                 let tx = builder.build(&from, &to_addr, amount, fee)?;
 
+                // Verify and queue for the next mined block
+                self.validator
+                    .verify_transaction_scripts(&tx, &self.storage)
+                    .map_err(|e| format!("Transaction rejected ({}: {}): {}", e.class(), e.code(), e))?;
+                self.mempool.add(tx.clone(), &self.storage)?;
+                self.save_mempool()?;
+
                 println!("Transaction created:");
                 println!("  TXID: {}", tx.txid());
                 println!("  Inputs: {}", tx.inputs.len());
                 println!("  Outputs: {}", tx.outputs.len());
                 println!("  Total output: {} satoshis", tx.total_output_value());
+                println!("  Added to mempool ({} pending)", self.mempool.len());
 
                 Ok(())
             }
@@ -341,23 +1020,29 @@ impl CliHandler {
     fn handle_block(&self, cmd: BlockCommands) -> Result<(), String> {
         match cmd {
             BlockCommands::Get { id } => {
-                // Try parsing as height first
-                if let Ok(height) = id.parse::<u32>() {
-                    if let Some(block) = self.storage.blockchain.get_block_by_height(height)? {
-                        self.print_block(&block);
-                        return Ok(());
-                    }
-                }
+                // Try parsing as height first, then as hash
+                let hash = match id.parse::<u32>() {
+                    Ok(height) => self.storage.blockchain.get_hash_by_height(height)?,
+                    Err(_) => None,
+                };
+                let hash = match hash {
+                    Some(hash) => hash,
+                    None => crate::core::Hash256::from_hex(&id).map_err(|_| format!("Block not found: {}", id))?,
+                };
 
-                // Try as hash
-                if let Ok(hash) = crate::core::Hash256::from_hex(&id) {
-                    if let Some(block) = self.storage.blockchain.get_block(&hash)? {
-                        self.print_block(&block);
-                        return Ok(());
-                    }
+                if let Some(block) = self.storage.blockchain.get_block(&hash)? {
+                    self.print_block(&block);
+                    return Ok(());
+                }
+                match self.storage.blockchain.get_index_entry(&hash)? {
+                    Some(entry) if entry.is_pruned() => Err(format!(
+                        "Block {} at height {} was pruned: its data file {} was deleted and only the header is kept",
+                        hash,
+                        entry.height,
+                        crate::storage::block_file_name(entry.data.file)
+                    )),
+                    _ => Err(format!("Block not found: {}", id)),
                 }
-
-                Err(format!("Block not found: {}", id))
             }
             BlockCommands::Height => {
                 let height = self.storage.blockchain.get_chain_height()?;
@@ -375,6 +1060,109 @@ impl CliHandler {
         }
     }
 
+    /// Print balance, totals and history of an address
+    fn address(&self, address: &str) -> Result<(), String> {
+        let pubkey_hash = crate::wallet::Address(address.to_string()).to_pubkey_hash()?;
+        let script_pubkey = crate::core::Script::p2pkh_script_pubkey(&pubkey_hash);
+
+        let utxos = self.storage.utxo_set.get_utxos_for_script(&script_pubkey)?;
+        let balance: u64 = utxos.iter().map(|(_, utxo)| utxo.output.value).sum();
+        let history = self.storage.address_index.history(&script_pubkey)?;
+        let received: u64 = history.iter().map(|entry| entry.received).sum();
+        let spent: u64 = history.iter().map(|entry| entry.spent).sum();
+
+        println!("Address: {}", address);
+        println!("  Balance: {} satoshis ({} BTC)", balance, balance as f64 / 100_000_000.0);
+        println!("  Total received: {} satoshis", received);
+        println!("  Total spent: {} satoshis", spent);
+        println!("  Unspent outputs: {}", utxos.len());
+        println!("  Transactions: {}", history.len());
+        for entry in &history {
+            println!(
+                "    {:>6}:{:<3} {}  +{} -{}",
+                entry.height, entry.position, entry.txid, entry.received, entry.spent
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Handle transaction commands
+    fn handle_tx(&mut self, cmd: TxCommands) -> Result<(), String> {
+        match cmd {
+            TxCommands::Get { txid } => {
+                let txid = crate::core::Hash256::from_hex(&txid)?;
+
+                if let Some(entry) = self.mempool.get(&txid) {
+                    self.print_transaction(&entry.tx);
+                    println!("  Status: unconfirmed (in mempool)");
+                    return Ok(());
+                }
+
+                if !self.storage.txindex_enabled() {
+                    return Err("Transaction not in mempool. Run with --txindex to look up confirmed transactions".to_string());
+                }
+                self.wait_for_txindex()?;
+
+                let location = self
+                    .storage
+                    .txindex
+                    .get(&txid)?
+                    .ok_or_else(|| format!("Transaction not found: {}", txid))?;
+                let block = self
+                    .storage
+                    .blockchain
+                    .get_block(&location.block_hash)?
+                    .ok_or_else(|| format!("Block {} is missing from storage", location.block_hash))?;
+                let tx = block
+                    .transactions
+                    .get(location.position as usize)
+                    .ok_or_else(|| format!("Block {} has no transaction {}", location.block_hash, location.position))?;
+                let confirmations = self.storage.blockchain.get_chain_height()? - location.height;
+
+                self.print_transaction(tx);
+                println!("  Block: {}", location.block_hash);
+                println!("  Height: {}", location.height);
+                println!("  Position: {}", location.position);
+                println!("  Confirmations: {}", confirmations);
+                Ok(())
+            }
+        }
+    }
+
+    // Helper: let the background index build reach the tip
+    fn wait_for_txindex(&mut self) -> Result<(), String> {
+        if let Some(build) = self.txindex_build.take() {
+            let height = self.storage.blockchain.get_chain_height()?;
+            let indexed = self.storage.txindex.best_block()?.map(|(_, best)| best + 1);
+            if !build.is_finished() && indexed != Some(height) {
+                println!("Waiting for the transaction index to catch up...");
+            }
+            let indexed = build.join().map_err(|_| "Transaction index build panicked".to_string())??;
+            log::info!("Indexed {} blocks", indexed);
+        }
+        Ok(())
+    }
+
+    /// Print transaction information
+    fn print_transaction(&self, tx: &crate::core::Transaction) {
+        println!("Transaction:");
+        println!("  Txid: {}", tx.txid());
+        println!("  Version: {}", tx.version);
+        println!("  Inputs: {}", tx.inputs.len());
+        for (i, input) in tx.inputs.iter().enumerate() {
+            if tx.is_coinbase() {
+                println!("    [{}] coinbase", i);
+            } else {
+                println!("    [{}] {}:{}", i, input.prev_tx_hash, input.prev_index);
+            }
+        }
+        println!("  Outputs: {}", tx.outputs.len());
+        for (i, output) in tx.outputs.iter().enumerate() {
+            println!("    [{}] {} satoshis -> {}", i, output.value, hex::encode(&output.script_pubkey));
+        }
+    }
+
     /// Print block information
     fn print_block(&self, block: &Block) {
         println!("Block:");
@@ -383,6 +1171,14 @@ impl CliHandler {
         println!("  Merkle root: {}", block.header.merkle_root);
         println!("  Timestamp: {}", block.header.timestamp);
         println!("  Nonce: {}", block.header.nonce);
+        if let Ok(Some(entry)) = self.storage.blockchain.get_index_entry(&block.hash()) {
+            println!(
+                "  Stored: {} at offset {} ({} bytes)",
+                crate::storage::block_file_name(entry.data.file),
+                entry.data.offset,
+                entry.data.length
+            );
+        }
         println!("  Transactions: {}", block.transactions.len());
 
         for (i, tx) in block.transactions.iter().enumerate() {
@@ -0,0 +1,306 @@
+// Pluggable mining backends
+//
+// Every way of searching for a nonce implements `MiningBackend`:
+//   - "cpu":     multi-threaded CPU miner (midstate hashing)
+//   - "gpu":     wgpu compute shader, falls back to the CPU miner without a GPU
+//   - "instant": single-threaded, deterministic scan for tests and regtest-style
+//                chains; can be given a nonce limit to exercise extraNonce rolling
+//
+// Callers pick a backend by name (`backend_by_name`), list what this machine
+// supports (`available_backends`) and either mine synchronously or submit
+// the work as a background `MiningJob` that can be cancelled.
+
+use crate::consensus::gpu_pow::GpuMiner;
+use crate::consensus::pow::{CancelToken, Miner, MiningProgress, MiningResult, ParallelMiner};
+use crate::consensus::work::MiningWork;
+use crate::core::BlockHeader;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Capabilities of a mining backend
+#[derive(Debug, Clone)]
+pub struct BackendInfo {
+    /// Name used to select the backend
+    pub name: &'static str,
+    /// Human-readable description (device, thread count, ...)
+    pub description: String,
+    /// Whether the backend can run natively on this machine
+    /// (an unavailable GPU backend still works through its CPU fallback)
+    pub available: bool,
+    /// Whether the backend stops promptly when cancelled
+    pub cancellable: bool,
+}
+
+/// A nonce-search implementation
+pub trait MiningBackend: Send + Sync {
+    /// Name used to select the backend
+    fn name(&self) -> &'static str;
+
+    /// Describe what this backend can do on this machine
+    fn info(&self) -> BackendInfo;
+
+    /// Search the nonce space of one header (target taken from `header.bits`)
+    /// `on_progress` may be called periodically; backends without progress
+    /// reporting never call it.
+    fn mine_header(
+        &self,
+        header: &mut BlockHeader,
+        cancel: &CancelToken,
+        on_progress: &dyn Fn(MiningProgress),
+    ) -> MiningResult;
+
+    /// Mine a full work unit, rolling extraNonce/timestamp between headers
+    fn mine_work(
+        &self,
+        work: &mut MiningWork,
+        cancel: &CancelToken,
+        on_progress: &dyn Fn(MiningProgress),
+    ) -> MiningResult {
+        work.mine(cancel, |header| self.mine_header(header, cancel, on_progress))
+    }
+}
+
+/// Multi-threaded CPU backend
+pub struct CpuBackend {
+    threads: usize,
+}
+
+impl CpuBackend {
+    /// Create a CPU backend (0 threads = one per core)
+    pub fn new(threads: usize) -> Self {
+        Self { threads }
+    }
+}
+
+impl MiningBackend for CpuBackend {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn info(&self) -> BackendInfo {
+        let threads = ParallelMiner::new(0, self.threads).threads();
+        BackendInfo {
+            name: self.name(),
+            description: format!("CPU, {} threads, SHA256 midstate", threads),
+            available: true,
+            cancellable: true,
+        }
+    }
+
+    fn mine_header(
+        &self,
+        header: &mut BlockHeader,
+        cancel: &CancelToken,
+        on_progress: &dyn Fn(MiningProgress),
+    ) -> MiningResult {
+        ParallelMiner::new(header.bits, self.threads).mine(header, cancel, on_progress)
+    }
+}
+
+/// wgpu compute-shader backend with CPU fallback
+pub struct GpuBackend;
+
+impl MiningBackend for GpuBackend {
+    fn name(&self) -> &'static str {
+        "gpu"
+    }
+
+    fn info(&self) -> BackendInfo {
+        match GpuMiner::adapter_name() {
+            Some(adapter) => BackendInfo {
+                name: self.name(),
+                description: format!("GPU: {}", adapter),
+                available: true,
+                cancellable: true,
+            },
+            None => BackendInfo {
+                name: self.name(),
+                description: "No GPU adapter found, falls back to CPU".to_string(),
+                available: false,
+                cancellable: true,
+            },
+        }
+    }
+
+    fn mine_header(
+        &self,
+        header: &mut BlockHeader,
+        cancel: &CancelToken,
+        _on_progress: &dyn Fn(MiningProgress),
+    ) -> MiningResult {
+        GpuMiner::new(header.bits).mine_cancellable(header, cancel)
+    }
+}
+
+/// Deterministic single-threaded backend for tests
+/// Always scans nonces upwards from 0, so the same work gives the same block.
+pub struct InstantBackend {
+    /// Highest nonce tried per header (u32::MAX = full nonce space)
+    max_nonce: u32,
+}
+
+impl InstantBackend {
+    /// Scan the full nonce space
+    pub fn new() -> Self {
+        Self { max_nonce: u32::MAX }
+    }
+
+    /// Only try nonces `0..=max_nonce` per header, forcing extraNonce rolls
+    pub fn with_nonce_limit(max_nonce: u32) -> Self {
+        Self { max_nonce }
+    }
+}
+
+impl Default for InstantBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MiningBackend for InstantBackend {
+    fn name(&self) -> &'static str {
+        "instant"
+    }
+
+    fn info(&self) -> BackendInfo {
+        BackendInfo {
+            name: self.name(),
+            description: "Deterministic single-threaded scan (tests, easy targets)".to_string(),
+            available: true,
+            cancellable: false,
+        }
+    }
+
+    fn mine_header(
+        &self,
+        header: &mut BlockHeader,
+        _cancel: &CancelToken,
+        _on_progress: &dyn Fn(MiningProgress),
+    ) -> MiningResult {
+        Miner::new(header.bits).mine_midstate_range(header, 0..=self.max_nonce)
+    }
+}
+
+/// Names of all built-in backends
+pub const BACKEND_NAMES: [&str; 3] = ["cpu", "gpu", "instant"];
+
+/// Create a backend by name (`threads` applies to the CPU backend)
+pub fn backend_by_name(name: &str, threads: usize) -> Result<Box<dyn MiningBackend>, String> {
+    match name {
+        "cpu" => Ok(Box::new(CpuBackend::new(threads))),
+        "gpu" => Ok(Box::new(GpuBackend)),
+        "instant" => Ok(Box::new(InstantBackend::new())),
+        _ => Err(format!(
+            "Unknown mining backend '{}' (available: {})",
+            name,
+            BACKEND_NAMES.join(", ")
+        )),
+    }
+}
+
+/// Capabilities of every built-in backend on this machine
+pub fn available_backends(threads: usize) -> Vec<BackendInfo> {
+    BACKEND_NAMES
+        .iter()
+        .filter_map(|name| backend_by_name(name, threads).ok())
+        .map(|backend| backend.info())
+        .collect()
+}
+
+/// Work submitted to a backend on a background thread
+pub struct MiningJob {
+    cancel: CancelToken,
+    handle: JoinHandle<(MiningWork, MiningResult)>,
+}
+
+impl MiningJob {
+    /// Start mining `work` on `backend` in the background
+    pub fn submit(backend: Arc<dyn MiningBackend>, mut work: MiningWork) -> Self {
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+
+        let handle = std::thread::spawn(move || {
+            let result = backend.mine_work(&mut work, &token, &|_| {});
+            (work, result)
+        });
+
+        Self { cancel, handle }
+    }
+
+    /// Ask the backend to stop (e.g. because a new tip arrived)
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Whether the backend has finished (found a block, gave up or was cancelled)
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Wait for the result; the returned work holds the mined header and transactions
+    pub fn wait(self) -> Result<(MiningWork, MiningResult), String> {
+        self.handle
+            .join()
+            .map_err(|_| "Mining thread panicked".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Hash256, Transaction, TxOutput};
+
+    // Helper: coinbase-only work at the given difficulty
+    fn work(bits: u32) -> MiningWork {
+        let coinbase = Transaction::coinbase(b"Block 1".to_vec(), TxOutput::new(5000, vec![]), 1);
+        let header = BlockHeader::new(1, Hash256::zero(), Hash256::zero(), 1_000_000, bits, 0);
+        MiningWork::new(header, vec![coinbase]).unwrap()
+    }
+
+    #[test]
+    fn test_backend_by_name() {
+        for name in BACKEND_NAMES {
+            assert_eq!(backend_by_name(name, 1).unwrap().name(), name);
+        }
+        assert!(backend_by_name("fpga", 1).is_err());
+    }
+
+    #[test]
+    fn test_instant_backend_is_deterministic() {
+        let backend = InstantBackend::new();
+        let mut a = work(0x207fffff);
+        let mut b = work(0x207fffff);
+
+        let result_a = backend.mine_work(&mut a, &CancelToken::new(), &|_| {});
+        let result_b = backend.mine_work(&mut b, &CancelToken::new(), &|_| {});
+
+        assert!(result_a.success && result_b.success);
+        assert_eq!(a.header, b.header);
+        assert!(Miner::new(0x207fffff).verify(&a.header));
+    }
+
+    #[test]
+    fn test_nonce_limit_rolls_extra_nonce() {
+        // ~1/256 of hashes are valid, so 4 nonces per header rarely suffice
+        let backend = InstantBackend::with_nonce_limit(3);
+        let mut work = work(0x2000ffff);
+
+        let result = backend.mine_work(&mut work, &CancelToken::new(), &|_| {});
+
+        assert!(result.success);
+        assert!(work.header.nonce <= 3);
+        assert!(Miner::new(0x2000ffff).verify(&work.header));
+        assert_eq!(result.attempts, work.extra_nonce * 4 + work.header.nonce as u64 + 1);
+    }
+
+    #[test]
+    fn test_mining_job_cancel() {
+        // Unreachable target: the job only ends when cancelled
+        let job = MiningJob::submit(Arc::new(CpuBackend::new(1)), work(0x03000001));
+        job.cancel();
+
+        let (_, result) = job.wait().unwrap();
+        assert!(!result.success);
+        assert!(result.cancelled);
+    }
+}
@@ -0,0 +1,522 @@
+// Block template assembly
+//
+// Builds the next block from the mempool:
+//   1. Repeatedly pick the transaction whose package (itself plus its
+//      not-yet-selected ancestors) has the highest fee rate.
+//   2. Add the whole package, parents first, if it fits the remaining
+//      weight and sigop budget; otherwise skip it.
+//   3. Pay subsidy + collected fees to the coinbase.
+// Selecting by ancestor fee rate lets a high-fee child pull in its
+// low-fee parent ("child pays for parent"). Like Bitcoin Core's modified
+// entries, each candidate keeps the fee and weight of its package, and
+// selecting a package subtracts it from the candidates that descend from it,
+// so no package is recomputed from scratch.
+//
+// The result is a `BlockTemplate`, which the CLI miner turns into
+// `MiningWork` and which `to_json` exposes in `getblocktemplate` form
+// for external miners.
+
+use crate::consensus::mempool::{Mempool, WITNESS_SCALE_FACTOR};
+use crate::consensus::pow::Target;
+use crate::consensus::work::MiningWork;
+use crate::core::{BlockHeader, Hash256, Serializable, Transaction, TxOutput};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Maximum block weight
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+/// Maximum signature-operation cost per block
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+/// Weight kept free for the block header and coinbase transaction
+pub const COINBASE_RESERVED_WEIGHT: usize = 4_000;
+/// Sigop cost kept free for the coinbase transaction
+pub const COINBASE_RESERVED_SIGOPS: usize = 400;
+
+/// Satoshis per coin
+pub const COIN: u64 = 100_000_000;
+/// Blocks between subsidy halvings
+pub const HALVING_INTERVAL: u32 = 210_000;
+
+/// Block subsidy at `height` (50 BTC, halved every HALVING_INTERVAL blocks)
+pub fn block_subsidy(height: u32) -> u64 {
+    let halvings = height / HALVING_INTERVAL;
+    if halvings >= 64 {
+        0
+    } else {
+        (50 * COIN) >> halvings
+    }
+}
+
+/// A mempool transaction selected for the template
+#[derive(Debug, Clone)]
+pub struct TemplateTransaction {
+    /// The transaction
+    pub tx: Transaction,
+    /// Transaction ID
+    pub txid: Hash256,
+    /// Fee paid (satoshis)
+    pub fee: u64,
+    /// Signature-operation cost
+    pub sigops: usize,
+    /// Block weight
+    pub weight: usize,
+    /// 1-based indices of earlier template transactions this one spends from
+    pub depends: Vec<usize>,
+}
+
+/// A block ready to be mined, minus the coinbase
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    /// Block version
+    pub version: u32,
+    /// Hash of the block being built on
+    pub previous_block_hash: Hash256,
+    /// Height of the new block
+    pub height: u32,
+    /// Compact difficulty target
+    pub bits: u32,
+    /// Suggested block timestamp
+    pub cur_time: u32,
+    /// Selected transactions, in block order
+    pub transactions: Vec<TemplateTransaction>,
+    /// Subsidy plus fees: the most the coinbase may pay out
+    pub coinbase_value: u64,
+    /// Sum of transaction fees
+    pub total_fees: u64,
+    /// Block weight including the coinbase reservation
+    pub weight: usize,
+    /// Sigop cost including the coinbase reservation
+    pub sigops: usize,
+}
+
+impl BlockTemplate {
+    /// Coinbase paying `coinbase_value` to `script_pubkey`
+    pub fn coinbase_transaction(&self, script_pubkey: Vec<u8>, script_sig: Vec<u8>) -> Transaction {
+        Transaction::coinbase(script_sig, TxOutput::new(self.coinbase_value, script_pubkey), self.height)
+    }
+
+    /// Mining work for this template, paying the reward to `script_pubkey`
+    pub fn to_work(&self, script_pubkey: Vec<u8>) -> Result<MiningWork, String> {
+        let script_sig = format!("Block {}", self.height).into_bytes();
+        let mut transactions = vec![self.coinbase_transaction(script_pubkey, script_sig)];
+        transactions.extend(self.transactions.iter().map(|entry| entry.tx.clone()));
+
+        let header = BlockHeader::new(
+            self.version,
+            self.previous_block_hash,
+            Hash256::zero(),
+            self.cur_time,
+            self.bits,
+            0,
+        );
+        MiningWork::new(header, transactions)
+    }
+
+    /// Template in `getblocktemplate` (BIP 22) JSON form
+    pub fn to_json(&self) -> serde_json::Value {
+        let transactions: Vec<serde_json::Value> = self
+            .transactions
+            .iter()
+            .map(|entry| {
+                json!({
+                    "data": hex::encode(entry.tx.serialize()),
+                    "txid": entry.txid.to_hex(),
+                    "hash": entry.txid.to_hex(),
+                    "depends": entry.depends,
+                    "fee": entry.fee,
+                    "sigops": entry.sigops,
+                    "weight": entry.weight,
+                })
+            })
+            .collect();
+
+        json!({
+            "version": self.version,
+            "previousblockhash": self.previous_block_hash.to_hex(),
+            "transactions": transactions,
+            "coinbaseaux": {},
+            "coinbasevalue": self.coinbase_value,
+            "target": Target::from_bits(self.bits).to_hash256().to_hex(),
+            "mintime": self.cur_time,
+            "mutable": ["time", "transactions", "prevblock"],
+            "noncerange": "00000000ffffffff",
+            "sigoplimit": MAX_BLOCK_SIGOPS_COST,
+            "sizelimit": MAX_BLOCK_WEIGHT / WITNESS_SCALE_FACTOR,
+            "weightlimit": MAX_BLOCK_WEIGHT,
+            "curtime": self.cur_time,
+            "bits": format!("{:08x}", self.bits),
+            "height": self.height,
+        })
+    }
+}
+
+/// Selects mempool transactions into block templates
+pub struct BlockAssembler {
+    version: u32,
+    max_weight: usize,
+    max_sigops: usize,
+}
+
+impl BlockAssembler {
+    /// Assembler using the consensus block limits
+    pub fn new() -> Self {
+        Self {
+            version: 1,
+            max_weight: MAX_BLOCK_WEIGHT,
+            max_sigops: MAX_BLOCK_SIGOPS_COST,
+        }
+    }
+
+    /// Set the block version (e.g. with BIP9 signalling bits)
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Limit template weight (capped at MAX_BLOCK_WEIGHT)
+    pub fn with_max_weight(mut self, max_weight: usize) -> Self {
+        self.max_weight = max_weight.min(MAX_BLOCK_WEIGHT);
+        self
+    }
+
+    /// Build a template on top of `prev_hash`
+    pub fn create_template(
+        &self,
+        mempool: &Mempool,
+        prev_hash: Hash256,
+        height: u32,
+        bits: u32,
+        time: u32,
+    ) -> BlockTemplate {
+        let mut weight = COINBASE_RESERVED_WEIGHT;
+        let mut sigops = COINBASE_RESERVED_SIGOPS;
+        let mut selected: Vec<Hash256> = Vec::new();
+        let mut packages = AncestorPackages::new(mempool);
+
+        while let Some(package) = packages.pop_best() {
+            let package_weight: usize = package.iter().map(|txid| mempool.get(txid).unwrap().weight).sum();
+            let package_sigops: usize = package.iter().map(|txid| mempool.get(txid).unwrap().sigops).sum();
+
+            if weight + package_weight > self.max_weight || sigops + package_sigops > self.max_sigops {
+                // Descendants include this package, so they will fail too
+                packages.fail(*package.last().unwrap());
+                continue;
+            }
+
+            weight += package_weight;
+            sigops += package_sigops;
+            packages.include(&package);
+            selected.extend(package);
+        }
+
+        // Block order: index (1-based, coinbase is 0) for `depends`
+        let index: HashMap<Hash256, usize> = selected
+            .iter()
+            .enumerate()
+            .map(|(i, txid)| (*txid, i + 1))
+            .collect();
+
+        let transactions: Vec<TemplateTransaction> = selected
+            .iter()
+            .map(|txid| {
+                let entry = mempool.get(txid).unwrap();
+                let mut depends: Vec<usize> = mempool.parents(txid).iter().map(|parent| index[parent]).collect();
+                depends.sort_unstable();
+                TemplateTransaction {
+                    tx: entry.tx.clone(),
+                    txid: *txid,
+                    fee: entry.fee,
+                    sigops: entry.sigops,
+                    weight: entry.weight,
+                    depends,
+                }
+            })
+            .collect();
+
+        let total_fees: u64 = transactions.iter().map(|entry| entry.fee).sum();
+
+        BlockTemplate {
+            version: self.version,
+            previous_block_hash: prev_hash,
+            height,
+            bits,
+            cur_time: time,
+            transactions,
+            coinbase_value: block_subsidy(height) + total_fees,
+            total_fees,
+            weight,
+            sigops,
+        }
+    }
+}
+
+/// A candidate and the score of its package when it was queued
+#[derive(PartialEq, Eq)]
+struct Candidate {
+    fee: u64,
+    weight: usize,
+    position: usize,
+    txid: Hash256,
+}
+
+impl Ord for Candidate {
+    // Higher package fee rate first, then earlier in the mempool
+    fn cmp(&self, other: &Self) -> Ordering {
+        // fee / weight against other.fee / other.weight, compared without division
+        (self.fee as u128 * other.weight as u128)
+            .cmp(&(other.fee as u128 * self.weight as u128))
+            .then_with(|| other.position.cmp(&self.position))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Ancestor packages of the mempool, scored as transactions are selected
+struct AncestorPackages {
+    /// Mempool order of each transaction (parents before children)
+    positions: HashMap<Hash256, usize>,
+    /// In-pool ancestors of each transaction
+    ancestors: HashMap<Hash256, HashSet<Hash256>>,
+    /// In-pool descendants of each transaction
+    descendants: HashMap<Hash256, Vec<Hash256>>,
+    /// Own fee and weight of each transaction
+    own: HashMap<Hash256, (u64, usize)>,
+    /// Fee and weight of each remaining transaction plus its unselected ancestors
+    scores: HashMap<Hash256, (u64, usize)>,
+    /// Candidates by score; entries whose score has since changed are stale
+    queue: BinaryHeap<Candidate>,
+    included: HashSet<Hash256>,
+    failed: HashSet<Hash256>,
+}
+
+impl AncestorPackages {
+    /// Score every mempool transaction by its full ancestor package
+    fn new(mempool: &Mempool) -> Self {
+        let mut packages = Self {
+            positions: HashMap::new(),
+            ancestors: HashMap::new(),
+            descendants: HashMap::new(),
+            own: HashMap::new(),
+            scores: HashMap::new(),
+            queue: BinaryHeap::new(),
+            included: HashSet::new(),
+            failed: HashSet::new(),
+        };
+
+        // Parents come first, so their ancestor sets are already known
+        for (position, entry) in mempool.entries().enumerate() {
+            let mut ancestors = HashSet::new();
+            for parent in mempool.parents(&entry.txid) {
+                ancestors.extend(packages.ancestors[&parent].iter().copied());
+                ancestors.insert(parent);
+            }
+            let (mut fee, mut weight) = (entry.fee, entry.weight);
+            for ancestor in &ancestors {
+                let (ancestor_fee, ancestor_weight) = packages.own[ancestor];
+                fee += ancestor_fee;
+                weight += ancestor_weight;
+                packages.descendants.entry(*ancestor).or_default().push(entry.txid);
+            }
+
+            packages.positions.insert(entry.txid, position);
+            packages.ancestors.insert(entry.txid, ancestors);
+            packages.own.insert(entry.txid, (entry.fee, entry.weight));
+            packages.rescore(entry.txid, fee, weight);
+        }
+        packages
+    }
+
+    /// Best remaining package, parents first with the candidate itself last
+    fn pop_best(&mut self) -> Option<Vec<Hash256>> {
+        while let Some(candidate) = self.queue.pop() {
+            let txid = candidate.txid;
+            if self.included.contains(&txid)
+                || self.failed.contains(&txid)
+                || self.scores.get(&txid) != Some(&(candidate.fee, candidate.weight))
+            {
+                continue;
+            }
+
+            let mut package: Vec<Hash256> = self.ancestors[&txid]
+                .iter()
+                .filter(|ancestor| !self.included.contains(*ancestor))
+                .copied()
+                .collect();
+            if package.iter().any(|ancestor| self.failed.contains(ancestor)) {
+                continue;
+            }
+            package.sort_by_key(|ancestor| self.positions[ancestor]);
+            package.push(txid);
+            return Some(package);
+        }
+        None
+    }
+
+    /// Drop a candidate whose package did not fit
+    fn fail(&mut self, txid: Hash256) {
+        self.failed.insert(txid);
+    }
+
+    /// Mark a package selected and take it out of its descendants' packages
+    fn include(&mut self, package: &[Hash256]) {
+        for txid in package {
+            self.included.insert(*txid);
+            self.scores.remove(txid);
+        }
+        for txid in package {
+            let (fee, weight) = self.own[txid];
+            for descendant in self.descendants.get(txid).cloned().unwrap_or_default() {
+                if let Some(&(package_fee, package_weight)) = self.scores.get(&descendant) {
+                    self.rescore(descendant, package_fee - fee, package_weight - weight);
+                }
+            }
+        }
+    }
+
+    // Helper: set a transaction's package score and queue it
+    fn rescore(&mut self, txid: Hash256, fee: u64, weight: usize) {
+        self.scores.insert(txid, (fee, weight));
+        self.queue.push(Candidate {
+            fee,
+            weight,
+            position: self.positions[&txid],
+            txid,
+        });
+    }
+}
+
+impl Default for BlockAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Block, TxInput};
+    use crate::storage::{MemoryBackend, OutPoint, Utxo, UtxoSet};
+
+    // Helper: UTXO set with `count` confirmed 100_000 sat outputs
+    fn funded(count: u8) -> (UtxoSet<MemoryBackend>, Vec<OutPoint>) {
+        let utxo_set = UtxoSet::memory().unwrap();
+        let outpoints: Vec<OutPoint> = (0..count)
+            .map(|i| OutPoint::new(Hash256::new([i + 1; 32]), 0))
+            .collect();
+        for outpoint in &outpoints {
+            let utxo = Utxo::new(TxOutput::new(100_000, vec![0xac]), 1, false);
+            utxo_set.add_utxo(outpoint, &utxo).unwrap();
+        }
+        (utxo_set, outpoints)
+    }
+
+    // Helper: transaction spending `outpoint` with the given fee
+    fn spend(outpoint: &OutPoint, input_value: u64, fee: u64) -> Transaction {
+        Transaction::new(
+            vec![TxInput::new(outpoint.txid, outpoint.vout, vec![])],
+            vec![TxOutput::new(input_value - fee, vec![0xac])],
+        )
+    }
+
+    #[test]
+    fn test_block_subsidy() {
+        assert_eq!(block_subsidy(0), 50 * COIN);
+        assert_eq!(block_subsidy(HALVING_INTERVAL - 1), 50 * COIN);
+        assert_eq!(block_subsidy(HALVING_INTERVAL), 25 * COIN);
+        assert_eq!(block_subsidy(64 * HALVING_INTERVAL), 0);
+    }
+
+    #[test]
+    fn test_orders_by_fee_rate_and_pays_fees() {
+        let (utxo_set, outpoints) = funded(2);
+        let mut mempool = Mempool::new();
+        let low = mempool.add(spend(&outpoints[0], 100_000, 1_000), &utxo_set).unwrap();
+        let high = mempool.add(spend(&outpoints[1], 100_000, 5_000), &utxo_set).unwrap();
+
+        let template = BlockAssembler::new().create_template(&mempool, Hash256::zero(), 1, 0x207fffff, 1_000);
+
+        let order: Vec<Hash256> = template.transactions.iter().map(|entry| entry.txid).collect();
+        assert_eq!(order, vec![high, low]);
+        assert_eq!(template.total_fees, 6_000);
+        assert_eq!(template.coinbase_value, 50 * COIN + 6_000);
+    }
+
+    #[test]
+    fn test_child_pays_for_parent() {
+        let (utxo_set, outpoints) = funded(2);
+        let mut mempool = Mempool::new();
+        let parent = mempool.add(spend(&outpoints[0], 100_000, 100), &utxo_set).unwrap();
+        let child = mempool
+            .add(spend(&OutPoint::new(parent, 0), 99_900, 20_000), &utxo_set)
+            .unwrap();
+        let other = mempool.add(spend(&outpoints[1], 100_000, 5_000), &utxo_set).unwrap();
+
+        let template = BlockAssembler::new().create_template(&mempool, Hash256::zero(), 1, 0x207fffff, 1_000);
+
+        let order: Vec<Hash256> = template.transactions.iter().map(|entry| entry.txid).collect();
+        assert_eq!(order, vec![parent, child, other]);
+        assert_eq!(template.transactions[1].depends, vec![1]);
+    }
+
+    #[test]
+    fn test_selected_parent_leaves_child_package() {
+        let (utxo_set, outpoints) = funded(2);
+        let mut mempool = Mempool::new();
+        let parent = mempool.add(spend(&outpoints[0], 100_000, 10_000), &utxo_set).unwrap();
+        let child = mempool
+            .add(spend(&OutPoint::new(parent, 0), 90_000, 100), &utxo_set)
+            .unwrap();
+        let other = mempool.add(spend(&outpoints[1], 100_000, 5_000), &utxo_set).unwrap();
+
+        let template = BlockAssembler::new().create_template(&mempool, Hash256::zero(), 1, 0x207fffff, 1_000);
+
+        // With its parent the child outbids `other`; once the parent is in, it pays only its own fee
+        let order: Vec<Hash256> = template.transactions.iter().map(|entry| entry.txid).collect();
+        assert_eq!(order, vec![parent, other, child]);
+        assert_eq!(template.transactions[2].depends, vec![1]);
+    }
+
+    #[test]
+    fn test_respects_weight_limit() {
+        let (utxo_set, outpoints) = funded(3);
+        let mut mempool = Mempool::new();
+        for outpoint in &outpoints {
+            mempool.add(spend(outpoint, 100_000, 1_000), &utxo_set).unwrap();
+        }
+        let tx_weight = mempool.entries().next().unwrap().weight;
+
+        let template = BlockAssembler::new()
+            .with_max_weight(COINBASE_RESERVED_WEIGHT + 2 * tx_weight)
+            .create_template(&mempool, Hash256::zero(), 1, 0x207fffff, 1_000);
+
+        assert_eq!(template.transactions.len(), 2);
+        assert!(template.weight <= COINBASE_RESERVED_WEIGHT + 2 * tx_weight);
+    }
+
+    #[test]
+    fn test_template_work_and_json() {
+        let (utxo_set, outpoints) = funded(1);
+        let mut mempool = Mempool::new();
+        mempool.add(spend(&outpoints[0], 100_000, 1_000), &utxo_set).unwrap();
+
+        let template = BlockAssembler::new().create_template(&mempool, Hash256::new([7; 32]), 5, 0x207fffff, 1_000);
+
+        let work = template.to_work(vec![0xac]).unwrap();
+        assert_eq!(work.transactions.len(), 2);
+        assert_eq!(work.transactions[0].outputs[0].value, template.coinbase_value);
+        let block = Block::new(work.header, work.transactions);
+        assert_eq!(block.header.merkle_root, Block::calculate_merkle_root(&block.transactions));
+
+        let json = template.to_json();
+        assert_eq!(json["height"], 5);
+        assert_eq!(json["bits"], "207fffff");
+        assert_eq!(json["coinbasevalue"], template.coinbase_value);
+        assert_eq!(json["previousblockhash"], Hash256::new([7; 32]).to_hex());
+        assert_eq!(json["transactions"].as_array().unwrap().len(), 1);
+    }
+}
@@ -7,7 +7,8 @@
 //   - Automatically falls back to CPU if no GPU adapter is found
 
 use crate::core::BlockHeader;
-use crate::consensus::pow::{Miner, MiningResult, Target};
+use crate::consensus::pow::{CancelToken, Miner, MiningResult, ParallelMiner, Target};
+use crate::consensus::work::MiningWork;
 use std::time::Instant;
 
 /// Number of threads per workgroup (must match @workgroup_size in WGSL)
@@ -269,7 +270,7 @@ fn main(@builtin(global_invocation_id) gid : vec3<u32>) {
 // ── GpuMiner ─────────────────────────────────────────────────────────────────
 
 /// GPU miner backed by wgpu compute shaders.
-/// Falls back to the CPU `Miner` automatically if no GPU adapter is available.
+/// Falls back to the CPU `ParallelMiner` automatically if no GPU adapter is available.
 pub struct GpuMiner {
     bits: u32,
     target_be: [u32; 8],
@@ -297,31 +298,57 @@ impl GpuMiner {
 
     /// Mine a block header. Tries GPU first, falls back to CPU on any error.
     pub fn mine(&self, header: &mut BlockHeader) -> MiningResult {
-        match self.mine_gpu(header) {
+        self.mine_cancellable(header, &CancelToken::new())
+    }
+
+    /// Mine a block header until a nonce is found, the nonce space is
+    /// exhausted or `cancel` is triggered (checked between GPU batches).
+    /// Falls back to the multi-threaded CPU miner on any GPU error.
+    pub fn mine_cancellable(&self, header: &mut BlockHeader, cancel: &CancelToken) -> MiningResult {
+        match self.mine_gpu(header, cancel) {
             Ok(r) => r,
             Err(e) => {
                 log::warn!("GPU mining unavailable ({}), falling back to CPU", e);
-                let cpu = Miner::new(self.bits);
-                cpu.mine(header)
+                let cpu = ParallelMiner::new(self.bits, 0);
+                cpu.mine(header, cancel, |_| {})
             }
         }
     }
 
-    fn mine_gpu(&self, header: &mut BlockHeader) -> Result<MiningResult, String> {
-        // ── Initialise wgpu ───────────────────────────────────────────────────
+    /// Mine a full work unit, rolling extraNonce/timestamp whenever a header's
+    /// nonce space is exhausted (same mechanism as the CPU miner)
+    pub fn mine_work(&self, work: &mut MiningWork, cancel: &CancelToken) -> MiningResult {
+        work.mine(cancel, |header| self.mine_cancellable(header, cancel))
+    }
+
+    /// Name of the GPU adapter that would be used, if any (capability probe)
+    pub fn adapter_name() -> Option<String> {
+        Self::request_adapter().map(|adapter| {
+            let info = adapter.get_info();
+            format!("{} ({:?})", info.name, info.backend)
+        })
+    }
+
+    // Helper: find a high-performance adapter on any wgpu backend
+    fn request_adapter() -> Option<wgpu::Adapter> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             ..Default::default()
         });
 
-        let adapter = pollster::block_on(instance.request_adapter(
+        pollster::block_on(instance.request_adapter(
             &wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 compatible_surface: None,
                 force_fallback_adapter: false,
             },
         ))
-        .ok_or("No GPU adapter found – is a GPU driver installed?")?;
+    }
+
+    fn mine_gpu(&self, header: &mut BlockHeader, cancel: &CancelToken) -> Result<MiningResult, String> {
+        // ── Initialise wgpu ───────────────────────────────────────────────────
+        let adapter = Self::request_adapter()
+            .ok_or("No GPU adapter found – is a GPU driver installed?")?;
 
         let adapter_info = adapter.get_info();
         log::info!(
@@ -335,7 +362,6 @@ impl GpuMiner {
                 label: Some("bitcoin-mining"),
                 required_features: wgpu::Features::empty(),
                 required_limits: wgpu::Limits::default(),
-                ..Default::default()
             },
             None,
         ))
@@ -456,6 +482,17 @@ impl GpuMiner {
         );
 
         loop {
+            if cancel.is_cancelled() {
+                return Ok(MiningResult {
+                    success: false,
+                    nonce: 0,
+                    hash: crate::core::Hash256::zero(),
+                    attempts: total_attempts,
+                    duration: start_time.elapsed(),
+                    cancelled: true,
+                });
+            }
+
             // Write params for this batch
             let gpu_params = GpuParams {
                 header_prefix,
@@ -526,6 +563,7 @@ impl GpuMiner {
                         hash,
                         attempts: total_attempts,
                         duration: elapsed,
+                        cancelled: false,
                     });
                 }
                 // Rare race: two threads found simultaneously; scan next few on CPU
@@ -540,6 +578,7 @@ impl GpuMiner {
                             hash: header.hash(),
                             attempts: total_attempts + offset as u64,
                             duration: elapsed,
+                            cancelled: false,
                         });
                     }
                 }
@@ -569,6 +608,7 @@ impl GpuMiner {
                         hash: crate::core::Hash256::zero(),
                         attempts: total_attempts,
                         duration: elapsed,
+                        cancelled: false,
                     });
                 }
             };
@@ -0,0 +1,426 @@
+// Transaction memory pool
+//
+// Holds transactions that are waiting to be confirmed. Each entry records
+// the fee, weight and signature-operation cost needed by block assembly.
+// Inputs may spend confirmed outputs (UTXO set) or outputs of other pool
+// transactions, so the pool forms a DAG of parents and children;
+// `ancestors` walks it for ancestor-fee-rate selection.
+//
+// The pool only checks structure, input availability, conflicts and fees.
+// Input scripts are verified by the caller
+// (`BlockValidator::verify_transaction_scripts`) before `add`.
+
+use crate::consensus::validation::TransactionValidator;
+use crate::core::{Block, Hash256, Script, Serializable, Transaction};
+use crate::storage::{CoinsView, OutPoint};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Weight units per byte of non-witness data
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
+/// A transaction waiting in the pool
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    /// The transaction
+    pub tx: Transaction,
+    /// Transaction ID
+    pub txid: Hash256,
+    /// Input value minus output value (satoshis)
+    pub fee: u64,
+    /// Serialized size in bytes
+    pub size: usize,
+    /// Block weight (size * WITNESS_SCALE_FACTOR, no witness data)
+    pub weight: usize,
+    /// Signature-operation cost (legacy sigops * WITNESS_SCALE_FACTOR)
+    pub sigops: usize,
+    /// Unix time the transaction entered the pool
+    pub time: u64,
+}
+
+impl MempoolEntry {
+    /// Fee rate in satoshis per virtual byte
+    pub fn fee_rate(&self) -> f64 {
+        self.fee as f64 * WITNESS_SCALE_FACTOR as f64 / self.weight as f64
+    }
+}
+
+/// Pool of unconfirmed transactions
+#[derive(Default)]
+pub struct Mempool {
+    /// Entries by txid
+    entries: HashMap<Hash256, MempoolEntry>,
+    /// Outpoints spent by pool transactions -> spending txid
+    spent: HashMap<OutPoint, Hash256>,
+    /// Txids in insertion order (parents always precede children)
+    order: Vec<Hash256>,
+}
+
+impl Mempool {
+    /// Create an empty pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a transaction whose inputs are in `utxo_set` or in the pool
+    /// Returns the txid.
+    pub fn add(&mut self, tx: Transaction, utxo_set: &dyn CoinsView) -> Result<Hash256, String> {
+        TransactionValidator::validate_for_mempool(&tx).map_err(|e| e.to_string())?;
+
+        let txid = tx.txid();
+        if self.entries.contains_key(&txid) {
+            return Err(format!("Transaction {} already in mempool", txid));
+        }
+
+        let mut input_value = 0u64;
+        let mut sigops = 0usize;
+        let mut spends = HashSet::new();
+
+        for input in &tx.inputs {
+            let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+            if let Some(other) = self.spent.get(&outpoint) {
+                return Err(format!("Input {}:{} already spent by {}", outpoint.txid, outpoint.vout, other));
+            }
+            if !spends.insert(outpoint.clone()) {
+                return Err(format!("Input {}:{} spent twice", outpoint.txid, outpoint.vout));
+            }
+
+            let output = match self.entries.get(&input.prev_tx_hash) {
+                Some(parent) => parent.tx.outputs.get(input.prev_index as usize).cloned(),
+                None => utxo_set.get_utxo(&outpoint)?.map(|utxo| utxo.output),
+            };
+            let output = output
+                .ok_or_else(|| format!("Missing input {}:{}", outpoint.txid, outpoint.vout))?;
+
+            input_value += output.value;
+            sigops += Script::count_sigops(&input.script_sig);
+        }
+
+        let output_value = tx.total_output_value();
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or_else(|| format!("Outputs ({}) exceed inputs ({})", output_value, input_value))?;
+        sigops += tx
+            .outputs
+            .iter()
+            .map(|output| Script::count_sigops(&output.script_pubkey))
+            .sum::<usize>();
+
+        let size = tx.serialize().len();
+        let entry = MempoolEntry {
+            txid,
+            fee,
+            size,
+            weight: size * WITNESS_SCALE_FACTOR,
+            sigops: sigops * WITNESS_SCALE_FACTOR,
+            time: Self::now(),
+            tx,
+        };
+
+        for outpoint in spends {
+            self.spent.insert(outpoint, txid);
+        }
+        self.entries.insert(txid, entry);
+        self.order.push(txid);
+
+        log::debug!("Accepted {} to mempool (fee {} sat)", txid, fee);
+        Ok(txid)
+    }
+
+    /// Get an entry by txid
+    pub fn get(&self, txid: &Hash256) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    /// Whether a pool transaction spends `outpoint`
+    pub fn is_spent(&self, outpoint: &OutPoint) -> bool {
+        self.spent.contains_key(outpoint)
+    }
+
+    /// Check whether a transaction is in the pool
+    pub fn contains(&self, txid: &Hash256) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    /// Number of transactions in the pool
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the pool is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sum of all fees in the pool
+    pub fn total_fees(&self) -> u64 {
+        self.entries.values().map(|entry| entry.fee).sum()
+    }
+
+    /// Entries in insertion order (parents before children)
+    pub fn entries(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.order.iter().map(|txid| &self.entries[txid])
+    }
+
+    /// In-pool transactions that `txid` spends from
+    pub fn parents(&self, txid: &Hash256) -> Vec<Hash256> {
+        let Some(entry) = self.entries.get(txid) else {
+            return Vec::new();
+        };
+
+        let mut parents: Vec<Hash256> = entry
+            .tx
+            .inputs
+            .iter()
+            .map(|input| input.prev_tx_hash)
+            .filter(|parent| self.entries.contains_key(parent))
+            .collect();
+        // Keep the first input spending from each parent, in input order
+        let mut seen = HashSet::new();
+        parents.retain(|parent| seen.insert(*parent));
+        parents
+    }
+
+    /// All in-pool ancestors of `txid` (not including itself)
+    pub fn ancestors(&self, txid: &Hash256) -> HashSet<Hash256> {
+        let mut ancestors = HashSet::new();
+        let mut stack = self.parents(txid);
+
+        while let Some(parent) = stack.pop() {
+            if ancestors.insert(parent) {
+                stack.extend(self.parents(&parent));
+            }
+        }
+        ancestors
+    }
+
+    /// Position of a transaction in insertion order (topological order)
+    pub fn position(&self, txid: &Hash256) -> Option<usize> {
+        self.order.iter().position(|id| id == txid)
+    }
+
+    /// Remove a transaction and everything that spends from it
+    /// Returns the removed entries.
+    pub fn remove(&mut self, txid: &Hash256) -> Vec<MempoolEntry> {
+        let mut removed = Vec::new();
+        let mut stack = vec![*txid];
+
+        while let Some(txid) = stack.pop() {
+            let Some(entry) = self.entries.remove(&txid) else {
+                continue;
+            };
+
+            for input in &entry.tx.inputs {
+                self.spent.remove(&OutPoint::new(input.prev_tx_hash, input.prev_index));
+            }
+            for vout in 0..entry.tx.outputs.len() {
+                if let Some(child) = self.spent.get(&OutPoint::new(txid, vout as u32)) {
+                    stack.push(*child);
+                }
+            }
+            removed.push(entry);
+        }
+
+        let gone: HashSet<Hash256> = removed.iter().map(|entry| entry.txid).collect();
+        self.order.retain(|id| !gone.contains(id));
+        removed
+    }
+
+    /// Drop transactions confirmed by `block` and those conflicting with it
+    /// Returns the number of transactions removed.
+    pub fn remove_for_block(&mut self, block: &Block) -> usize {
+        let mut removed = 0;
+
+        for tx in &block.transactions {
+            let txid = tx.txid();
+
+            // Confirmed: remove only this entry, its children stay valid
+            if let Some(entry) = self.entries.remove(&txid) {
+                for input in &entry.tx.inputs {
+                    self.spent.remove(&OutPoint::new(input.prev_tx_hash, input.prev_index));
+                }
+                self.order.retain(|id| *id != txid);
+                removed += 1;
+                continue;
+            }
+
+            // Conflicts: pool transactions spending the same outputs
+            if tx.is_coinbase() {
+                continue;
+            }
+            for input in &tx.inputs {
+                let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+                if let Some(conflict) = self.spent.get(&outpoint).copied() {
+                    removed += self.remove(&conflict).len();
+                }
+            }
+        }
+
+        removed
+    }
+
+    /// Save the pool to a file (transactions as hex, parents first)
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let txs: Vec<String> = self
+            .entries()
+            .map(|entry| hex::encode(entry.tx.serialize()))
+            .collect();
+
+        let json = serde_json::to_string_pretty(&txs)
+            .map_err(|e| format!("Failed to serialize mempool: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Failed to write mempool file: {}", e))
+    }
+
+    /// Load a pool saved with `save`, re-checking every transaction
+    /// against `utxo_set`; transactions that no longer fit are dropped.
+    pub fn load<P: AsRef<Path>>(path: P, utxo_set: &dyn CoinsView) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read mempool file: {}", e))?;
+        let txs: Vec<String> = serde_json::from_str(&json)
+            .map_err(|e| format!("Failed to deserialize mempool: {}", e))?;
+
+        let mut mempool = Self::new();
+        for tx_hex in txs {
+            let bytes = hex::decode(&tx_hex).map_err(|e| format!("Invalid mempool entry: {}", e))?;
+            let tx = Transaction::deserialize(&bytes)?;
+            let txid = tx.txid();
+            if let Err(e) = mempool.add(tx, utxo_set) {
+                log::info!("Dropping {} from mempool: {}", txid, e);
+            }
+        }
+        Ok(mempool)
+    }
+
+    // Helper: current Unix time
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{TxInput, TxOutput};
+    use crate::storage::{MemoryBackend, Utxo, UtxoSet};
+
+    // Helper: UTXO set with one confirmed 10_000 sat output
+    fn funded() -> (UtxoSet<MemoryBackend>, OutPoint) {
+        let utxo_set = UtxoSet::memory().unwrap();
+        let outpoint = OutPoint::new(Hash256::new([1; 32]), 0);
+        let utxo = Utxo::new(TxOutput::new(10_000, vec![0xac]), 1, false);
+        utxo_set.add_utxo(&outpoint, &utxo).unwrap();
+        (utxo_set, outpoint)
+    }
+
+    // Helper: transaction spending `outpoint` into one output of `value`
+    fn spend(outpoint: &OutPoint, value: u64) -> Transaction {
+        Transaction::new(
+            vec![TxInput::new(outpoint.txid, outpoint.vout, vec![])],
+            vec![TxOutput::new(value, vec![0xac])],
+        )
+    }
+
+    #[test]
+    fn test_add_computes_fee_and_chains() {
+        let (utxo_set, outpoint) = funded();
+        let mut mempool = Mempool::new();
+
+        let parent = mempool.add(spend(&outpoint, 9_000), &utxo_set).unwrap();
+        let child = mempool
+            .add(spend(&OutPoint::new(parent, 0), 8_500), &utxo_set)
+            .unwrap();
+
+        assert_eq!(mempool.get(&parent).unwrap().fee, 1_000);
+        assert_eq!(mempool.get(&child).unwrap().fee, 500);
+        assert_eq!(mempool.get(&child).unwrap().sigops, WITNESS_SCALE_FACTOR);
+        assert_eq!(mempool.ancestors(&child), HashSet::from([parent]));
+        assert_eq!(mempool.total_fees(), 1_500);
+    }
+
+    #[test]
+    fn test_parents_are_listed_once() {
+        let (utxo_set, outpoint) = funded();
+        let other = OutPoint::new(Hash256::new([2; 32]), 0);
+        utxo_set.add_utxo(&other, &Utxo::new(TxOutput::new(10_000, vec![0xac]), 1, false)).unwrap();
+        let mut mempool = Mempool::new();
+
+        let split = Transaction::new(
+            vec![TxInput::new(outpoint.txid, outpoint.vout, vec![])],
+            vec![TxOutput::new(4_000, vec![0xac]), TxOutput::new(4_000, vec![0xac])],
+        );
+        let first = mempool.add(split, &utxo_set).unwrap();
+        let second = mempool.add(spend(&other, 9_000), &utxo_set).unwrap();
+
+        // Spends the first parent on both sides of the second
+        let child = Transaction::new(
+            vec![
+                TxInput::new(first, 0, vec![]),
+                TxInput::new(second, 0, vec![]),
+                TxInput::new(first, 1, vec![]),
+            ],
+            vec![TxOutput::new(16_000, vec![0xac])],
+        );
+        let child = mempool.add(child, &utxo_set).unwrap();
+        assert_eq!(mempool.parents(&child), vec![first, second]);
+    }
+
+    #[test]
+    fn test_rejects_conflicts_and_missing_inputs() {
+        let (utxo_set, outpoint) = funded();
+        let mut mempool = Mempool::new();
+
+        mempool.add(spend(&outpoint, 9_000), &utxo_set).unwrap();
+        assert!(mempool.add(spend(&outpoint, 8_000), &utxo_set).is_err());
+        assert!(mempool.add(spend(&OutPoint::new(Hash256::new([9; 32]), 0), 1), &utxo_set).is_err());
+
+        let (utxo_set, outpoint) = funded();
+        assert!(Mempool::new().add(spend(&outpoint, 20_000), &utxo_set).is_err());
+    }
+
+    #[test]
+    fn test_remove_for_block() {
+        let (utxo_set, outpoint) = funded();
+        let mut mempool = Mempool::new();
+
+        let parent = spend(&outpoint, 9_000);
+        let parent_id = mempool.add(parent.clone(), &utxo_set).unwrap();
+        let child = mempool
+            .add(spend(&OutPoint::new(parent_id, 0), 8_500), &utxo_set)
+            .unwrap();
+
+        // Confirming the parent keeps the child
+        let block = Block::new(Block::genesis().header, vec![parent]);
+        assert_eq!(mempool.remove_for_block(&block), 1);
+        assert!(mempool.contains(&child));
+        assert!(mempool.ancestors(&child).is_empty());
+
+        // A conflicting spend of the child's input evicts it
+        let conflict = spend(&OutPoint::new(parent_id, 0), 1);
+        let block = Block::new(Block::genesis().header, vec![conflict]);
+        assert_eq!(mempool.remove_for_block(&block), 1);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let (utxo_set, outpoint) = funded();
+        let mut mempool = Mempool::new();
+        let parent = mempool.add(spend(&outpoint, 9_000), &utxo_set).unwrap();
+        let child = mempool
+            .add(spend(&OutPoint::new(parent, 0), 8_500), &utxo_set)
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("mempool-test-{}.json", std::process::id()));
+        mempool.save(&path).unwrap();
+        let loaded = Mempool::load(&path, &utxo_set).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.position(&parent), Some(0));
+        assert_eq!(loaded.position(&child), Some(1));
+    }
+}
@@ -0,0 +1,111 @@
+// SHA256 midstate hashing for block headers
+//
+// A block header is 80 bytes, so the first SHA256 pass needs two 64-byte
+// compression blocks:
+//   block 1: bytes  0-63 (version, prev hash, most of the merkle root)
+//   block 2: bytes 64-79 (end of merkle root, time, bits, nonce) + padding
+// Block 1 never changes while the nonce is varied, so its output state
+// (the "midstate") is computed once. Each nonce then costs two compressions
+// (block 2 and the second SHA256 pass) instead of three, and no
+// serialization or allocation.
+//
+// `sha2::compress256` picks a SIMD/SHA-NI implementation at runtime when
+// the CPU supports one. The GPU shader uses the same layout (see gpu_pow.rs).
+
+use crate::core::{BlockHeader, Hash256};
+use sha2::digest::generic_array::GenericArray;
+
+/// SHA256 initial hash values
+const SHA256_H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+    0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Header hasher with the first compression block precomputed
+#[derive(Debug, Clone)]
+pub struct MidstateHasher {
+    /// SHA256 state after compressing header bytes 0-63
+    midstate: [u32; 8],
+    /// Second block: header bytes 64-79, padding and the 640-bit length
+    tail: [u8; 64],
+}
+
+impl MidstateHasher {
+    /// Offset of the nonce inside the second compression block
+    const NONCE_OFFSET: usize = 12;
+
+    /// Precompute the midstate for a header (its nonce is ignored)
+    pub fn new(header: &BlockHeader) -> Self {
+        let raw = header.serialize_to_array();
+
+        let mut midstate = SHA256_H0;
+        sha2::compress256(&mut midstate, &[*GenericArray::from_slice(&raw[..64])]);
+
+        let mut tail = [0u8; 64];
+        tail[..16].copy_from_slice(&raw[64..]);
+        tail[16] = 0x80;
+        tail[56..].copy_from_slice(&(80u64 * 8).to_be_bytes());
+
+        Self { midstate, tail }
+    }
+
+    /// Double-SHA256 of the header with the given nonce
+    /// Same result as `BlockHeader::hash` with `header.nonce = nonce`
+    #[inline]
+    pub fn hash_with_nonce(&self, nonce: u32) -> Hash256 {
+        // First pass: finish the 80-byte message from the midstate
+        let mut block = self.tail;
+        block[Self::NONCE_OFFSET..Self::NONCE_OFFSET + 4].copy_from_slice(&nonce.to_le_bytes());
+
+        let mut state = self.midstate;
+        sha2::compress256(&mut state, &[GenericArray::from(block)]);
+
+        // Second pass: hash the 32-byte digest (single padded block)
+        let mut block = [0u8; 64];
+        for (chunk, word) in block.chunks_exact_mut(4).zip(state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        block[32] = 0x80;
+        block[56..].copy_from_slice(&(32u64 * 8).to_be_bytes());
+
+        let mut state = SHA256_H0;
+        sha2::compress256(&mut state, &[GenericArray::from(block)]);
+
+        let mut hash = [0u8; 32];
+        for (chunk, word) in hash.chunks_exact_mut(4).zip(state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        Hash256::new(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Block;
+
+    #[test]
+    fn test_midstate_matches_header_hash() {
+        let mut header = BlockHeader::new(
+            1,
+            Hash256::new([0x11; 32]),
+            Hash256::new([0x22; 32]),
+            1234567890,
+            0x1d00ffff,
+            0,
+        );
+        let hasher = MidstateHasher::new(&header);
+
+        for nonce in [0, 1, 42, 0xdeadbeef, u32::MAX] {
+            header.nonce = nonce;
+            assert_eq!(hasher.hash_with_nonce(nonce), header.hash());
+        }
+    }
+
+    #[test]
+    fn test_midstate_genesis() {
+        let genesis = Block::genesis();
+        let hasher = MidstateHasher::new(&genesis.header);
+        assert_eq!(hasher.hash_with_nonce(genesis.header.nonce), genesis.hash());
+    }
+}
@@ -3,7 +3,27 @@
 pub mod pow;
 pub mod validation;
 pub mod gpu_pow;
+pub mod midstate;
+pub mod script_check;
+pub mod sig_cache;
+pub mod work;
+pub mod backend;
+pub mod mempool;
+pub mod block_assembly;
+pub mod params;
+pub mod versionbits;
+pub mod verify_db;
 
-pub use pow::{Miner, Target, MiningResult};
-pub use validation::{BlockValidator, TransactionValidator, ValidationError};
+pub use pow::{Miner, ParallelMiner, CancelToken, MiningProgress, Target, MiningResult};
+pub use validation::{BlockValidator, TransactionValidator, ValidationError, RuleClass, ScriptValidation};
 pub use gpu_pow::GpuMiner;
+pub use midstate::MidstateHasher;
+pub use script_check::{ScriptCheck, ScriptCheckQueue, ScriptCheckFailure};
+pub use sig_cache::{SignatureCache, ScriptExecutionCache, CacheStats};
+pub use work::MiningWork;
+pub use backend::{MiningBackend, BackendInfo, CpuBackend, GpuBackend, InstantBackend, MiningJob};
+pub use mempool::{Mempool, MempoolEntry};
+pub use block_assembly::{BlockAssembler, BlockTemplate, TemplateTransaction};
+pub use params::ChainParams;
+pub use versionbits::{Deployment, DeploymentStats, ThresholdState};
+pub use verify_db::{verify_chain, VerifyReport};
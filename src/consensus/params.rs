@@ -0,0 +1,162 @@
+// Chain parameters: difficulty, checkpoints and assume-valid
+//
+// Checkpoints pin the block hash at chosen heights. A block that disagrees
+// with a checkpoint is rejected, and so is any fork that would replace
+// blocks at or below the last checkpoint. The assume-valid hash names a
+// block whose ancestors are trusted to have valid scripts: when connecting
+// one of them the validator skips script verification but still runs every
+// other check (proof of work, merkle root, coinbase rules, checkpoints).
+// Soft forks are listed as BIP9 deployments (see versionbits.rs).
+
+use crate::core::{Block, Hash256};
+use crate::consensus::validation::ValidationError;
+use crate::consensus::versionbits::Deployment;
+use crate::core::script::SCRIPT_VERIFY_COMPRESSED_PUBKEYS;
+use std::collections::BTreeMap;
+
+/// Blocks per version-bits signalling window (regtest value)
+pub const VERSION_BITS_WINDOW: u32 = 144;
+
+/// Signalling blocks per window needed to lock in (75%)
+pub const VERSION_BITS_THRESHOLD: u32 = 108;
+
+/// Consensus parameters of a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    /// Compact difficulty target every block must meet
+    pub bits: u32,
+    /// Hard-coded block hashes by height
+    pub checkpoints: BTreeMap<u32, Hash256>,
+    /// Block whose ancestors skip script verification
+    pub assume_valid: Option<Hash256>,
+    /// Blocks per version-bits signalling window
+    pub version_bits_window: u32,
+    /// Signalling blocks per window needed to lock in a deployment
+    pub version_bits_threshold: u32,
+    /// Soft forks deployed with version bits
+    pub deployments: Vec<Deployment>,
+}
+
+impl ChainParams {
+    /// Parameters with the genesis block as the only checkpoint and the
+    /// `strictkeys` deployment (compressed public keys only) on bit 1
+    pub fn new(bits: u32) -> Self {
+        let mut checkpoints = BTreeMap::new();
+        checkpoints.insert(0, Block::genesis().hash());
+
+        Self {
+            bits,
+            checkpoints,
+            assume_valid: None,
+            version_bits_window: VERSION_BITS_WINDOW,
+            version_bits_threshold: VERSION_BITS_THRESHOLD,
+            deployments: vec![Deployment {
+                name: "strictkeys".to_string(),
+                bit: 1,
+                start_time: 0,
+                timeout: u32::MAX,
+                min_activation_height: 0,
+                script_flags: SCRIPT_VERIFY_COMPRESSED_PUBKEYS,
+            }],
+        }
+    }
+
+    /// Deployment with the given name
+    pub fn deployment(&self, name: &str) -> Option<&Deployment> {
+        self.deployments.iter().find(|deployment| deployment.name == name)
+    }
+
+    /// Add (or replace) the checkpoint at `height`
+    pub fn with_checkpoint(mut self, height: u32, hash: Hash256) -> Self {
+        self.checkpoints.insert(height, hash);
+        self
+    }
+
+    /// Set the assume-valid block
+    pub fn with_assume_valid(mut self, hash: Option<Hash256>) -> Self {
+        self.assume_valid = hash;
+        self
+    }
+
+    /// Checkpoint hash at `height`, if any
+    pub fn checkpoint(&self, height: u32) -> Option<&Hash256> {
+        self.checkpoints.get(&height)
+    }
+
+    /// Highest checkpoint height
+    pub fn last_checkpoint_height(&self) -> Option<u32> {
+        self.checkpoints.keys().next_back().copied()
+    }
+
+    /// Reject a block whose hash contradicts the checkpoint at its height
+    pub fn check_checkpoint(&self, height: u32, hash: &Hash256) -> Result<(), ValidationError> {
+        match self.checkpoint(height) {
+            Some(expected) if expected != hash => Err(ValidationError::CheckpointMismatch { height }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject a fork that would replace the block at `height` when a
+    /// checkpoint at or above that height already fixes the chain
+    pub fn check_fork(&self, height: u32) -> Result<(), ValidationError> {
+        match self.last_checkpoint_height() {
+            Some(checkpoint) if height <= checkpoint => Err(ValidationError::ForkBelowCheckpoint { height, checkpoint }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parse a "HEIGHT:HASH" checkpoint argument
+    pub fn parse_checkpoint(arg: &str) -> Result<(u32, Hash256), String> {
+        let (height, hash) = arg
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid checkpoint '{}': expected HEIGHT:HASH", arg))?;
+        let height = height
+            .parse()
+            .map_err(|e| format!("Invalid checkpoint height '{}': {}", height, e))?;
+        Ok((height, Hash256::from_hex(hash)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_genesis_checkpoint() {
+        let params = ChainParams::new(0x20ffffff);
+        let genesis = Block::genesis().hash();
+
+        assert_eq!(params.checkpoint(0), Some(&genesis));
+        assert!(params.check_checkpoint(0, &genesis).is_ok());
+        assert_eq!(
+            params.check_checkpoint(0, &Hash256::zero()),
+            Err(ValidationError::CheckpointMismatch { height: 0 })
+        );
+        // Heights without a checkpoint accept anything
+        assert!(params.check_checkpoint(1, &Hash256::zero()).is_ok());
+    }
+
+    #[test]
+    fn test_forks_below_last_checkpoint_rejected() {
+        let params = ChainParams::new(0x20ffffff).with_checkpoint(10, Hash256::zero());
+
+        assert_eq!(params.last_checkpoint_height(), Some(10));
+        assert_eq!(
+            params.check_fork(5),
+            Err(ValidationError::ForkBelowCheckpoint { height: 5, checkpoint: 10 })
+        );
+        assert!(params.check_fork(10).is_err());
+        assert!(params.check_fork(11).is_ok());
+    }
+
+    #[test]
+    fn test_parse_checkpoint() {
+        let hash = Block::genesis().hash();
+        let (height, parsed) = ChainParams::parse_checkpoint(&format!("42:{}", hash)).unwrap();
+
+        assert_eq!(height, 42);
+        assert_eq!(parsed, hash);
+        assert!(ChainParams::parse_checkpoint("42").is_err());
+        assert!(ChainParams::parse_checkpoint("x:00").is_err());
+    }
+}
@@ -1,7 +1,14 @@
 // Proof of Work implementation
 
+use crate::consensus::midstate::MidstateHasher;
 use crate::core::{BlockHeader, Hash256};
-use std::time::Instant;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Compact target that defines difficulty 1 (Bitcoin's genesis difficulty)
+pub const DIFFICULTY_1_BITS: u32 = 0x1d00ffff;
 
 /// Difficulty target representation
 #[derive(Debug, Clone, Copy)]
@@ -59,6 +66,36 @@ impl Target {
         false
     }
 
+    /// Target for a difficulty relative to `DIFFICULTY_1_BITS`
+    /// (difficulty 2 = half the difficulty-1 target). Rounded to compact
+    /// precision; clamped to the largest representable target.
+    pub fn from_difficulty(difficulty: f64) -> Self {
+        let mut value = Self::from_bits(DIFFICULTY_1_BITS).value() / difficulty.max(f64::MIN_POSITIVE);
+        let mut exponent = 3u32;
+        while value >= 16_777_216.0 && exponent < 32 {
+            value /= 256.0;
+            exponent += 1;
+        }
+        let mantissa = (value as u32).clamp(1, 0x00ffffff);
+
+        // `to_hash256` stores the coefficient bytes low-first
+        let coefficient = mantissa.swap_bytes() >> 8;
+        Self::from_bits((exponent << 24) | coefficient)
+    }
+
+    /// Difficulty of this target relative to `DIFFICULTY_1_BITS`
+    pub fn difficulty(&self) -> f64 {
+        Self::from_bits(DIFFICULTY_1_BITS).value() / self.value()
+    }
+
+    // Helper: target as a number (approximate, for difficulty arithmetic)
+    fn value(&self) -> f64 {
+        self.to_hash256()
+            .as_bytes()
+            .iter()
+            .fold(0.0, |value, byte| value * 256.0 + *byte as f64)
+    }
+
     /// Count leading zero bits in target (difficulty indicator)
     pub fn leading_zeros(&self) -> u32 {
         let target = self.to_hash256();
@@ -99,13 +136,38 @@ impl Miner {
     /// Mine a block by finding a valid nonce
     /// Returns the nonce that satisfies the PoW condition
     pub fn mine(&self, header: &mut BlockHeader) -> MiningResult {
+        self.mine_range(header, 0..=u32::MAX)
+    }
+
+    /// Mine over a nonce range, reserializing and hashing the full header per nonce
+    pub fn mine_range(&self, header: &mut BlockHeader, nonces: RangeInclusive<u32>) -> MiningResult {
+        self.search(header, nonces, |header| header.hash())
+    }
+
+    /// Mine a block using the SHA256 midstate of the first 64 header bytes
+    pub fn mine_midstate(&self, header: &mut BlockHeader) -> MiningResult {
+        self.mine_midstate_range(header, 0..=u32::MAX)
+    }
+
+    /// Mine over a nonce range using the cached midstate (only the last 16
+    /// header bytes are hashed per nonce)
+    pub fn mine_midstate_range(&self, header: &mut BlockHeader, nonces: RangeInclusive<u32>) -> MiningResult {
+        let hasher = MidstateHasher::new(header);
+        self.search(header, nonces, |header| hasher.hash_with_nonce(header.nonce))
+    }
+
+    // Helper: try every nonce in `nonces`, hashing the header with `hash_header`
+    fn search<F>(&self, header: &mut BlockHeader, nonces: RangeInclusive<u32>, hash_header: F) -> MiningResult
+    where
+        F: Fn(&BlockHeader) -> Hash256,
+    {
         let start_time = Instant::now();
         let mut attempts = 0u64;
 
-        // Try nonces from 0 to max
-        for nonce in 0..=u32::MAX {
+        // Try every nonce in the range
+        for nonce in nonces {
             header.nonce = nonce;
-            let hash = header.hash();
+            let hash = hash_header(header);
             attempts += 1;
 
             // Fast comparison using cached target hash
@@ -117,11 +179,12 @@ impl Miner {
                     hash,
                     attempts,
                     duration: elapsed,
+                    cancelled: false,
                 };
             }
 
             // Progress indicator every 100k attempts
-            if attempts % 100_000 == 0 {
+            if attempts.is_multiple_of(100_000) {
                 let elapsed = start_time.elapsed();
                 log::debug!("Mining attempts: {} ({:.1} KH/s)",
                     attempts,
@@ -137,6 +200,7 @@ impl Miner {
             hash: Hash256::zero(),
             attempts,
             duration: start_time.elapsed(),
+            cancelled: false,
         }
     }
 
@@ -174,6 +238,8 @@ pub struct MiningResult {
     pub attempts: u64,
     /// Time taken
     pub duration: std::time::Duration,
+    /// Whether mining was stopped early by a `CancelToken`
+    pub cancelled: bool,
 }
 
 impl MiningResult {
@@ -183,6 +249,196 @@ impl MiningResult {
     }
 }
 
+/// Cooperative cancellation flag shared between a miner and its controller
+/// (e.g. a node cancels the current job when a new tip arrives)
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation; miners stop at their next check
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether cancellation was requested
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress snapshot emitted periodically while mining
+#[derive(Debug, Clone, Copy)]
+pub struct MiningProgress {
+    /// Hashes tried so far (all threads)
+    pub attempts: u64,
+    /// Time since mining started
+    pub elapsed: Duration,
+}
+
+impl MiningProgress {
+    /// Current hash rate (hashes per second)
+    pub fn hash_rate(&self) -> f64 {
+        self.attempts as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Multi-threaded CPU miner
+/// Splits the nonce space into one contiguous range per thread and hashes
+/// with a cached SHA256 midstate.
+pub struct ParallelMiner {
+    /// Single-threaded miner holding the target
+    miner: Miner,
+    /// Number of worker threads
+    threads: usize,
+    /// How often progress callbacks fire
+    progress_interval: Duration,
+}
+
+impl ParallelMiner {
+    /// Hashes a worker tries between checks of the stop flag
+    const CHECK_INTERVAL: u64 = 4096;
+
+    /// Create a miner with the given thread count (0 = one per CPU core)
+    pub fn new(bits: u32, threads: usize) -> Self {
+        let threads = if threads == 0 {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            threads
+        };
+
+        Self {
+            miner: Miner::new(bits),
+            threads,
+            progress_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Set how often the progress callback is invoked
+    pub fn with_progress_interval(mut self, interval: Duration) -> Self {
+        self.progress_interval = interval;
+        self
+    }
+
+    /// Number of worker threads
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Mine a block header on all threads until a valid nonce is found,
+    /// the nonce space is exhausted or `cancel` is triggered.
+    /// `on_progress` is called from the calling thread every progress interval.
+    pub fn mine<F>(&self, header: &mut BlockHeader, cancel: &CancelToken, on_progress: F) -> MiningResult
+    where
+        F: Fn(MiningProgress),
+    {
+        let start_time = Instant::now();
+        let stop = AtomicBool::new(false);
+        let attempts = AtomicU64::new(0);
+        let finished = AtomicUsize::new(0);
+        let found: Mutex<Option<(u32, Hash256)>> = Mutex::new(None);
+        let template = header.clone();
+        let nonce_space = u32::MAX as u64 + 1;
+
+        std::thread::scope(|scope| {
+            for i in 0..self.threads as u64 {
+                let first = i * nonce_space / self.threads as u64;
+                let last = (i + 1) * nonce_space / self.threads as u64 - 1;
+                let hasher = MidstateHasher::new(&template);
+                let (stop, attempts, finished, found) = (&stop, &attempts, &finished, &found);
+
+                scope.spawn(move || {
+                    // Counts this worker as finished even if it panics; the scope
+                    // then re-raises the panic once every worker has stopped
+                    let _finished = FinishGuard { finished, stop };
+                    let mut pending = 0u64;
+
+                    for nonce in first as u32..=last as u32 {
+                        if pending == Self::CHECK_INTERVAL {
+                            attempts.fetch_add(pending, Ordering::Relaxed);
+                            pending = 0;
+                            if stop.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                                break;
+                            }
+                        }
+
+                        let hash = hasher.hash_with_nonce(nonce);
+                        pending += 1;
+
+                        if self.miner.is_valid_hash_fast(&hash) {
+                            found.lock().unwrap().get_or_insert((nonce, hash));
+                            stop.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    }
+
+                    attempts.fetch_add(pending, Ordering::Relaxed);
+                });
+            }
+
+            // Report progress from the calling thread until all workers are done
+            let mut last_report = Instant::now();
+            while finished.load(Ordering::Acquire) < self.threads {
+                std::thread::sleep(self.progress_interval.min(Duration::from_millis(20)));
+                if last_report.elapsed() >= self.progress_interval {
+                    last_report = Instant::now();
+                    on_progress(MiningProgress {
+                        attempts: attempts.load(Ordering::Relaxed),
+                        elapsed: start_time.elapsed(),
+                    });
+                }
+            }
+        });
+
+        let attempts = attempts.into_inner();
+        let duration = start_time.elapsed();
+
+        match found.into_inner().unwrap() {
+            Some((nonce, hash)) => {
+                header.nonce = nonce;
+                MiningResult {
+                    success: true,
+                    nonce,
+                    hash,
+                    attempts,
+                    duration,
+                    cancelled: false,
+                }
+            }
+            None => MiningResult {
+                success: false,
+                nonce: 0,
+                hash: Hash256::zero(),
+                attempts,
+                duration,
+                cancelled: cancel.is_cancelled(),
+            },
+        }
+    }
+}
+
+/// Marks a mining worker finished when it returns or unwinds
+/// A panicking worker also stops the others, so the progress loop ends.
+struct FinishGuard<'a> {
+    finished: &'a AtomicUsize,
+    stop: &'a AtomicBool,
+}
+
+impl Drop for FinishGuard<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        self.finished.fetch_add(1, Ordering::Release);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -208,6 +464,21 @@ mod tests {
         assert!(!target.is_valid_hash(&Hash256::new([0xff; 32])));
     }
 
+    #[test]
+    fn test_target_difficulty() {
+        assert_eq!(Target::from_difficulty(1.0).bits, DIFFICULTY_1_BITS);
+        assert_eq!(Target::from_bits(DIFFICULTY_1_BITS).difficulty(), 1.0);
+
+        // Higher difficulty means a smaller target, round-tripping closely
+        let hard = Target::from_difficulty(1000.0);
+        assert!((hard.difficulty() - 1000.0).abs() < 1.0);
+        assert!(hard.leading_zeros() > Target::from_bits(DIFFICULTY_1_BITS).leading_zeros());
+
+        // Fractional difficulty for easy (test) chains
+        let easy = Target::from_difficulty(1.0 / 65536.0);
+        assert!((easy.difficulty() * 65536.0 - 1.0).abs() < 0.01);
+    }
+
     #[test]
     #[ignore] // Too slow for regular test runs
     fn test_pow_mining_easy() {
@@ -243,4 +514,55 @@ mod tests {
         assert!(zeros > 0);
         println!("Leading zeros: {}", zeros);
     }
+
+    #[test]
+    fn test_parallel_miner_finds_valid_nonce() {
+        // Very easy target: almost every hash is valid
+        let miner = ParallelMiner::new(0x20ffffff, 4);
+        let mut header = BlockHeader::new(1, Hash256::zero(), Hash256::zero(), 1234567890, 0x20ffffff, 0);
+
+        let result = miner.mine(&mut header, &CancelToken::new(), |_| {});
+        assert!(result.success);
+        assert!(!result.cancelled);
+        assert_eq!(header.nonce, result.nonce);
+        assert_eq!(header.hash(), result.hash);
+        assert!(Miner::new(0x20ffffff).verify(&header));
+    }
+
+    #[test]
+    fn test_parallel_miner_cancellation() {
+        // Impossible target, so only cancellation can stop the miner
+        let miner = ParallelMiner::new(0x03000001, 2).with_progress_interval(Duration::from_millis(10));
+        let mut header = BlockHeader::new(1, Hash256::zero(), Hash256::zero(), 1234567890, 0x03000001, 0);
+        let cancel = CancelToken::new();
+        let reports = AtomicUsize::new(0);
+
+        let result = miner.mine(&mut header, &cancel, |progress| {
+            assert!(progress.elapsed > Duration::ZERO);
+            if reports.fetch_add(1, Ordering::Relaxed) == 2 {
+                cancel.cancel();
+            }
+        });
+
+        assert!(!result.success);
+        assert!(result.cancelled);
+        assert!(result.attempts > 0);
+        assert!(reports.load(Ordering::Relaxed) >= 3);
+    }
+
+    #[test]
+    fn test_panicking_worker_counts_as_finished() {
+        let (finished, stop) = (AtomicUsize::new(0), AtomicBool::new(false));
+        let result = std::panic::catch_unwind(|| {
+            let _finished = FinishGuard { finished: &finished, stop: &stop };
+            panic!("worker failed");
+        });
+
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::Acquire), 1);
+        assert!(stop.load(Ordering::Relaxed));
+
+        drop(FinishGuard { finished: &finished, stop: &AtomicBool::new(false) });
+        assert_eq!(finished.load(Ordering::Acquire), 2);
+    }
 }
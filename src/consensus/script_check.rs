@@ -0,0 +1,418 @@
+// Parallel script verification for block connection
+//
+// Architecture:
+//   - Every non-coinbase input of a block becomes one `ScriptCheck`
+//   - Checks run on scoped worker threads that share one secp256k1 context
+//   - Workers pull checks in index order, so the failure that is reported is
+//     always the first one in block order, no matter which thread found it
+
+use crate::consensus::sig_cache::SignatureCache;
+use crate::core::{Block, Hash256, Script, Transaction, TxOutput};
+use crate::core::script::SCRIPT_VERIFY_COMPRESSED_PUBKEYS;
+use crate::storage::{CoinsView, OutPoint};
+use secp256k1::{Secp256k1, VerifyOnly};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A single deferred input script verification
+#[derive(Debug, Clone)]
+pub struct ScriptCheck {
+    /// Position of the spending transaction in the block
+    pub tx_index: usize,
+    /// Position of the input in the spending transaction
+    pub input_index: usize,
+    /// ID of the spending transaction
+    pub txid: Hash256,
+    /// Unlocking script from the input
+    pub script_sig: Vec<u8>,
+    /// Locking script of the output being spent
+    pub script_pubkey: Vec<u8>,
+    /// Hash the signature commits to
+    pub sighash: Hash256,
+    /// Script verification flags (SCRIPT_VERIFY_*)
+    pub flags: u32,
+}
+
+impl ScriptCheck {
+    /// Run the check against a shared verification context
+    /// Signatures found in `sig_cache` skip ECDSA; newly verified ones are added
+    pub fn verify(
+        &self,
+        secp: &Secp256k1<VerifyOnly>,
+        sig_cache: Option<&SignatureCache>,
+    ) -> Result<(), String> {
+        let result = Script::verify_p2pkh_with_checker(
+            &self.script_sig,
+            &self.script_pubkey,
+            |signature, pubkey| {
+                if self.flags & SCRIPT_VERIFY_COMPRESSED_PUBKEYS != 0 && !Script::is_compressed_pubkey(pubkey) {
+                    return Err("Public key is not compressed".to_string());
+                }
+
+                if let Some(cache) = sig_cache
+                    && cache.contains(&self.sighash, pubkey, signature)
+                {
+                    return Ok(true);
+                }
+
+                let valid = Script::verify_signature(secp, signature, pubkey, self.sighash.as_bytes())?;
+                if let (true, Some(cache)) = (valid, sig_cache) {
+                    cache.insert(&self.sighash, pubkey, signature);
+                }
+                Ok(valid)
+            },
+        );
+
+        match result {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("Signature verification failed".to_string()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// First failing input found while checking a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptCheckFailure {
+    /// Position of the failing transaction in the block
+    pub tx_index: usize,
+    /// Position of the failing input in that transaction
+    pub input_index: usize,
+    /// ID of the failing transaction
+    pub txid: Hash256,
+    /// Why the input was rejected
+    pub reason: String,
+}
+
+impl std::fmt::Display for ScriptCheckFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "tx {} ({}) input {}: {}",
+            self.tx_index, self.txid, self.input_index, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ScriptCheckFailure {}
+
+/// Worker pool that verifies script checks in parallel
+pub struct ScriptCheckQueue {
+    /// Verification context shared by all workers
+    secp: Secp256k1<VerifyOnly>,
+    /// Number of worker threads
+    workers: usize,
+    /// Cache of already-verified signatures (optional)
+    sig_cache: Option<Arc<SignatureCache>>,
+}
+
+impl ScriptCheckQueue {
+    /// Create a queue with the given number of workers (0 = one per CPU core)
+    pub fn new(workers: usize) -> Self {
+        let workers = if workers == 0 {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            workers
+        };
+
+        Self {
+            secp: Secp256k1::verification_only(),
+            workers,
+            sig_cache: None,
+        }
+    }
+
+    /// Create a queue whose workers consult and fill a shared signature cache
+    pub fn with_signature_cache(workers: usize, sig_cache: Arc<SignatureCache>) -> Self {
+        Self {
+            sig_cache: Some(sig_cache),
+            ..Self::new(workers)
+        }
+    }
+
+    /// Number of worker threads used by `run`
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Collect a script check for every non-coinbase input in a block
+    /// Outputs created earlier in the same block are resolved before the UTXO set
+    pub fn collect_checks(
+        block: &Block,
+        utxo_set: &dyn CoinsView,
+        flags: u32,
+    ) -> Result<Vec<ScriptCheck>, ScriptCheckFailure> {
+        let mut created: HashMap<OutPoint, &TxOutput> = HashMap::new();
+        let mut checks = Vec::new();
+
+        for (tx_index, tx) in block.transactions.iter().enumerate() {
+            let txid = tx.txid();
+
+            if !tx.is_coinbase() {
+                checks.extend(Self::transaction_checks(tx_index, tx, txid, flags, |outpoint| {
+                    match created.get(outpoint) {
+                        Some(output) => Ok(Some(output.script_pubkey.clone())),
+                        None => Ok(utxo_set.get_utxo(outpoint)?.map(|utxo| utxo.output.script_pubkey)),
+                    }
+                })?);
+            }
+
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                created.insert(OutPoint::new(txid, vout as u32), output);
+            }
+        }
+
+        Ok(checks)
+    }
+
+    /// Collect a script check for every input of a loose (mempool) transaction
+    pub fn collect_transaction_checks(
+        tx: &Transaction,
+        utxo_set: &dyn CoinsView,
+        flags: u32,
+    ) -> Result<Vec<ScriptCheck>, ScriptCheckFailure> {
+        Self::transaction_checks(0, tx, tx.txid(), flags, |outpoint| {
+            Ok(utxo_set.get_utxo(outpoint)?.map(|utxo| utxo.output.script_pubkey))
+        })
+    }
+
+    // Helper: build the checks for one transaction, resolving prevout scripts with `resolve`
+    fn transaction_checks<F>(
+        tx_index: usize,
+        tx: &Transaction,
+        txid: Hash256,
+        flags: u32,
+        resolve: F,
+    ) -> Result<Vec<ScriptCheck>, ScriptCheckFailure>
+    where
+        F: Fn(&OutPoint) -> Result<Option<Vec<u8>>, String>,
+    {
+        let sighash = tx.signature_hash();
+        let mut checks = Vec::with_capacity(tx.inputs.len());
+
+        for (input_index, input) in tx.inputs.iter().enumerate() {
+            let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+            let failure = |reason: String| ScriptCheckFailure {
+                tx_index,
+                input_index,
+                txid,
+                reason,
+            };
+
+            let script_pubkey = resolve(&outpoint).map_err(failure)?.ok_or_else(|| {
+                failure(format!("Missing input {}:{}", outpoint.txid, outpoint.vout))
+            })?;
+
+            checks.push(ScriptCheck {
+                tx_index,
+                input_index,
+                txid,
+                script_sig: input.script_sig.clone(),
+                script_pubkey,
+                sighash,
+                flags,
+            });
+        }
+
+        Ok(checks)
+    }
+
+    /// Verify all checks on the worker pool
+    /// Returns the first failure in block order
+    pub fn run(&self, checks: &[ScriptCheck]) -> Result<(), ScriptCheckFailure> {
+        let workers = self.workers.min(checks.len());
+        if workers <= 1 {
+            return self.run_sequential(checks);
+        }
+
+        // Index of the next unclaimed check, and of the earliest failure so far
+        let next = AtomicUsize::new(0);
+        let first_failure = AtomicUsize::new(usize::MAX);
+        let failures: Mutex<Vec<(usize, String)>> = Mutex::new(Vec::new());
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+
+                    // Checks after a known failure can no longer change the result
+                    if index >= checks.len() || index > first_failure.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    if let Err(reason) = checks[index].verify(&self.secp, self.sig_cache.as_deref()) {
+                        first_failure.fetch_min(index, Ordering::Relaxed);
+                        failures.lock().unwrap().push((index, reason));
+                    }
+                });
+            }
+        });
+
+        let failures = failures.into_inner().unwrap();
+        match failures.into_iter().min_by_key(|(index, _)| *index) {
+            Some((index, reason)) => Err(Self::failure(&checks[index], reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Verify all checks on the calling thread (reference implementation)
+    pub fn run_sequential(&self, checks: &[ScriptCheck]) -> Result<(), ScriptCheckFailure> {
+        for check in checks {
+            check
+                .verify(&self.secp, self.sig_cache.as_deref())
+                .map_err(|reason| Self::failure(check, reason))?;
+        }
+        Ok(())
+    }
+
+    // Helper: attach the check's position to a failure reason
+    fn failure(check: &ScriptCheck, reason: String) -> ScriptCheckFailure {
+        ScriptCheckFailure {
+            tx_index: check.tx_index,
+            input_index: check.input_index,
+            txid: check.txid,
+            reason,
+        }
+    }
+}
+
+impl Default for ScriptCheckQueue {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{BlockHeader, Transaction, TxInput};
+    use crate::storage::{MemoryBackend, Utxo, UtxoSet};
+    use crate::wallet::KeyPair;
+    use crate::core::script::SCRIPT_VERIFY_NONE;
+    use secp256k1::Message;
+
+    // Helper: spend `outpoint` owned by `keypair` with a valid signature
+    fn signed_spend(keypair: &KeyPair, outpoint: &OutPoint, value: u64) -> Transaction {
+        let mut tx = Transaction::new(
+            vec![TxInput::new(outpoint.txid, outpoint.vout, vec![])],
+            vec![TxOutput::new(value, keypair.script_pubkey())],
+        );
+
+        let secp = Secp256k1::new();
+        let message = Message::from_digest_slice(tx.signature_hash().as_bytes()).unwrap();
+        let signature = secp.sign_ecdsa(&message, &keypair.secret_key);
+        tx.inputs[0].script_sig =
+            Script::p2pkh_script_sig(&signature.serialize_der(), &keypair.pubkey_bytes());
+        tx
+    }
+
+    // Helper: block of `count` signed spends, each funded by its own UTXO
+    fn funded_block(count: usize) -> (Block, UtxoSet<MemoryBackend>) {
+        let keypair = KeyPair::generate();
+        let utxo_set = UtxoSet::memory().unwrap();
+        let coinbase = Transaction::coinbase(vec![1], TxOutput::new(5000, vec![]), 1);
+        let mut transactions = vec![coinbase];
+
+        for i in 0..count {
+            let outpoint = OutPoint::new(Hash256::new([i as u8 + 1; 32]), 0);
+            let utxo = Utxo::new(TxOutput::new(1000, keypair.script_pubkey()), 0, false);
+            utxo_set.add_utxo(&outpoint, &utxo).unwrap();
+            transactions.push(signed_spend(&keypair, &outpoint, 900));
+        }
+
+        let merkle_root = Block::calculate_merkle_root(&transactions);
+        let header = BlockHeader::new(1, Hash256::zero(), merkle_root, 0, 0x20ffffff, 0);
+        (Block::new(header, transactions), utxo_set)
+    }
+
+    #[test]
+    fn test_parallel_checks_pass() {
+        let (block, utxo_set) = funded_block(16);
+        let checks = ScriptCheckQueue::collect_checks(&block, &utxo_set, SCRIPT_VERIFY_NONE).unwrap();
+        assert_eq!(checks.len(), 16);
+
+        let queue = ScriptCheckQueue::new(4);
+        assert!(queue.run(&checks).is_ok());
+        assert!(queue.run_sequential(&checks).is_ok());
+    }
+
+    #[test]
+    fn test_first_failure_reported() {
+        let (mut block, utxo_set) = funded_block(16);
+
+        // Corrupt the signatures of transactions 5 and 11
+        block.transactions[5].inputs[0].script_sig[10] ^= 0xff;
+        block.transactions[11].inputs[0].script_sig[10] ^= 0xff;
+
+        let checks = ScriptCheckQueue::collect_checks(&block, &utxo_set, SCRIPT_VERIFY_NONE).unwrap();
+        let queue = ScriptCheckQueue::new(4);
+
+        let failure = queue.run(&checks).unwrap_err();
+        assert_eq!(failure.tx_index, 5);
+        assert_eq!(failure.input_index, 0);
+        assert_eq!(failure.txid, block.transactions[5].txid());
+        assert_eq!(Err(failure), queue.run_sequential(&checks));
+    }
+
+    #[test]
+    fn test_missing_input() {
+        let (block, _) = funded_block(2);
+        let empty = UtxoSet::memory().unwrap();
+
+        let failure = ScriptCheckQueue::collect_checks(&block, &empty, SCRIPT_VERIFY_NONE).unwrap_err();
+        assert_eq!(failure.tx_index, 1);
+        assert!(failure.reason.contains("Missing input"));
+    }
+
+    #[test]
+    fn test_spend_output_created_in_same_block() {
+        let keypair = KeyPair::generate();
+        let utxo_set = UtxoSet::memory().unwrap();
+
+        let funding_outpoint = OutPoint::new(Hash256::new([9; 32]), 0);
+        let utxo = Utxo::new(TxOutput::new(1000, keypair.script_pubkey()), 0, false);
+        utxo_set.add_utxo(&funding_outpoint, &utxo).unwrap();
+
+        let parent = signed_spend(&keypair, &funding_outpoint, 900);
+        let child = signed_spend(&keypair, &OutPoint::new(parent.txid(), 0), 800);
+        let coinbase = Transaction::coinbase(vec![1], TxOutput::new(5000, vec![]), 1);
+        let transactions = vec![coinbase, parent, child];
+
+        let merkle_root = Block::calculate_merkle_root(&transactions);
+        let header = BlockHeader::new(1, Hash256::zero(), merkle_root, 0, 0x20ffffff, 0);
+        let block = Block::new(header, transactions);
+
+        let checks = ScriptCheckQueue::collect_checks(&block, &utxo_set, SCRIPT_VERIFY_NONE).unwrap();
+        assert_eq!(checks.len(), 2);
+        assert!(ScriptCheckQueue::new(2).run(&checks).is_ok());
+    }
+
+    #[test]
+    fn test_compressed_pubkey_flag() {
+        let keypair = KeyPair::generate();
+        let uncompressed = keypair.public_key.serialize_uncompressed();
+        let script_pubkey = Script::p2pkh_script_pubkey(&crate::core::hash160(&uncompressed));
+
+        let sighash = Hash256::new([3; 32]);
+        let message = Message::from_digest_slice(sighash.as_bytes()).unwrap();
+        let signature = Secp256k1::new().sign_ecdsa(&message, &keypair.secret_key);
+        let mut check = ScriptCheck {
+            tx_index: 1,
+            input_index: 0,
+            txid: sighash,
+            script_sig: Script::p2pkh_script_sig(&signature.serialize_der(), &uncompressed),
+            script_pubkey,
+            sighash,
+            flags: SCRIPT_VERIFY_NONE,
+        };
+        let secp = Secp256k1::verification_only();
+
+        // Uncompressed keys stay valid until the flag is enforced
+        assert!(check.verify(&secp, None).is_ok());
+        check.flags = SCRIPT_VERIFY_COMPRESSED_PUBKEYS;
+        assert_eq!(check.verify(&secp, None), Err("Public key is not compressed".to_string()));
+    }
+}
@@ -0,0 +1,302 @@
+// Signature and script-execution caches
+//
+// A transaction is usually verified twice: once when it enters the mempool
+// and again when it is confirmed in a block. These caches remember
+// successful checks so the second pass is (almost) free.
+//
+//   - SignatureCache: valid (sighash, pubkey, signature) triples
+//   - ScriptExecutionCache: transactions whose inputs all passed under a flag set
+//
+// Entries are stored as salted SHA256 digests, so an attacker cannot craft
+// inputs that collide in the cache, and both caches are bounded (oldest
+// entries are evicted first).
+
+use crate::core::{sha256_hash, Hash256};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Default number of entries kept by the signature cache
+pub const DEFAULT_SIGNATURE_CACHE_SIZE: usize = 50_000;
+/// Default number of entries kept by the script-execution cache
+pub const DEFAULT_SCRIPT_CACHE_SIZE: usize = 20_000;
+
+/// Hit/miss counters for a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups that found an entry
+    pub hits: u64,
+    /// Lookups that did not find an entry
+    pub misses: u64,
+    /// Entries currently stored
+    pub entries: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that were hits (0.0 when there were no lookups)
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit rate), {} entries",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0,
+            self.entries
+        )
+    }
+}
+
+/// Cached digests plus their insertion order (for FIFO eviction)
+#[derive(Default)]
+struct Entries {
+    set: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+/// Bounded set of salted digests with FIFO eviction (shared by both caches)
+struct SaltedSet {
+    salt: [u8; 32],
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SaltedSet {
+    fn new(capacity: usize) -> Self {
+        Self {
+            salt: rand::random(),
+            capacity,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // Helper: salted digest of the length-prefixed key parts
+    // (prefixes keep different splits of the same bytes from colliding)
+    fn digest(&self, parts: &[&[u8]]) -> [u8; 32] {
+        let mut data = Vec::with_capacity(32 + parts.iter().map(|p| p.len() + 4).sum::<usize>());
+        data.extend_from_slice(&self.salt);
+        for part in parts {
+            data.extend_from_slice(&(part.len() as u32).to_le_bytes());
+            data.extend_from_slice(part);
+        }
+        sha256_hash(&data)
+    }
+
+    // Helper: whether any of `keys` is stored, counted as a single hit or miss
+    fn contains(&self, keys: &[[u8; 32]]) -> bool {
+        let found = {
+            let entries = self.entries.lock().unwrap();
+            keys.iter().any(|key| entries.set.contains(key))
+        };
+        if found {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    fn insert(&self, key: [u8; 32]) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.set.insert(key) {
+            entries.order.push_back(key);
+            while entries.order.len() > self.capacity {
+                if let Some(oldest) = entries.order.pop_front() {
+                    entries.set.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().set.len(),
+        }
+    }
+
+    fn clear(&self) {
+        *self.entries.lock().unwrap() = Entries::default();
+    }
+}
+
+/// Cache of signatures that have already been verified as valid
+pub struct SignatureCache {
+    inner: SaltedSet,
+}
+
+impl SignatureCache {
+    /// Create a cache holding at most `capacity` signatures
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: SaltedSet::new(capacity),
+        }
+    }
+
+    /// Check whether a signature was previously verified
+    pub fn contains(&self, sighash: &Hash256, pubkey: &[u8], signature: &[u8]) -> bool {
+        let key = self.inner.digest(&[sighash.as_bytes(), pubkey, signature]);
+        self.inner.contains(&[key])
+    }
+
+    /// Remember a valid signature
+    pub fn insert(&self, sighash: &Hash256, pubkey: &[u8], signature: &[u8]) {
+        let key = self.inner.digest(&[sighash.as_bytes(), pubkey, signature]);
+        self.inner.insert(key);
+    }
+
+    /// Current hit/miss statistics
+    pub fn stats(&self) -> CacheStats {
+        self.inner.stats()
+    }
+
+    /// Drop all cached entries (statistics are kept)
+    pub fn clear(&self) {
+        self.inner.clear();
+    }
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIGNATURE_CACHE_SIZE)
+    }
+}
+
+/// Cache of transactions whose input scripts all verified under a flag set
+pub struct ScriptExecutionCache {
+    inner: SaltedSet,
+}
+
+impl ScriptExecutionCache {
+    /// Create a cache holding at most `capacity` transactions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: SaltedSet::new(capacity),
+        }
+    }
+
+    /// Check whether a transaction's scripts were already verified with `flags`
+    /// `wtxid` commits to the scriptSigs (without segwit it equals the txid)
+    pub fn contains(&self, wtxid: &Hash256, flags: u32) -> bool {
+        self.contains_any(wtxid, &[flags])
+    }
+
+    /// Check whether a transaction's scripts were already verified with any of `flag_sets`
+    /// Counts as one lookup however many flag sets are probed
+    pub fn contains_any(&self, wtxid: &Hash256, flag_sets: &[u32]) -> bool {
+        let keys: Vec<_> = flag_sets
+            .iter()
+            .map(|flags| self.inner.digest(&[wtxid.as_bytes(), &flags.to_le_bytes()]))
+            .collect();
+        self.inner.contains(&keys)
+    }
+
+    /// Remember that a transaction's scripts verified with `flags`
+    pub fn insert(&self, wtxid: &Hash256, flags: u32) {
+        let key = self.inner.digest(&[wtxid.as_bytes(), &flags.to_le_bytes()]);
+        self.inner.insert(key);
+    }
+
+    /// Current hit/miss statistics
+    pub fn stats(&self) -> CacheStats {
+        self.inner.stats()
+    }
+
+    /// Drop all cached entries (statistics are kept)
+    pub fn clear(&self) {
+        self.inner.clear();
+    }
+}
+
+impl Default for ScriptExecutionCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCRIPT_CACHE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_cache_hit_and_miss() {
+        let cache = SignatureCache::new(10);
+        let sighash = Hash256::new([1; 32]);
+
+        assert!(!cache.contains(&sighash, &[2; 33], &[3; 70]));
+        cache.insert(&sighash, &[2; 33], &[3; 70]);
+        assert!(cache.contains(&sighash, &[2; 33], &[3; 70]));
+
+        // Any differing key part is a miss
+        assert!(!cache.contains(&sighash, &[2; 33], &[4; 70]));
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let cache = SignatureCache::new(3);
+        for i in 0..5u8 {
+            cache.insert(&Hash256::new([i; 32]), &[i], &[i]);
+        }
+
+        assert_eq!(cache.stats().entries, 3);
+        // Oldest entries were evicted first
+        assert!(!cache.contains(&Hash256::new([0; 32]), &[0], &[0]));
+        assert!(cache.contains(&Hash256::new([4; 32]), &[4], &[4]));
+    }
+
+    #[test]
+    fn test_script_cache_keyed_by_flags() {
+        let cache = ScriptExecutionCache::new(10);
+        let wtxid = Hash256::new([7; 32]);
+
+        cache.insert(&wtxid, 0);
+        assert!(cache.contains(&wtxid, 0));
+        assert!(!cache.contains(&wtxid, 1));
+    }
+
+    #[test]
+    fn test_script_cache_probes_flag_sets_as_one_lookup() {
+        let cache = ScriptExecutionCache::new(10);
+        let wtxid = Hash256::new([7; 32]);
+        cache.insert(&wtxid, 3);
+
+        assert!(cache.contains_any(&wtxid, &[1, 3]));
+        assert!(!cache.contains_any(&wtxid, &[1, 2]));
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn test_salts_differ_between_instances() {
+        let a = SignatureCache::new(1);
+        let b = SignatureCache::new(1);
+        let parts: [&[u8]; 1] = [b"same key"];
+        assert_ne!(a.inner.digest(&parts), b.inner.digest(&parts));
+    }
+}
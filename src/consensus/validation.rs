@@ -1,86 +1,279 @@
 // Transaction and block validation
 
-use crate::core::{Block, BlockHeader, Transaction, Script};
+use crate::core::{Block, BlockHeader, Hash256, Transaction, Script};
+use crate::core::script::{SCRIPT_VERIFY_NONE, STANDARD_SCRIPT_VERIFY_FLAGS};
+use crate::consensus::params::ChainParams;
 use crate::consensus::pow::Miner;
+use crate::consensus::script_check::{ScriptCheck, ScriptCheckFailure, ScriptCheckQueue};
+use crate::consensus::sig_cache::{CacheStats, ScriptExecutionCache, SignatureCache};
+use crate::consensus::versionbits::{
+    compute_block_version, Deployment, DeploymentStats, ThresholdState, VersionBitsCache, VERSIONBITS_TOP_BITS,
+};
+use crate::storage::{BlockchainDB, CoinsView, KvBackend};
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
+
+/// How far a block timestamp may be ahead of the local clock (seconds)
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Whether a failed rule is part of consensus or only local policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleClass {
+    /// Every node rejects the block or transaction
+    Consensus,
+    /// This node will not relay or mine it, but a block containing it is valid
+    Policy,
+}
+
+impl std::fmt::Display for RuleClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuleClass::Consensus => write!(f, "consensus"),
+            RuleClass::Policy => write!(f, "policy"),
+        }
+    }
+}
 
 /// Validation error types
+/// Transaction rules are reported on their own for loose transactions and
+/// wrapped in `InvalidTransaction` (with the tx position) inside a block.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValidationError {
     /// Block hash doesn't meet PoW target
-    InvalidProofOfWork,
+    InvalidProofOfWork { hash: Hash256, bits: u32 },
     /// Merkle root doesn't match calculated value
-    InvalidMerkleRoot,
+    InvalidMerkleRoot { header: Hash256, calculated: Hash256 },
     /// Block has no transactions
     NoTransactions,
     /// First transaction is not coinbase
     MissingCoinbase,
-    /// More than one coinbase transaction
-    MultipleCoinbase,
-    /// Transaction has no inputs or outputs
-    EmptyTransaction,
-    /// Transaction input script verification failed
-    InvalidSignature,
     /// Coinbase transaction in non-first position
-    CoinbaseNotFirst,
+    CoinbaseNotFirst { tx_index: usize },
     /// Block timestamp is too far in the future
-    InvalidTimestamp,
+    InvalidTimestamp { timestamp: u32, max: u32 },
     /// Block version not supported
-    InvalidVersion,
+    InvalidVersion { version: u32 },
+    /// Block hash differs from the checkpoint at its height
+    CheckpointMismatch { height: u32 },
+    /// Block would replace a chain fixed by a checkpoint
+    ForkBelowCheckpoint { height: u32, checkpoint: u32 },
+    /// A transaction in a block broke a rule
+    InvalidTransaction { tx_index: usize, txid: Hash256, error: Box<ValidationError> },
+    /// Transaction has no inputs or outputs
+    EmptyTransaction { inputs: usize, outputs: usize },
     /// Coinbase transaction must have exactly one input
-    InvalidCoinbaseInputCount,
+    InvalidCoinbaseInputCount { inputs: usize },
+    /// Coinbase transaction outside a block
+    LooseCoinbase,
     /// Total output value exceeds the maximum allowed supply
-    OutputValueExceedsMax,
+    OutputValueExceedsMax { value: u64 },
+    /// Input index beyond the transaction's inputs
+    InputIndexOutOfRange { input_index: usize, inputs: usize },
+    /// Input script failed under consensus rules
+    InvalidScript { input_index: usize, reason: String },
+    /// Input script is valid under consensus rules but fails the standard flags
+    NonStandardScript { input_index: usize, reason: String },
+}
+
+impl ValidationError {
+    /// Whether the failed rule is consensus or policy
+    pub fn class(&self) -> RuleClass {
+        match self {
+            ValidationError::NonStandardScript { .. } => RuleClass::Policy,
+            ValidationError::InvalidTransaction { error, .. } => error.class(),
+            _ => RuleClass::Consensus,
+        }
+    }
+
+    /// Short machine-readable reason, named after Bitcoin Core's reject reasons
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::InvalidProofOfWork { .. } => "high-hash",
+            ValidationError::InvalidMerkleRoot { .. } => "bad-txnmrklroot",
+            ValidationError::NoTransactions => "bad-blk-length",
+            ValidationError::MissingCoinbase => "bad-cb-missing",
+            ValidationError::CoinbaseNotFirst { .. } => "bad-cb-multiple",
+            ValidationError::InvalidTimestamp { .. } => "time-too-new",
+            ValidationError::InvalidVersion { .. } => "bad-version",
+            ValidationError::CheckpointMismatch { .. } => "checkpoint mismatch",
+            ValidationError::ForkBelowCheckpoint { .. } => "bad-fork-prior-to-checkpoint",
+            ValidationError::InvalidTransaction { error, .. } => error.code(),
+            ValidationError::EmptyTransaction { inputs: 0, .. } => "bad-txns-vin-empty",
+            ValidationError::EmptyTransaction { .. } => "bad-txns-vout-empty",
+            ValidationError::InvalidCoinbaseInputCount { .. } => "bad-cb-inputs",
+            ValidationError::LooseCoinbase => "coinbase",
+            ValidationError::OutputValueExceedsMax { .. } => "bad-txns-txouttotal-toolarge",
+            ValidationError::InputIndexOutOfRange { .. } => "bad-txns-inputs-missingorspent",
+            ValidationError::InvalidScript { .. } => "mandatory-script-verify-flag-failed",
+            ValidationError::NonStandardScript { .. } => "non-mandatory-script-verify-flag",
+        }
+    }
+
+    /// Position of the offending transaction in its block, if any
+    pub fn tx_index(&self) -> Option<usize> {
+        match self {
+            ValidationError::InvalidTransaction { tx_index, .. } | ValidationError::CoinbaseNotFirst { tx_index } => {
+                Some(*tx_index)
+            }
+            _ => None,
+        }
+    }
+
+    /// Index of the offending input, if any
+    pub fn input_index(&self) -> Option<usize> {
+        match self {
+            ValidationError::InvalidTransaction { error, .. } => error.input_index(),
+            ValidationError::InputIndexOutOfRange { input_index, .. }
+            | ValidationError::InvalidScript { input_index, .. }
+            | ValidationError::NonStandardScript { input_index, .. } => Some(*input_index),
+            _ => None,
+        }
+    }
+
+    // Helper: wrap a transaction rule failure with its position in the block
+    fn in_block(self, tx_index: usize, tx: &Transaction) -> Self {
+        ValidationError::InvalidTransaction {
+            tx_index,
+            txid: tx.txid(),
+            error: Box::new(self),
+        }
+    }
 }
 
 impl std::fmt::Display for ValidationError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            ValidationError::InvalidProofOfWork => write!(f, "Invalid proof of work"),
-            ValidationError::InvalidMerkleRoot => write!(f, "Invalid merkle root"),
+            ValidationError::InvalidProofOfWork { hash, bits } => {
+                write!(f, "Invalid proof of work: hash {} above target {:08x}", hash, bits)
+            }
+            ValidationError::InvalidMerkleRoot { header, calculated } => {
+                write!(f, "Invalid merkle root: header has {}, transactions give {}", header, calculated)
+            }
             ValidationError::NoTransactions => write!(f, "Block has no transactions"),
             ValidationError::MissingCoinbase => write!(f, "Missing coinbase transaction"),
-            ValidationError::MultipleCoinbase => write!(f, "Multiple coinbase transactions"),
-            ValidationError::EmptyTransaction => write!(f, "Empty transaction"),
-            ValidationError::InvalidSignature => write!(f, "Invalid signature"),
-            ValidationError::CoinbaseNotFirst => write!(f, "Coinbase not in first position"),
-            ValidationError::InvalidTimestamp => write!(f, "Invalid timestamp"),
-            ValidationError::InvalidVersion => write!(f, "Invalid version"),
-            ValidationError::InvalidCoinbaseInputCount => write!(f, "Coinbase must have exactly one input"),
-            ValidationError::OutputValueExceedsMax => write!(f, "Total output value exceeds maximum supply"),
+            ValidationError::CoinbaseNotFirst { tx_index } => write!(f, "Coinbase at position {} (only the first transaction may be coinbase)", tx_index),
+            ValidationError::InvalidTimestamp { timestamp, max } => {
+                write!(f, "Invalid timestamp {}: more than 2 hours ahead (max {})", timestamp, max)
+            }
+            ValidationError::InvalidVersion { version } => write!(f, "Invalid version {}", version),
+            ValidationError::CheckpointMismatch { height } => write!(f, "Block does not match checkpoint at height {}", height),
+            ValidationError::ForkBelowCheckpoint { height, checkpoint } => {
+                write!(f, "Fork at height {} is below the checkpoint at height {}", height, checkpoint)
+            }
+            ValidationError::InvalidTransaction { tx_index, txid, error } => write!(f, "tx {} ({}): {}", tx_index, txid, error),
+            ValidationError::EmptyTransaction { inputs, outputs } => {
+                write!(f, "Empty transaction ({} inputs, {} outputs)", inputs, outputs)
+            }
+            ValidationError::InvalidCoinbaseInputCount { inputs } => {
+                write!(f, "Coinbase must have exactly one input, has {}", inputs)
+            }
+            ValidationError::LooseCoinbase => write!(f, "Coinbase transaction outside a block"),
+            ValidationError::OutputValueExceedsMax { value } => {
+                write!(f, "Total output value {} exceeds maximum supply", value)
+            }
+            ValidationError::InputIndexOutOfRange { input_index, inputs } => {
+                write!(f, "Input {} out of range ({} inputs)", input_index, inputs)
+            }
+            ValidationError::InvalidScript { input_index, reason } => {
+                write!(f, "input {}: script verification failed: {}", input_index, reason)
+            }
+            ValidationError::NonStandardScript { input_index, reason } => {
+                write!(f, "input {}: non-standard script: {}", input_index, reason)
+            }
         }
     }
 }
 
 impl std::error::Error for ValidationError {}
 
+/// How the input scripts of a connected block were handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptValidation {
+    /// Every input script was verified (or found in the script cache)
+    Verified,
+    /// Scripts were skipped because the block is an ancestor of the assume-valid block
+    AssumedValid,
+}
+
 /// Block validator
 pub struct BlockValidator {
+    /// Difficulty, checkpoints and assume-valid block
+    params: ChainParams,
     /// PoW miner for verification
     miner: Miner,
+    /// Worker pool for input script verification
+    script_checks: ScriptCheckQueue,
+    /// Signatures already verified (shared with the script check workers)
+    sig_cache: Arc<SignatureCache>,
+    /// Transactions whose scripts already verified
+    script_cache: Arc<ScriptExecutionCache>,
+    /// Hashes of the assume-valid block and its ancestors by height, once its header is indexed
+    assume_valid_chain: OnceLock<Vec<Hash256>>,
+    /// Deployment states by window
+    versionbits: VersionBitsCache,
 }
 
 impl BlockValidator {
     /// Create a new block validator with fixed difficulty
     pub fn new(bits: u32) -> Self {
+        Self::with_params(ChainParams::new(bits))
+    }
+
+    /// Create a validator enforcing the given chain parameters
+    pub fn with_params(params: ChainParams) -> Self {
+        let mut validator = Self::with_caches(
+            params.bits,
+            Arc::new(SignatureCache::default()),
+            Arc::new(ScriptExecutionCache::default()),
+        );
+        validator.params = params;
+        validator
+    }
+
+    /// Create a validator that shares its verification caches with other components
+    /// (e.g. mempool acceptance), so scripts checked there are not re-checked in blocks
+    pub fn with_caches(
+        bits: u32,
+        sig_cache: Arc<SignatureCache>,
+        script_cache: Arc<ScriptExecutionCache>,
+    ) -> Self {
         Self {
+            params: ChainParams::new(bits),
             miner: Miner::new(bits),
+            script_checks: ScriptCheckQueue::with_signature_cache(0, sig_cache.clone()),
+            sig_cache,
+            script_cache,
+            assume_valid_chain: OnceLock::new(),
+            versionbits: VersionBitsCache::new(),
         }
     }
 
+    /// Chain parameters enforced by this validator
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Hit/miss statistics of the signature and script-execution caches
+    pub fn cache_stats(&self) -> (CacheStats, CacheStats) {
+        (self.sig_cache.stats(), self.script_cache.stats())
+    }
+
     /// Validate a block header
     pub fn validate_header(&self, header: &BlockHeader) -> Result<(), ValidationError> {
         // Skip PoW validation for genesis block (prev_hash is zero)
-        use crate::core::Hash256;
         if header.prev_block_hash != Hash256::zero() {
             // Check proof of work for non-genesis blocks
             if !self.miner.verify(header) {
-                return Err(ValidationError::InvalidProofOfWork);
+                return Err(ValidationError::InvalidProofOfWork {
+                    hash: header.hash(),
+                    bits: self.params.bits,
+                });
             }
         }
 
         // Check version (must be >= 1)
         if header.version < 1 {
-            return Err(ValidationError::InvalidVersion);
+            return Err(ValidationError::InvalidVersion { version: header.version });
         }
 
         // Check timestamp (not too far in the future - within 2 hours)
@@ -89,8 +282,11 @@ impl BlockValidator {
             .unwrap()
             .as_secs() as u32;
 
-        if header.timestamp > now + 2 * 60 * 60 {
-            return Err(ValidationError::InvalidTimestamp);
+        if header.timestamp > now + MAX_FUTURE_BLOCK_TIME {
+            return Err(ValidationError::InvalidTimestamp {
+                timestamp: header.timestamp,
+                max: now + MAX_FUTURE_BLOCK_TIME,
+            });
         }
 
         Ok(())
@@ -112,47 +308,246 @@ impl BlockValidator {
         }
 
         // Only first transaction can be coinbase
-        for tx in &block.transactions[1..] {
-            if tx.is_coinbase() {
-                return Err(ValidationError::CoinbaseNotFirst);
-            }
-        }
-
-        // Check for multiple coinbase transactions
-        let coinbase_count = block.transactions.iter()
-            .filter(|tx| tx.is_coinbase())
-            .count();
-
-        if coinbase_count > 1 {
-            return Err(ValidationError::MultipleCoinbase);
+        if let Some(tx_index) = block.transactions.iter().skip(1).position(|tx| tx.is_coinbase()) {
+            return Err(ValidationError::CoinbaseNotFirst { tx_index: tx_index + 1 });
         }
 
         // Validate merkle root
         let calculated_merkle = Block::calculate_merkle_root(&block.transactions);
         if calculated_merkle != block.header.merkle_root {
-            return Err(ValidationError::InvalidMerkleRoot);
+            return Err(ValidationError::InvalidMerkleRoot {
+                header: block.header.merkle_root,
+                calculated: calculated_merkle,
+            });
         }
 
         // Validate all transactions
-        for tx in &block.transactions {
-            self.validate_transaction(tx)?;
+        for (tx_index, tx) in block.transactions.iter().enumerate() {
+            self.validate_transaction(tx).map_err(|e| e.in_block(tx_index, tx))?;
+        }
+
+        Ok(())
+    }
+
+    /// Verify every input script in a block against the UTXO set (in parallel)
+    /// Called when connecting a block, after `validate_block` has passed.
+    /// Transactions found in the script-execution cache are skipped entirely.
+    pub fn verify_block_scripts(&self, block: &Block, utxo_set: &dyn CoinsView) -> Result<(), ValidationError> {
+        self.verify_block_scripts_with_flags(block, utxo_set, SCRIPT_VERIFY_NONE)
+    }
+
+    /// Verify every input script in a block with the given script flags
+    pub fn verify_block_scripts_with_flags(
+        &self,
+        block: &Block,
+        utxo_set: &dyn CoinsView,
+        flags: u32,
+    ) -> Result<(), ValidationError> {
+        let result = ScriptCheckQueue::collect_checks(block, utxo_set, flags).and_then(|checks| {
+            // One cache lookup per transaction, not per input. Mempool checks
+            // use the standard flags, which imply every consensus subset.
+            let standard_subset = flags != STANDARD_SCRIPT_VERIFY_FLAGS && flags & !STANDARD_SCRIPT_VERIFY_FLAGS == 0;
+            let accepted: &[u32] = if standard_subset { &[flags, STANDARD_SCRIPT_VERIFY_FLAGS] } else { &[flags] };
+            let mut cached = HashSet::new();
+            for (tx_index, tx) in block.transactions.iter().enumerate().skip(1) {
+                if self.script_cache.contains_any(&tx.txid(), accepted) {
+                    cached.insert(tx_index);
+                }
+            }
+
+            let pending: Vec<_> = checks
+                .into_iter()
+                .filter(|check| !cached.contains(&check.tx_index))
+                .collect();
+            self.script_checks.run(&pending)?;
+
+            for check in &pending {
+                self.script_cache.insert(&check.txid, flags);
+            }
+            Ok(())
+        });
+
+        result.map_err(|failure| Self::script_failure(block, failure))
+    }
+
+    /// Run every check needed before connecting `block` at `height`
+    /// Checkpoints and the block rules always apply; input scripts are skipped
+    /// when the block is the assume-valid block or one of its indexed ancestors.
+    pub fn validate_for_connection<B: KvBackend>(
+        &self,
+        block: &Block,
+        height: u32,
+        utxo_set: &dyn CoinsView,
+        blocks: &BlockchainDB<B>,
+    ) -> Result<ScriptValidation, ValidationError> {
+        let block_hash = block.hash();
+        self.params.check_checkpoint(height, &block_hash)?;
+
+        // A block at an occupied height replaces part of the active chain
+        if let Ok(Some(existing)) = blocks.get_hash_by_height(height)
+            && existing != block_hash
+        {
+            self.params.check_fork(height)?;
+        }
+
+        self.validate_block(block)?;
+
+        if self.is_assumed_valid(&block_hash, height, blocks) {
+            return Ok(ScriptValidation::AssumedValid);
+        }
+        self.verify_block_scripts_with_flags(block, utxo_set, self.script_flags(height, blocks))?;
+        Ok(ScriptValidation::Verified)
+    }
+
+    /// BIP9 state of `deployment` for the block at `height` on the stored chain
+    pub fn deployment_state<B: KvBackend>(&self, deployment: &Deployment, height: u32, blocks: &BlockchainDB<B>) -> ThresholdState {
+        self.versionbits.state(
+            deployment,
+            self.params.version_bits_window,
+            self.params.version_bits_threshold,
+            height,
+            |h| Self::header_at(blocks, h),
+        )
+    }
+
+    /// State and current-window signalling of every deployment for the block at `height`
+    pub fn deployment_stats<B: KvBackend>(&self, height: u32, blocks: &BlockchainDB<B>) -> Vec<(&Deployment, DeploymentStats)> {
+        self.params
+            .deployments
+            .iter()
+            .map(|deployment| {
+                let stats = self.versionbits.stats(
+                    deployment,
+                    self.params.version_bits_window,
+                    self.params.version_bits_threshold,
+                    height,
+                    |h| Self::header_at(blocks, h),
+                );
+                (deployment, stats)
+            })
+            .collect()
+    }
+
+    /// Script flags enforced for the block at `height`: those of every active deployment
+    pub fn script_flags<B: KvBackend>(&self, height: u32, blocks: &BlockchainDB<B>) -> u32 {
+        self.params
+            .deployments
+            .iter()
+            .filter(|deployment| self.deployment_state(deployment, height, blocks) == ThresholdState::Active)
+            .fold(SCRIPT_VERIFY_NONE, |flags, deployment| flags | deployment.script_flags)
+    }
+
+    /// Version for a new block at `height`
+    /// With `signal`, it sets the bit of every deployment that is started or locked in.
+    pub fn block_version<B: KvBackend>(&self, height: u32, blocks: &BlockchainDB<B>, signal: bool) -> u32 {
+        if !signal {
+            return VERSIONBITS_TOP_BITS;
         }
+        compute_block_version(
+            self.params
+                .deployments
+                .iter()
+                .map(|deployment| (deployment, self.deployment_state(deployment, height, blocks))),
+        )
+    }
 
+    // Helper: header of the stored active-chain block at `height`
+    fn header_at<B: KvBackend>(blocks: &BlockchainDB<B>, height: u32) -> Option<BlockHeader> {
+        blocks.get_header_by_height(height).ok().flatten()
+    }
+
+    /// Whether `block_hash` at `height` is the assume-valid block or one of its ancestors
+    /// The assume-valid header and those below it must be in the block index,
+    /// as stored blocks or pre-synced headers (`BlockchainDB::store_header`).
+    pub fn is_assumed_valid<B: KvBackend>(&self, block_hash: &Hash256, height: u32, blocks: &BlockchainDB<B>) -> bool {
+        self.assume_valid_chain(blocks)
+            .is_some_and(|chain| chain.get(height as usize) == Some(block_hash))
+    }
+
+    // Helper: the assume-valid chain, walked back from its header once it is indexed
+    fn assume_valid_chain<B: KvBackend>(&self, blocks: &BlockchainDB<B>) -> Option<&Vec<Hash256>> {
+        let assume_valid = self.params.assume_valid?;
+        if let Some(chain) = self.assume_valid_chain.get() {
+            return Some(chain);
+        }
+
+        // A storage error or missing header means we cannot prove ancestry: verify instead
+        let entry = blocks.get_index_entry(&assume_valid).ok()??;
+        let mut chain = vec![Hash256::zero(); entry.height as usize + 1];
+        let mut current = assume_valid;
+        for hash in chain.iter_mut().rev() {
+            *hash = current;
+            current = blocks.get_header(&current).ok()??.prev_block_hash;
+        }
+        if current != Hash256::zero() {
+            return None;
+        }
+        Some(self.assume_valid_chain.get_or_init(|| chain))
+    }
+
+    /// Verify the input scripts of a loose transaction (mempool acceptance)
+    /// Uses the standard flags, so transactions stay valid once pending soft forks activate.
+    /// Successful checks populate the caches consulted by `verify_block_scripts`
+    pub fn verify_transaction_scripts(&self, tx: &Transaction, utxo_set: &dyn CoinsView) -> Result<(), ValidationError> {
+        let txid = tx.txid();
+        if self.script_cache.contains(&txid, STANDARD_SCRIPT_VERIFY_FLAGS) {
+            return Ok(());
+        }
+
+        let checks = ScriptCheckQueue::collect_transaction_checks(tx, utxo_set, STANDARD_SCRIPT_VERIFY_FLAGS)
+            .map_err(|failure| ValidationError::InvalidScript {
+                input_index: failure.input_index,
+                reason: failure.reason,
+            })?;
+
+        if let Err(failure) = self.script_checks.run(&checks) {
+            // Like Bitcoin Core: re-check without the policy flags to tell
+            // a non-standard transaction from an invalid one
+            let consensus: Vec<_> = checks
+                .into_iter()
+                .map(|check| ScriptCheck { flags: SCRIPT_VERIFY_NONE, ..check })
+                .collect();
+            return Err(match self.script_checks.run_sequential(&consensus) {
+                Ok(()) => ValidationError::NonStandardScript {
+                    input_index: failure.input_index,
+                    reason: failure.reason,
+                },
+                Err(failure) => ValidationError::InvalidScript {
+                    input_index: failure.input_index,
+                    reason: failure.reason,
+                },
+            });
+        }
+
+        self.script_cache.insert(&txid, STANDARD_SCRIPT_VERIFY_FLAGS);
         Ok(())
     }
 
+    // Helper: log a script failure and convert it to a validation error
+    fn script_failure(block: &Block, failure: ScriptCheckFailure) -> ValidationError {
+        log::warn!("Block {} rejected: {}", block.hash(), failure);
+        ValidationError::InvalidTransaction {
+            tx_index: failure.tx_index,
+            txid: failure.txid,
+            error: Box::new(ValidationError::InvalidScript {
+                input_index: failure.input_index,
+                reason: failure.reason,
+            }),
+        }
+    }
+
     /// Validate a transaction (basic checks)
     pub fn validate_transaction(&self, tx: &Transaction) -> Result<(), ValidationError> {
         // Must have inputs and outputs
         if tx.inputs.is_empty() || tx.outputs.is_empty() {
-            return Err(ValidationError::EmptyTransaction);
+            return Err(Self::empty_transaction(tx));
         }
 
         // Coinbase transactions have special rules
         if tx.is_coinbase() {
             // Coinbase must have exactly 1 input
             if tx.inputs.len() != 1 {
-                return Err(ValidationError::InvalidCoinbaseInputCount);
+                return Err(ValidationError::InvalidCoinbaseInputCount { inputs: tx.inputs.len() });
             }
             // Coinbase validation is simple - just structure check
             return Ok(());
@@ -174,7 +569,10 @@ impl BlockValidator {
         script_pubkey: &[u8],
     ) -> Result<(), ValidationError> {
         if input_index >= tx.inputs.len() {
-            return Err(ValidationError::EmptyTransaction);
+            return Err(ValidationError::InputIndexOutOfRange {
+                input_index,
+                inputs: tx.inputs.len(),
+            });
         }
 
         let input = &tx.inputs[input_index];
@@ -184,14 +582,24 @@ impl BlockValidator {
             return Ok(());
         }
 
-        // Get transaction hash for signature verification
-        let tx_hash = tx.txid();
+        // Get the hash the input signatures commit to
+        let tx_hash = tx.signature_hash();
 
         // Verify P2PKH script
-        Script::verify_p2pkh(&input.script_sig, script_pubkey, tx_hash.as_bytes())
-            .map_err(|_| ValidationError::InvalidSignature)?
-            .then_some(())
-            .ok_or(ValidationError::InvalidSignature)
+        let invalid = |reason: String| ValidationError::InvalidScript { input_index, reason };
+        match Script::verify_p2pkh(&input.script_sig, script_pubkey, tx_hash.as_bytes()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(invalid("Signature verification failed".to_string())),
+            Err(reason) => Err(invalid(reason)),
+        }
+    }
+
+    // Helper: describe a transaction without inputs or outputs
+    fn empty_transaction(tx: &Transaction) -> ValidationError {
+        ValidationError::EmptyTransaction {
+            inputs: tx.inputs.len(),
+            outputs: tx.outputs.len(),
+        }
     }
 }
 
@@ -203,12 +611,12 @@ impl TransactionValidator {
     pub fn validate_for_mempool(tx: &Transaction) -> Result<(), ValidationError> {
         // Must have inputs and outputs
         if tx.inputs.is_empty() || tx.outputs.is_empty() {
-            return Err(ValidationError::EmptyTransaction);
+            return Err(BlockValidator::empty_transaction(tx));
         }
 
         // Cannot be coinbase
         if tx.is_coinbase() {
-            return Err(ValidationError::CoinbaseNotFirst);
+            return Err(ValidationError::LooseCoinbase);
         }
 
         // Check that total output doesn't exceed reasonable limits
@@ -216,7 +624,7 @@ impl TransactionValidator {
         const MAX_MONEY: u64 = 21_000_000 * 100_000_000; // 21M BTC in satoshis
 
         if total_output > MAX_MONEY {
-            return Err(ValidationError::OutputValueExceedsMax);
+            return Err(ValidationError::OutputValueExceedsMax { value: total_output });
         }
 
         Ok(())
@@ -239,7 +647,8 @@ mod tests {
 
     #[test]
     fn test_validate_header_pow() {
-        let validator = BlockValidator::new(0x20ffffff);
+        // Realistic difficulty so an arbitrary nonce cannot satisfy PoW by chance
+        let validator = BlockValidator::new(0x1d00ffff);
 
         // Genesis header (PoW skipped)
         let genesis_header = BlockHeader::new(
@@ -247,7 +656,7 @@ mod tests {
             Hash256::zero(),
             Hash256::zero(),
             1231006505,
-            0x20ffffff,
+            0x1d00ffff,
             0,
         );
 
@@ -260,14 +669,17 @@ mod tests {
             Hash256::new([1; 32]), // Non-zero prev hash
             Hash256::zero(),
             1234567890,
-            0x20ffffff,
+            0x1d00ffff,
             0, // Wrong nonce
         );
 
         // Should fail - non-genesis blocks require PoW
         assert_eq!(
             validator.validate_header(&invalid_header),
-            Err(ValidationError::InvalidProofOfWork)
+            Err(ValidationError::InvalidProofOfWork {
+                hash: invalid_header.hash(),
+                bits: 0x1d00ffff,
+            })
         );
     }
 
@@ -307,7 +719,7 @@ mod tests {
             vec![TxOutput::new(1000, vec![])],
         );
 
-        let merkle = Block::calculate_merkle_root(&[tx.clone()]);
+        let merkle = Block::calculate_merkle_root(std::slice::from_ref(&tx));
         let mut header = BlockHeader::new(
             1,
             Hash256::zero(),
@@ -352,7 +764,255 @@ mod tests {
 
         assert_eq!(
             TransactionValidator::validate_for_mempool(&coinbase),
-            Err(ValidationError::CoinbaseNotFirst)
+            Err(ValidationError::LooseCoinbase)
+        );
+    }
+
+    #[test]
+    fn test_script_caches_skip_reverification() {
+        use crate::storage::{OutPoint, Utxo, UtxoSet};
+        use crate::wallet::{Keystore, TransactionBuilder};
+
+        let mut keystore = Keystore::new();
+        let utxo_set = UtxoSet::memory().unwrap();
+        let from = keystore.new_address();
+        let to = keystore.new_address();
+
+        let script = keystore.get_script_pubkey(&from).unwrap();
+        let outpoint = OutPoint::new(Hash256::new([1; 32]), 0);
+        utxo_set.add_utxo(&outpoint, &Utxo::new(TxOutput::new(100_000, script), 1, false)).unwrap();
+
+        let tx = TransactionBuilder::new(&keystore, &utxo_set)
+            .build(&from, &to, 50_000, 1_000)
+            .unwrap();
+
+        let validator = BlockValidator::new(0x20ffffff);
+
+        // Mempool acceptance verifies the signature and fills both caches
+        validator.verify_transaction_scripts(&tx, &utxo_set).unwrap();
+        let (sig_stats, script_stats) = validator.cache_stats();
+        assert_eq!(sig_stats.misses, 1);
+        assert_eq!(sig_stats.entries, 1);
+        assert_eq!(script_stats.entries, 1);
+
+        // Block connection finds the transaction in the script-execution cache
+        let coinbase = Transaction::coinbase(vec![1], TxOutput::new(5_000_000_000, vec![]), 2);
+        let transactions = vec![coinbase, tx];
+        let merkle = Block::calculate_merkle_root(&transactions);
+        let header = BlockHeader::new(1, Hash256::zero(), merkle, 1234567890, 0x20ffffff, 0);
+        let block = Block::new(header, transactions);
+
+        validator.verify_block_scripts(&block, &utxo_set).unwrap();
+        let (sig_stats, script_stats) = validator.cache_stats();
+        assert_eq!(sig_stats.misses, 1);
+        assert_eq!(script_stats.hits, 1);
+        // One probe per transaction, even though the block's flags differ from the mempool's
+        assert_eq!(script_stats.misses, 1);
+
+        validator.verify_block_scripts(&block, &utxo_set).unwrap();
+        let (_, script_stats) = validator.cache_stats();
+        assert_eq!((script_stats.hits, script_stats.misses), (2, 1));
+    }
+
+    #[test]
+    fn test_verify_block_scripts_reports_failing_input() {
+        use crate::storage::{OutPoint, Utxo, UtxoSet};
+        use crate::wallet::{Keystore, TransactionBuilder};
+
+        let mut keystore = Keystore::new();
+        let utxo_set = UtxoSet::memory().unwrap();
+        let from = keystore.new_address();
+        let to = keystore.new_address();
+
+        let script = keystore.get_script_pubkey(&from).unwrap();
+        let outpoint = OutPoint::new(Hash256::new([1; 32]), 0);
+        utxo_set.add_utxo(&outpoint, &Utxo::new(TxOutput::new(100_000, script), 1, false)).unwrap();
+
+        let mut tx = TransactionBuilder::new(&keystore, &utxo_set)
+            .build(&from, &to, 50_000, 1_000)
+            .unwrap();
+        tx.outputs[0].value += 1; // Invalidates the signature
+
+        let coinbase = Transaction::coinbase(vec![1], TxOutput::new(5_000_000_000, vec![]), 2);
+        let transactions = vec![coinbase, tx];
+        let merkle = Block::calculate_merkle_root(&transactions);
+        let header = BlockHeader::new(1, Hash256::zero(), merkle, 1234567890, 0x20ffffff, 0);
+        let block = Block::new(header, transactions);
+
+        let validator = BlockValidator::new(0x20ffffff);
+        let error = validator.verify_block_scripts(&block, &utxo_set).unwrap_err();
+        assert_eq!(
+            error,
+            ValidationError::InvalidTransaction {
+                tx_index: 1,
+                txid: block.transactions[1].txid(),
+                error: Box::new(ValidationError::InvalidScript {
+                    input_index: 0,
+                    reason: "Signature verification failed".to_string(),
+                }),
+            }
         );
+        assert_eq!(error.tx_index(), Some(1));
+        assert_eq!(error.input_index(), Some(0));
+        assert_eq!(error.class(), RuleClass::Consensus);
+        assert_eq!(error.code(), "mandatory-script-verify-flag-failed");
+        assert!(error.to_string().contains(&block.transactions[1].txid().to_string()));
+    }
+
+    #[test]
+    fn test_transaction_errors_carry_context() {
+        let validator = BlockValidator::new(0x20ffffff);
+        let coinbase = Transaction::coinbase(vec![1], TxOutput::new(50, vec![]), 1);
+        let empty = Transaction::new(vec![crate::core::TxInput::new(Hash256::new([1; 32]), 0, vec![])], vec![]);
+        let transactions = vec![coinbase, empty.clone()];
+        let merkle = Block::calculate_merkle_root(&transactions);
+        let block = Block::new(BlockHeader::new(1, Hash256::zero(), merkle, 0, 0x20ffffff, 0), transactions);
+
+        let error = validator.validate_block(&block).unwrap_err();
+        assert_eq!(
+            error,
+            ValidationError::InvalidTransaction {
+                tx_index: 1,
+                txid: empty.txid(),
+                error: Box::new(ValidationError::EmptyTransaction { inputs: 1, outputs: 0 }),
+            }
+        );
+        assert_eq!(error.code(), "bad-txns-vout-empty");
+
+        // Out-of-range inputs are no longer reported as empty transactions
+        assert_eq!(
+            validator.validate_transaction_signature(&empty, 3, &[]),
+            Err(ValidationError::InputIndexOutOfRange { input_index: 3, inputs: 1 })
+        );
+    }
+
+    #[test]
+    fn test_non_standard_script_is_policy() {
+        use crate::core::script::SCRIPT_VERIFY_COMPRESSED_PUBKEYS;
+        use crate::core::{hash160, TxInput};
+        use crate::storage::{OutPoint, Utxo, UtxoSet};
+        use crate::wallet::KeyPair;
+        use secp256k1::{Message, Secp256k1};
+
+        // Spend an output locked to an uncompressed key
+        let keypair = KeyPair::generate();
+        let uncompressed = keypair.public_key.serialize_uncompressed();
+        let utxo_set = UtxoSet::memory().unwrap();
+        let outpoint = OutPoint::new(Hash256::new([1; 32]), 0);
+        let script_pubkey = Script::p2pkh_script_pubkey(&hash160(&uncompressed));
+        utxo_set.add_utxo(&outpoint, &Utxo::new(TxOutput::new(1000, script_pubkey), 0, false)).unwrap();
+
+        let mut tx = Transaction::new(vec![TxInput::new(outpoint.txid, 0, vec![])], vec![TxOutput::new(900, vec![])]);
+        let message = Message::from_digest_slice(tx.signature_hash().as_bytes()).unwrap();
+        let signature = Secp256k1::new().sign_ecdsa(&message, &keypair.secret_key);
+        tx.inputs[0].script_sig = Script::p2pkh_script_sig(&signature.serialize_der(), &uncompressed);
+
+        assert_eq!(STANDARD_SCRIPT_VERIFY_FLAGS & SCRIPT_VERIFY_COMPRESSED_PUBKEYS, SCRIPT_VERIFY_COMPRESSED_PUBKEYS);
+        let error = BlockValidator::new(0x20ffffff)
+            .verify_transaction_scripts(&tx, &utxo_set)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ValidationError::NonStandardScript {
+                input_index: 0,
+                reason: "Public key is not compressed".to_string(),
+            }
+        );
+        assert_eq!(error.class(), RuleClass::Policy);
+    }
+
+    #[test]
+    fn test_assume_valid_skips_scripts_only() {
+        use crate::storage::{OutPoint, Utxo, UtxoSet};
+        use crate::wallet::{Keystore, TransactionBuilder};
+
+        let mut keystore = Keystore::new();
+        let utxo_set = UtxoSet::memory().unwrap();
+        let blocks = BlockchainDB::memory().unwrap();
+        let from = keystore.new_address();
+        let to = keystore.new_address();
+
+        let script = keystore.get_script_pubkey(&from).unwrap();
+        let outpoint = OutPoint::new(Hash256::new([1; 32]), 0);
+        utxo_set.add_utxo(&outpoint, &Utxo::new(TxOutput::new(100_000, script), 1, false)).unwrap();
+
+        let mut tx = TransactionBuilder::new(&keystore, &utxo_set)
+            .build(&from, &to, 50_000, 1_000)
+            .unwrap();
+        tx.outputs[0].value += 1; // Invalidates the signature
+
+        // Genesis, a block with the bad signature, and the assume-valid block on top
+        let block_on = |prev: Hash256, height: u32, mut transactions: Vec<Transaction>| {
+            transactions.insert(0, Transaction::coinbase(vec![height as u8], TxOutput::new(5_000_000_000, vec![]), height));
+            let merkle = Block::calculate_merkle_root(&transactions);
+            Block::new(BlockHeader::new(1, prev, merkle, 1234567890, 0x20ffffff, 0), transactions)
+        };
+        let genesis = Block::genesis();
+        let block = block_on(genesis.hash(), 1, vec![tx]);
+        let assume_valid = block_on(block.hash(), 2, vec![]);
+        let params = ChainParams::new(0x20ffffff).with_assume_valid(Some(assume_valid.hash()));
+        let validator = BlockValidator::with_params(params.clone());
+
+        // Until the assume-valid header is known the bad signature is caught
+        assert!(validator.validate_for_connection(&block, 1, &utxo_set, &blocks).is_err());
+
+        // Below the pre-synced assume-valid header scripts are skipped...
+        for (height, header) in [&genesis, &block, &assume_valid].into_iter().map(|b| &b.header).enumerate() {
+            assert!(blocks.store_header(header, height as u32).unwrap());
+        }
+        assert_eq!(
+            validator.validate_for_connection(&block, 1, &utxo_set, &blocks),
+            Ok(ScriptValidation::AssumedValid)
+        );
+        assert!(!blocks.has_block(&assume_valid.hash()).unwrap());
+
+        // ...but not for a block off its chain, or at another height
+        let sibling = block_on(genesis.hash(), 1, vec![]);
+        assert!(!validator.is_assumed_valid(&sibling.hash(), 1, &blocks));
+        assert!(!validator.is_assumed_valid(&block.hash(), 2, &blocks));
+
+        // Checkpoints and block rules still apply
+        let validator = BlockValidator::with_params(params.with_checkpoint(1, Hash256::zero()));
+        assert_eq!(
+            validator.validate_for_connection(&block, 1, &utxo_set, &blocks),
+            Err(ValidationError::CheckpointMismatch { height: 1 })
+        );
+        let mut no_coinbase = block.clone();
+        no_coinbase.transactions.remove(0);
+        assert!(BlockValidator::with_params(ChainParams::new(0x20ffffff).with_assume_valid(Some(no_coinbase.hash())))
+            .validate_for_connection(&no_coinbase, 1, &utxo_set, &blocks)
+            .is_err());
+    }
+
+    #[test]
+    fn test_deployment_gates_script_flags() {
+        use crate::core::script::SCRIPT_VERIFY_COMPRESSED_PUBKEYS;
+
+        let mut params = ChainParams::new(0x20ffffff);
+        params.version_bits_window = 2;
+        params.version_bits_threshold = 2;
+        let validator = BlockValidator::with_params(params);
+        let blocks = BlockchainDB::memory().unwrap();
+
+        // Store a chain where every block signals what the validator asks for
+        for height in 0..8 {
+            let version = validator.block_version(height, &blocks, true);
+            let coinbase = Transaction::coinbase(vec![height as u8], TxOutput::new(50, vec![]), height);
+            let header = BlockHeader::new(version, Hash256::zero(), Hash256::zero(), height, 0x20ffffff, height);
+            let block = Block::new(header, vec![coinbase]);
+            blocks.store_block(&block, height).unwrap();
+            blocks.store_height(height, &block.hash()).unwrap();
+        }
+
+        let deployment = validator.params().deployment("strictkeys").unwrap().clone();
+        assert_eq!(validator.deployment_state(&deployment, 1, &blocks), ThresholdState::Defined);
+        assert_eq!(validator.deployment_state(&deployment, 2, &blocks), ThresholdState::Started);
+        assert_eq!(validator.deployment_state(&deployment, 4, &blocks), ThresholdState::LockedIn);
+        assert_eq!(validator.deployment_state(&deployment, 6, &blocks), ThresholdState::Active);
+
+        assert_eq!(validator.script_flags(5, &blocks), SCRIPT_VERIFY_NONE);
+        assert_eq!(validator.script_flags(6, &blocks), SCRIPT_VERIFY_COMPRESSED_PUBKEYS);
+        // Nothing left to signal once active
+        assert_eq!(validator.block_version(8, &blocks, true), VERSIONBITS_TOP_BITS);
     }
 }
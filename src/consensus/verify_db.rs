@@ -0,0 +1,378 @@
+// Integrity audit of the stored chain (verifychain)
+//
+// Walks back from the tip over the last `depth` blocks. Each level adds
+// checks to the ones below it:
+//   0  block data readable, header linked to its parent, proof of work
+//   1  block rules and merkle root
+//   2  undo data readable and matching the block's inputs
+//   3  disconnect the blocks on a scratch view of the UTXO set, using the undo data
+//   4  reconnect them with full validation and compare the view with the UTXO set
+// The scratch view is dropped at the end, so nothing is written. Problems are
+// collected instead of stopping at the first one.
+
+use crate::consensus::validation::BlockValidator;
+use crate::core::{Block, Hash256};
+use crate::storage::{BlockUndo, CoinsView, KvBackend, OutPoint, Storage, Utxo};
+
+/// Highest check level
+pub const MAX_CHECK_LEVEL: u32 = 4;
+
+/// Check level used when none is given
+pub const DEFAULT_CHECK_LEVEL: u32 = 3;
+
+/// Number of blocks checked when no depth is given
+pub const DEFAULT_CHECK_DEPTH: u32 = 6;
+
+/// Outcome of `verify_chain`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of blocks checked
+    pub blocks: u32,
+    /// Lowest height checked
+    pub first_height: u32,
+    pub tip_height: u32,
+    /// The walk stopped early at a pruned block
+    pub reached_pruned: bool,
+    /// One line per problem, e.g. "height 812: UTXO <txid>:1 missing"
+    pub problems: Vec<String>,
+}
+
+impl VerifyReport {
+    // Helper: record a problem once
+    fn problem(&mut self, height: u32, message: impl std::fmt::Display) {
+        let line = format!("height {}: {}", height, message);
+        if !self.problems.contains(&line) {
+            self.problems.push(line);
+        }
+    }
+}
+
+/// Check the last `depth` blocks of the active chain (0 = every block) up to `level`
+/// Fails only if storage cannot be read; what is wrong with the chain is in the report.
+pub fn verify_chain<B: KvBackend>(
+    validator: &BlockValidator,
+    storage: &Storage<B>,
+    level: u32,
+    depth: u32,
+) -> Result<VerifyReport, String> {
+    if level > MAX_CHECK_LEVEL {
+        return Err(format!("Check level must be 0 to {}, got {}", MAX_CHECK_LEVEL, level));
+    }
+    let chain_height = storage.blockchain.get_chain_height()?;
+    if chain_height == 0 {
+        return Err("Blockchain not initialized. Run 'init' first.".to_string());
+    }
+    let tip_height = chain_height - 1;
+    let lowest = if depth == 0 { 0 } else { chain_height.saturating_sub(depth) };
+
+    let mut report = VerifyReport {
+        first_height: tip_height,
+        tip_height,
+        ..VerifyReport::default()
+    };
+
+    // Tip first; only blocks that passed the per-block checks can be replayed
+    let mut loaded = Vec::new();
+    let mut complete = true;
+    for height in (lowest..=tip_height).rev() {
+        match check_block(validator, storage, &mut report, level, height)? {
+            BlockCheck::Loaded(block, undo) => loaded.push((height, block, undo)),
+            BlockCheck::Pruned => {
+                report.reached_pruned = true;
+                break;
+            }
+            BlockCheck::Unusable => complete = false,
+        }
+        report.blocks += 1;
+        report.first_height = height;
+    }
+
+    if level >= 3 && complete && loaded.iter().all(|(_, _, undo)| undo.is_some()) {
+        replay(validator, storage, &mut report, level, &loaded)?;
+    }
+
+    Ok(report)
+}
+
+/// What the per-block checks left for the replay
+enum BlockCheck {
+    Loaded(Block, Option<BlockUndo>),
+    Pruned,
+    Unusable,
+}
+
+// Helper: run the level 0 to 2 checks on the active-chain block at `height`
+fn check_block<B: KvBackend>(
+    validator: &BlockValidator,
+    storage: &Storage<B>,
+    report: &mut VerifyReport,
+    level: u32,
+    height: u32,
+) -> Result<BlockCheck, String> {
+    let blocks = &storage.blockchain;
+    let Some(hash) = blocks.get_hash_by_height(height)? else {
+        report.problem(height, "missing from the height index");
+        return Ok(BlockCheck::Unusable);
+    };
+    match blocks.get_index_entry(&hash)? {
+        None => {
+            report.problem(height, format!("block {} is not in the block index", hash));
+            return Ok(BlockCheck::Unusable);
+        }
+        Some(entry) if entry.is_pruned() => return Ok(BlockCheck::Pruned),
+        Some(entry) if entry.height != height => {
+            report.problem(height, format!("block index says block {} is at height {}", hash, entry.height));
+        }
+        Some(_) => {}
+    }
+
+    let block = match blocks.get_block(&hash) {
+        Ok(Some(block)) => block,
+        Ok(None) => {
+            report.problem(height, format!("data of block {} is missing", hash));
+            return Ok(BlockCheck::Unusable);
+        }
+        Err(e) => {
+            report.problem(height, e);
+            return Ok(BlockCheck::Unusable);
+        }
+    };
+    if block.hash() != hash {
+        report.problem(height, format!("block file holds {}, the index points at {}", block.hash(), hash));
+        return Ok(BlockCheck::Unusable);
+    }
+
+    let parent = match height {
+        0 => Some(Hash256::zero()),
+        _ => blocks.get_hash_by_height(height - 1)?,
+    };
+    if parent != Some(block.header.prev_block_hash) {
+        report.problem(
+            height,
+            format!("previous block {} is not the block at height {}", block.header.prev_block_hash, height.saturating_sub(1)),
+        );
+    }
+    if let Err(e) = validator.validate_header(&block.header) {
+        report.problem(height, e);
+    }
+
+    if level >= 1
+        && let Err(e) = validator.validate_block(&block)
+    {
+        report.problem(height, e);
+    }
+
+    let mut undo = None;
+    if level >= 2 {
+        match blocks.get_undo(&hash) {
+            Ok(Some(data)) => {
+                check_undo(report, &block, height, &data);
+                undo = Some(data);
+            }
+            Ok(None) => report.problem(height, "undo data missing"),
+            Err(e) => report.problem(height, e),
+        }
+    }
+
+    Ok(BlockCheck::Loaded(block, undo))
+}
+
+// Helper: the undo data lists one output per input, none created above the block
+fn check_undo(report: &mut VerifyReport, block: &Block, height: u32, undo: &BlockUndo) {
+    let inputs: usize = block.transactions.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.inputs.len()).sum();
+    if undo.spent.len() != inputs {
+        report.problem(
+            height,
+            format!("undo data has {} entries, the block spends {} outputs", undo.spent.len(), inputs),
+        );
+    }
+    for (index, utxo) in undo.spent.iter().enumerate() {
+        if utxo.height > height {
+            report.problem(height, format!("undo entry {} was created at height {}", index, utxo.height));
+        }
+    }
+}
+
+// Helper: disconnect the loaded blocks (tip first) on a scratch view, then
+// reconnect them and compare with the UTXO set (level 4)
+fn replay<B: KvBackend>(
+    validator: &BlockValidator,
+    storage: &Storage<B>,
+    report: &mut VerifyReport,
+    level: u32,
+    loaded: &[(u32, Block, Option<BlockUndo>)],
+) -> Result<(), String> {
+    let mut view = storage.coins_view();
+
+    for (height, block, undo) in loaded {
+        let undo = undo.as_ref().expect("replay needs undo data");
+        let mut spent = undo.spent.iter().rev();
+        for tx in block.transactions.iter().rev() {
+            let txid = tx.txid();
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, vout as u32);
+                match view.spend_utxo(&outpoint)? {
+                    Some(utxo) if utxo == Utxo::new(output.clone(), *height, tx.is_coinbase()) => {}
+                    Some(_) => report.problem(*height, format!("UTXO {}:{} differs from the output creating it", txid, vout)),
+                    None => report.problem(*height, format!("UTXO {}:{} missing", txid, vout)),
+                }
+            }
+
+            if !tx.is_coinbase() {
+                for input in tx.inputs.iter().rev() {
+                    let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+                    let Some(utxo) = spent.next() else {
+                        continue;
+                    };
+                    if view.get_utxo(&outpoint)?.is_some() {
+                        report.problem(*height, format!("UTXO {}:{} spent by this block is unspent", outpoint.txid, outpoint.vout));
+                    }
+                    view.add_utxo(&outpoint, utxo.clone(), true);
+                }
+            }
+        }
+    }
+
+    if level < 4 {
+        return Ok(());
+    }
+
+    for (height, block, undo) in loaded.iter().rev() {
+        let undo = undo.as_ref().expect("replay needs undo data");
+        if let Err(e) = validator.validate_for_connection(block, *height, &view, &storage.blockchain) {
+            report.problem(*height, e);
+        }
+
+        let mut undo_entries = undo.spent.iter().enumerate();
+        for tx in &block.transactions {
+            if !tx.is_coinbase() {
+                for input in &tx.inputs {
+                    let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+                    let utxo = view.spend_utxo(&outpoint)?;
+                    match (utxo, undo_entries.next()) {
+                        (None, _) => report.problem(*height, format!("input {}:{} missing", outpoint.txid, outpoint.vout)),
+                        (Some(utxo), Some((index, recorded))) if utxo != *recorded => report.problem(
+                            *height,
+                            format!("undo entry {} does not match the spent output {}:{}", index, outpoint.txid, outpoint.vout),
+                        ),
+                        _ => {}
+                    }
+                }
+            }
+
+            let txid = tx.txid();
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                view.add_utxo(&OutPoint::new(txid, vout as u32), Utxo::new(output.clone(), *height, tx.is_coinbase()), true);
+            }
+        }
+    }
+
+    // Whatever the replay changed must end up as it is in the UTXO set
+    for (outpoint, entry) in view.into_changes() {
+        let live = storage.get_utxo(&outpoint)?;
+        match (&entry.utxo, &live) {
+            (Some(replayed), Some(stored)) if replayed != stored => report.problem(
+                replayed.height,
+                format!("UTXO {}:{} differs from the replayed one", outpoint.txid, outpoint.vout),
+            ),
+            (Some(replayed), None) => {
+                report.problem(replayed.height, format!("UTXO {}:{} missing", outpoint.txid, outpoint.vout))
+            }
+            (None, Some(stored)) => report.problem(
+                stored.height,
+                format!("UTXO {}:{} is spent by the chain but still in the UTXO set", outpoint.txid, outpoint.vout),
+            ),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::pow::Miner;
+    use crate::core::{BlockHeader, Script, Transaction, TxOutput};
+    use crate::storage::MemoryBackend;
+    use crate::wallet::{Keystore, TransactionBuilder};
+
+    const BITS: u32 = 0x20ffffff;
+
+    // Helper: mine a block on `prev` and connect it at `height`
+    fn connect(storage: &Storage<MemoryBackend>, prev: Hash256, height: u32, mut transactions: Vec<Transaction>, reward: &[u8]) -> Block {
+        transactions.insert(0, Transaction::coinbase(vec![height as u8], TxOutput::new(5_000_000_000, reward.to_vec()), height));
+        let merkle_root = Block::calculate_merkle_root(&transactions);
+        let mut header = BlockHeader::new(1, prev, merkle_root, 1_700_000_000 + height, BITS, 0);
+        assert!(Miner::new(BITS).mine(&mut header).success);
+        let block = Block::new(header, transactions);
+        storage.connect_block(&block, height).unwrap();
+        block
+    }
+
+    // Helper: genesis and four blocks paying one wallet address, the last spending the earlier rewards
+    fn chain() -> Storage<MemoryBackend> {
+        let storage = Storage::memory().unwrap();
+        let mut keystore = Keystore::new();
+        let from = keystore.new_address();
+        let to = keystore.new_address();
+        let reward = Script::p2pkh_script_pubkey(&from.to_pubkey_hash().unwrap());
+
+        let genesis = Block::genesis();
+        storage.connect_block(&genesis, 0).unwrap();
+        let mut prev = genesis.hash();
+        for height in 1..4 {
+            prev = connect(&storage, prev, height, vec![], &reward).hash();
+        }
+        storage.flush().unwrap();
+        let spend = TransactionBuilder::new(&keystore, &storage.utxo_set)
+            .build(&from, &to, 1_000_000, 1_000)
+            .unwrap();
+        connect(&storage, prev, 4, vec![spend], &reward);
+        storage.flush().unwrap();
+        storage
+    }
+
+    #[test]
+    fn test_consistent_chain_passes_every_level() {
+        let storage = chain();
+        let validator = BlockValidator::new(BITS);
+
+        let report = verify_chain(&validator, &storage, MAX_CHECK_LEVEL, 0).unwrap();
+        assert_eq!(report.problems, Vec::<String>::new());
+        assert_eq!((report.blocks, report.first_height, report.tip_height), (5, 0, 4));
+
+        let report = verify_chain(&validator, &storage, 1, 2).unwrap();
+        assert_eq!((report.blocks, report.first_height), (2, 3));
+        assert!(verify_chain(&validator, &storage, 5, 1).is_err());
+    }
+
+    #[test]
+    fn test_missing_utxo_is_reported_at_its_height() {
+        let storage = chain();
+        let validator = BlockValidator::new(BITS);
+        // The spend at height 4 takes one of the earlier rewards, so pick one it left
+        let (height, txid) = (1..4)
+            .map(|height| (height, storage.blockchain.get_block_by_height(height).unwrap().unwrap().transactions[0].txid()))
+            .find(|(_, txid)| storage.utxo_set.get_utxo(&OutPoint::new(*txid, 0)).unwrap().is_some())
+            .unwrap();
+        assert!(storage.utxo_set.remove_utxo(&OutPoint::new(txid, 0)).unwrap());
+
+        // Only the replay looks at the UTXO set
+        assert!(verify_chain(&validator, &storage, 2, 0).unwrap().problems.is_empty());
+        let report = verify_chain(&validator, &storage, MAX_CHECK_LEVEL, 0).unwrap();
+        assert_eq!(report.problems, vec![format!("height {}: UTXO {}:0 missing", height, txid)]);
+    }
+
+    #[test]
+    fn test_broken_link_and_undo_are_reported() {
+        let storage = chain();
+        let validator = BlockValidator::new(BITS);
+        let stale = storage.blockchain.get_hash_by_height(1).unwrap().unwrap();
+        storage.blockchain.store_height(2, &stale).unwrap();
+
+        let report = verify_chain(&validator, &storage, MAX_CHECK_LEVEL, 0).unwrap();
+        assert!(report.problems.contains(&format!("height 2: block index says block {} is at height 1", stale)));
+        assert!(report.problems.iter().any(|problem| problem.starts_with("height 3: previous block")));
+    }
+}
@@ -0,0 +1,322 @@
+// BIP9 version bits: soft-fork deployment state machine
+//
+// Each deployment owns one bit of the block version. Miners signal support
+// by setting the bit (with the top bits 001). The chain is split into
+// windows of `window` blocks; the state only changes at window boundaries:
+//
+//   DEFINED ──(median time >= start)──> STARTED
+//   STARTED ──(>= threshold blocks signalled in the window)──> LOCKED_IN
+//   STARTED ──(median time >= timeout)──> FAILED
+//   LOCKED_IN ──(next window, height >= min_activation_height)──> ACTIVE
+//
+// ACTIVE and FAILED are final. The state of a block depends only on its
+// ancestors, so results are cached by the hash of the last block of the
+// previous window.
+
+use crate::core::{BlockHeader, Hash256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Top three version bits of a BIP9 block (001)
+pub const VERSIONBITS_TOP_BITS: u32 = 0x2000_0000;
+
+/// Mask selecting the top three version bits
+pub const VERSIONBITS_TOP_MASK: u32 = 0xe000_0000;
+
+/// Number of bits available for deployments
+pub const VERSIONBITS_NUM_BITS: u8 = 29;
+
+/// Blocks used to compute the median time past
+const MEDIAN_TIME_SPAN: u32 = 11;
+
+/// Deployment state of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThresholdState {
+    /// Deployment defined, start time not reached
+    Defined,
+    /// Miners signal during this window
+    Started,
+    /// Threshold reached; rules activate at the next window
+    LockedIn,
+    /// Rules are enforced
+    Active,
+    /// Timed out without locking in
+    Failed,
+}
+
+impl std::fmt::Display for ThresholdState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ThresholdState::Defined => "defined",
+            ThresholdState::Started => "started",
+            ThresholdState::LockedIn => "locked_in",
+            ThresholdState::Active => "active",
+            ThresholdState::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A soft fork deployed with version bits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deployment {
+    /// Name shown in `info` and getblocktemplate
+    pub name: String,
+    /// Version bit used for signalling (0..29)
+    pub bit: u8,
+    /// Median time past at which signalling starts
+    pub start_time: u32,
+    /// Median time past after which an unfinished deployment fails
+    pub timeout: u32,
+    /// Earliest height at which the deployment may become active
+    pub min_activation_height: u32,
+    /// Script verification flags enforced once active
+    pub script_flags: u32,
+}
+
+impl Deployment {
+    /// Version bit mask of this deployment
+    pub fn mask(&self) -> u32 {
+        1 << self.bit
+    }
+
+    /// Whether a block version signals for this deployment
+    pub fn signals(&self, version: u32) -> bool {
+        version & VERSIONBITS_TOP_MASK == VERSIONBITS_TOP_BITS && version & self.mask() != 0
+    }
+}
+
+/// Signalling progress of a deployment in the current window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeploymentStats {
+    /// State of the next block
+    pub state: ThresholdState,
+    /// Blocks of the current window already mined
+    pub elapsed: u32,
+    /// How many of them signal
+    pub count: u32,
+    /// Window length
+    pub window: u32,
+    /// Signalling blocks needed to lock in
+    pub threshold: u32,
+}
+
+/// Deployment states computed so far, keyed by (bit, last block of the previous window)
+#[derive(Default)]
+pub struct VersionBitsCache {
+    states: Mutex<HashMap<(u8, Hash256), ThresholdState>>,
+}
+
+impl VersionBitsCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// State of `deployment` for the block at `height`
+    /// `header_at` returns the ancestor header at a height below `height`.
+    pub fn state<F>(&self, deployment: &Deployment, window: u32, threshold: u32, height: u32, header_at: F) -> ThresholdState
+    where
+        F: Fn(u32) -> Option<BlockHeader>,
+    {
+        let mut state = ThresholdState::Defined;
+
+        // Walk the window boundaries up to the one containing `height`
+        for period in 1..=height / window {
+            let last_height = period * window - 1;
+            let Some(last) = header_at(last_height) else {
+                return state;
+            };
+            let key = (deployment.bit, last.hash());
+
+            if let Some(cached) = self.states.lock().unwrap().get(&key) {
+                state = *cached;
+                continue;
+            }
+
+            state = Self::next_state(deployment, state, window, threshold, last_height, &header_at);
+            self.states.lock().unwrap().insert(key, state);
+        }
+
+        state
+    }
+
+    /// State of the next block plus signalling in its window so far
+    pub fn stats<F>(&self, deployment: &Deployment, window: u32, threshold: u32, height: u32, header_at: F) -> DeploymentStats
+    where
+        F: Fn(u32) -> Option<BlockHeader>,
+    {
+        let state = self.state(deployment, window, threshold, height, &header_at);
+        let start = height - height % window;
+        let count = (start..height)
+            .filter_map(&header_at)
+            .filter(|header| deployment.signals(header.version))
+            .count() as u32;
+
+        DeploymentStats {
+            state,
+            elapsed: height - start,
+            count,
+            window,
+            threshold,
+        }
+    }
+
+    // Helper: state of the window after the one ending at `last_height`
+    fn next_state<F>(
+        deployment: &Deployment,
+        state: ThresholdState,
+        window: u32,
+        threshold: u32,
+        last_height: u32,
+        header_at: &F,
+    ) -> ThresholdState
+    where
+        F: Fn(u32) -> Option<BlockHeader>,
+    {
+        let time = median_time_past(last_height, header_at);
+
+        match state {
+            ThresholdState::Defined if time >= deployment.timeout => ThresholdState::Failed,
+            ThresholdState::Defined if time >= deployment.start_time => ThresholdState::Started,
+            ThresholdState::Started => {
+                let first_height = last_height + 1 - window;
+                let count = (first_height..=last_height)
+                    .filter_map(header_at)
+                    .filter(|header| deployment.signals(header.version))
+                    .count() as u32;
+
+                if count >= threshold {
+                    ThresholdState::LockedIn
+                } else if time >= deployment.timeout {
+                    ThresholdState::Failed
+                } else {
+                    ThresholdState::Started
+                }
+            }
+            ThresholdState::LockedIn if last_height + 1 >= deployment.min_activation_height => ThresholdState::Active,
+            state => state,
+        }
+    }
+}
+
+/// Median timestamp of the block at `height` and the 10 before it
+pub fn median_time_past<F>(height: u32, header_at: F) -> u32
+where
+    F: Fn(u32) -> Option<BlockHeader>,
+{
+    let first = height.saturating_sub(MEDIAN_TIME_SPAN - 1);
+    let mut times: Vec<u32> = (first..=height)
+        .filter_map(header_at)
+        .map(|header| header.timestamp)
+        .collect();
+    if times.is_empty() {
+        return 0;
+    }
+    times.sort_unstable();
+    times[times.len() / 2]
+}
+
+/// Block version signalling every deployment that is started or locked in
+pub fn compute_block_version<'a, I>(states: I) -> u32
+where
+    I: IntoIterator<Item = (&'a Deployment, ThresholdState)>,
+{
+    states
+        .into_iter()
+        .filter(|(_, state)| matches!(state, ThresholdState::Started | ThresholdState::LockedIn))
+        .fold(VERSIONBITS_TOP_BITS, |version, (deployment, _)| version | deployment.mask())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u32 = 10;
+    const THRESHOLD: u32 = 8;
+
+    fn deployment() -> Deployment {
+        Deployment {
+            name: "test".to_string(),
+            bit: 1,
+            start_time: 1_000,
+            timeout: 5_000,
+            min_activation_height: 0,
+            script_flags: 0,
+        }
+    }
+
+    // Chain whose block at height h has timestamp 100*h and `version(h)`
+    fn chain(len: u32, version: impl Fn(u32) -> u32) -> Vec<BlockHeader> {
+        (0..len)
+            .map(|h| BlockHeader::new(version(h), Hash256::zero(), Hash256::zero(), 100 * h, 0x20ffffff, h))
+            .collect()
+    }
+
+    fn state_at(headers: &[BlockHeader], deployment: &Deployment, height: u32) -> ThresholdState {
+        VersionBitsCache::new().state(deployment, WINDOW, THRESHOLD, height, |h| headers.get(h as usize).cloned())
+    }
+
+    #[test]
+    fn test_activation_path() {
+        let deployment = deployment();
+        let signal = VERSIONBITS_TOP_BITS | deployment.mask();
+        let headers = chain(60, |_| signal);
+
+        // MTP of block 9 is 400 < start, of block 19 is 1400 >= start
+        assert_eq!(state_at(&headers, &deployment, 5), ThresholdState::Defined);
+        assert_eq!(state_at(&headers, &deployment, 10), ThresholdState::Defined);
+        assert_eq!(state_at(&headers, &deployment, 20), ThresholdState::Started);
+        assert_eq!(state_at(&headers, &deployment, 30), ThresholdState::LockedIn);
+        assert_eq!(state_at(&headers, &deployment, 40), ThresholdState::Active);
+        assert_eq!(state_at(&headers, &deployment, 59), ThresholdState::Active);
+    }
+
+    #[test]
+    fn test_below_threshold_times_out() {
+        let deployment = deployment();
+        // 7 of every 10 blocks signal: one short of the threshold
+        let headers = chain(80, |h| if h % 10 < 7 { VERSIONBITS_TOP_BITS | 2 } else { 1 });
+
+        assert_eq!(state_at(&headers, &deployment, 30), ThresholdState::Started);
+        // MTP of block 59 is 5400 >= timeout
+        assert_eq!(state_at(&headers, &deployment, 60), ThresholdState::Failed);
+        assert_eq!(state_at(&headers, &deployment, 79), ThresholdState::Failed);
+    }
+
+    #[test]
+    fn test_min_activation_height_delays_active() {
+        let deployment = Deployment {
+            min_activation_height: 50,
+            ..deployment()
+        };
+        let headers = chain(60, |_| VERSIONBITS_TOP_BITS | 2);
+
+        assert_eq!(state_at(&headers, &deployment, 40), ThresholdState::LockedIn);
+        assert_eq!(state_at(&headers, &deployment, 50), ThresholdState::Active);
+    }
+
+    #[test]
+    fn test_signalling_requires_top_bits() {
+        let deployment = deployment();
+        assert!(deployment.signals(VERSIONBITS_TOP_BITS | 2));
+        assert!(!deployment.signals(VERSIONBITS_TOP_BITS));
+        assert!(!deployment.signals(2));
+        assert!(!deployment.signals(0x6000_0002));
+
+        let version = compute_block_version([(&deployment, ThresholdState::Started)]);
+        assert_eq!(version, VERSIONBITS_TOP_BITS | 2);
+        assert_eq!(compute_block_version([(&deployment, ThresholdState::Active)]), VERSIONBITS_TOP_BITS);
+    }
+
+    #[test]
+    fn test_stats_count_current_window() {
+        let deployment = deployment();
+        let headers = chain(25, |h| if h >= 20 && h % 2 == 0 { VERSIONBITS_TOP_BITS | 2 } else { 1 });
+        let stats = VersionBitsCache::new().stats(&deployment, WINDOW, THRESHOLD, 25, |h| headers.get(h as usize).cloned());
+
+        assert_eq!(stats.state, ThresholdState::Started);
+        assert_eq!(stats.elapsed, 5);
+        assert_eq!(stats.count, 3);
+    }
+}
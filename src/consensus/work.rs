@@ -0,0 +1,227 @@
+// Mining work units with extraNonce and timestamp rolling
+//
+// A block header only has a 32-bit nonce, which a fast miner exhausts in
+// seconds at realistic difficulty. When that happens the miner changes
+// other parts of the header to get a fresh nonce space:
+//   - extraNonce: a counter appended to the coinbase scriptSig. Changing it
+//     changes the coinbase txid and therefore the merkle root.
+//   - timestamp: moved forward to the current time, but never further than
+//     the validator allows into the future.
+// CPU and GPU miners share this mechanism through `MiningWork::mine`.
+
+use crate::consensus::pow::{CancelToken, MiningResult};
+use crate::consensus::validation::MAX_FUTURE_BLOCK_TIME;
+use crate::core::{Block, BlockHeader, Hash256, Transaction};
+use std::time::Duration;
+
+/// A block being mined: header plus transactions, coinbase first
+#[derive(Debug, Clone)]
+pub struct MiningWork {
+    /// Header being mined (merkle root always matches `transactions`)
+    pub header: BlockHeader,
+    /// Block transactions; the first one is the coinbase
+    pub transactions: Vec<Transaction>,
+    /// Current extraNonce value (last 8 bytes of the coinbase scriptSig)
+    pub extra_nonce: u64,
+    /// Coinbase scriptSig without the extraNonce suffix
+    coinbase_prefix: Vec<u8>,
+}
+
+impl MiningWork {
+    /// Create work from a header and transactions (`transactions[0]` must be the coinbase)
+    /// Appends extraNonce 0 to the coinbase scriptSig and recomputes the merkle root.
+    pub fn new(header: BlockHeader, transactions: Vec<Transaction>) -> Result<Self, String> {
+        let coinbase = transactions.first().ok_or("Mining work needs a coinbase transaction")?;
+        if !coinbase.is_coinbase() {
+            return Err("First transaction of mining work must be a coinbase".to_string());
+        }
+
+        let coinbase_prefix = coinbase.inputs[0].script_sig.clone();
+        let mut work = Self {
+            header,
+            transactions,
+            extra_nonce: 0,
+            coinbase_prefix,
+        };
+        work.apply_extra_nonce();
+        Ok(work)
+    }
+
+    /// Move to the next extraNonce, rebuilding the coinbase and merkle root
+    pub fn increment_extra_nonce(&mut self) {
+        self.extra_nonce += 1;
+        self.apply_extra_nonce();
+    }
+
+    /// Move the timestamp forward to `now`, never past `now + MAX_FUTURE_BLOCK_TIME`
+    /// and never backwards. Returns whether the timestamp changed.
+    pub fn update_time(&mut self, now: u32) -> bool {
+        let latest = now.saturating_add(MAX_FUTURE_BLOCK_TIME);
+        let timestamp = self.header.timestamp.max(now).min(latest);
+
+        if timestamp != self.header.timestamp {
+            self.header.timestamp = timestamp;
+            self.header.nonce = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Mine until a valid header is found or `cancel` is triggered
+    /// `mine_header` searches the nonce space of one header (any backend);
+    /// whenever it comes back empty, the extraNonce and timestamp are rolled.
+    pub fn mine<F>(&mut self, cancel: &CancelToken, mut mine_header: F) -> MiningResult
+    where
+        F: FnMut(&mut BlockHeader) -> MiningResult,
+    {
+        let mut attempts = 0u64;
+        let mut duration = Duration::ZERO;
+
+        loop {
+            let result = mine_header(&mut self.header);
+            attempts += result.attempts;
+            duration += result.duration;
+
+            if result.success || result.cancelled || cancel.is_cancelled() {
+                return MiningResult {
+                    attempts,
+                    duration,
+                    cancelled: !result.success,
+                    ..result
+                };
+            }
+
+            log::info!(
+                "Nonce space exhausted, rolling extraNonce to {}",
+                self.extra_nonce + 1
+            );
+            self.increment_extra_nonce();
+            self.update_time(Self::now());
+        }
+    }
+
+    /// Assemble the finished block
+    pub fn into_block(self) -> Block {
+        Block::new(self.header, self.transactions)
+    }
+
+    /// ID of the current coinbase transaction
+    pub fn coinbase_txid(&self) -> Hash256 {
+        self.transactions[0].txid()
+    }
+
+    // Helper: write prefix || extraNonce into the coinbase and refresh the merkle root
+    fn apply_extra_nonce(&mut self) {
+        let mut script_sig = self.coinbase_prefix.clone();
+        script_sig.extend_from_slice(&self.extra_nonce.to_le_bytes());
+        self.transactions[0].inputs[0].script_sig = script_sig;
+
+        self.header.merkle_root = Block::calculate_merkle_root(&self.transactions);
+        self.header.nonce = 0;
+    }
+
+    // Helper: current Unix time
+    fn now() -> u32 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::pow::Miner;
+    use crate::core::TxOutput;
+
+    // Helper: work with a coinbase-only block
+    fn work() -> MiningWork {
+        let coinbase = Transaction::coinbase(b"Block 1".to_vec(), TxOutput::new(5000, vec![]), 1);
+        let header = BlockHeader::new(1, Hash256::zero(), Hash256::zero(), 1_000_000, 0x20ffffff, 0);
+        MiningWork::new(header, vec![coinbase]).unwrap()
+    }
+
+    // Helper: result of a backend that searched the whole nonce space in vain
+    fn exhausted() -> MiningResult {
+        MiningResult {
+            success: false,
+            nonce: 0,
+            hash: Hash256::zero(),
+            attempts: 10,
+            duration: Duration::from_millis(1),
+            cancelled: false,
+        }
+    }
+
+    #[test]
+    fn test_extra_nonce_changes_merkle_root() {
+        let mut work = work();
+        let script_sig = &work.transactions[0].inputs[0].script_sig;
+        assert_eq!(&script_sig[..7], b"Block 1");
+        assert_eq!(&script_sig[7..], &0u64.to_le_bytes());
+
+        let root = work.header.merkle_root;
+        work.increment_extra_nonce();
+
+        assert_eq!(work.extra_nonce, 1);
+        assert_eq!(&work.transactions[0].inputs[0].script_sig[7..], &1u64.to_le_bytes());
+        assert_ne!(work.header.merkle_root, root);
+        assert_eq!(work.header.merkle_root, Block::calculate_merkle_root(&work.transactions));
+    }
+
+    #[test]
+    fn test_update_time_bounds() {
+        let mut work = work();
+
+        // Never moves backwards
+        assert!(!work.update_time(999_000));
+        assert_eq!(work.header.timestamp, 1_000_000);
+
+        // Follows the clock forwards
+        assert!(work.update_time(1_000_500));
+        assert_eq!(work.header.timestamp, 1_000_500);
+
+        // Pulled back into the allowed window if it was too far ahead
+        work.header.timestamp = 2_000_000;
+        assert!(work.update_time(1_000_500));
+        assert_eq!(work.header.timestamp, 1_000_500 + MAX_FUTURE_BLOCK_TIME);
+    }
+
+    #[test]
+    fn test_mine_rolls_work_until_found() {
+        let mut work = work();
+        let miner = Miner::new(0x20ffffff);
+        let mut rounds = 0;
+
+        let result = work.mine(&CancelToken::new(), |header| {
+            rounds += 1;
+            if rounds < 3 { exhausted() } else { miner.mine(header) }
+        });
+
+        assert!(result.success);
+        assert_eq!(work.extra_nonce, 2);
+        assert!(result.attempts > 20);
+
+        // The finished block is internally consistent
+        let block = work.into_block();
+        assert_eq!(block.header.merkle_root, Block::calculate_merkle_root(&block.transactions));
+        assert!(miner.verify(&block.header));
+    }
+
+    #[test]
+    fn test_mine_stops_on_cancel() {
+        let mut work = work();
+        let cancel = CancelToken::new();
+
+        let result = work.mine(&cancel, |_| {
+            cancel.cancel();
+            exhausted()
+        });
+
+        assert!(!result.success);
+        assert!(result.cancelled);
+        assert_eq!(work.extra_nonce, 0);
+    }
+}
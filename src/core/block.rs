@@ -154,7 +154,7 @@ impl Block {
         );
         let coinbase_tx = Transaction::coinbase(coinbase_sig, coinbase_output, 0);
 
-        let merkle_root = Self::calculate_merkle_root(&[coinbase_tx.clone()]);
+        let merkle_root = Self::calculate_merkle_root(std::slice::from_ref(&coinbase_tx));
 
         // Use very easy difficulty for educational purposes
         let header = BlockHeader::new(
@@ -320,7 +320,7 @@ mod tests {
         let output = crate::core::TxOutput::new(5000000000, vec![1, 2, 3]);
         let tx = Transaction::coinbase(vec![4, 5, 6], output, 0);
 
-        let merkle = Block::calculate_merkle_root(&[tx.clone()]);
+        let merkle = Block::calculate_merkle_root(std::slice::from_ref(&tx));
         let expected = tx.txid();
         assert_eq!(merkle, expected);
     }
@@ -7,7 +7,7 @@ use crate::core::Hash256;
 /// hash256 = SHA256(SHA256(data))
 pub fn hash256(data: &[u8]) -> Hash256 {
     let first_hash = Sha256::digest(data);
-    let second_hash = Sha256::digest(&first_hash);
+    let second_hash = Sha256::digest(first_hash);
     Hash256::from_slice(&second_hash).expect("SHA256 always returns 32 bytes")
 }
 
@@ -23,7 +23,7 @@ pub fn sha256_hash(data: &[u8]) -> [u8; 32] {
 pub fn hash160(data: &[u8]) -> [u8; 20] {
     use ripemd::{Ripemd160, Digest as RipemdDigest};
     let sha = Sha256::digest(data);
-    let ripemd = Ripemd160::digest(&sha);
+    let ripemd = Ripemd160::digest(sha);
     let mut result = [0u8; 20];
     result.copy_from_slice(&ripemd);
     result
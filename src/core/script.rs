@@ -1,7 +1,20 @@
 // Bitcoin Script implementation (simplified for P2PKH)
 
 use crate::core::hash160;
-use secp256k1::{Secp256k1, Message, PublicKey, ecdsa::Signature};
+use secp256k1::{Secp256k1, Message, PublicKey, VerifyOnly, ecdsa::Signature};
+
+/// Script verification flags (bitmask)
+pub const SCRIPT_VERIFY_NONE: u32 = 0;
+
+/// Require 33-byte compressed public keys in scriptSigs (like segwit's
+/// WITNESS_PUBKEYTYPE); deployed as a soft fork through version bits
+pub const SCRIPT_VERIFY_COMPRESSED_PUBKEYS: u32 = 1 << 0;
+
+/// Flags applied to loose transactions: a superset of every consensus flag
+pub const STANDARD_SCRIPT_VERIFY_FLAGS: u32 = SCRIPT_VERIFY_COMPRESSED_PUBKEYS;
+
+/// Signature operations counted for each OP_CHECKMULTISIG(VERIFY)
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
 
 /// Opcodes for P2PKH script
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -74,6 +87,33 @@ impl Script {
         script_pubkey: &[u8],
         tx_hash: &[u8; 32],
     ) -> Result<bool, String> {
+        let secp = Secp256k1::verification_only();
+        Self::verify_p2pkh_with_context(&secp, script_sig, script_pubkey, tx_hash)
+    }
+
+    /// Verify a P2PKH script using a caller-provided secp256k1 context
+    /// Building a context is expensive, so batch verifiers share one across calls
+    pub fn verify_p2pkh_with_context(
+        secp: &Secp256k1<VerifyOnly>,
+        script_sig: &[u8],
+        script_pubkey: &[u8],
+        tx_hash: &[u8; 32],
+    ) -> Result<bool, String> {
+        Self::verify_p2pkh_with_checker(script_sig, script_pubkey, |signature, pubkey| {
+            Self::verify_signature(secp, signature, pubkey, tx_hash)
+        })
+    }
+
+    /// Verify a P2PKH script, delegating the ECDSA check to `check_sig(signature, pubkey)`
+    /// Lets callers put a signature cache in front of the expensive verification
+    pub fn verify_p2pkh_with_checker<F>(
+        script_sig: &[u8],
+        script_pubkey: &[u8],
+        check_sig: F,
+    ) -> Result<bool, String>
+    where
+        F: FnOnce(&[u8], &[u8]) -> Result<bool, String>,
+    {
         // Parse scriptSig
         let (signature, pubkey) = Self::parse_script_sig(script_sig)?;
 
@@ -87,7 +127,7 @@ impl Script {
         }
 
         // Step 2: Verify the signature
-        Self::verify_signature(&signature, &pubkey, tx_hash)
+        check_sig(&signature, &pubkey)
     }
 
     /// Parse scriptSig: <sig> <pubkey>
@@ -156,14 +196,61 @@ impl Script {
         Ok(pubkey_hash)
     }
 
-    /// Verify ECDSA signature
-    fn verify_signature(
+    /// Count legacy signature operations in a script
+    /// OP_CHECKSIG(VERIFY) counts 1, OP_CHECKMULTISIG(VERIFY) counts
+    /// MAX_PUBKEYS_PER_MULTISIG. Pushed data is skipped; counting stops at a
+    /// truncated push, like Bitcoin's GetSigOpCount.
+    pub fn count_sigops(script: &[u8]) -> usize {
+        let mut count = 0;
+        let mut pos = 0;
+
+        while pos < script.len() {
+            let opcode = script[pos];
+            pos += 1;
+
+            let push_len = match opcode {
+                0x01..=0x4b => opcode as usize,
+                // OP_PUSHDATA1 / 2 / 4: length prefix follows the opcode
+                0x4c..=0x4e => {
+                    let width = 1 << (opcode - 0x4c);
+                    let Some(bytes) = script.get(pos..pos + width) else {
+                        break;
+                    };
+                    pos += width;
+                    bytes.iter().rev().fold(0usize, |len, b| (len << 8) | *b as usize)
+                }
+                0xac | 0xad => {
+                    count += 1;
+                    0
+                }
+                0xae | 0xaf => {
+                    count += MAX_PUBKEYS_PER_MULTISIG;
+                    0
+                }
+                _ => 0,
+            };
+
+            if push_len > script.len() - pos {
+                break;
+            }
+            pos += push_len;
+        }
+
+        count
+    }
+
+    /// Whether a public key uses the 33-byte compressed encoding
+    pub fn is_compressed_pubkey(pubkey: &[u8]) -> bool {
+        pubkey.len() == 33 && (pubkey[0] == 0x02 || pubkey[0] == 0x03)
+    }
+
+    /// Verify ECDSA signature (DER-encoded) over a 32-byte message
+    pub fn verify_signature(
+        secp: &Secp256k1<VerifyOnly>,
         signature: &[u8],
         pubkey: &[u8],
         message: &[u8; 32],
     ) -> Result<bool, String> {
-        let secp = Secp256k1::verification_only();
-
         // Parse public key
         let pubkey = PublicKey::from_slice(pubkey)
             .map_err(|e| format!("Invalid public key: {}", e))?;
@@ -201,6 +288,19 @@ mod tests {
         assert_eq!(script[24], OpCode::OpCheckSig as u8);
     }
 
+    #[test]
+    fn test_count_sigops() {
+        let p2pkh = Script::p2pkh_script_pubkey(&[0xac; 20]);
+        // The 0xac bytes inside the pushed hash are data, not OP_CHECKSIG
+        assert_eq!(Script::count_sigops(&p2pkh), 1);
+
+        // OP_CHECKMULTISIG, OP_PUSHDATA1 <0xac 0xac>, OP_CHECKSIGVERIFY
+        assert_eq!(Script::count_sigops(&[0xae, 0x4c, 0x02, 0xac, 0xac, 0xad]), 21);
+
+        // Truncated push: the rest of the script is not counted
+        assert_eq!(Script::count_sigops(&[0xac, 0x05, 0xac]), 1);
+    }
+
     #[test]
     fn test_script_sig_creation() {
         let signature = vec![1, 2, 3, 4];
@@ -165,6 +165,16 @@ impl Transaction {
         hash256(&serialized)
     }
 
+    /// Calculate the hash that input signatures commit to
+    /// (simplified SIGHASH_ALL: txid of the transaction with every scriptSig cleared)
+    pub fn signature_hash(&self) -> Hash256 {
+        let mut unsigned = self.clone();
+        for input in &mut unsigned.inputs {
+            input.script_sig.clear();
+        }
+        unsigned.txid()
+    }
+
     /// Calculate total input value (requires UTXO set lookup in real impl)
     pub fn total_input_value(&self) -> u64 {
         // Note: In a real implementation, we'd need to look up the UTXO set
@@ -296,6 +306,20 @@ mod tests {
         assert_eq!(txid, txid2);
     }
 
+    #[test]
+    fn test_signature_hash_ignores_script_sig() {
+        let input = TxInput::new(Hash256::new([1; 32]), 0, vec![]);
+        let output = TxOutput::new(1000, vec![4, 5, 6]);
+        let unsigned = Transaction::new(vec![input], vec![output]);
+
+        let mut signed = unsigned.clone();
+        signed.inputs[0].script_sig = vec![7, 8, 9];
+
+        assert_eq!(unsigned.signature_hash(), unsigned.txid());
+        assert_eq!(signed.signature_hash(), unsigned.txid());
+        assert_ne!(signed.txid(), unsigned.txid());
+    }
+
     #[test]
     fn test_coinbase_transaction() {
         let output = TxOutput::new(5000000000, vec![1, 2, 3]);
@@ -1,6 +1,7 @@
 // Bitcoin Educational Implementation - CLI
 
 use bit_coin::{Cli, CliHandler};
+use bit_coin::cli::handle_stateless;
 use clap::Parser;
 
 fn main() {
@@ -8,6 +9,16 @@ fn main() {
 
     let cli = Cli::parse();
 
+    // Commands like stratum-mine run without opening ./data, so they can
+    // run next to a node that holds the database lock
+    if let Some(result) = handle_stateless(&cli) {
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Use data directory in current folder
     let data_dir = "./data";
 
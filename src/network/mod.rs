@@ -3,6 +3,7 @@
 mod message;
 mod peer;
 mod node;
+pub mod stratum;
 
 pub use message::{Message, MessageType, VersionMessage, InvMessage, InvType};
 pub use peer::{Peer, PeerInfo};
@@ -1,26 +1,26 @@
 // Network node - manages peer connections
 
 use crate::network::{Peer, PeerInfo, Message, InvMessage, InvType};
-use crate::core::{Block, Transaction};
-use crate::storage::Storage;
+use crate::core::{Block, Hash256, Transaction};
+use crate::storage::{KvBackend, SledBackend, Storage};
 use tokio::net::TcpListener;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 /// Network node
-pub struct Node {
+pub struct Node<B: KvBackend = SledBackend> {
     /// Node address
     pub addr: SocketAddr,
     /// Connected peers
     pub peers: Arc<RwLock<Vec<PeerInfo>>>,
     /// Storage
-    pub storage: Arc<RwLock<Storage>>,
+    pub storage: Arc<RwLock<Storage<B>>>,
 }
 
-impl Node {
+impl<B: KvBackend> Node<B> {
     /// Create a new node
-    pub fn new(addr: SocketAddr, storage: Storage) -> Self {
+    pub fn new(addr: SocketAddr, storage: Storage<B>) -> Self {
         Self {
             addr,
             peers: Arc::new(RwLock::new(Vec::new())),
@@ -81,7 +81,7 @@ impl Node {
         stream: tokio::net::TcpStream,
         addr: SocketAddr,
         peers: Arc<RwLock<Vec<PeerInfo>>>,
-        storage: Arc<RwLock<Storage>>,
+        storage: Arc<RwLock<Storage<B>>>,
     ) -> Result<(), String> {
         let mut peer = Peer::new(stream, addr);
 
@@ -113,6 +113,14 @@ impl Node {
                             // Handle inventory announcement
                             log::debug!("Received inv from {}: {} items", addr, inv.hashes.len());
                         }
+                        Message::GetData(inv) if inv.inv_type == InvType::Block => {
+                            for hash in &inv.hashes {
+                                match Self::block_for_peer(&*storage.read().await, hash)? {
+                                    Some(block) => peer.send_message(&Message::Block(block)).await?,
+                                    None => log::debug!("Not serving block {} to {}: not available", hash, addr),
+                                }
+                            }
+                        }
                         _ => {
                             log::debug!("Unhandled message type: {:?}", message.message_type());
                         }
@@ -131,6 +139,12 @@ impl Node {
         Ok(())
     }
 
+    /// Block to send a peer that asked for `hash`
+    /// Pruned blocks are never served: only their headers are kept.
+    pub fn block_for_peer(storage: &Storage<B>, hash: &Hash256) -> Result<Option<Block>, String> {
+        storage.blockchain.get_block(hash)
+    }
+
     /// Broadcast a block to all peers
     pub async fn broadcast_block(&self, block: &Block) -> Result<(), String> {
         let inv = InvMessage::new(InvType::Block, vec![block.hash()]);
@@ -180,4 +194,28 @@ mod tests {
 
         assert_eq!(node.addr, addr);
     }
+
+    #[test]
+    fn test_pruned_blocks_are_not_served() {
+        use crate::core::{BlockHeader, TxOutput};
+        use crate::storage::{BlockFiles, MemoryBackend, MIN_BLOCKS_TO_KEEP};
+
+        let mut storage = Storage::with_backend(MemoryBackend::new(), BlockFiles::memory().with_max_file_size(2048)).unwrap();
+        let mut prev = Hash256::zero();
+        let mut hashes = Vec::new();
+        for height in 0..MIN_BLOCKS_TO_KEEP + 20 {
+            let coinbase = Transaction::coinbase(vec![height as u8], TxOutput::new(50, vec![1]), height);
+            let merkle_root = Block::calculate_merkle_root(std::slice::from_ref(&coinbase));
+            let block = Block::new(BlockHeader::new(1, prev, merkle_root, height, 0x20ffffff, 0), vec![coinbase]);
+            storage.connect_block(&block, height).unwrap();
+            prev = block.hash();
+            hashes.push(prev);
+        }
+        storage.set_prune_target(Some(1));
+        storage.flush().unwrap();
+
+        assert!(storage.blockchain.get_index_entry(&hashes[0]).unwrap().unwrap().is_pruned());
+        assert_eq!(Node::block_for_peer(&storage, &hashes[0]).unwrap(), None);
+        assert_eq!(Node::block_for_peer(&storage, &prev).unwrap().map(|block| block.hash()), Some(prev));
+    }
 }
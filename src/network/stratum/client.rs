@@ -0,0 +1,321 @@
+// Stratum v1 miner (client side)
+//
+// Connects to a Stratum server, receives jobs and mines shares with the
+// local CPU miner. A reader thread processes server messages: a new job
+// with clean_jobs=true cancels the search in progress, so miners switch
+// to the new chain tip immediately. Each search round uses the next
+// extranonce2, so two rounds never hash the same header.
+
+use crate::consensus::pow::{CancelToken, ParallelMiner, Target};
+use crate::core::Hash256;
+use crate::network::stratum::job::{encode_u32, StratumJob};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Request ids of the handshake messages
+const SUBSCRIBE_ID: u64 = 1;
+const AUTHORIZE_ID: u64 = 2;
+
+/// Share counters seen by the miner
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShareStats {
+    /// Shares sent to the server
+    pub submitted: u64,
+    /// Shares the server accepted
+    pub accepted: u64,
+    /// Shares the server rejected
+    pub rejected: u64,
+}
+
+/// A share found by the local miner
+#[derive(Debug, Clone)]
+pub struct FoundShare {
+    /// Job the share belongs to
+    pub job_id: String,
+    /// Header hash
+    pub hash: Hash256,
+    /// Winning nonce
+    pub nonce: u32,
+    /// Hashes computed for this share
+    pub attempts: u64,
+}
+
+/// Session state updated by the reader thread
+#[derive(Default)]
+struct Session {
+    extranonce1: Option<Vec<u8>>,
+    extranonce2_size: usize,
+    difficulty: f64,
+    job: Option<Arc<StratumJob>>,
+    cancel: CancelToken,
+    error: Option<String>,
+    closed: bool,
+}
+
+/// State shared with the reader thread
+#[derive(Default)]
+struct Shared {
+    session: Mutex<Session>,
+    changed: Condvar,
+    stats: Mutex<ShareStats>,
+}
+
+/// Stratum v1 mining client
+pub struct StratumClient {
+    writer: Mutex<TcpStream>,
+    shared: Arc<Shared>,
+    worker: String,
+    next_id: AtomicU64,
+}
+
+impl StratumClient {
+    /// Connect, subscribe and authorize `worker`
+    /// The password may carry a share difficulty request ("d=0.01").
+    pub fn connect(addr: &str, worker: &str, password: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+        let reader = stream.try_clone().map_err(|e| e.to_string())?;
+
+        let shared = Arc::new(Shared {
+            session: Mutex::new(Session {
+                difficulty: 1.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let thread_shared = shared.clone();
+        std::thread::spawn(move || Self::read_loop(reader, &thread_shared));
+
+        let client = Self {
+            writer: Mutex::new(stream),
+            shared,
+            worker: worker.to_string(),
+            next_id: AtomicU64::new(AUTHORIZE_ID + 1),
+        };
+        client.send(SUBSCRIBE_ID, "mining.subscribe", json!(["bit-coin-miner"]))?;
+        client.send(AUTHORIZE_ID, "mining.authorize", json!([worker, password]))?;
+
+        // Wait for the subscription (extranonce1) before mining
+        let session = client.shared.session.lock().unwrap();
+        let (session, timeout) = client
+            .shared
+            .changed
+            .wait_timeout_while(session, Duration::from_secs(10), |s| {
+                s.extranonce1.is_none() && s.error.is_none() && !s.closed
+            })
+            .unwrap();
+        if let Some(error) = &session.error {
+            return Err(error.clone());
+        }
+        if timeout.timed_out() || session.closed {
+            return Err("Stratum server did not answer mining.subscribe".to_string());
+        }
+        drop(session);
+
+        Ok(client)
+    }
+
+    /// Mine shares until `stop` is cancelled or the connection drops
+    pub fn mine<F>(&self, threads: usize, stop: &CancelToken, mut on_share: F) -> Result<ShareStats, String>
+    where
+        F: FnMut(&FoundShare),
+    {
+        let mut extranonce2 = 0u64;
+
+        while !stop.is_cancelled() {
+            // Wait for a job, then snapshot everything needed for one round
+            let (job, extranonce1, extranonce2_size, difficulty, cancel) = {
+                let session = self.shared.session.lock().unwrap();
+                let mut session = self
+                    .shared
+                    .changed
+                    .wait_timeout_while(session, Duration::from_millis(200), |s| {
+                        s.job.is_none() && s.error.is_none() && !s.closed
+                    })
+                    .unwrap()
+                    .0;
+                if let Some(error) = &session.error {
+                    return Err(error.clone());
+                }
+                if session.closed {
+                    return Err("Connection closed by server".to_string());
+                }
+                let Some(job) = session.job.clone() else {
+                    continue;
+                };
+
+                session.cancel = CancelToken::new();
+                (
+                    job,
+                    session.extranonce1.clone().unwrap_or_default(),
+                    session.extranonce2_size,
+                    session.difficulty,
+                    session.cancel.clone(),
+                )
+            };
+
+            let extranonce2_bytes = extranonce2.to_be_bytes()[8 - extranonce2_size.min(8)..].to_vec();
+            extranonce2 = extranonce2.wrapping_add(1);
+
+            let mut header = job.header(&extranonce1, &extranonce2_bytes, job.time, 0);
+            let share_bits = Target::from_difficulty(difficulty).bits;
+            let result = ParallelMiner::new(share_bits, threads).mine(&mut header, &cancel, |_| {});
+
+            if result.success {
+                self.send(
+                    self.next_id.fetch_add(1, Ordering::Relaxed),
+                    "mining.submit",
+                    json!([
+                        self.worker,
+                        job.id,
+                        hex::encode(&extranonce2_bytes),
+                        encode_u32(header.timestamp),
+                        encode_u32(result.nonce),
+                    ]),
+                )?;
+                self.shared.stats.lock().unwrap().submitted += 1;
+
+                on_share(&FoundShare {
+                    job_id: job.id.clone(),
+                    hash: result.hash,
+                    nonce: result.nonce,
+                    attempts: result.attempts,
+                });
+            }
+        }
+
+        Ok(self.stats())
+    }
+
+    /// Share counters so far
+    pub fn stats(&self) -> ShareStats {
+        *self.shared.stats.lock().unwrap()
+    }
+
+    // Helper: send one JSON-RPC request
+    fn send(&self, id: u64, method: &str, params: Value) -> Result<(), String> {
+        let mut line = json!({ "id": id, "method": method, "params": params }).to_string();
+        line.push('\n');
+        self.writer
+            .lock()
+            .unwrap()
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("Failed to send {}: {}", method, e))
+    }
+
+    // Helper: process server messages until the connection closes
+    fn read_loop(stream: TcpStream, shared: &Shared) {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            match serde_json::from_str::<Value>(&line) {
+                Ok(message) => Self::handle_message(shared, &message),
+                Err(e) => log::warn!("Invalid message from Stratum server: {}", e),
+            }
+        }
+
+        let mut session = shared.session.lock().unwrap();
+        session.closed = true;
+        session.cancel.cancel();
+        shared.changed.notify_all();
+    }
+
+    // Helper: apply one server message to the session
+    fn handle_message(shared: &Shared, message: &Value) {
+        let mut session = shared.session.lock().unwrap();
+
+        match message["method"].as_str() {
+            Some("mining.set_difficulty") => {
+                if let Some(difficulty) = message["params"][0].as_f64() {
+                    log::info!("Share difficulty set to {}", difficulty);
+                    session.difficulty = difficulty;
+                }
+            }
+            Some("mining.notify") => match StratumJob::from_notify_params(&message["params"]) {
+                Ok((job, clean)) => {
+                    log::info!("New job {} (clean: {})", job.id, clean);
+                    session.job = Some(Arc::new(job));
+                    if clean {
+                        session.cancel.cancel();
+                    }
+                }
+                Err(e) => log::warn!("Ignoring malformed job: {}", e),
+            },
+            Some(method) => log::debug!("Ignoring Stratum notification '{}'", method),
+            None => match message["id"].as_u64() {
+                Some(SUBSCRIBE_ID) => {
+                    let result = &message["result"];
+                    match result[1].as_str().and_then(|hex_str| hex::decode(hex_str).ok()) {
+                        Some(extranonce1) => {
+                            session.extranonce1 = Some(extranonce1);
+                            session.extranonce2_size = result[2].as_u64().unwrap_or(4) as usize;
+                        }
+                        None => session.error = Some(format!("Subscription failed: {}", message["error"])),
+                    }
+                }
+                Some(AUTHORIZE_ID) if message["result"] != json!(true) => {
+                    session.error = Some(format!("Authorization failed: {}", message["error"]));
+                }
+                Some(AUTHORIZE_ID) => {}
+                Some(_) => {
+                    let mut stats = shared.stats.lock().unwrap();
+                    if message["result"] == json!(true) {
+                        stats.accepted += 1;
+                    } else {
+                        stats.rejected += 1;
+                        log::warn!("Share rejected: {}", message["error"]);
+                    }
+                }
+                None => {}
+            },
+        }
+
+        shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::block_assembly::BlockAssembler;
+    use crate::consensus::mempool::Mempool;
+    use crate::network::stratum::server::{StratumConfig, StratumServer};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_pooled_mining_finds_block() {
+        let config = StratumConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            share_difficulty: 1e-9,
+        };
+        let (server, mut found) = StratumServer::bind(config).await.unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+
+        let template = BlockAssembler::new().create_template(&Mempool::new(), Hash256::zero(), 1, 0x20ffffff, 1_000);
+        server.set_job(StratumJob::from_template("1".to_string(), &template, vec![0xac]).unwrap(), true);
+
+        let miner = tokio::task::spawn_blocking(move || {
+            let client = StratumClient::connect(&addr, "bob", "x").unwrap();
+            let stop = CancelToken::new();
+            client
+                .mine(1, &stop, |_| {
+                    if client.stats().submitted >= 2 {
+                        stop.cancel();
+                    }
+                })
+                .unwrap()
+        });
+
+        let block = tokio::select! {
+            _ = server.run() => unreachable!(),
+            block = found.recv() => block.unwrap(),
+        };
+        let stats = miner.await.unwrap();
+
+        assert_eq!(block.worker, "bob");
+        assert_eq!(block.block.header.prev_block_hash, Hash256::zero());
+        assert_eq!(stats.submitted, 2);
+        assert_eq!(server.worker_stats()[0].name, "bob");
+    }
+}
@@ -0,0 +1,318 @@
+// Stratum jobs and wire encodings
+//
+// A job is a block template with the coinbase split around the extranonce:
+//
+//   coinbase = coinb1 || extranonce1 || extranonce2 || coinb2
+//
+// extranonce1 is assigned per connection by the server, extranonce2 is
+// rolled by the miner, so no two miners ever hash the same header. The 8
+// extranonce bytes sit at the end of the coinbase scriptSig, the same place
+// `MiningWork` puts its extraNonce.
+//
+// The miner rebuilds the merkle root from the coinbase txid and the merkle
+// branch (the sibling hashes on the coinbase's path to the root) without
+// needing the other transactions.
+//
+// Encodings follow the usual Stratum v1 conventions:
+//   - prevhash: internal byte order with every 4-byte word byte-swapped
+//   - version, nbits, ntime, nonce: big-endian hex of the u32 value
+//   - coinb1, coinb2, extranonces, merkle branch: raw bytes as hex
+
+use crate::consensus::block_assembly::BlockTemplate;
+use crate::core::{hash256, write_varint, Block, BlockHeader, Hash256, Serializable, Transaction};
+use serde_json::{json, Value};
+
+/// Bytes of extranonce1 assigned to each connection
+pub const EXTRANONCE1_SIZE: usize = 4;
+/// Bytes of extranonce2 rolled by the miner
+pub const EXTRANONCE2_SIZE: usize = 4;
+
+/// Work handed out to Stratum miners
+#[derive(Debug, Clone)]
+pub struct StratumJob {
+    /// Job identifier (hex)
+    pub id: String,
+    /// Height of the block being mined
+    pub height: u32,
+    /// Block version
+    pub version: u32,
+    /// Previous block hash
+    pub prev_hash: Hash256,
+    /// Compact block target
+    pub bits: u32,
+    /// Block timestamp suggested by the server
+    pub time: u32,
+    /// Coinbase bytes before the extranonces
+    pub coinb1: Vec<u8>,
+    /// Coinbase bytes after the extranonces
+    pub coinb2: Vec<u8>,
+    /// Sibling hashes from the coinbase up to the merkle root
+    pub merkle_branch: Vec<Hash256>,
+    /// Non-coinbase transactions, in block order
+    pub transactions: Vec<Transaction>,
+}
+
+impl StratumJob {
+    /// Build a job from a block template, paying the reward to `script_pubkey`
+    pub fn from_template(id: String, template: &BlockTemplate, script_pubkey: Vec<u8>) -> Result<Self, String> {
+        // Coinbase with zeroed extranonce placeholders at the end of the scriptSig
+        let mut script_sig = format!("Block {}", template.height).into_bytes();
+        script_sig.extend_from_slice(&[0u8; EXTRANONCE1_SIZE + EXTRANONCE2_SIZE]);
+        let coinbase = template.coinbase_transaction(script_pubkey, script_sig.clone());
+
+        // version(4) + input count(1) + prev txid(32) + prev index(4) + scriptSig length
+        let mut script_len = Vec::new();
+        write_varint(&mut script_len, script_sig.len() as u64).map_err(|e| e.to_string())?;
+        let extranonce_offset = 4 + 1 + 32 + 4 + script_len.len() + script_sig.len()
+            - (EXTRANONCE1_SIZE + EXTRANONCE2_SIZE);
+
+        let serialized = coinbase.serialize();
+        let coinb1 = serialized[..extranonce_offset].to_vec();
+        let coinb2 = serialized[extranonce_offset + EXTRANONCE1_SIZE + EXTRANONCE2_SIZE..].to_vec();
+
+        let transactions: Vec<Transaction> = template.transactions.iter().map(|entry| entry.tx.clone()).collect();
+        let mut txids = vec![Hash256::zero()];
+        txids.extend(transactions.iter().map(|tx| tx.txid()));
+
+        Ok(Self {
+            id,
+            height: template.height,
+            version: template.version,
+            prev_hash: template.previous_block_hash,
+            bits: template.bits,
+            time: template.cur_time,
+            coinb1,
+            coinb2,
+            merkle_branch: merkle_branch(&txids),
+            transactions,
+        })
+    }
+
+    /// Serialized coinbase for the given extranonces
+    pub fn coinbase_bytes(&self, extranonce1: &[u8], extranonce2: &[u8]) -> Vec<u8> {
+        let mut bytes = self.coinb1.clone();
+        bytes.extend_from_slice(extranonce1);
+        bytes.extend_from_slice(extranonce2);
+        bytes.extend_from_slice(&self.coinb2);
+        bytes
+    }
+
+    /// Header for the given extranonces, timestamp and nonce
+    pub fn header(&self, extranonce1: &[u8], extranonce2: &[u8], time: u32, nonce: u32) -> BlockHeader {
+        let coinbase_txid = hash256(&self.coinbase_bytes(extranonce1, extranonce2));
+        BlockHeader::new(
+            self.version,
+            self.prev_hash,
+            merkle_root_from_branch(coinbase_txid, &self.merkle_branch),
+            time,
+            self.bits,
+            nonce,
+        )
+    }
+
+    /// Full block for a solved header
+    pub fn block(&self, extranonce1: &[u8], extranonce2: &[u8], time: u32, nonce: u32) -> Result<Block, String> {
+        let coinbase = Transaction::deserialize(&self.coinbase_bytes(extranonce1, extranonce2))?;
+        let mut transactions = vec![coinbase];
+        transactions.extend(self.transactions.iter().cloned());
+        Ok(Block::new(self.header(extranonce1, extranonce2, time, nonce), transactions))
+    }
+
+    /// `mining.notify` parameters
+    pub fn notify_params(&self, clean_jobs: bool) -> Value {
+        let branch: Vec<String> = self.merkle_branch.iter().map(|hash| hex::encode(hash.as_bytes())).collect();
+        json!([
+            self.id,
+            encode_prev_hash(&self.prev_hash),
+            hex::encode(&self.coinb1),
+            hex::encode(&self.coinb2),
+            branch,
+            encode_u32(self.version),
+            encode_u32(self.bits),
+            encode_u32(self.time),
+            clean_jobs,
+        ])
+    }
+
+    /// Parse `mining.notify` parameters (used by the miner side)
+    pub fn from_notify_params(params: &Value) -> Result<(Self, bool), String> {
+        let field = |index: usize| -> Result<&str, String> {
+            params[index]
+                .as_str()
+                .ok_or_else(|| format!("mining.notify: missing field {}", index))
+        };
+        let bytes = |index: usize| -> Result<Vec<u8>, String> {
+            hex::decode(field(index)?).map_err(|e| format!("mining.notify: field {}: {}", index, e))
+        };
+
+        let merkle_branch = params[4]
+            .as_array()
+            .ok_or("mining.notify: missing merkle branch")?
+            .iter()
+            .map(|hash| {
+                let bytes = hex::decode(hash.as_str().unwrap_or_default()).map_err(|e| e.to_string())?;
+                Hash256::from_slice(&bytes)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let job = Self {
+            id: field(0)?.to_string(),
+            height: 0,
+            version: decode_u32(field(5)?)?,
+            prev_hash: decode_prev_hash(field(1)?)?,
+            bits: decode_u32(field(6)?)?,
+            time: decode_u32(field(7)?)?,
+            coinb1: bytes(2)?,
+            coinb2: bytes(3)?,
+            merkle_branch,
+            transactions: Vec::new(),
+        };
+        Ok((job, params[8].as_bool().unwrap_or(false)))
+    }
+}
+
+/// Merkle branch for the first leaf (the coinbase) of a transaction list
+/// The value of `txids[0]` is irrelevant: only its siblings are collected.
+pub fn merkle_branch(txids: &[Hash256]) -> Vec<Hash256> {
+    let mut branch = Vec::new();
+    let mut level = txids.to_vec();
+
+    while level.len() > 1 {
+        // Sibling of the leftmost node on this level
+        branch.push(level[1]);
+
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&pair[0]);
+                let mut combined = Vec::with_capacity(64);
+                combined.extend_from_slice(pair[0].as_bytes());
+                combined.extend_from_slice(right.as_bytes());
+                hash256(&combined)
+            })
+            .collect();
+    }
+
+    branch
+}
+
+/// Merkle root from the coinbase txid and its branch
+pub fn merkle_root_from_branch(coinbase_txid: Hash256, branch: &[Hash256]) -> Hash256 {
+    branch.iter().fold(coinbase_txid, |root, sibling| {
+        let mut combined = Vec::with_capacity(64);
+        combined.extend_from_slice(root.as_bytes());
+        combined.extend_from_slice(sibling.as_bytes());
+        hash256(&combined)
+    })
+}
+
+/// Encode a previous block hash for `mining.notify`
+pub fn encode_prev_hash(hash: &Hash256) -> String {
+    hex::encode(swap_words(hash.as_bytes()))
+}
+
+/// Decode a `mining.notify` previous block hash
+pub fn decode_prev_hash(encoded: &str) -> Result<Hash256, String> {
+    let bytes = hex::decode(encoded).map_err(|e| format!("Invalid prevhash: {}", e))?;
+    let hash = Hash256::from_slice(&bytes)?;
+    Ok(Hash256::new(swap_words(hash.as_bytes())))
+}
+
+/// Encode a u32 header field as big-endian hex
+pub fn encode_u32(value: u32) -> String {
+    format!("{:08x}", value)
+}
+
+/// Decode a big-endian hex u32 header field
+pub fn decode_u32(encoded: &str) -> Result<u32, String> {
+    if encoded.len() != 8 {
+        return Err(format!("Expected 8 hex digits, got '{}'", encoded));
+    }
+    u32::from_str_radix(encoded, 16).map_err(|e| format!("Invalid hex u32 '{}': {}", encoded, e))
+}
+
+// Helper: reverse the bytes of every 4-byte word
+fn swap_words(bytes: &[u8; 32]) -> [u8; 32] {
+    let mut swapped = *bytes;
+    for word in swapped.chunks_exact_mut(4) {
+        word.reverse();
+    }
+    swapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::block_assembly::BlockAssembler;
+    use crate::consensus::mempool::Mempool;
+    use crate::core::{TxInput, TxOutput};
+    use crate::storage::{OutPoint, Utxo, UtxoSet};
+
+    // Helper: template with `count` mempool transactions
+    fn template(count: u8) -> BlockTemplate {
+        let utxo_set = UtxoSet::memory().unwrap();
+        let mut mempool = Mempool::new();
+        for i in 0..count {
+            let outpoint = OutPoint::new(Hash256::new([i + 1; 32]), 0);
+            utxo_set
+                .add_utxo(&outpoint, &Utxo::new(TxOutput::new(10_000, vec![]), 1, false))
+                .unwrap();
+            let tx = Transaction::new(
+                vec![TxInput::new(outpoint.txid, 0, vec![])],
+                vec![TxOutput::new(9_000, vec![])],
+            );
+            mempool.add(tx, &utxo_set).unwrap();
+        }
+        BlockAssembler::new().create_template(&mempool, Hash256::new([3; 32]), 7, 0x207fffff, 1_000)
+    }
+
+    #[test]
+    fn test_merkle_branch_matches_merkle_root() {
+        for count in 0..6 {
+            let job = StratumJob::from_template("1".to_string(), &template(count), vec![0xac]).unwrap();
+            let block = job.block(&[1, 2, 3, 4], &[5, 6, 7, 8], job.time, 0).unwrap();
+
+            assert_eq!(block.transactions.len(), count as usize + 1);
+            assert_eq!(block.header.merkle_root, Block::calculate_merkle_root(&block.transactions));
+        }
+    }
+
+    #[test]
+    fn test_coinbase_split_matches_mining_work() {
+        let template = template(1);
+        let job = StratumJob::from_template("1".to_string(), &template, vec![0xac]).unwrap();
+        let block = job.block(&[0; 4], &[0; 4], job.time, 0).unwrap();
+
+        // Zero extranonces give the same coinbase as MiningWork's extraNonce 0
+        let work = template.to_work(vec![0xac]).unwrap();
+        assert_eq!(block.transactions, work.transactions);
+        assert_eq!(block.header, work.header);
+    }
+
+    #[test]
+    fn test_notify_round_trip() {
+        let job = StratumJob::from_template("2a".to_string(), &template(3), vec![0xac]).unwrap();
+        let (parsed, clean) = StratumJob::from_notify_params(&job.notify_params(true)).unwrap();
+
+        assert!(clean);
+        assert_eq!(parsed.id, "2a");
+        assert_eq!(parsed.prev_hash, job.prev_hash);
+        assert_eq!(parsed.bits, 0x207fffff);
+        assert_eq!(parsed.merkle_branch, job.merkle_branch);
+        assert_eq!(
+            parsed.header(&[9; 4], &[8; 4], 1_234, 5),
+            job.header(&[9; 4], &[8; 4], 1_234, 5)
+        );
+    }
+
+    #[test]
+    fn test_prev_hash_encoding() {
+        let mut bytes = [0u8; 32];
+        bytes[..4].copy_from_slice(&[1, 2, 3, 4]);
+        let hash = Hash256::new(bytes);
+
+        assert!(encode_prev_hash(&hash).starts_with("04030201"));
+        assert_eq!(decode_prev_hash(&encode_prev_hash(&hash)).unwrap(), hash);
+        assert_eq!(decode_u32(&encode_u32(0x1d00ffff)).unwrap(), 0x1d00ffff);
+    }
+}
@@ -0,0 +1,9 @@
+// Stratum v1 mining protocol (server for external miners, CPU miner client)
+
+mod job;
+mod server;
+mod client;
+
+pub use job::{StratumJob, EXTRANONCE1_SIZE, EXTRANONCE2_SIZE};
+pub use server::{StratumServer, StratumConfig, FoundBlock, WorkerStats};
+pub use client::{StratumClient, ShareStats, FoundShare};
@@ -0,0 +1,522 @@
+// Stratum v1 server
+//
+// Newline-delimited JSON-RPC over TCP. Supported methods:
+//   mining.subscribe          -> extranonce1 for this connection, extranonce2 size
+//   mining.authorize          -> registers a worker; password "d=<difficulty>"
+//                                picks the share difficulty
+//   mining.suggest_difficulty -> changes the share difficulty
+//   mining.submit             -> share check against the worker's share target,
+//                                block check against the job's nBits
+// and the notifications mining.set_difficulty and mining.notify.
+//
+// The node owns the chain: it pushes jobs with `set_job` and receives
+// solved blocks from the channel returned by `bind`.
+
+use crate::consensus::pow::Target;
+use crate::consensus::validation::MAX_FUTURE_BLOCK_TIME;
+use crate::core::Block;
+use crate::network::stratum::job::{decode_u32, StratumJob, EXTRANONCE2_SIZE};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+
+/// Jobs kept for late share submissions
+const MAX_JOBS: usize = 8;
+
+/// Stratum error codes
+const ERR_OTHER: i64 = 20;
+const ERR_JOB_NOT_FOUND: i64 = 21;
+const ERR_DUPLICATE_SHARE: i64 = 22;
+const ERR_LOW_DIFFICULTY: i64 = 23;
+const ERR_UNAUTHORIZED: i64 = 24;
+const ERR_NOT_SUBSCRIBED: i64 = 25;
+
+/// Server settings
+#[derive(Debug, Clone)]
+pub struct StratumConfig {
+    /// Address to listen on
+    pub listen: SocketAddr,
+    /// Share difficulty for workers that do not ask for one
+    pub share_difficulty: f64,
+}
+
+/// A block solved by a worker
+#[derive(Debug, Clone)]
+pub struct FoundBlock {
+    /// The solved block
+    pub block: Block,
+    /// Worker that found it
+    pub worker: String,
+}
+
+/// Share counters of one worker
+#[derive(Debug, Clone, Default)]
+pub struct WorkerStats {
+    /// Worker name (from mining.authorize)
+    pub name: String,
+    /// Current share difficulty
+    pub difficulty: f64,
+    /// Valid shares
+    pub accepted: u64,
+    /// Invalid, stale or duplicate shares
+    pub rejected: u64,
+    /// Shares that were also valid blocks
+    pub blocks: u64,
+}
+
+/// Identity of a submitted share: job, extranonce1, extranonce2, ntime, nonce
+type ShareKey = (String, Vec<u8>, Vec<u8>, u32, u32);
+
+/// Current and recent jobs plus shares already seen for them
+#[derive(Default)]
+struct JobBook {
+    jobs: VecDeque<Arc<StratumJob>>,
+    shares: HashSet<ShareKey>,
+}
+
+/// State shared by all connections
+struct Shared {
+    share_difficulty: f64,
+    jobs: Mutex<JobBook>,
+    updates: broadcast::Sender<(Arc<StratumJob>, bool)>,
+    blocks: mpsc::UnboundedSender<FoundBlock>,
+    next_extranonce1: AtomicU32,
+    workers: Mutex<HashMap<String, WorkerStats>>,
+}
+
+/// Per-connection protocol state
+struct Session {
+    extranonce1: Vec<u8>,
+    subscribed: bool,
+    workers: HashSet<String>,
+    difficulty: f64,
+}
+
+/// Stratum v1 server handing out jobs to external miners
+pub struct StratumServer {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+impl StratumServer {
+    /// Bind the listening socket
+    /// Returns the server and the channel on which solved blocks arrive.
+    pub async fn bind(config: StratumConfig) -> Result<(Self, mpsc::UnboundedReceiver<FoundBlock>), String> {
+        let listener = TcpListener::bind(config.listen)
+            .await
+            .map_err(|e| format!("Failed to bind Stratum server: {}", e))?;
+
+        let (blocks, found) = mpsc::unbounded_channel();
+        let (updates, _) = broadcast::channel(16);
+        let shared = Arc::new(Shared {
+            share_difficulty: config.share_difficulty,
+            jobs: Mutex::new(JobBook::default()),
+            updates,
+            blocks,
+            next_extranonce1: AtomicU32::new(1),
+            workers: Mutex::new(HashMap::new()),
+        });
+
+        Ok((Self { listener, shared }, found))
+    }
+
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    /// Publish a new job to every connected miner
+    /// `clean` invalidates older jobs (e.g. the chain tip changed).
+    pub fn set_job(&self, job: StratumJob, clean: bool) {
+        self.shared.set_job(job, clean);
+    }
+
+    /// Share statistics of every worker seen so far
+    pub fn worker_stats(&self) -> Vec<WorkerStats> {
+        let mut stats: Vec<WorkerStats> = self.shared.workers.lock().unwrap().values().cloned().collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    /// Accept connections until an error occurs
+    pub async fn run(&self) -> Result<(), String> {
+        log::info!("Stratum server listening on {}", self.local_addr()?);
+
+        loop {
+            let (stream, addr) = self
+                .listener
+                .accept()
+                .await
+                .map_err(|e| format!("Failed to accept connection: {}", e))?;
+
+            log::info!("Stratum miner connected from {}", addr);
+            let shared = self.shared.clone();
+
+            tokio::spawn(async move {
+                if let Err(e) = shared.handle_connection(stream).await {
+                    log::warn!("Stratum connection {} closed: {}", addr, e);
+                }
+                log::info!("Stratum miner {} disconnected", addr);
+            });
+        }
+    }
+}
+
+impl Shared {
+    fn set_job(&self, job: StratumJob, clean: bool) {
+        let job = Arc::new(job);
+        {
+            let mut book = self.jobs.lock().unwrap();
+            if clean {
+                *book = JobBook::default();
+            }
+            book.jobs.push_back(job.clone());
+            while book.jobs.len() > MAX_JOBS {
+                book.jobs.pop_front();
+            }
+        }
+        // No receivers just means no miner is connected yet
+        let _ = self.updates.send((job, clean));
+    }
+
+    fn current_job(&self) -> Option<Arc<StratumJob>> {
+        self.jobs.lock().unwrap().jobs.back().cloned()
+    }
+
+    // Helper: serve one miner until it disconnects
+    async fn handle_connection(&self, stream: TcpStream) -> Result<(), String> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut updates = self.updates.subscribe();
+
+        let extranonce1 = self.next_extranonce1.fetch_add(1, Ordering::Relaxed);
+        let mut session = Session {
+            extranonce1: extranonce1.to_be_bytes().to_vec(),
+            subscribed: false,
+            workers: HashSet::new(),
+            difficulty: self.share_difficulty,
+        };
+
+        loop {
+            let messages = tokio::select! {
+                line = lines.next_line() => match line.map_err(|e| e.to_string())? {
+                    Some(line) if line.trim().is_empty() => continue,
+                    Some(line) => self.handle_line(&mut session, &line),
+                    None => return Ok(()),
+                },
+                update = updates.recv() => match update {
+                    Ok((job, clean)) if !session.workers.is_empty() => {
+                        vec![notification("mining.notify", job.notify_params(clean))]
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+            };
+
+            for message in messages {
+                let mut line = message.to_string();
+                line.push('\n');
+                writer.write_all(line.as_bytes()).await.map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    // Helper: handle one JSON-RPC request, returning the messages to send back
+    fn handle_line(&self, session: &mut Session, line: &str) -> Vec<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return vec![error_response(Value::Null, ERR_OTHER, &format!("Invalid JSON: {}", e))],
+        };
+        let id = request["id"].clone();
+        let params = &request["params"];
+
+        match request["method"].as_str().unwrap_or_default() {
+            "mining.subscribe" => {
+                session.subscribed = true;
+                let subscription = hex::encode(&session.extranonce1);
+                vec![response(
+                    id,
+                    json!([
+                        [["mining.set_difficulty", subscription], ["mining.notify", subscription]],
+                        hex::encode(&session.extranonce1),
+                        EXTRANONCE2_SIZE,
+                    ]),
+                )]
+            }
+            "mining.authorize" => {
+                if !session.subscribed {
+                    return vec![error_response(id, ERR_NOT_SUBSCRIBED, "Not subscribed")];
+                }
+                let worker = params[0].as_str().unwrap_or_default().to_string();
+                if worker.is_empty() {
+                    return vec![error_response(id, ERR_UNAUTHORIZED, "Missing worker name")];
+                }
+
+                // "d=<difficulty>" in the password requests a share difficulty
+                if let Some(difficulty) = params[1]
+                    .as_str()
+                    .and_then(|password| password.strip_prefix("d="))
+                    .and_then(|d| d.parse::<f64>().ok())
+                    .filter(|d| *d > 0.0)
+                {
+                    session.difficulty = difficulty;
+                }
+
+                session.workers.insert(worker.clone());
+                self.update_worker(&worker, |stats| stats.difficulty = session.difficulty);
+                log::info!("Stratum worker '{}' authorized (difficulty {})", worker, session.difficulty);
+
+                let mut messages = vec![
+                    response(id, json!(true)),
+                    notification("mining.set_difficulty", json!([session.difficulty])),
+                ];
+                if let Some(job) = self.current_job() {
+                    messages.push(notification("mining.notify", job.notify_params(true)));
+                }
+                messages
+            }
+            "mining.suggest_difficulty" => match params[0].as_f64().filter(|d| *d > 0.0) {
+                Some(difficulty) => {
+                    session.difficulty = difficulty;
+                    for worker in &session.workers {
+                        self.update_worker(worker, |stats| stats.difficulty = difficulty);
+                    }
+                    vec![
+                        response(id, json!(true)),
+                        notification("mining.set_difficulty", json!([difficulty])),
+                    ]
+                }
+                None => vec![error_response(id, ERR_OTHER, "Invalid difficulty")],
+            },
+            "mining.submit" => {
+                let worker = params[0].as_str().unwrap_or_default().to_string();
+                match self.submit(session, &worker, params) {
+                    Ok(()) => {
+                        self.update_worker(&worker, |stats| stats.accepted += 1);
+                        vec![response(id, json!(true))]
+                    }
+                    Err((code, message)) => {
+                        if session.workers.contains(&worker) {
+                            self.update_worker(&worker, |stats| stats.rejected += 1);
+                        }
+                        log::debug!("Rejected share from '{}': {}", worker, message);
+                        vec![error_response(id, code, &message)]
+                    }
+                }
+            }
+            method => vec![error_response(id, ERR_OTHER, &format!("Unknown method '{}'", method))],
+        }
+    }
+
+    // Helper: validate a share and forward it if it solves the block
+    fn submit(&self, session: &Session, worker: &str, params: &Value) -> Result<(), (i64, String)> {
+        if !session.workers.contains(worker) {
+            return Err((ERR_UNAUTHORIZED, "Unauthorized worker".to_string()));
+        }
+
+        let field = |index: usize| params[index].as_str().unwrap_or_default();
+        let invalid = |e: String| (ERR_OTHER, e);
+
+        let job_id = field(1);
+        let extranonce2 = hex::decode(field(2)).map_err(|e| invalid(format!("Invalid extranonce2: {}", e)))?;
+        let time = decode_u32(field(3)).map_err(invalid)?;
+        let nonce = decode_u32(field(4)).map_err(invalid)?;
+
+        if extranonce2.len() != EXTRANONCE2_SIZE {
+            return Err(invalid(format!("extranonce2 must be {} bytes", EXTRANONCE2_SIZE)));
+        }
+
+        let mut book = self.jobs.lock().unwrap();
+        let job = book
+            .jobs
+            .iter()
+            .find(|job| job.id == job_id)
+            .cloned()
+            .ok_or((ERR_JOB_NOT_FOUND, "Job not found".to_string()))?;
+
+        if time < job.time || time > now().saturating_add(MAX_FUTURE_BLOCK_TIME) {
+            return Err(invalid("ntime out of range".to_string()));
+        }
+
+        let share = (job.id.clone(), session.extranonce1.clone(), extranonce2.clone(), time, nonce);
+        if !book.shares.insert(share) {
+            return Err((ERR_DUPLICATE_SHARE, "Duplicate share".to_string()));
+        }
+        drop(book);
+
+        let header = job.header(&session.extranonce1, &extranonce2, time, nonce);
+        let hash = header.hash();
+        if !Target::from_difficulty(session.difficulty).is_valid_hash(&hash) {
+            return Err((ERR_LOW_DIFFICULTY, "Low difficulty share".to_string()));
+        }
+
+        if Target::from_bits(job.bits).is_valid_hash(&hash) {
+            let block = job
+                .block(&session.extranonce1, &extranonce2, time, nonce)
+                .map_err(invalid)?;
+            log::info!("Worker '{}' found block {} at height {}", worker, hash, job.height);
+            self.update_worker(worker, |stats| stats.blocks += 1);
+            let _ = self.blocks.send(FoundBlock {
+                block,
+                worker: worker.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    // Helper: update (creating if needed) a worker's statistics
+    fn update_worker(&self, worker: &str, update: impl FnOnce(&mut WorkerStats)) {
+        let mut workers = self.workers.lock().unwrap();
+        let stats = workers.entry(worker.to_string()).or_insert_with(|| WorkerStats {
+            name: worker.to_string(),
+            difficulty: self.share_difficulty,
+            ..Default::default()
+        });
+        update(stats);
+    }
+}
+
+// Helper: JSON-RPC success response
+fn response(id: Value, result: Value) -> Value {
+    json!({ "id": id, "result": result, "error": null })
+}
+
+// Helper: JSON-RPC error response in Stratum's [code, message, traceback] form
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "id": id, "result": null, "error": [code, message, null] })
+}
+
+// Helper: server-to-client notification
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "id": null, "method": method, "params": params })
+}
+
+// Helper: current Unix time
+fn now() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::block_assembly::BlockAssembler;
+    use crate::consensus::mempool::Mempool;
+    use crate::core::Hash256;
+    use crate::network::stratum::job::encode_u32;
+
+    // Helper: shared state with one job at the given block difficulty
+    fn shared(bits: u32) -> (Shared, mpsc::UnboundedReceiver<FoundBlock>) {
+        let (blocks, found) = mpsc::unbounded_channel();
+        let shared = Shared {
+            share_difficulty: 1.0,
+            jobs: Mutex::new(JobBook::default()),
+            updates: broadcast::channel(4).0,
+            blocks,
+            next_extranonce1: AtomicU32::new(1),
+            workers: Mutex::new(HashMap::new()),
+        };
+        let template = BlockAssembler::new().create_template(&Mempool::new(), Hash256::zero(), 1, bits, now());
+        shared.set_job(StratumJob::from_template("1".to_string(), &template, vec![0xac]).unwrap(), true);
+        (shared, found)
+    }
+
+    // Helper: subscribed and authorized session
+    fn session(shared: &Shared, password: &str) -> Session {
+        let mut session = Session {
+            extranonce1: vec![0, 0, 0, 1],
+            subscribed: false,
+            workers: HashSet::new(),
+            difficulty: shared.share_difficulty,
+        };
+        shared.handle_line(&mut session, r#"{"id":1,"method":"mining.subscribe","params":[]}"#);
+        let authorize = json!({"id": 2, "method": "mining.authorize", "params": ["alice", password]});
+        shared.handle_line(&mut session, &authorize.to_string());
+        session
+    }
+
+    // Helper: submit a share and return the response
+    fn submit(shared: &Shared, session: &mut Session, job: &str, extranonce2: &str, nonce: u32) -> Value {
+        let job_time = shared.current_job().unwrap().time;
+        let request = json!({
+            "id": 4,
+            "method": "mining.submit",
+            "params": ["alice", job, extranonce2, encode_u32(job_time), encode_u32(nonce)],
+        });
+        shared.handle_line(session, &request.to_string()).remove(0)
+    }
+
+    #[test]
+    fn test_subscribe_and_authorize() {
+        let (shared, _) = shared(0x207fffff);
+        let mut session = Session {
+            extranonce1: vec![0xab, 0xcd, 0, 1],
+            subscribed: false,
+            workers: HashSet::new(),
+            difficulty: 1.0,
+        };
+
+        // Authorizing before subscribing is an error
+        let reply = shared.handle_line(&mut session, r#"{"id":1,"method":"mining.authorize","params":["a","x"]}"#);
+        assert_eq!(reply[0]["error"][0], ERR_NOT_SUBSCRIBED);
+
+        let reply = shared.handle_line(&mut session, r#"{"id":2,"method":"mining.subscribe","params":[]}"#);
+        assert_eq!(reply[0]["result"][1], "abcd0001");
+        assert_eq!(reply[0]["result"][2], EXTRANONCE2_SIZE);
+
+        // Authorize answers, then sends the difficulty and the current job
+        let reply = shared.handle_line(&mut session, r#"{"id":3,"method":"mining.authorize","params":["a","d=0.5"]}"#);
+        assert_eq!(reply[0]["result"], true);
+        assert_eq!(reply[1]["method"], "mining.set_difficulty");
+        assert_eq!(reply[1]["params"][0], 0.5);
+        assert_eq!(reply[2]["method"], "mining.notify");
+        assert_eq!(reply[2]["params"][0], "1");
+    }
+
+    #[test]
+    fn test_submit_rejects_bad_shares() {
+        // Difficulty 1 shares are out of reach for a single nonce
+        let (shared, mut found) = shared(0x1d00ffff);
+        let mut session = session(&shared, "x");
+
+        let reply = submit(&shared, &mut session, "1", "00000000", 0);
+        assert_eq!(reply["error"][0], ERR_LOW_DIFFICULTY);
+
+        let reply = submit(&shared, &mut session, "1", "00000000", 0);
+        assert_eq!(reply["error"][0], ERR_DUPLICATE_SHARE);
+
+        let reply = submit(&shared, &mut session, "ff", "00000000", 1);
+        assert_eq!(reply["error"][0], ERR_JOB_NOT_FOUND);
+
+        let reply = submit(&shared, &mut session, "1", "00", 1);
+        assert_eq!(reply["error"][0], ERR_OTHER);
+
+        let stats = &shared.workers.lock().unwrap()["alice"];
+        assert_eq!((stats.accepted, stats.rejected), (0, 4));
+        assert!(found.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_block_solving_share_is_forwarded() {
+        // Trivial block and share targets: the first nonce solves both
+        let (shared, mut found) = shared(0x20ffffff);
+        let mut session = session(&shared, "d=0.000000001");
+
+        let reply = submit(&shared, &mut session, "1", "0000002a", 0);
+        assert_eq!(reply["result"], true);
+
+        let found = found.try_recv().unwrap();
+        assert_eq!(found.worker, "alice");
+        assert_eq!(found.block.header.merkle_root, Block::calculate_merkle_root(&found.block.transactions));
+        assert_eq!(&found.block.transactions[0].inputs[0].script_sig[7..], &[0, 0, 0, 1, 0, 0, 0, 0x2a]);
+        assert_eq!(shared.workers.lock().unwrap()["alice"].blocks, 1);
+    }
+}
@@ -0,0 +1,189 @@
+// Address history: every transaction that paid to or spent from a script
+//
+// Keys are SHA-256(scriptPubKey) + height (BE) + position (BE), so a prefix
+// scan returns one address's history in chain order. Each entry records the
+// txid and how much the transaction received by and spent from the script.
+// The history is updated with the block in the same transaction, using the
+// undo data for the spent side. If it falls behind the tip (a chain written
+// by an older version), `rebuild` replays the chain from genesis.
+
+use super::{
+    decode_index_best, encode_index_best, index_extends, BlockchainDB, KvBackend, KvBatch, KvTree, OutPoint, SledBackend, Utxo,
+    INDEX_BEST_KEY,
+};
+use crate::core::{sha256_hash, Block, Hash256};
+use std::collections::{BTreeMap, HashMap};
+
+/// One transaction in an address's history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressHistoryEntry {
+    pub txid: Hash256,
+    pub height: u32,
+    /// Index of the transaction inside its block
+    pub position: u32,
+    /// Satoshis paid to the script by this transaction
+    pub received: u64,
+    /// Satoshis spent from the script by this transaction
+    pub spent: u64,
+}
+
+/// Address history database
+pub struct AddressIndex<B: KvBackend = SledBackend> {
+    backend: B,
+    db: B::Tree,
+}
+
+impl<B: KvBackend> AddressIndex<B> {
+    /// Use one tree of a shared database
+    pub(super) fn from_tree(backend: B, db: B::Tree) -> Self {
+        Self { backend, db }
+    }
+
+    /// History of a script in chain order
+    pub fn history(&self, script_pubkey: &[u8]) -> Result<Vec<AddressHistoryEntry>, String> {
+        let mut entries = Vec::new();
+
+        for item in self.db.scan_prefix(&sha256_hash(script_pubkey)) {
+            let (key, value) = item?;
+            if key.len() != 40 || value.len() != 48 {
+                return Err("Invalid address history entry".to_string());
+            }
+
+            entries.push(AddressHistoryEntry {
+                txid: Hash256::new(value[0..32].try_into().unwrap()),
+                height: u32::from_be_bytes(key[32..36].try_into().unwrap()),
+                position: u32::from_be_bytes(key[36..40].try_into().unwrap()),
+                received: u64::from_le_bytes(value[32..40].try_into().unwrap()),
+                spent: u64::from_le_bytes(value[40..48].try_into().unwrap()),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Last block in the history as (hash, height)
+    pub fn best_block(&self) -> Result<Option<(Hash256, u32)>, String> {
+        match self.db.get(INDEX_BEST_KEY)? {
+            Some(data) => Ok(Some(decode_index_best(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove every history entry
+    pub(super) fn clear(&self) -> Result<(), String> {
+        self.db.clear().map_err(|e| format!("Failed to clear address history: {}", e))
+    }
+
+    /// Clear the history and replay the active chain from genesis
+    /// Returns the number of blocks replayed. Spent outputs are tracked in
+    /// memory, so blocks without undo data can be replayed too.
+    pub fn rebuild(&self, blocks: &BlockchainDB<B>) -> Result<u32, String> {
+        self.clear()?;
+
+        let chain_height = blocks.get_chain_height()?;
+        let mut outputs: HashMap<OutPoint, Utxo> = HashMap::new();
+
+        for height in 0..chain_height {
+            let block = blocks
+                .get_block_by_height(height)?
+                .ok_or_else(|| format!("Block at height {} is missing from storage", height))?;
+
+            let mut spent = Vec::new();
+            for tx in &block.transactions {
+                if !tx.is_coinbase() {
+                    for input in &tx.inputs {
+                        let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+                        let utxo = outputs
+                            .remove(&outpoint)
+                            .ok_or_else(|| format!("Block at height {} spends unknown output {}:{}", height, outpoint.txid, outpoint.vout))?;
+                        spent.push(utxo);
+                    }
+                }
+                let txid = tx.txid();
+                for (vout, output) in tx.outputs.iter().enumerate() {
+                    outputs.insert(OutPoint::new(txid, vout as u32), Utxo::new(output.clone(), height, tx.is_coinbase()));
+                }
+            }
+
+            self.backend
+                .transaction(|tx| self.connect(tx, &block, height, &spent))
+                .map_err(|e| format!("Failed to index block: {}", e))?;
+        }
+
+        Ok(chain_height)
+    }
+
+    /// Add the history entries of `block` if the index stands at its parent
+    /// `spent` lists the outputs spent by the block's inputs, in order.
+    pub(super) fn connect(&self, tx: &mut KvBatch<B::Tree>, block: &Block, height: u32, spent: &[Utxo]) -> Result<bool, String> {
+        if !index_extends(tx, &self.db, block, height)? {
+            return Ok(false);
+        }
+
+        for (key, entry) in Self::entries(block, height, spent) {
+            let mut value = Vec::with_capacity(48);
+            value.extend_from_slice(entry.txid.as_bytes());
+            value.extend_from_slice(&entry.received.to_le_bytes());
+            value.extend_from_slice(&entry.spent.to_le_bytes());
+            tx.put(&self.db, &key, &value);
+        }
+        tx.put(&self.db, INDEX_BEST_KEY, &encode_index_best(&block.hash(), height));
+
+        Ok(true)
+    }
+
+    /// Remove the history entries of `block` if it is the last block in the index
+    pub(super) fn disconnect(&self, tx: &mut KvBatch<B::Tree>, block: &Block, height: u32, spent: &[Utxo]) -> Result<(), String> {
+        let best = tx.get(&self.db, INDEX_BEST_KEY)?.and_then(|data| decode_index_best(&data).ok());
+        if best != Some((block.hash(), height)) {
+            return Ok(());
+        }
+
+        for key in Self::entries(block, height, spent).into_keys() {
+            tx.delete(&self.db, &key);
+        }
+        tx.put(&self.db, INDEX_BEST_KEY, &encode_index_best(&block.header.prev_block_hash, height - 1));
+
+        Ok(())
+    }
+
+    // Helper: history entries of a block, keyed by script hash + height + position
+    fn entries(block: &Block, height: u32, spent: &[Utxo]) -> BTreeMap<Vec<u8>, AddressHistoryEntry> {
+        let mut entries: BTreeMap<Vec<u8>, AddressHistoryEntry> = BTreeMap::new();
+        let mut spent = spent.iter();
+
+        for (position, tx) in block.transactions.iter().enumerate() {
+            let position = position as u32;
+            let txid = tx.txid();
+            let empty = AddressHistoryEntry {
+                txid,
+                height,
+                position,
+                received: 0,
+                spent: 0,
+            };
+
+            if !tx.is_coinbase() {
+                for utxo in spent.by_ref().take(tx.inputs.len()) {
+                    let key = Self::history_key(&utxo.output.script_pubkey, height, position);
+                    entries.entry(key).or_insert_with(|| empty.clone()).spent += utxo.output.value;
+                }
+            }
+            for output in &tx.outputs {
+                let key = Self::history_key(&output.script_pubkey, height, position);
+                entries.entry(key).or_insert_with(|| empty.clone()).received += output.value;
+            }
+        }
+
+        entries
+    }
+
+    // Helper: history key of a transaction for one script
+    fn history_key(script_pubkey: &[u8], height: u32, position: u32) -> Vec<u8> {
+        let mut key = Vec::with_capacity(40);
+        key.extend_from_slice(&sha256_hash(script_pubkey));
+        key.extend_from_slice(&height.to_be_bytes());
+        key.extend_from_slice(&position.to_be_bytes());
+        key
+    }
+}
@@ -0,0 +1,209 @@
+// Key-value storage backends
+//
+// The storage structs see the database as named trees of byte keys and
+// values. `KvTree` reads and writes one tree; `KvBackend` opens trees and
+// commits a `KvBatch` of writes spanning several of them atomically.
+// Updates that read before they write (UTXO statistics, index tips) run in
+// `KvBackend::transaction`: the closure stages its writes in a batch whose
+// reads see them, and holds the backend's transaction lock until the batch
+// is committed, so two transactions never interleave.
+//
+// `SledBackend` keeps the trees on disk; `MemoryBackend` keeps them in
+// `BTreeMap`s, for tests and throwaway chains.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Iterator over (key, value) pairs in key order
+pub type KvIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), String>> + 'a>;
+
+/// One named tree of a backend
+pub trait KvTree: Clone + Send + Sync + 'static {
+    /// Name the tree was opened with
+    fn name(&self) -> &str;
+
+    /// Value stored under `key`
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String>;
+
+    /// Store `value` under `key`
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), String>;
+
+    /// Remove `key`
+    fn delete(&self, key: &[u8]) -> Result<(), String>;
+
+    /// Entries whose key starts with `prefix`, in key order
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_>;
+
+    /// Remove every entry
+    fn clear(&self) -> Result<(), String>;
+
+    /// Number of entries
+    fn len(&self) -> usize;
+
+    /// Whether `key` is stored
+    fn contains(&self, key: &[u8]) -> Result<bool, String> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Every entry in key order
+    fn iter(&self) -> KvIter<'_> {
+        self.scan_prefix(&[])
+    }
+
+    /// Whether the tree has no entries
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A key-value store made of named trees
+pub trait KvBackend: Clone + Send + Sync + 'static {
+    type Tree: KvTree;
+
+    /// The unnamed tree every database starts with
+    fn default_tree(&self) -> Self::Tree;
+
+    /// Open a named tree, creating it if needed
+    fn open_tree(&self, name: &str) -> Result<Self::Tree, String>;
+
+    /// Apply every write of `batch` atomically
+    fn write_batch(&self, batch: &KvBatch<Self::Tree>) -> Result<(), String>;
+
+    /// Make all writes durable
+    fn flush(&self) -> Result<(), String>;
+
+    /// Lock held by `transaction`
+    fn transaction_lock(&self) -> &Mutex<()>;
+
+    /// Run `f` with a fresh batch and commit what it staged
+    /// Nothing is written if `f` fails.
+    fn transaction<R, F>(&self, f: F) -> Result<R, String>
+    where
+        F: FnOnce(&mut KvBatch<Self::Tree>) -> Result<R, String>,
+    {
+        let _guard = self.transaction_lock().lock().unwrap();
+        let mut batch = KvBatch::default();
+        let result = f(&mut batch)?;
+        if !batch.is_empty() {
+            self.write_batch(&batch)?;
+        }
+        Ok(result)
+    }
+}
+
+/// Writes staged for one tree, by key (`None` removes the key)
+pub type KvWrites = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Writes staged for several trees
+pub struct KvBatch<T: KvTree> {
+    trees: Vec<(T, KvWrites)>,
+}
+
+impl<T: KvTree> Default for KvBatch<T> {
+    fn default() -> Self {
+        Self { trees: Vec::new() }
+    }
+}
+
+impl<T: KvTree> KvBatch<T> {
+    /// Value of `key`, including writes staged in this batch
+    pub fn get(&self, tree: &T, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let staged = self
+            .trees
+            .iter()
+            .find(|(staged, _)| staged.name() == tree.name())
+            .and_then(|(_, writes)| writes.get(key));
+        match staged {
+            Some(value) => Ok(value.clone()),
+            None => tree.get(key),
+        }
+    }
+
+    /// Stage storing `value` under `key`
+    pub fn put(&mut self, tree: &T, key: &[u8], value: &[u8]) {
+        self.writes(tree).insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    /// Stage removing `key`
+    pub fn delete(&mut self, tree: &T, key: &[u8]) {
+        self.writes(tree).insert(key.to_vec(), None);
+    }
+
+    /// Staged writes, grouped by tree
+    pub fn trees(&self) -> impl Iterator<Item = (&T, &KvWrites)> {
+        self.trees.iter().map(|(tree, writes)| (tree, writes))
+    }
+
+    /// Whether nothing is staged
+    pub fn is_empty(&self) -> bool {
+        self.trees.iter().all(|(_, writes)| writes.is_empty())
+    }
+
+    // Helper: staged writes of one tree
+    fn writes(&mut self, tree: &T) -> &mut KvWrites {
+        let index = match self.trees.iter().position(|(staged, _)| staged.name() == tree.name()) {
+            Some(index) => index,
+            None => {
+                self.trees.push((tree.clone(), BTreeMap::new()));
+                self.trees.len() - 1
+            }
+        };
+        &mut self.trees[index].1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryBackend, SledBackend};
+
+    // Helper: the same checks against any backend
+    fn exercise<B: KvBackend>(backend: B) {
+        let a = backend.open_tree("a").unwrap();
+        let b = backend.open_tree("b").unwrap();
+        a.put(b"k1", b"v1").unwrap();
+        a.put(b"k2", b"v2").unwrap();
+        a.put(b"x", b"v3").unwrap();
+
+        let prefixed: Vec<_> = a.scan_prefix(b"k").map(|item| item.unwrap().0).collect();
+        assert_eq!(prefixed, vec![b"k1".to_vec(), b"k2".to_vec()]);
+        assert!(b.is_empty());
+
+        // Reads inside a transaction see its own writes; a failure writes nothing
+        let failed: Result<(), String> = backend.transaction(|batch| {
+            batch.delete(&a, b"k1");
+            batch.put(&b, b"k", b"staged");
+            assert_eq!(batch.get(&a, b"k1").unwrap(), None);
+            assert_eq!(batch.get(&b, b"k").unwrap(), Some(b"staged".to_vec()));
+            Err("abort".to_string())
+        });
+        assert!(failed.is_err());
+        assert!(a.contains(b"k1").unwrap() && b.is_empty());
+
+        backend
+            .transaction(|batch| {
+                batch.delete(&a, b"k1");
+                batch.put(&b, b"k", b"v");
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(a.len(), 2);
+        assert_eq!(b.get(b"k").unwrap(), Some(b"v".to_vec()));
+
+        // Handles opened twice share their contents
+        assert_eq!(backend.open_tree("a").unwrap().len(), 2);
+        a.clear().unwrap();
+        assert!(a.iter().next().is_none());
+        backend.flush().unwrap();
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        exercise(MemoryBackend::new());
+    }
+
+    #[test]
+    fn test_sled_backend() {
+        exercise(SledBackend::temporary().unwrap());
+    }
+}
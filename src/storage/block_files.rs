@@ -0,0 +1,435 @@
+// Flat files holding raw blocks and their undo data
+//
+// Blocks are appended to blkNNNNN.dat files in Bitcoin Core's layout: each
+// record is the network magic, the data length (u32 LE) and the serialized
+// block. Once a file would grow past `MAX_BLOCKFILE_SIZE` the next number is
+// started. The undo data of a block goes to the revNNNNN.dat file with the
+// same number, framed the same way and followed by a checksum. Records are
+// never rewritten: the chain database keeps where each one starts (`FilePos`),
+// and bytes appended before a crash that no index entry points at are
+// ignored.
+
+use crate::core::{hash256, Hash256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Magic bytes starting every record (Bitcoin mainnet)
+pub const NETWORK_MAGIC: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
+
+/// Size at which a block file is closed and the next one started
+pub const MAX_BLOCKFILE_SIZE: u64 = 128 << 20;
+
+/// Magic and length in front of every record
+const RECORD_HEADER_SIZE: u64 = 8;
+
+/// Checksum following every undo record
+const UNDO_CHECKSUM_SIZE: u64 = 32;
+
+/// Position of a record's data inside a numbered file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilePos {
+    pub file: u32,
+    /// Offset of the data, just past the magic and length
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl FilePos {
+    /// Serialize to bytes (file, offset, length, all LE)
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[0..4].copy_from_slice(&self.file.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != 12 {
+            return Err(format!("Invalid file position length: {}", bytes.len()));
+        }
+        Ok(Self {
+            file: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            offset: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            length: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+        })
+    }
+}
+
+/// Numbered block and undo files in a directory, or in memory
+pub struct BlockFiles {
+    store: FileStore,
+    max_file_size: u64,
+    /// Number of the block file new blocks are appended to
+    current: Mutex<u32>,
+    /// Files written since the last `flush`
+    unsynced: Mutex<BTreeSet<String>>,
+}
+
+impl BlockFiles {
+    /// Use the block files in `dir`, creating it if needed
+    /// Appending continues in the highest-numbered blk file.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create block file directory: {}", e))?;
+        Self::with_store(FileStore::Dir(dir), MAX_BLOCKFILE_SIZE)
+    }
+
+    /// Block files held in memory (for testing)
+    pub fn memory() -> Self {
+        Self::with_store(FileStore::Memory(Mutex::new(BTreeMap::new())), MAX_BLOCKFILE_SIZE).unwrap()
+    }
+
+    /// Start a new file once one would grow past `bytes`
+    pub fn with_max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Number of the block file new blocks are appended to
+    pub fn current_file(&self) -> u32 {
+        *self.current.lock().unwrap()
+    }
+
+    /// Append a serialized block, moving to a new file if the current one is full
+    pub fn write_block(&self, data: &[u8]) -> Result<FilePos, String> {
+        let mut current = self.current.lock().unwrap();
+        let size = self.store.size(&block_file_name(*current))?;
+        if size > 0 && size + RECORD_HEADER_SIZE + data.len() as u64 > self.max_file_size {
+            *current += 1;
+        }
+        self.append(&block_file_name(*current), *current, data, &[])
+    }
+
+    /// Serialized block stored at `pos`
+    pub fn read_block(&self, pos: &FilePos) -> Result<Vec<u8>, String> {
+        self.read(&block_file_name(pos.file), pos, 0)
+    }
+
+    /// Append the undo data of `block_hash` to rev file `file`
+    /// The record is followed by a checksum over the block hash and the data.
+    pub fn write_undo(&self, file: u32, block_hash: &Hash256, data: &[u8]) -> Result<FilePos, String> {
+        // Held so appends never interleave
+        let _current = self.current.lock().unwrap();
+        let checksum = undo_checksum(block_hash, data);
+        self.append(&undo_file_name(file), file, data, checksum.as_bytes())
+    }
+
+    /// Undo data of `block_hash` stored at `pos`, checked against its checksum
+    pub fn read_undo(&self, pos: &FilePos, block_hash: &Hash256) -> Result<Vec<u8>, String> {
+        let mut record = self.read(&undo_file_name(pos.file), pos, UNDO_CHECKSUM_SIZE)?;
+        let checksum = record.split_off(pos.length as usize);
+        if checksum != undo_checksum(block_hash, &record).as_bytes() {
+            return Err(format!("Undo data checksum mismatch for block {}", block_hash));
+        }
+        Ok(record)
+    }
+
+    /// Every block record in blk file `file`, in file order
+    /// Reads files written by Bitcoin Core too: zero padding between records
+    /// is skipped and a truncated last record ends the scan.
+    pub fn scan_blocks(&self, file: u32) -> Result<Vec<(FilePos, Vec<u8>)>, String> {
+        let bytes = self.store.read_all(&block_file_name(file))?;
+        let mut records = Vec::new();
+        let mut offset = 0;
+
+        while offset + RECORD_HEADER_SIZE as usize <= bytes.len() {
+            if bytes[offset..offset + 4] != NETWORK_MAGIC {
+                offset += 1;
+                continue;
+            }
+            let length = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+            let start = offset + RECORD_HEADER_SIZE as usize;
+            let end = start + length as usize;
+            if end > bytes.len() {
+                break;
+            }
+
+            let pos = FilePos { file, offset: start as u32, length };
+            records.push((pos, bytes[start..end].to_vec()));
+            offset = end;
+        }
+
+        Ok(records)
+    }
+
+    /// Numbers of the blk files present, in order
+    pub fn block_file_numbers(&self) -> Result<Vec<u32>, String> {
+        Ok(self.store.names()?.iter().filter_map(|name| parse_file_name(name, "blk")).collect())
+    }
+
+    /// Delete block file `file` and its undo file
+    pub fn remove_file(&self, file: u32) -> Result<(), String> {
+        if file == self.current_file() {
+            return Err(format!("Cannot remove block file {} while blocks are appended to it", file));
+        }
+        for name in [block_file_name(file), undo_file_name(file)] {
+            self.unsynced.lock().unwrap().remove(&name);
+            self.store.remove(&name)?;
+        }
+        Ok(())
+    }
+
+    /// Make every write so far durable
+    pub fn flush(&self) -> Result<(), String> {
+        let names: Vec<String> = std::mem::take(&mut *self.unsynced.lock().unwrap()).into_iter().collect();
+        for name in names {
+            self.store.sync(&name)?;
+        }
+        Ok(())
+    }
+
+    // Helper: create the store and find the file appending continues in
+    fn with_store(store: FileStore, max_file_size: u64) -> Result<Self, String> {
+        let names = store.names()?;
+        let current = names.iter().filter_map(|name| parse_file_name(name, "blk")).max().unwrap_or(0);
+        Ok(Self {
+            store,
+            max_file_size,
+            current: Mutex::new(current),
+            unsynced: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    // Helper: append magic, length, data and trailer to a file
+    fn append(&self, name: &str, file: u32, data: &[u8], trailer: &[u8]) -> Result<FilePos, String> {
+        let length = u32::try_from(data.len()).map_err(|_| format!("Record too large: {} bytes", data.len()))?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE as usize + data.len() + trailer.len());
+        record.extend_from_slice(&NETWORK_MAGIC);
+        record.extend_from_slice(&length.to_le_bytes());
+        record.extend_from_slice(data);
+        record.extend_from_slice(trailer);
+
+        let start = self.store.append(name, &record)?;
+        let offset = u32::try_from(start + RECORD_HEADER_SIZE).map_err(|_| format!("File {} is too large", name))?;
+        self.unsynced.lock().unwrap().insert(name.to_string());
+        Ok(FilePos { file, offset, length })
+    }
+
+    // Helper: read a record's data (plus `trailer` bytes) after checking its header
+    fn read(&self, name: &str, pos: &FilePos, trailer: u64) -> Result<Vec<u8>, String> {
+        let start = (pos.offset as u64)
+            .checked_sub(RECORD_HEADER_SIZE)
+            .ok_or_else(|| format!("Invalid offset {} in {}", pos.offset, name))?;
+        let total = RECORD_HEADER_SIZE + pos.length as u64 + trailer;
+        let mut record = self.store.read(name, start, total as usize)?;
+
+        if record[0..4] != NETWORK_MAGIC {
+            return Err(format!("Bad magic at offset {} in {}", start, name));
+        }
+        let length = u32::from_le_bytes(record[4..8].try_into().unwrap());
+        if length != pos.length {
+            return Err(format!(
+                "Record at offset {} in {} has length {}, index says {}",
+                start, name, length, pos.length
+            ));
+        }
+        Ok(record.split_off(RECORD_HEADER_SIZE as usize))
+    }
+}
+
+/// Name of block file `file`
+pub fn block_file_name(file: u32) -> String {
+    format!("blk{:05}.dat", file)
+}
+
+/// Name of undo file `file`
+pub fn undo_file_name(file: u32) -> String {
+    format!("rev{:05}.dat", file)
+}
+
+// Helper: number of a `<prefix>NNNNN.dat` file name
+fn parse_file_name(name: &str, prefix: &str) -> Option<u32> {
+    name.strip_prefix(prefix)?.strip_suffix(".dat")?.parse().ok()
+}
+
+// Helper: checksum stored after an undo record
+fn undo_checksum(block_hash: &Hash256, data: &[u8]) -> Hash256 {
+    let mut bytes = Vec::with_capacity(32 + data.len());
+    bytes.extend_from_slice(block_hash.as_bytes());
+    bytes.extend_from_slice(data);
+    hash256(&bytes)
+}
+
+/// Where the numbered files live
+enum FileStore {
+    Dir(PathBuf),
+    Memory(Mutex<BTreeMap<String, Vec<u8>>>),
+}
+
+impl FileStore {
+    // Helper: append bytes to a file, returning where they start
+    fn append(&self, name: &str, bytes: &[u8]) -> Result<u64, String> {
+        match self {
+            FileStore::Dir(dir) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(dir.join(name))
+                    .map_err(|e| format!("Failed to open {}: {}", name, e))?;
+                let start = file.seek(SeekFrom::End(0)).map_err(|e| format!("Failed to seek {}: {}", name, e))?;
+                file.write_all(bytes).map_err(|e| format!("Failed to write {}: {}", name, e))?;
+                Ok(start)
+            }
+            FileStore::Memory(files) => {
+                let mut files = files.lock().unwrap();
+                let file = files.entry(name.to_string()).or_default();
+                let start = file.len() as u64;
+                file.extend_from_slice(bytes);
+                Ok(start)
+            }
+        }
+    }
+
+    // Helper: read `len` bytes of a file from `offset`
+    fn read(&self, name: &str, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![0u8; len];
+        match self {
+            FileStore::Dir(dir) => {
+                let mut file = File::open(dir.join(name)).map_err(|e| format!("Failed to open {}: {}", name, e))?;
+                file.seek(SeekFrom::Start(offset)).map_err(|e| format!("Failed to seek {}: {}", name, e))?;
+                file.read_exact(&mut bytes)
+                    .map_err(|e| format!("Failed to read {} bytes at offset {} in {}: {}", len, offset, name, e))?;
+            }
+            FileStore::Memory(files) => {
+                let files = files.lock().unwrap();
+                let data = files
+                    .get(name)
+                    .and_then(|file| file.get(offset as usize..offset as usize + len))
+                    .ok_or_else(|| format!("Failed to read {} bytes at offset {} in {}", len, offset, name))?;
+                bytes.copy_from_slice(data);
+            }
+        }
+        Ok(bytes)
+    }
+
+    // Helper: whole contents of a file (empty if missing)
+    fn read_all(&self, name: &str) -> Result<Vec<u8>, String> {
+        match self {
+            FileStore::Dir(dir) => match fs::read(dir.join(name)) {
+                Ok(bytes) => Ok(bytes),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(e) => Err(format!("Failed to read {}: {}", name, e)),
+            },
+            FileStore::Memory(files) => Ok(files.lock().unwrap().get(name).cloned().unwrap_or_default()),
+        }
+    }
+
+    // Helper: size of a file (0 if missing)
+    fn size(&self, name: &str) -> Result<u64, String> {
+        match self {
+            FileStore::Dir(dir) => match fs::metadata(dir.join(name)) {
+                Ok(metadata) => Ok(metadata.len()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+                Err(e) => Err(format!("Failed to stat {}: {}", name, e)),
+            },
+            FileStore::Memory(files) => Ok(files.lock().unwrap().get(name).map_or(0, |file| file.len() as u64)),
+        }
+    }
+
+    // Helper: names of the files present
+    fn names(&self) -> Result<Vec<String>, String> {
+        match self {
+            FileStore::Dir(dir) => {
+                let entries = fs::read_dir(dir).map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+                let mut names = Vec::new();
+                for entry in entries {
+                    let entry = entry.map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+                    names.push(entry.file_name().to_string_lossy().into_owned());
+                }
+                names.sort();
+                Ok(names)
+            }
+            FileStore::Memory(files) => Ok(files.lock().unwrap().keys().cloned().collect()),
+        }
+    }
+
+    // Helper: delete a file if it exists
+    fn remove(&self, name: &str) -> Result<(), String> {
+        match self {
+            FileStore::Dir(dir) => match fs::remove_file(dir.join(name)) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(format!("Failed to remove {}: {}", name, e)),
+            },
+            FileStore::Memory(files) => {
+                files.lock().unwrap().remove(name);
+                Ok(())
+            }
+        }
+    }
+
+    // Helper: wait until a file's contents are on disk
+    fn sync(&self, name: &str) -> Result<(), String> {
+        match self {
+            FileStore::Dir(dir) => {
+                let file = File::open(dir.join(name)).map_err(|e| format!("Failed to open {}: {}", name, e))?;
+                file.sync_all().map_err(|e| format!("Failed to sync {}: {}", name, e))
+            }
+            FileStore::Memory(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_roundtrip_and_rotate() {
+        let files = BlockFiles::memory().with_max_file_size(100);
+        let first = files.write_block(&[1; 60]).unwrap();
+        let second = files.write_block(&[2; 60]).unwrap();
+
+        assert_eq!(first, FilePos { file: 0, offset: 8, length: 60 });
+        assert_eq!(second, FilePos { file: 1, offset: 8, length: 60 });
+        assert_eq!(files.read_block(&second).unwrap(), vec![2; 60]);
+        assert_eq!(files.block_file_numbers().unwrap(), vec![0, 1]);
+
+        // Undo data checks its checksum against the block hash
+        let hash = Hash256::new([5; 32]);
+        let undo = files.write_undo(0, &hash, b"undo").unwrap();
+        assert_eq!(files.read_undo(&undo, &hash).unwrap(), b"undo".to_vec());
+        assert!(files.read_undo(&undo, &Hash256::zero()).unwrap_err().contains("checksum"));
+
+        // A position whose length disagrees with the record is rejected
+        let wrong = FilePos { length: 59, ..first };
+        assert!(files.read_block(&wrong).is_err());
+    }
+
+    #[test]
+    fn test_scan_skips_padding_and_truncated_records() {
+        let files = BlockFiles::memory();
+        files.write_block(b"first").unwrap();
+        files.store.append(&block_file_name(0), &[0; 16]).unwrap();
+        files.write_block(b"second").unwrap();
+        // Record cut short by a crash
+        files.store.append(&block_file_name(0), &NETWORK_MAGIC).unwrap();
+        files.store.append(&block_file_name(0), &[50, 0, 0, 0, 1]).unwrap();
+
+        let records: Vec<Vec<u8>> = files.scan_blocks(0).unwrap().into_iter().map(|(_, data)| data).collect();
+        assert_eq!(records, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[test]
+    fn test_directory_files_survive_reopen() {
+        let dir = std::env::temp_dir().join(format!("block-files-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let files = BlockFiles::open(&dir).unwrap().with_max_file_size(50);
+        files.write_block(&[1; 30]).unwrap();
+        let pos = files.write_block(&[2; 30]).unwrap();
+        files.flush().unwrap();
+        drop(files);
+
+        let reopened = BlockFiles::open(&dir).unwrap();
+        assert_eq!(reopened.current_file(), 1);
+        assert_eq!(reopened.read_block(&pos).unwrap(), vec![2; 30]);
+        assert!(dir.join("blk00001.dat").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
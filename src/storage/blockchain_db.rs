@@ -1,50 +1,270 @@
-// Blockchain database using sled
-
-use crate::core::{Block, Hash256, Serializable};
-use sled::Db;
+// Blockchain database: block index, height index and chain metadata in one tree
+//
+// Raw blocks and undo data live in the flat block files (block_files.rs). The
+// tree maps each block hash to a `BlockIndexEntry` saying where its data is.
+// Pruning deletes whole block files; the headers of their blocks are copied
+// into the tree first, so the chain of headers stays complete.
+
+use super::{block_file_name, BlockFiles, BlockUndo, FilePos, KvBackend, KvBatch, KvTree, MemoryBackend, SledBackend};
+use crate::core::{Block, BlockHeader, Hash256, Serializable};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
+use std::sync::Arc;
+
+/// Key of the best block hash
+pub(super) const TIP_KEY: &[u8] = b"tip";
+
+/// Key of the chain height (tip height + 1)
+pub(super) const HEIGHT_KEY: &[u8] = b"height";
+
+/// Key of the block whose effects the UTXO set reflects
+pub(super) const UTXO_TIP_KEY: &[u8] = b"utxotip";
+
+/// Key of the count and highest height of blocks connected without script checks
+pub(super) const ASSUME_VALID_KEY: &[u8] = b"assumevalid";
+
+/// Status flag: the block's data is in a block file
+pub const BLOCK_HAVE_DATA: u8 = 1;
+
+/// Status flag: the block's undo data is in an undo file
+pub const BLOCK_HAVE_UNDO: u8 = 2;
+
+/// Status flag: the block's file was pruned; only the header is kept
+pub const BLOCK_PRUNED: u8 = 4;
+
+/// Key of the highest block height whose data was pruned
+const PRUNE_HEIGHT_KEY: &[u8] = b"pruneheight";
+
+/// Where a stored block's data and undo data are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockIndexEntry {
+    pub height: u32,
+    /// `BLOCK_*` flags; none for a header indexed ahead of its block
+    pub status: u8,
+    pub data: FilePos,
+    /// Meaningful only with `BLOCK_HAVE_UNDO`
+    pub undo: FilePos,
+}
+
+impl BlockIndexEntry {
+    /// Serialize to bytes (height LE, status, data and undo positions)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(29);
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.push(self.status);
+        bytes.extend_from_slice(&self.data.to_bytes());
+        bytes.extend_from_slice(&self.undo.to_bytes());
+        bytes
+    }
+
+    /// Deserialize from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != 29 {
+            return Err(format!("Invalid block index entry length: {}", bytes.len()));
+        }
+        Ok(Self {
+            height: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            status: bytes[4],
+            data: FilePos::from_bytes(&bytes[5..17])?,
+            undo: FilePos::from_bytes(&bytes[17..29])?,
+        })
+    }
+
+    /// Whether the block's data is stored
+    pub fn has_data(&self) -> bool {
+        self.status & BLOCK_HAVE_DATA != 0
+    }
+
+    /// Position of the undo data, if stored
+    pub fn undo_pos(&self) -> Option<FilePos> {
+        (self.status & BLOCK_HAVE_UNDO != 0).then_some(self.undo)
+    }
+
+    /// Whether the block's data was deleted by pruning
+    pub fn is_pruned(&self) -> bool {
+        self.status & BLOCK_PRUNED != 0
+    }
+}
+
+/// What one numbered block file and its undo file hold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockFileInfo {
+    pub blocks: u32,
+    /// Bytes of block records
+    pub size: u64,
+    /// Bytes of undo records
+    pub undo_size: u64,
+    pub height_first: u32,
+    pub height_last: u32,
+}
+
+impl BlockFileInfo {
+    /// Serialize to bytes (all fields LE)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(28);
+        bytes.extend_from_slice(&self.blocks.to_le_bytes());
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&self.undo_size.to_le_bytes());
+        bytes.extend_from_slice(&self.height_first.to_le_bytes());
+        bytes.extend_from_slice(&self.height_last.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != 28 {
+            return Err(format!("Invalid block file info length: {}", bytes.len()));
+        }
+        Ok(Self {
+            blocks: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            size: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+            undo_size: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+            height_first: u32::from_le_bytes(bytes[20..24].try_into().unwrap()),
+            height_last: u32::from_le_bytes(bytes[24..28].try_into().unwrap()),
+        })
+    }
+
+    // Helper: account a block at `height` taking `bytes` in the block file
+    fn add_block(&mut self, height: u32, bytes: u64) {
+        if self.blocks == 0 || height < self.height_first {
+            self.height_first = height;
+        }
+        self.height_last = self.height_last.max(height);
+        self.blocks += 1;
+        self.size += bytes;
+    }
+}
+
+/// Prefix of block index entries
+const INDEX_PREFIX: &[u8] = b"i";
+
+/// Prefix of block file info records
+const FILE_INFO_PREFIX: &[u8] = b"f";
+
+/// Prefix of the height index
+const HEIGHT_PREFIX: &[u8] = b"h";
+
+/// Prefix of blocks stored inside the database by older versions
+const LEGACY_BLOCK_PREFIX: &[u8] = b"b";
+
+/// Prefix of undo data stored inside the database by older versions
+const LEGACY_UNDO_PREFIX: &[u8] = b"u";
+
+/// Framing around a block record (magic and length)
+const BLOCK_RECORD_OVERHEAD: u64 = 8;
+
+/// Framing around an undo record (magic, length and checksum)
+const UNDO_RECORD_OVERHEAD: u64 = 40;
 
 /// Blockchain database
-pub struct BlockchainDB {
-    db: Db,
+pub struct BlockchainDB<B: KvBackend = SledBackend> {
+    backend: B,
+    db: B::Tree,
+    files: Arc<BlockFiles>,
+}
+
+impl<B: KvBackend> Clone for BlockchainDB<B> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            db: self.db.clone(),
+            files: self.files.clone(),
+        }
+    }
 }
 
-impl BlockchainDB {
+impl BlockchainDB<SledBackend> {
     /// Create a new blockchain database
+    /// The block files are kept in the database directory.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        let db = sled::open(path).map_err(|e| format!("Failed to open database: {}", e))?;
-        Ok(Self { db })
+        let backend = SledBackend::open(path.as_ref())?;
+        let files = BlockFiles::open(path)?;
+        Ok(Self::from_parts(backend.clone(), backend.default_tree(), Arc::new(files)))
     }
+}
 
+impl BlockchainDB<MemoryBackend> {
     /// Create an in-memory database (for testing)
     pub fn memory() -> Result<Self, String> {
-        let config = sled::Config::new().temporary(true);
-        let db = config.open().map_err(|e| format!("Failed to create memory db: {}", e))?;
-        Ok(Self { db })
+        let backend = MemoryBackend::new();
+        Ok(Self::from_parts(backend.clone(), backend.default_tree(), Arc::new(BlockFiles::memory())))
     }
+}
 
-    /// Store a block
-    pub fn store_block(&self, block: &Block) -> Result<(), String> {
-        let hash = block.hash();
-        let serialized = block.serialize();
+impl<B: KvBackend> BlockchainDB<B> {
+    /// Use one tree of a shared database and the block files
+    pub(super) fn from_parts(backend: B, db: B::Tree, files: Arc<BlockFiles>) -> Self {
+        Self { backend, db, files }
+    }
 
-        // Store block by hash (flush removed for performance)
-        self.db
-            .insert(Self::block_key(&hash), serialized.as_slice())
-            .map_err(|e| format!("Failed to store block: {}", e))?;
+    /// Underlying tree (for atomic batches spanning several trees)
+    pub(super) fn tree(&self) -> &B::Tree {
+        &self.db
+    }
 
-        Ok(())
+    /// Block and undo files
+    pub fn files(&self) -> &BlockFiles {
+        &self.files
+    }
+
+    /// Store a block at `height`: append it to the block files and index it
+    pub fn store_block(&self, block: &Block, height: u32) -> Result<BlockIndexEntry, String> {
+        let hash = block.hash();
+        let entry = self.write_block(block, height)?;
+        self.backend
+            .transaction(|tx| self.stage_block(tx, &hash, &entry))
+            .map_err(|e| format!("Failed to store block: {}", e))?;
+        Ok(entry)
     }
 
     /// Get a block by hash
     pub fn get_block(&self, hash: &Hash256) -> Result<Option<Block>, String> {
-        let key = Self::block_key(hash);
-
-        match self.db.get(&key).map_err(|e| format!("Database error: {}", e))? {
-            Some(data) => {
+        match self.get_index_entry(hash)? {
+            Some(entry) if entry.has_data() => {
+                let data = self.files.read_block(&entry.data)?;
                 let block = Block::deserialize(&data)?;
                 Ok(Some(block))
             }
+            _ => Ok(None),
+        }
+    }
+
+    /// Header of a stored block, including pruned and header-only ones
+    pub fn get_header(&self, hash: &Hash256) -> Result<Option<BlockHeader>, String> {
+        match self.get_index_entry(hash)? {
+            Some(entry) if entry.has_data() => {
+                let data = self.files.read_block(&entry.data)?;
+                Ok(Some(BlockHeader::deserialize(&data)?))
+            }
+            Some(_) => match self.db.get(&Self::header_key(hash))? {
+                Some(data) => Ok(Some(BlockHeader::deserialize(&data)?)),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Header of the active-chain block at `height`, including pruned ones
+    pub fn get_header_by_height(&self, height: u32) -> Result<Option<BlockHeader>, String> {
+        match self.get_hash_by_height(height)? {
+            Some(hash) => self.get_header(&hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Index entry of a stored block
+    pub fn get_index_entry(&self, hash: &Hash256) -> Result<Option<BlockIndexEntry>, String> {
+        match self.db.get(&Self::index_key(hash))? {
+            Some(data) => Ok(Some(BlockIndexEntry::from_bytes(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// What block file `file` holds, if anything was written to it
+    pub fn get_file_info(&self, file: u32) -> Result<Option<BlockFileInfo>, String> {
+        match self.db.get(&Self::file_info_key(file))? {
+            Some(data) => Ok(Some(BlockFileInfo::from_bytes(&data)?)),
             None => Ok(None),
         }
     }
@@ -54,7 +274,7 @@ impl BlockchainDB {
         let key = Self::height_key(height);
 
         self.db
-            .insert(key, hash.as_bytes().as_slice())
+            .put(&key, hash.as_bytes())
             .map_err(|e| format!("Failed to store height: {}", e))?;
 
         Ok(())
@@ -64,7 +284,7 @@ impl BlockchainDB {
     pub fn get_hash_by_height(&self, height: u32) -> Result<Option<Hash256>, String> {
         let key = Self::height_key(height);
 
-        match self.db.get(&key).map_err(|e| format!("Database error: {}", e))? {
+        match self.db.get(&key)? {
             Some(data) => {
                 if data.len() != 32 {
                     return Err(format!("Invalid hash length: {}", data.len()));
@@ -77,6 +297,27 @@ impl BlockchainDB {
         }
     }
 
+    /// Hashes of the height index from genesis up to its first gap
+    pub fn get_active_chain(&self) -> Result<Vec<Hash256>, String> {
+        let mut chain = Vec::new();
+        for item in self.db.scan_prefix(HEIGHT_PREFIX) {
+            let (key, data) = item?;
+            // The chain height key shares the prefix
+            if key.len() != 5 {
+                continue;
+            }
+            if u32::from_be_bytes(key[1..5].try_into().unwrap()) != chain.len() as u32 {
+                break;
+            }
+            let hash: [u8; 32] = data
+                .as_slice()
+                .try_into()
+                .map_err(|_| format!("Invalid hash length: {}", data.len()))?;
+            chain.push(Hash256::new(hash));
+        }
+        Ok(chain)
+    }
+
     /// Get block by height
     pub fn get_block_by_height(&self, height: u32) -> Result<Option<Block>, String> {
         match self.get_hash_by_height(height)? {
@@ -88,7 +329,7 @@ impl BlockchainDB {
     /// Store the chain tip (best block hash)
     pub fn store_tip(&self, hash: &Hash256) -> Result<(), String> {
         self.db
-            .insert(b"tip", hash.as_bytes().as_slice())
+            .put(TIP_KEY, hash.as_bytes())
             .map_err(|e| format!("Failed to store tip: {}", e))?;
 
         Ok(())
@@ -96,7 +337,7 @@ impl BlockchainDB {
 
     /// Get the chain tip (best block hash)
     pub fn get_tip(&self) -> Result<Option<Hash256>, String> {
-        match self.db.get(b"tip").map_err(|e| format!("Database error: {}", e))? {
+        match self.db.get(TIP_KEY)? {
             Some(data) => {
                 if data.len() != 32 {
                     return Err(format!("Invalid hash length: {}", data.len()));
@@ -112,23 +353,15 @@ impl BlockchainDB {
     /// Store the blockchain height
     pub fn store_chain_height(&self, height: u32) -> Result<(), String> {
         self.db
-            .insert(b"height", &height.to_le_bytes())
+            .put(HEIGHT_KEY, &height.to_le_bytes())
             .map_err(|e| format!("Failed to store height: {}", e))?;
 
         Ok(())
     }
 
-    /// Manually flush database (call after batch operations)
-    pub fn flush(&self) -> Result<(), String> {
-        self.db
-            .flush()
-            .map_err(|e| format!("Failed to flush: {}", e))?;
-        Ok(())
-    }
-
     /// Get the blockchain height
     pub fn get_chain_height(&self) -> Result<u32, String> {
-        match self.db.get(b"height").map_err(|e| format!("Database error: {}", e))? {
+        match self.db.get(HEIGHT_KEY)? {
             Some(data) => {
                 if data.len() != 4 {
                     return Err(format!("Invalid height data length: {}", data.len()));
@@ -143,25 +376,383 @@ impl BlockchainDB {
 
     /// Check if a block exists
     pub fn has_block(&self, hash: &Hash256) -> Result<bool, String> {
-        let key = Self::block_key(hash);
-        self.db
-            .contains_key(&key)
-            .map_err(|e| format!("Database error: {}", e))
+        Ok(self.get_index_entry(hash)?.is_some_and(|entry| entry.has_data()))
+    }
+
+    /// Index the header of a block at `height` ahead of its data (header pre-sync)
+    /// Does nothing if the block or header is indexed; returns whether it was new.
+    pub fn store_header(&self, header: &BlockHeader, height: u32) -> Result<bool, String> {
+        let hash = header.hash();
+        if self.get_index_entry(&hash)?.is_some() {
+            return Ok(false);
+        }
+        let entry = BlockIndexEntry {
+            height,
+            status: 0,
+            data: FilePos { file: 0, offset: 0, length: 0 },
+            undo: FilePos { file: 0, offset: 0, length: 0 },
+        };
+        self.backend
+            .transaction(|tx| {
+                tx.put(&self.db, &Self::header_key(&hash), &header.serialize());
+                tx.put(&self.db, &Self::index_key(&hash), &entry.to_bytes());
+                Ok(())
+            })
+            .map_err(|e| format!("Failed to store header: {}", e))?;
+        Ok(true)
+    }
+
+    /// Whether `ancestor` is `descendant` or one of its stored ancestors
+    /// Walks back through previous-block hashes until a block is missing.
+    pub fn is_ancestor(&self, ancestor: &Hash256, descendant: &Hash256) -> Result<bool, String> {
+        let mut current = *descendant;
+        loop {
+            if current == *ancestor {
+                return Ok(true);
+            }
+            match self.get_header(&current)? {
+                Some(header) if header.prev_block_hash != Hash256::zero() => {
+                    current = header.prev_block_hash;
+                }
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Stage counting a block at `height` connected without script checks
+    pub(super) fn stage_assumed_valid(&self, tx: &mut KvBatch<B::Tree>, height: u32) -> Result<(), String> {
+        let (count, highest) = match tx.get(&self.db, ASSUME_VALID_KEY)? {
+            Some(data) => Self::parse_assumed_valid(&data)?,
+            None => (0, 0),
+        };
+        let mut value = Vec::with_capacity(8);
+        value.extend_from_slice(&(count + 1).to_le_bytes());
+        value.extend_from_slice(&highest.max(height).to_le_bytes());
+        tx.put(&self.db, ASSUME_VALID_KEY, &value);
+        Ok(())
+    }
+
+    /// Blocks connected without script checks: (count, highest height)
+    pub fn get_assumed_valid(&self) -> Result<Option<(u32, u32)>, String> {
+        match self.db.get(ASSUME_VALID_KEY)? {
+            Some(data) => Self::parse_assumed_valid(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Helper: parse the assume-valid stats (count, highest height)
+    fn parse_assumed_valid(data: &[u8]) -> Result<(u32, u32), String> {
+        if data.len() != 8 {
+            return Err(format!("Invalid assume-valid data length: {}", data.len()));
+        }
+        Ok((
+            u32::from_le_bytes(data[0..4].try_into().unwrap()),
+            u32::from_le_bytes(data[4..8].try_into().unwrap()),
+        ))
+    }
+
+    /// Hash of the block whose effects the UTXO set reflects
+    pub fn get_utxo_tip(&self) -> Result<Option<Hash256>, String> {
+        match self.db.get(UTXO_TIP_KEY)? {
+            Some(data) if data.len() == 32 => Ok(Some(Hash256::new(data.as_slice().try_into().unwrap()))),
+            Some(data) => Err(format!("Invalid UTXO tip length: {}", data.len())),
+            None => Ok(None),
+        }
+    }
+
+    /// Undo data of a connected block
+    pub fn get_undo(&self, hash: &Hash256) -> Result<Option<BlockUndo>, String> {
+        match self.get_index_entry(hash)?.and_then(|entry| entry.undo_pos()) {
+            Some(pos) => Ok(Some(BlockUndo::from_bytes(&self.files.read_undo(&pos, hash)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete block file `file` and its undo file, keeping the headers of their blocks
+    /// The index is updated first; a crash before the files are deleted
+    /// leaves them unreferenced. Returns the number of blocks pruned.
+    pub fn prune_file(&self, file: u32) -> Result<u32, String> {
+        let entries = self.entries_in_file(file)?;
+        let mut headers = Vec::with_capacity(entries.len());
+        for (hash, entry) in &entries {
+            headers.push(BlockHeader::deserialize(&self.files.read_block(&entry.data)?)?);
+            if headers.last().map(|header| header.hash()) != Some(*hash) {
+                return Err(format!("Block file {} does not hold block {}", file, hash));
+            }
+        }
+
+        let result = self.backend.transaction(|tx| {
+            let mut pruned_height = self.get_prune_height()?.unwrap_or(0);
+            for ((hash, entry), header) in entries.iter().zip(&headers) {
+                let pruned = BlockIndexEntry {
+                    status: BLOCK_PRUNED,
+                    ..*entry
+                };
+                tx.put(&self.db, &Self::header_key(hash), &header.serialize());
+                tx.put(&self.db, &Self::index_key(hash), &pruned.to_bytes());
+                pruned_height = pruned_height.max(entry.height);
+            }
+
+            let mut info = self.staged_file_info(tx, file)?;
+            info.size = 0;
+            info.undo_size = 0;
+            tx.put(&self.db, &Self::file_info_key(file), &info.to_bytes());
+            tx.put(&self.db, PRUNE_HEIGHT_KEY, &pruned_height.to_le_bytes());
+            Ok(())
+        });
+        result.map_err(|e| format!("Failed to prune block file {}: {}", file, e))?;
+
+        self.files.remove_file(file)?;
+        Ok(entries.len() as u32)
+    }
+
+    /// Highest height whose block data was pruned
+    pub fn get_prune_height(&self) -> Result<Option<u32>, String> {
+        match self.db.get(PRUNE_HEIGHT_KEY)? {
+            Some(data) if data.len() == 4 => Ok(Some(u32::from_le_bytes(data.as_slice().try_into().unwrap()))),
+            Some(data) => Err(format!("Invalid prune height length: {}", data.len())),
+            None => Ok(None),
+        }
+    }
+
+    /// Index every block record in the block files again, replacing the block and height index
+    /// Heights follow from the previous-block links; the longest chain from a
+    /// genesis block becomes the height index (the first one found wins a
+    /// tie). Undo records are forgotten and the tip is cleared. Returns the
+    /// chain, genesis first.
+    pub(super) fn rebuild_index(&self) -> Result<Vec<Hash256>, String> {
+        let mut found = HashMap::new();
+        let mut order = Vec::new();
+        for file in self.files.block_file_numbers()? {
+            for (pos, data) in self.files.scan_blocks(file)? {
+                match Block::deserialize(&data) {
+                    Ok(block) => {
+                        let hash = block.hash();
+                        if let Entry::Vacant(slot) = found.entry(hash) {
+                            slot.insert((block.header.prev_block_hash, pos));
+                            order.push(hash);
+                        }
+                    }
+                    Err(e) => log::warn!("Skipping unreadable record at offset {} in {}: {}", pos.offset, block_file_name(file), e),
+                }
+            }
+        }
+
+        // Breadth-first from every genesis block, so heights only grow
+        let mut children: HashMap<Hash256, Vec<Hash256>> = HashMap::new();
+        let mut heights = HashMap::new();
+        let mut queue = VecDeque::new();
+        for hash in &order {
+            let prev = found[hash].0;
+            if prev == Hash256::zero() {
+                heights.insert(*hash, 0u32);
+                queue.push_back(*hash);
+            } else {
+                children.entry(prev).or_default().push(*hash);
+            }
+        }
+        let mut best: Option<(Hash256, u32)> = None;
+        while let Some(hash) = queue.pop_front() {
+            let height = heights[&hash];
+            if best.is_none_or(|(_, best_height)| height > best_height) {
+                best = Some((hash, height));
+            }
+            for child in children.get(&hash).into_iter().flatten() {
+                heights.insert(*child, height + 1);
+                queue.push_back(*child);
+            }
+        }
+        if heights.len() < order.len() {
+            log::warn!("Skipping {} blocks that do not connect to a genesis block", order.len() - heights.len());
+        }
+
+        let mut chain = Vec::new();
+        if let Some((mut hash, _)) = best {
+            loop {
+                chain.push(hash);
+                let prev = found[&hash].0;
+                if prev == Hash256::zero() {
+                    break;
+                }
+                hash = prev;
+            }
+        }
+        chain.reverse();
+
+        let result = self.backend.transaction(|tx| {
+            for prefix in [INDEX_PREFIX, FILE_INFO_PREFIX, HEIGHT_PREFIX] {
+                for item in self.db.scan_prefix(prefix) {
+                    tx.delete(&self.db, &item?.0);
+                }
+            }
+            for key in [TIP_KEY, HEIGHT_KEY, UTXO_TIP_KEY, ASSUME_VALID_KEY] {
+                tx.delete(&self.db, key);
+            }
+
+            for hash in &order {
+                if let Some(&height) = heights.get(hash) {
+                    let entry = BlockIndexEntry {
+                        height,
+                        status: BLOCK_HAVE_DATA,
+                        data: found[hash].1,
+                        undo: FilePos { file: 0, offset: 0, length: 0 },
+                    };
+                    self.stage_block(tx, hash, &entry)?;
+                }
+            }
+            for (height, hash) in chain.iter().enumerate() {
+                tx.put(&self.db, &Self::height_key(height as u32), hash.as_bytes());
+            }
+            Ok(())
+        });
+        result.map_err(|e| format!("Failed to rebuild the block index: {}", e))?;
+
+        Ok(chain)
+    }
+
+    /// Append `block` to the block files; `stage_block` indexes it
+    pub(super) fn write_block(&self, block: &Block, height: u32) -> Result<BlockIndexEntry, String> {
+        let data = self.files.write_block(&block.serialize())?;
+        Ok(BlockIndexEntry {
+            height,
+            status: BLOCK_HAVE_DATA,
+            data,
+            undo: FilePos { file: 0, offset: 0, length: 0 },
+        })
+    }
+
+    /// Append undo data to the undo file matching the block's file; `stage_undo` indexes it
+    pub(super) fn write_undo(&self, hash: &Hash256, entry: &mut BlockIndexEntry, undo: &BlockUndo) -> Result<(), String> {
+        entry.undo = self.files.write_undo(entry.data.file, hash, &undo.to_bytes())?;
+        entry.status |= BLOCK_HAVE_UNDO;
+        Ok(())
+    }
+
+    /// Stage the index entry of a newly written block and account it in its file
+    pub(super) fn stage_block(&self, tx: &mut KvBatch<B::Tree>, hash: &Hash256, entry: &BlockIndexEntry) -> Result<(), String> {
+        let mut info = self.staged_file_info(tx, entry.data.file)?;
+        info.add_block(entry.height, entry.data.length as u64 + BLOCK_RECORD_OVERHEAD);
+        if let Some(undo) = entry.undo_pos() {
+            info.undo_size += undo.length as u64 + UNDO_RECORD_OVERHEAD;
+        }
+        tx.put(&self.db, &Self::file_info_key(entry.data.file), &info.to_bytes());
+        tx.put(&self.db, &Self::index_key(hash), &entry.to_bytes());
+        Ok(())
+    }
+
+    /// Stage the index entry of a block whose undo data was just written
+    pub(super) fn stage_undo(&self, tx: &mut KvBatch<B::Tree>, hash: &Hash256, entry: &BlockIndexEntry) -> Result<(), String> {
+        if let Some(undo) = entry.undo_pos() {
+            let mut info = self.staged_file_info(tx, undo.file)?;
+            info.undo_size += undo.length as u64 + UNDO_RECORD_OVERHEAD;
+            tx.put(&self.db, &Self::file_info_key(undo.file), &info.to_bytes());
+        }
+        tx.put(&self.db, &Self::index_key(hash), &entry.to_bytes());
+        Ok(())
     }
 
-    // Helper: create key for block storage
-    fn block_key(hash: &Hash256) -> Vec<u8> {
+    /// Stage dropping the undo data of a block; its bytes stay in the undo file
+    pub(super) fn stage_drop_undo(&self, tx: &mut KvBatch<B::Tree>, hash: &Hash256) -> Result<(), String> {
+        if let Some(data) = tx.get(&self.db, &Self::index_key(hash))? {
+            let mut entry = BlockIndexEntry::from_bytes(&data)?;
+            entry.status &= !BLOCK_HAVE_UNDO;
+            tx.put(&self.db, &Self::index_key(hash), &entry.to_bytes());
+        }
+        Ok(())
+    }
+
+    /// Hashes of blocks stored inside the database by older versions
+    pub(super) fn legacy_hashes(&self) -> Result<Vec<Hash256>, String> {
+        let mut hashes = Vec::new();
+        for item in self.db.scan_prefix(LEGACY_BLOCK_PREFIX) {
+            let (key, _) = item?;
+            if key.len() == 33 {
+                hashes.push(Hash256::new(key[1..33].try_into().unwrap()));
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Block an older version stored inside the database
+    pub(super) fn legacy_block(&self, hash: &Hash256) -> Result<Option<Block>, String> {
+        match self.db.get(&Self::legacy_key(LEGACY_BLOCK_PREFIX, hash))? {
+            Some(data) => Ok(Some(Block::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Undo data an older version stored inside the database
+    pub(super) fn legacy_undo(&self, hash: &Hash256) -> Result<Option<BlockUndo>, String> {
+        match self.db.get(&Self::legacy_key(LEGACY_UNDO_PREFIX, hash))? {
+            Some(data) => Ok(Some(BlockUndo::from_bytes(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stage removing a block stored by an older version
+    pub(super) fn stage_drop_legacy(&self, tx: &mut KvBatch<B::Tree>, hash: &Hash256) {
+        tx.delete(&self.db, &Self::legacy_key(LEGACY_BLOCK_PREFIX, hash));
+        tx.delete(&self.db, &Self::legacy_key(LEGACY_UNDO_PREFIX, hash));
+    }
+
+    // Helper: index entries of the blocks whose data is in block file `file`
+    fn entries_in_file(&self, file: u32) -> Result<Vec<(Hash256, BlockIndexEntry)>, String> {
+        let mut entries = Vec::new();
+        for item in self.db.scan_prefix(INDEX_PREFIX) {
+            let (key, data) = item?;
+            let entry = BlockIndexEntry::from_bytes(&data)?;
+            if entry.has_data() && entry.data.file == file {
+                entries.push((Hash256::new(key[1..33].try_into().unwrap()), entry));
+            }
+        }
+        Ok(entries)
+    }
+
+    // Helper: file info as staged in `tx`, or empty for a new file
+    fn staged_file_info(&self, tx: &KvBatch<B::Tree>, file: u32) -> Result<BlockFileInfo, String> {
+        match tx.get(&self.db, &Self::file_info_key(file))? {
+            Some(data) => BlockFileInfo::from_bytes(&data),
+            None => Ok(BlockFileInfo::default()),
+        }
+    }
+
+    // Helper: create key for a block index entry
+    fn index_key(hash: &Hash256) -> Vec<u8> {
+        let mut key = Vec::with_capacity(33);
+        key.extend_from_slice(INDEX_PREFIX);
+        key.extend_from_slice(hash.as_bytes());
+        key
+    }
+
+    // Helper: create key for the header of a pruned or header-only block
+    fn header_key(hash: &Hash256) -> Vec<u8> {
         let mut key = Vec::with_capacity(33);
-        key.push(b'b'); // 'b' for block
+        key.push(b'H'); // 'H' for header
         key.extend_from_slice(hash.as_bytes());
         key
     }
 
-    // Helper: create key for height index
-    fn height_key(height: u32) -> Vec<u8> {
+    // Helper: create key for block file info
+    fn file_info_key(file: u32) -> Vec<u8> {
         let mut key = Vec::with_capacity(5);
-        key.push(b'h'); // 'h' for height
-        key.extend_from_slice(&height.to_le_bytes());
+        key.extend_from_slice(FILE_INFO_PREFIX);
+        key.extend_from_slice(&file.to_le_bytes());
+        key
+    }
+
+    // Helper: key of a block or undo record stored inside the database by older versions
+    fn legacy_key(prefix: &[u8], hash: &Hash256) -> Vec<u8> {
+        let mut key = Vec::with_capacity(33);
+        key.extend_from_slice(prefix);
+        key.extend_from_slice(hash.as_bytes());
+        key
+    }
+
+    // Helper: create key for height index (big-endian, so keys sort by height)
+    pub(super) fn height_key(height: u32) -> Vec<u8> {
+        let mut key = Vec::with_capacity(5);
+        key.extend_from_slice(HEIGHT_PREFIX);
+        key.extend_from_slice(&height.to_be_bytes());
         key
     }
 }
@@ -176,7 +767,7 @@ mod tests {
         let block = Block::genesis();
 
         // Store block
-        db.store_block(&block).unwrap();
+        db.store_block(&block, 0).unwrap();
 
         // Retrieve block
         let hash = block.hash();
@@ -192,7 +783,7 @@ mod tests {
         let hash = block.hash();
 
         // Store block first
-        db.store_block(&block).unwrap();
+        db.store_block(&block, 0).unwrap();
 
         // Store height mapping
         db.store_height(0, &hash).unwrap();
@@ -241,9 +832,79 @@ mod tests {
         assert!(!db.has_block(&hash).unwrap());
 
         // Store block
-        db.store_block(&block).unwrap();
+        db.store_block(&block, 0).unwrap();
 
         // Block exists now
         assert!(db.has_block(&hash).unwrap());
     }
+
+    #[test]
+    fn test_header_is_indexed_ahead_of_its_block() {
+        let db = BlockchainDB::memory().unwrap();
+        let block = Block::genesis();
+        let hash = block.hash();
+
+        assert!(db.store_header(&block.header, 0).unwrap());
+        assert!(!db.store_header(&block.header, 0).unwrap());
+        assert_eq!(db.get_header(&hash).unwrap(), Some(block.header.clone()));
+        assert!(!db.has_block(&hash).unwrap());
+        assert_eq!(db.get_block(&hash).unwrap(), None);
+
+        // Storing the block replaces the header-only entry
+        db.store_block(&block, 0).unwrap();
+        assert!(db.get_index_entry(&hash).unwrap().unwrap().has_data());
+        assert!(!db.store_header(&block.header, 0).unwrap());
+    }
+
+    #[test]
+    fn test_is_ancestor() {
+        let db = BlockchainDB::memory().unwrap();
+        let genesis = Block::genesis();
+        let mut child = Block::genesis();
+        child.header.prev_block_hash = genesis.hash();
+        child.header.nonce += 1;
+        db.store_block(&genesis, 0).unwrap();
+        db.store_block(&child, 1).unwrap();
+
+        assert!(db.is_ancestor(&genesis.hash(), &child.hash()).unwrap());
+        assert!(db.is_ancestor(&child.hash(), &child.hash()).unwrap());
+        assert!(!db.is_ancestor(&child.hash(), &genesis.hash()).unwrap());
+        // Unknown descendants prove nothing
+        assert!(!db.is_ancestor(&genesis.hash(), &Hash256::new([7; 32])).unwrap());
+    }
+
+    #[test]
+    fn test_index_entries_and_file_info() {
+        let db = BlockchainDB::memory().unwrap();
+        let genesis = Block::genesis();
+        let mut child = Block::genesis();
+        child.header.prev_block_hash = genesis.hash();
+        let first = db.store_block(&genesis, 0).unwrap();
+        let second = db.store_block(&child, 1).unwrap();
+
+        assert_eq!(db.get_index_entry(&child.hash()).unwrap(), Some(second));
+        assert_eq!(second.data.offset, first.data.offset + first.data.length + 8);
+        assert_eq!(second.undo_pos(), None);
+        assert_eq!(db.get_undo(&child.hash()).unwrap().map(|undo| undo.spent.len()), None);
+
+        let info = db.get_file_info(0).unwrap().unwrap();
+        assert_eq!((info.blocks, info.height_first, info.height_last), (2, 0, 1));
+        assert_eq!(info.size, (first.data.length + second.data.length + 16) as u64);
+        assert_eq!(db.get_file_info(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_assumed_valid_stats() {
+        let db = BlockchainDB::memory().unwrap();
+        assert_eq!(db.get_assumed_valid().unwrap(), None);
+
+        db.backend.transaction(|tx| db.stage_assumed_valid(tx, 1)).unwrap();
+        db.backend
+            .transaction(|tx| {
+                db.stage_assumed_valid(tx, 3)?;
+                db.stage_assumed_valid(tx, 2)
+            })
+            .unwrap();
+        assert_eq!(db.get_assumed_valid().unwrap(), Some((3, 3)));
+    }
 }
@@ -0,0 +1,175 @@
+// Bootstrap files: a chain as one stream of framed blocks
+//
+// The format of Bitcoin Core's bootstrap.dat, which is also the record format
+// of the blk files: each block is the network magic, its length (u32 LE) and
+// the serialized block, in height order from genesis. Zero bytes between
+// records (the padding Core leaves in blk files) are skipped, so a blk file
+// can be imported as well.
+
+use super::NETWORK_MAGIC;
+use crate::core::{Block, Serializable};
+use std::io::{ErrorKind, Read, Write};
+
+/// Largest record accepted; no valid block serializes to more than its weight limit
+const MAX_RECORD_SIZE: u32 = 4_000_000;
+
+/// Writes blocks to a bootstrap stream
+pub struct BootstrapWriter<W: Write> {
+    out: W,
+    blocks: u32,
+    bytes: u64,
+}
+
+impl<W: Write> BootstrapWriter<W> {
+    /// Write records to `out` (wrap files in a `BufWriter`)
+    pub fn new(out: W) -> Self {
+        Self { out, blocks: 0, bytes: 0 }
+    }
+
+    /// Append one block record
+    pub fn write_block(&mut self, block: &Block) -> Result<(), String> {
+        let data = block.serialize();
+        let length = u32::try_from(data.len()).map_err(|_| format!("Block too large: {} bytes", data.len()))?;
+        let mut record = Vec::with_capacity(8 + data.len());
+        record.extend_from_slice(&NETWORK_MAGIC);
+        record.extend_from_slice(&length.to_le_bytes());
+        record.extend_from_slice(&data);
+
+        self.out
+            .write_all(&record)
+            .map_err(|e| format!("Failed to write block {}: {}", self.blocks, e))?;
+        self.blocks += 1;
+        self.bytes += record.len() as u64;
+        Ok(())
+    }
+
+    /// Flush the stream; returns the number of blocks and bytes written
+    pub fn finish(mut self) -> Result<(u32, u64), String> {
+        self.out.flush().map_err(|e| format!("Failed to flush bootstrap file: {}", e))?;
+        Ok((self.blocks, self.bytes))
+    }
+}
+
+/// Reads blocks from a bootstrap stream, one record at a time
+pub struct BootstrapReader<R: Read> {
+    input: R,
+    /// Bytes consumed so far
+    offset: u64,
+}
+
+impl<R: Read> BootstrapReader<R> {
+    /// Read records from `input` (wrap files in a `BufReader`)
+    pub fn new(input: R) -> Self {
+        Self { input, offset: 0 }
+    }
+
+    /// Next block, or `None` at the end of the stream
+    /// A truncated record, foreign bytes or an undecodable block is an error.
+    pub fn next_block(&mut self) -> Result<Option<Block>, String> {
+        // Find the start of the next record past any zero padding
+        let mut byte = [0u8; 1];
+        loop {
+            if !self.read_exact_or_eof(&mut byte)? {
+                return Ok(None);
+            }
+            if byte[0] != 0 {
+                break;
+            }
+        }
+
+        let start = self.offset - 1;
+        let mut rest = [0u8; 7];
+        if !self.read_exact_or_eof(&mut rest)? || [byte[0], rest[0], rest[1], rest[2]] != NETWORK_MAGIC {
+            return Err(format!("No block record at offset {}", start));
+        }
+
+        let length = u32::from_le_bytes(rest[3..7].try_into().unwrap());
+        if length > MAX_RECORD_SIZE {
+            return Err(format!("Record at offset {} claims {} bytes", start, length));
+        }
+        let mut data = vec![0u8; length as usize];
+        if !self.read_exact_or_eof(&mut data)? {
+            return Err(format!("Record at offset {} is truncated", start));
+        }
+
+        Block::deserialize(&data)
+            .map(Some)
+            .map_err(|e| format!("Record at offset {} is not a block: {}", start, e))
+    }
+
+    // Helper: fill `buf`, or return false if the stream ends before it is full
+    fn read_exact_or_eof(&mut self, buf: &mut [u8]) -> Result<bool, String> {
+        match self.input.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len() as u64;
+                Ok(true)
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(format!("Failed to read bootstrap file at offset {}: {}", self.offset, e)),
+        }
+    }
+}
+
+impl<R: Read> Iterator for BootstrapReader<R> {
+    type Item = Result<Block, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_block().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{BlockHeader, Hash256, Transaction, TxOutput};
+
+    // Helper: a one-transaction block at `height` on `prev`
+    fn block(prev: Hash256, height: u32) -> Block {
+        let transactions = vec![Transaction::coinbase(vec![height as u8], TxOutput::new(50, vec![1]), height)];
+        let merkle_root = Block::calculate_merkle_root(&transactions);
+        Block::new(BlockHeader::new(1, prev, merkle_root, height, 0x20ffffff, 0), transactions)
+    }
+
+    #[test]
+    fn test_blocks_round_trip_with_padding() {
+        let first = block(Hash256::zero(), 0);
+        let second = block(first.hash(), 1);
+
+        let mut writer = BootstrapWriter::new(Vec::new());
+        writer.write_block(&first).unwrap();
+        writer.write_block(&second).unwrap();
+        let bytes = writer.out.clone();
+        assert_eq!(writer.finish().unwrap(), (2, bytes.len() as u64));
+
+        // Zero padding between and after records is skipped
+        let split = 8 + first.serialize().len();
+        let mut padded = bytes[..split].to_vec();
+        padded.extend_from_slice(&[0; 16]);
+        padded.extend_from_slice(&bytes[split..]);
+        padded.extend_from_slice(&[0; 5]);
+
+        let blocks: Vec<Block> = BootstrapReader::new(padded.as_slice()).map(|block| block.unwrap()).collect();
+        assert_eq!(blocks, vec![first, second]);
+    }
+
+    #[test]
+    fn test_damaged_streams_are_rejected() {
+        let mut writer = BootstrapWriter::new(Vec::new());
+        writer.write_block(&block(Hash256::zero(), 0)).unwrap();
+        let bytes = writer.out;
+
+        let mut reader = BootstrapReader::new(&bytes[..bytes.len() - 1]);
+        assert!(reader.next_block().unwrap_err().contains("truncated"));
+
+        let mut garbage = bytes.clone();
+        garbage[1] ^= 0xff;
+        assert_eq!(
+            BootstrapReader::new(garbage.as_slice()).next_block().unwrap_err(),
+            "No block record at offset 0"
+        );
+
+        let mut huge = bytes.clone();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BootstrapReader::new(huge.as_slice()).next_block().unwrap_err().contains("claims"));
+    }
+}
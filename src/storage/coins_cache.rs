@@ -0,0 +1,344 @@
+// Layered coins views: an in-memory write-back cache over the UTXO set
+//
+// A view answers UTXO lookups. `UtxoSet` is the view backed by the
+// database; `CoinsViewCache` is a memory layer over any other view. Reads
+// that miss the cache go to the parent and are kept; changes stay in the
+// cache until they are handed to the parent. Each entry carries two flags:
+// - dirty: the entry differs from the parent and has to be written back
+// - fresh: the parent does not have the output, so spending it again
+//   before a flush just drops the entry instead of writing a deletion
+//
+// `Storage` keeps one cache over the database and writes it back at block
+// boundaries. Blocks are validated and applied in a child view over that
+// cache; the child is merged only once the block is accepted, and simply
+// dropped otherwise.
+
+use super::{KvBackend, OutPoint, Utxo, UtxoSet};
+use crate::core::Hash256;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Read access to a set of unspent outputs
+pub trait CoinsView {
+    /// Get a UTXO
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, String>;
+
+    /// Check if a UTXO exists
+    fn has_utxo(&self, outpoint: &OutPoint) -> Result<bool, String> {
+        Ok(self.get_utxo(outpoint)?.is_some())
+    }
+}
+
+impl<B: KvBackend> CoinsView for UtxoSet<B> {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, String> {
+        UtxoSet::get_utxo(self, outpoint)
+    }
+
+    fn has_utxo(&self, outpoint: &OutPoint) -> Result<bool, String> {
+        UtxoSet::has_utxo(self, outpoint)
+    }
+}
+
+/// One cached output
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    /// The output, or `None` once it is spent
+    pub utxo: Option<Utxo>,
+    /// Differs from the parent view
+    pub dirty: bool,
+    /// Not present in the parent view
+    pub fresh: bool,
+}
+
+/// Cached outputs with their flags and an estimate of their memory use
+#[derive(Debug, Default)]
+pub struct CoinsCache {
+    entries: HashMap<OutPoint, CacheEntry>,
+    memory: usize,
+    best_block: Option<Hash256>,
+}
+
+impl CoinsCache {
+    /// Cached entry for an outpoint
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&CacheEntry> {
+        self.entries.get(outpoint)
+    }
+
+    /// Remember an output read from the parent view
+    pub fn insert_clean(&mut self, outpoint: &OutPoint, utxo: &Utxo) {
+        let entry = CacheEntry {
+            utxo: Some(utxo.clone()),
+            dirty: false,
+            fresh: false,
+        };
+        self.put(outpoint.clone(), entry);
+    }
+
+    /// Add an output
+    /// `possible_overwrite` is set when the parent may already hold the
+    /// outpoint; otherwise the entry is marked fresh.
+    pub fn add(&mut self, outpoint: &OutPoint, utxo: Utxo, possible_overwrite: bool) {
+        // A pending deletion keeps the entry non-fresh: the parent has it
+        let fresh = match self.entries.get(outpoint) {
+            Some(entry) => entry.fresh,
+            None => !possible_overwrite,
+        };
+        let entry = CacheEntry {
+            utxo: Some(utxo),
+            dirty: true,
+            fresh,
+        };
+        self.put(outpoint.clone(), entry);
+    }
+
+    /// Spend a cached output
+    /// Returns the output, or `None` if it is not cached unspent.
+    pub fn spend(&mut self, outpoint: &OutPoint) -> Option<Utxo> {
+        let entry = self.entries.get(outpoint)?;
+        entry.utxo.as_ref()?;
+
+        if entry.fresh {
+            return self.take(outpoint).and_then(|entry| entry.utxo);
+        }
+        let mut entry = self.take(outpoint)?;
+        let utxo = entry.utxo.take();
+        entry.dirty = true;
+        self.put(outpoint.clone(), entry);
+        utxo
+    }
+
+    /// Apply the dirty entries of a child view
+    pub fn merge(&mut self, changes: HashMap<OutPoint, CacheEntry>) {
+        for (outpoint, child) in changes {
+            match self.entries.get(&outpoint) {
+                // Created and spent above a parent that never had it
+                Some(entry) if entry.fresh && child.utxo.is_none() => {
+                    self.take(&outpoint);
+                }
+                Some(entry) => {
+                    let entry = CacheEntry {
+                        utxo: child.utxo,
+                        dirty: true,
+                        fresh: entry.fresh,
+                    };
+                    self.put(outpoint, entry);
+                }
+                None if child.fresh && child.utxo.is_none() => {}
+                None => {
+                    let entry = CacheEntry {
+                        utxo: child.utxo,
+                        dirty: true,
+                        fresh: child.fresh,
+                    };
+                    self.put(outpoint, entry);
+                }
+            }
+        }
+    }
+
+    /// Remove and return the dirty entries, dropping the clean ones
+    pub fn take_dirty(&mut self) -> HashMap<OutPoint, CacheEntry> {
+        self.memory = 0;
+        std::mem::take(&mut self.entries)
+            .into_iter()
+            .filter(|(_, entry)| entry.dirty)
+            .collect()
+    }
+
+    /// Dirty entries, for writing back without emptying the cache
+    pub fn dirty(&self) -> impl Iterator<Item = (&OutPoint, &CacheEntry)> {
+        self.entries.iter().filter(|(_, entry)| entry.dirty)
+    }
+
+    /// Drop every entry
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.memory = 0;
+        self.best_block = None;
+    }
+
+    /// Block the cached changes bring the parent up to
+    pub fn best_block(&self) -> Option<Hash256> {
+        self.best_block
+    }
+
+    /// Record the block the cached changes bring the parent up to
+    pub fn set_best_block(&mut self, hash: Hash256) {
+        self.best_block = Some(hash);
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Estimated memory use in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.memory
+    }
+
+    // Helper: insert an entry, keeping the memory estimate
+    fn put(&mut self, outpoint: OutPoint, entry: CacheEntry) {
+        self.memory += Self::entry_usage(&entry);
+        if let Some(old) = self.entries.insert(outpoint, entry) {
+            self.memory -= Self::entry_usage(&old);
+        }
+    }
+
+    // Helper: remove an entry, keeping the memory estimate
+    fn take(&mut self, outpoint: &OutPoint) -> Option<CacheEntry> {
+        let entry = self.entries.remove(outpoint)?;
+        self.memory -= Self::entry_usage(&entry);
+        Some(entry)
+    }
+
+    // Helper: bytes an entry occupies (map slot plus script)
+    fn entry_usage(entry: &CacheEntry) -> usize {
+        let script = entry.utxo.as_ref().map_or(0, |utxo| utxo.output.script_pubkey.capacity());
+        std::mem::size_of::<(OutPoint, CacheEntry)>() + script
+    }
+}
+
+/// Memory cache over a parent view
+/// Dropping the view discards its changes; `into_changes` hands them to the parent.
+pub struct CoinsViewCache<'a> {
+    base: &'a dyn CoinsView,
+    cache: RefCell<CoinsCache>,
+}
+
+impl<'a> CoinsViewCache<'a> {
+    /// Create an empty view over `base`
+    pub fn new(base: &'a dyn CoinsView) -> Self {
+        Self {
+            base,
+            cache: RefCell::new(CoinsCache::default()),
+        }
+    }
+
+    /// Add an output created by a transaction
+    /// Coinbase outputs pass `possible_overwrite`, since a coinbase can repeat an earlier txid.
+    pub fn add_utxo(&mut self, outpoint: &OutPoint, utxo: Utxo, possible_overwrite: bool) {
+        self.cache.get_mut().add(outpoint, utxo, possible_overwrite);
+    }
+
+    /// Spend an output, loading it from the parent if needed
+    /// Returns the spent output, or `None` if it does not exist.
+    pub fn spend_utxo(&mut self, outpoint: &OutPoint) -> Result<Option<Utxo>, String> {
+        self.get_utxo(outpoint)?;
+        Ok(self.cache.get_mut().spend(outpoint))
+    }
+
+    /// Estimated memory use in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.cache.borrow().memory_usage()
+    }
+
+    /// The changes to apply to the parent with `CoinsCache::merge`
+    pub fn into_changes(self) -> HashMap<OutPoint, CacheEntry> {
+        self.cache.into_inner().take_dirty()
+    }
+}
+
+impl CoinsView for CoinsViewCache<'_> {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, String> {
+        if let Some(entry) = self.cache.borrow().get(outpoint) {
+            return Ok(entry.utxo.clone());
+        }
+
+        let utxo = self.base.get_utxo(outpoint)?;
+        if let Some(utxo) = &utxo {
+            self.cache.borrow_mut().insert_clean(outpoint, utxo);
+        }
+        Ok(utxo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Hash256, TxOutput};
+    use crate::storage::MemoryBackend;
+
+    // Helper: outpoint of output 0 of a made-up transaction
+    fn outpoint(n: u8) -> OutPoint {
+        OutPoint::new(Hash256::new([n; 32]), 0)
+    }
+
+    // Helper: UTXO set holding outputs 1 and 2
+    fn funded() -> UtxoSet<MemoryBackend> {
+        let utxo_set = UtxoSet::memory().unwrap();
+        for n in 1..=2 {
+            let utxo = Utxo::new(TxOutput::new(n as u64 * 10, vec![n]), 0, false);
+            utxo_set.add_utxo(&outpoint(n), &utxo).unwrap();
+        }
+        utxo_set
+    }
+
+    #[test]
+    fn test_changes_stay_in_the_view() {
+        let utxo_set = funded();
+        let mut view = CoinsViewCache::new(&utxo_set);
+
+        assert_eq!(view.spend_utxo(&outpoint(1)).unwrap().unwrap().output.value, 10);
+        assert!(view.spend_utxo(&outpoint(1)).unwrap().is_none());
+        view.add_utxo(&outpoint(3), Utxo::new(TxOutput::new(5, vec![3]), 1, false), false);
+
+        assert!(!view.has_utxo(&outpoint(1)).unwrap());
+        assert!(view.has_utxo(&outpoint(3)).unwrap());
+        assert!(view.memory_usage() > 0);
+
+        // Dropping the view leaves the parent untouched
+        drop(view);
+        assert!(utxo_set.has_utxo(&outpoint(1)).unwrap());
+        assert!(!utxo_set.has_utxo(&outpoint(3)).unwrap());
+    }
+
+    #[test]
+    fn test_fresh_outputs_spent_before_flush_leave_no_entry() {
+        let utxo_set = funded();
+        let mut view = CoinsViewCache::new(&utxo_set);
+
+        view.add_utxo(&outpoint(3), Utxo::new(TxOutput::new(5, vec![3]), 1, false), false);
+        view.spend_utxo(&outpoint(3)).unwrap();
+        view.spend_utxo(&outpoint(2)).unwrap();
+
+        let changes = view.into_changes();
+        assert_eq!(changes.len(), 1);
+        let entry = &changes[&outpoint(2)];
+        assert!(entry.dirty && !entry.fresh && entry.utxo.is_none());
+    }
+
+    #[test]
+    fn test_merge_applies_child_changes() {
+        let mut parent = CoinsCache::default();
+        parent.add(&outpoint(3), Utxo::new(TxOutput::new(5, vec![3]), 1, false), false);
+        parent.insert_clean(&outpoint(1), &Utxo::new(TxOutput::new(10, vec![1]), 0, false));
+
+        let utxo_set = funded();
+        let mut child = CoinsViewCache::new(&utxo_set);
+        child.add_utxo(&outpoint(3), Utxo::new(TxOutput::new(5, vec![3]), 1, false), false);
+        child.spend_utxo(&outpoint(3)).unwrap();
+        child.spend_utxo(&outpoint(1)).unwrap();
+        child.add_utxo(&outpoint(4), Utxo::new(TxOutput::new(7, vec![4]), 2, false), false);
+
+        // Spending the parent's fresh output in the child must drop it too
+        let mut changes = child.into_changes();
+        changes.insert(outpoint(3), CacheEntry { utxo: None, dirty: true, fresh: false });
+        parent.merge(changes);
+
+        assert!(parent.get(&outpoint(3)).is_none());
+        let spent = parent.get(&outpoint(1)).unwrap();
+        assert!(spent.dirty && spent.utxo.is_none());
+        assert!(parent.get(&outpoint(4)).unwrap().fresh);
+
+        let dirty = parent.take_dirty();
+        assert_eq!(dirty.len(), 2);
+        assert!(parent.is_empty());
+        assert_eq!(parent.memory_usage(), 0);
+    }
+}
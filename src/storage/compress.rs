@@ -0,0 +1,238 @@
+// UTXO compression (the format of Bitcoin Core's chainstate)
+//
+// Integers are written as VARINTs: base-128 digits, most significant first,
+// with the high bit set on every byte but the last and one subtracted per
+// continuation byte, so each value has exactly one encoding. (Unlike the
+// CompactSize varints of the wire format, small values take one byte up to
+// 127.)
+//
+// Amounts drop their trailing decimal zeros: 50 BTC is stored as 1 byte.
+//
+// The common scripts are reduced to their payload, behind a one-byte type:
+//   0x00 + 20 bytes   P2PKH key hash
+//   0x01 + 20 bytes   P2SH script hash
+//   0x02/0x03 + 32    P2PK with a compressed key (the key's own prefix)
+//   0x04/0x05 + 32    P2PK with an uncompressed key: x coordinate, y parity in the type
+// Any other script is VARINT(length + 6) followed by the script.
+
+use crate::core::TxOutput;
+use secp256k1::PublicKey;
+use std::io::Read;
+
+/// Number of special script types (lengths are stored above them)
+const SPECIAL_SCRIPTS: u64 = 6;
+
+/// Longest script accepted when decoding
+const MAX_SCRIPT_SIZE: u64 = 10_000;
+
+/// Write `value` as a VARINT
+pub(super) fn write_var_int(bytes: &mut Vec<u8>, mut value: u64) {
+    let mut digits = Vec::with_capacity(10);
+    loop {
+        let continuation = if digits.is_empty() { 0 } else { 0x80 };
+        digits.push((value & 0x7f) as u8 | continuation);
+        if value <= 0x7f {
+            break;
+        }
+        value = (value >> 7) - 1;
+    }
+    bytes.extend(digits.iter().rev());
+}
+
+/// Read a VARINT
+pub(super) fn read_var_int<R: Read>(reader: &mut R) -> Result<u64, String> {
+    let mut value: u64 = 0;
+    loop {
+        let byte = read_byte(reader)?;
+        if value > u64::MAX >> 7 {
+            return Err("VARINT is too large".to_string());
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        value = value.checked_add(1).ok_or("VARINT is too large")?;
+    }
+}
+
+/// Compress an amount: mantissa and decimal exponent in one number
+/// Lossless up to about 1.8 * 10^18 satoshis, far above the money supply.
+pub(super) fn compress_amount(mut amount: u64) -> u64 {
+    if amount == 0 {
+        return 0;
+    }
+    let mut exponent = 0;
+    while amount.is_multiple_of(10) && exponent < 9 {
+        amount /= 10;
+        exponent += 1;
+    }
+    if exponent < 9 {
+        let digit = amount % 10;
+        amount /= 10;
+        amount.wrapping_mul(9).wrapping_add(digit - 1).wrapping_mul(10).wrapping_add(exponent + 1)
+    } else {
+        1 + (amount - 1) * 10 + 9
+    }
+}
+
+/// Reverse `compress_amount`
+pub(super) fn decompress_amount(mut compressed: u64) -> Result<u64, String> {
+    if compressed == 0 {
+        return Ok(0);
+    }
+    compressed -= 1;
+    let mut exponent = compressed % 10;
+    compressed /= 10;
+
+    let mut amount = if exponent < 9 {
+        let digit = compressed % 9 + 1;
+        compressed /= 9;
+        compressed.checked_mul(10).and_then(|n| n.checked_add(digit))
+    } else {
+        compressed.checked_add(1)
+    };
+    while exponent > 0 {
+        amount = amount.and_then(|n| n.checked_mul(10));
+        exponent -= 1;
+    }
+    amount.ok_or_else(|| "Compressed amount is out of range".to_string())
+}
+
+/// Write an output as its compressed amount and script
+pub(super) fn write_output(bytes: &mut Vec<u8>, output: &TxOutput) {
+    write_var_int(bytes, compress_amount(output.value));
+    let script = &output.script_pubkey;
+    match special_script(script) {
+        Some((kind, payload)) => {
+            bytes.push(kind);
+            bytes.extend_from_slice(payload);
+        }
+        None => {
+            write_var_int(bytes, script.len() as u64 + SPECIAL_SCRIPTS);
+            bytes.extend_from_slice(script);
+        }
+    }
+}
+
+/// Read an output written by `write_output`
+pub(super) fn read_output<R: Read>(reader: &mut R) -> Result<TxOutput, String> {
+    let value = decompress_amount(read_var_int(reader)?)?;
+    let kind = read_var_int(reader)?;
+
+    let script_pubkey = match kind {
+        0 => [&[0x76, 0xa9, 20][..], &read_array::<_, 20>(reader)?, &[0x88, 0xac]].concat(),
+        1 => [&[0xa9, 20][..], &read_array::<_, 20>(reader)?, &[0x87]].concat(),
+        2 | 3 => [&[33, kind as u8][..], &read_array::<_, 32>(reader)?, &[0xac]].concat(),
+        4 | 5 => {
+            let x = read_array::<_, 32>(reader)?;
+            let key = PublicKey::from_slice(&[&[kind as u8 - 2][..], &x].concat())
+                .map_err(|e| format!("Invalid compressed P2PK key: {}", e))?;
+            [&[65][..], &key.serialize_uncompressed(), &[0xac]].concat()
+        }
+        _ => {
+            let length = kind - SPECIAL_SCRIPTS;
+            if length > MAX_SCRIPT_SIZE {
+                return Err(format!("Script of {} bytes is too large", length));
+            }
+            let mut script = vec![0u8; length as usize];
+            reader.read_exact(&mut script).map_err(|_| "Truncated script".to_string())?;
+            script
+        }
+    };
+
+    Ok(TxOutput::new(value, script_pubkey))
+}
+
+// Helper: type byte and payload of a script with a special encoding
+fn special_script(script: &[u8]) -> Option<(u8, &[u8])> {
+    match script {
+        [0x76, 0xa9, 20, hash @ .., 0x88, 0xac] if hash.len() == 20 => Some((0x00, hash)),
+        [0xa9, 20, hash @ .., 0x87] if hash.len() == 20 => Some((0x01, hash)),
+        [33, key @ .., 0xac] if key.len() == 33 && matches!(key[0], 0x02 | 0x03) => Some((key[0], &key[1..])),
+        // Only a valid key can be rebuilt from its x coordinate
+        [65, key @ .., 0xac] if key.len() == 65 && key[0] == 0x04 && PublicKey::from_slice(key).is_ok() => {
+            Some((0x04 | (key[64] & 1), &key[1..33]))
+        }
+        _ => None,
+    }
+}
+
+// Helper: read one byte
+fn read_byte<R: Read>(reader: &mut R) -> Result<u8, String> {
+    Ok(read_array::<_, 1>(reader)?[0])
+}
+
+// Helper: read a fixed number of bytes
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes).map_err(|_| "Unexpected end of compressed data".to_string())?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Script;
+    use secp256k1::{Secp256k1, SecretKey};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_var_int_matches_bitcoin_core() {
+        // Vectors from Bitcoin Core's serialize_tests
+        for (value, encoded) in [
+            (0u64, vec![0x00]),
+            (0x7f, vec![0x7f]),
+            (0x80, vec![0x80, 0x00]),
+            (0x1234, vec![0xa3, 0x34]),
+            (0xffff, vec![0x82, 0xfe, 0x7f]),
+            (0x123456, vec![0xc7, 0xe7, 0x56]),
+            (u64::MAX, vec![0x80, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0x7f]),
+        ] {
+            let mut bytes = Vec::new();
+            write_var_int(&mut bytes, value);
+            assert_eq!(bytes, encoded);
+            assert_eq!(read_var_int(&mut Cursor::new(&bytes)).unwrap(), value);
+        }
+        assert!(read_var_int(&mut Cursor::new([0xff; 11])).is_err());
+    }
+
+    #[test]
+    fn test_amounts_round_trip() {
+        // Vectors from Bitcoin Core's compress_tests
+        assert_eq!(compress_amount(0), 0);
+        assert_eq!(compress_amount(1), 1);
+        assert_eq!(compress_amount(1_000_000), 7);
+        assert_eq!(compress_amount(100_000_000), 9);
+        assert_eq!(compress_amount(50 * 100_000_000), 50);
+        assert_eq!(compress_amount(21_000_000 * 100_000_000), 21_000_000);
+
+        for amount in [0, 1, 9, 10, 123_456_789, 5_000_000_000, 1_000_000_000_000_000_000, 1_844_674_407_370_955_161] {
+            assert_eq!(decompress_amount(compress_amount(amount)).unwrap(), amount);
+        }
+        assert!(decompress_amount(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_scripts_round_trip() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[7; 32]).unwrap().public_key(&secp);
+        let p2pk = |key: &[u8]| [&[key.len() as u8][..], key, &[0xac]].concat();
+
+        for (script, size) in [
+            (Script::p2pkh_script_pubkey(&[5; 20]), 21),
+            ([&[0xa9, 20][..], &[6; 20], &[0x87]].concat(), 21),
+            (p2pk(&key.serialize()), 33),
+            (p2pk(&key.serialize_uncompressed()), 33),
+            // Not a valid key, so stored as is
+            (p2pk(&[[0x04].as_slice(), &[1; 64]].concat()), 68),
+            (vec![1, 2, 3], 4),
+            (vec![], 1),
+        ] {
+            let output = TxOutput::new(50, script);
+            let mut bytes = Vec::new();
+            write_output(&mut bytes, &output);
+            assert_eq!(bytes.len(), 1 + size);
+            assert_eq!(read_output(&mut Cursor::new(&bytes)).unwrap(), output);
+        }
+    }
+}
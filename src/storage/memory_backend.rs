@@ -0,0 +1,135 @@
+// In-memory implementation of the key-value backend
+//
+// Every tree is a `BTreeMap` behind one shared lock, so a batch is applied
+// under a single write lock and readers never see half of it. Nothing is
+// persisted: the data is gone when the last handle is dropped.
+
+use super::{KvBackend, KvBatch, KvIter, KvTree};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Name of the default tree
+const DEFAULT_TREE: &str = "";
+
+type Trees = BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+/// Database held in memory
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    trees: Arc<RwLock<Trees>>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl MemoryBackend {
+    /// Create an empty database
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Helper: handle to a tree, creating it if needed
+    fn tree(&self, name: &str) -> MemoryTree {
+        self.trees.write().unwrap().entry(name.to_string()).or_default();
+        MemoryTree {
+            name: name.to_string(),
+            trees: self.trees.clone(),
+        }
+    }
+}
+
+impl KvBackend for MemoryBackend {
+    type Tree = MemoryTree;
+
+    fn default_tree(&self) -> MemoryTree {
+        self.tree(DEFAULT_TREE)
+    }
+
+    fn open_tree(&self, name: &str) -> Result<MemoryTree, String> {
+        Ok(self.tree(name))
+    }
+
+    fn write_batch(&self, batch: &KvBatch<MemoryTree>) -> Result<(), String> {
+        let mut trees = self.trees.write().unwrap();
+        for (tree, writes) in batch.trees() {
+            let entries = trees.entry(tree.name.clone()).or_default();
+            for (key, value) in writes {
+                match value {
+                    Some(value) => entries.insert(key.clone(), value.clone()),
+                    None => entries.remove(key),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn transaction_lock(&self) -> &Mutex<()> {
+        &self.lock
+    }
+}
+
+/// One tree of an in-memory database
+#[derive(Clone)]
+pub struct MemoryTree {
+    name: String,
+    trees: Arc<RwLock<Trees>>,
+}
+
+impl MemoryTree {
+    // Helper: run `f` on the entries of this tree
+    fn with<R>(&self, f: impl FnOnce(&BTreeMap<Vec<u8>, Vec<u8>>) -> R) -> R {
+        let trees = self.trees.read().unwrap();
+        match trees.get(&self.name) {
+            Some(entries) => f(entries),
+            None => f(&BTreeMap::new()),
+        }
+    }
+
+    // Helper: run `f` on the entries of this tree, for writing
+    fn with_mut<R>(&self, f: impl FnOnce(&mut BTreeMap<Vec<u8>, Vec<u8>>) -> R) -> R {
+        f(self.trees.write().unwrap().entry(self.name.clone()).or_default())
+    }
+}
+
+impl KvTree for MemoryTree {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.with(|entries| entries.get(key).cloned()))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), String> {
+        self.with_mut(|entries| entries.insert(key.to_vec(), value.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), String> {
+        self.with_mut(|entries| entries.remove(key));
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_> {
+        // Copied out so the lock is not held while the caller iterates
+        let matching: Vec<_> = self.with(|entries| {
+            entries
+                .range(prefix.to_vec()..)
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .map(|(key, value)| Ok((key.clone(), value.clone())))
+                .collect()
+        });
+        Box::new(matching.into_iter())
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        self.with_mut(|entries| entries.clear());
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.with(|entries| entries.len())
+    }
+}
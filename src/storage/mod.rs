@@ -1,39 +1,1250 @@
 // Storage layer for blockchain and UTXO set
+//
+// Blocks and UTXOs live in trees of one key-value backend (sled on disk, or
+// a `BTreeMap` in memory, see backend.rs). Connecting a
+// block (block data, height index, tip, chain height and undo data) commits
+// as a single transaction, so after a crash either all of it or none of it
+// is on disk. Its UTXO changes go to an in-memory coins cache that is
+// written back in one transaction, together with the block it reaches, when
+// it outgrows its size or on `flush`. `recover` replays the blocks above the
+// last written UTXO state, and repairs data written by older versions, which
+// updated the two stores with independent inserts. The undo data (the
+// outputs a block spent) lets `disconnect_block` roll the tip back. Each
+// connection also updates the address history and the optional transaction
+// index. `reset_chainstate` and `reindex_blocks` wipe that state so the
+// stored blocks can be revalidated and connected again from genesis. Opening
+// a database upgrades its schema first (see schema.rs).
 
+mod address_index;
+mod backend;
+mod block_files;
 mod blockchain_db;
+mod bootstrap;
+mod coins_cache;
+mod compress;
+mod memory_backend;
+mod schema;
+mod sled_backend;
+mod txindex;
+mod undo;
 mod utxo_set;
+mod utxo_stats;
 
-pub use blockchain_db::BlockchainDB;
+pub use address_index::{AddressHistoryEntry, AddressIndex};
+pub use backend::{KvBackend, KvBatch, KvIter, KvTree, KvWrites};
+pub use block_files::{block_file_name, undo_file_name, BlockFiles, FilePos, MAX_BLOCKFILE_SIZE, NETWORK_MAGIC};
+pub use blockchain_db::{BlockFileInfo, BlockIndexEntry, BlockchainDB, BLOCK_HAVE_DATA, BLOCK_HAVE_UNDO, BLOCK_PRUNED};
+pub use bootstrap::{BootstrapReader, BootstrapWriter};
+pub use coins_cache::{CacheEntry, CoinsCache, CoinsView, CoinsViewCache};
+pub use memory_backend::{MemoryBackend, MemoryTree};
+pub use schema::SCHEMA_VERSION;
+pub use sled_backend::{SledBackend, SledTree};
+pub use txindex::{TxIndex, TxLocation};
+pub use undo::BlockUndo;
 pub use utxo_set::{UtxoSet, Utxo, OutPoint};
+pub use utxo_stats::UtxoStats;
 
+use crate::core::{Block, Hash256};
+use blockchain_db::{ASSUME_VALID_KEY, HEIGHT_KEY, TIP_KEY, UTXO_TIP_KEY};
+use utxo_set::UtxoBatch;
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Default coins cache size in bytes
+pub const DEFAULT_COINS_CACHE_SIZE: usize = 64 << 20;
+
+/// Blocks below the tip whose data pruning always keeps, so they can be disconnected
+pub const MIN_BLOCKS_TO_KEEP: u32 = 288;
+
+/// Name of the UTXO tree inside the chain database
+const UTXO_TREE: &str = "utxo";
+
+/// Name of the transaction index tree inside the chain database
+const TXINDEX_TREE: &str = "txindex";
+
+/// Name of the address history tree inside the chain database
+const HISTORY_TREE: &str = "address_history";
+
+/// Key of the last block an index has processed (hash + height)
+const INDEX_BEST_KEY: &[u8] = b"best";
 
 /// Storage manager - combines blockchain DB and UTXO set
-pub struct Storage {
-    pub blockchain: BlockchainDB,
-    pub utxo_set: UtxoSet,
+pub struct Storage<B: KvBackend = SledBackend> {
+    pub blockchain: BlockchainDB<B>,
+    pub utxo_set: UtxoSet<B>,
+    pub txindex: TxIndex<B>,
+    pub address_index: AddressIndex<B>,
+    txindex_enabled: bool,
+    coins: Mutex<CoinsCache>,
+    coins_cache_size: usize,
+    prune_target: Option<u64>,
+    backend: B,
 }
 
-impl Storage {
+impl Storage<SledBackend> {
     /// Create a new storage instance
+    /// Imports a UTXO set left in the old separate `utxo` database and
+    /// repairs a half-applied block before returning.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        let blockchain = BlockchainDB::new(path.as_ref().join("blocks"))?;
-        let utxo_set = UtxoSet::new(path.as_ref().join("utxo"))?;
+        let dir = path.as_ref().join("blocks");
+        let storage = Self::with_backend(SledBackend::open(&dir)?, BlockFiles::open(&dir)?)?;
+        storage.import_legacy_utxo(&path.as_ref().join("utxo"))?;
+        storage.open_checks()?;
+        Ok(storage)
+    }
 
-        Ok(Self {
-            blockchain,
-            utxo_set,
-        })
+    // Helper: move a UTXO set from the old separate database into the UTXO tree
+    // Its records are in the plain encoding and are stored compactly.
+    fn import_legacy_utxo(&self, path: &Path) -> Result<(), String> {
+        if !path.is_dir() || self.utxo_set.count()? > 0 {
+            return Ok(());
+        }
+
+        let legacy = sled::open(path).map_err(|e| format!("Failed to open legacy UTXO db: {}", e))?;
+        for item in legacy.iter() {
+            let (key, value) = item.map_err(|e| format!("Iterator error: {}", e))?;
+            self.utxo_set
+                .tree()
+                .put(&key, &Utxo::from_bytes(&value)?.to_compact_bytes())
+                .map_err(|e| format!("Failed to import UTXO: {}", e))?;
+        }
+        self.utxo_set.rebuild_derived()?;
+        self.flush()?;
+        drop(legacy);
+
+        log::info!("Imported legacy UTXO database from {}", path.display());
+        std::fs::remove_dir_all(path).map_err(|e| format!("Failed to remove legacy UTXO db: {}", e))
     }
+}
 
+impl Storage<MemoryBackend> {
     /// Create an in-memory storage (for testing)
     pub fn memory() -> Result<Self, String> {
-        let blockchain = BlockchainDB::memory()?;
-        let utxo_set = UtxoSet::memory()?;
+        Self::with_backend(MemoryBackend::new(), BlockFiles::memory())
+    }
+}
+
+impl<B: KvBackend> Storage<B> {
+    /// Create a storage on any backend, with blocks kept in `files`
+    /// Migrates an older database schema and refuses a newer one, but does not
+    /// run the startup checks `open_checks` performs.
+    pub fn with_backend(backend: B, files: BlockFiles) -> Result<Self, String> {
+        for migration in schema::upgrade(&backend)? {
+            log::info!("Upgraded database schema to {}", migration);
+        }
 
         Ok(Self {
-            blockchain,
-            utxo_set,
+            blockchain: BlockchainDB::from_parts(backend.clone(), backend.default_tree(), Arc::new(files)),
+            utxo_set: UtxoSet::from_trees(
+                backend.clone(),
+                backend.open_tree(UTXO_TREE)?,
+                backend.open_tree(utxo_set::SCRIPT_TREE)?,
+                backend.open_tree(utxo_set::STATS_TREE)?,
+            ),
+            txindex: TxIndex::from_tree(backend.clone(), backend.open_tree(TXINDEX_TREE)?),
+            address_index: AddressIndex::from_tree(backend.clone(), backend.open_tree(HISTORY_TREE)?),
+            txindex_enabled: false,
+            coins: Mutex::new(CoinsCache::default()),
+            coins_cache_size: DEFAULT_COINS_CACHE_SIZE,
+            prune_target: None,
+            backend,
         })
     }
+
+    /// Schema version of the database (`SCHEMA_VERSION` once opened)
+    pub fn schema_version(&self) -> Result<u32, String> {
+        Ok(schema::stored_version(&self.backend)?.unwrap_or(SCHEMA_VERSION))
+    }
+
+    /// Move blocks out of the database, repair a half-applied block and build missing indexes
+    pub fn open_checks(&self) -> Result<(), String> {
+        let moved = self.import_legacy_blocks()?;
+        if moved > 0 {
+            log::info!("Moved {} blocks from the database to block files", moved);
+        }
+        if let Some(repair) = self.recover()? {
+            log::warn!("{}", repair);
+        }
+        self.build_indexes()
+    }
+
+    /// Store `block` as the new tip at `height` and apply it to the coins cache
+    /// Fails without changing anything if an input is not in the UTXO set.
+    pub fn connect_block(&self, block: &Block, height: u32) -> Result<(), String> {
+        self.connect_block_with(self.coins_view(), block, height, false)
+    }
+
+    /// Child view over the coins cache
+    /// Validate a block against it and pass it to `connect_block_with`, or drop
+    /// it to discard everything the validation loaded.
+    pub fn coins_view(&self) -> CoinsViewCache<'_> {
+        CoinsViewCache::new(self)
+    }
+
+    /// Store `block` as the new tip at `height`, applying its UTXO changes in `view`
+    /// The view's changes reach the coins cache only once the block is stored.
+    /// The cache is written back if it grows past its size. A block whose data
+    /// is already stored (reconnected or reindexed) keeps its records. With
+    /// `assumed_valid`, the block is counted as connected without script checks.
+    pub fn connect_block_with(
+        &self,
+        mut view: CoinsViewCache<'_>,
+        block: &Block,
+        height: u32,
+        assumed_valid: bool,
+    ) -> Result<(), String> {
+        let hash = block.hash();
+        let undo = Self::apply_to_view(&mut view, block, height).map_err(|e| format!("Cannot connect block {}: {}", hash, e))?;
+        let chain_height = (height + 1).to_le_bytes();
+
+        // The files are written first: if the process stops before the
+        // transaction commits, nothing points at the new records
+        let stored = self.blockchain.get_index_entry(&hash)?.filter(|entry| entry.has_data());
+        let mut entry = match stored {
+            Some(entry) => entry,
+            None => self.blockchain.write_block(block, height)?,
+        };
+        let new_undo = entry.undo_pos().is_none();
+        if new_undo {
+            self.blockchain.write_undo(&hash, &mut entry, &undo)?;
+        }
+
+        let blocks = self.blockchain.tree();
+        let result = self.backend.transaction(|tx| {
+            match (stored.is_some(), new_undo) {
+                (false, _) => self.blockchain.stage_block(tx, &hash, &entry)?,
+                (true, true) => self.blockchain.stage_undo(tx, &hash, &entry)?,
+                (true, false) => {}
+            }
+            tx.put(blocks, &BlockchainDB::<B>::height_key(height), hash.as_bytes());
+            tx.put(blocks, TIP_KEY, hash.as_bytes());
+            tx.put(blocks, HEIGHT_KEY, &chain_height);
+            if assumed_valid {
+                self.blockchain.stage_assumed_valid(tx, height)?;
+            }
+
+            self.address_index.connect(tx, block, height, &undo.spent)?;
+            if self.txindex_enabled {
+                self.txindex.connect(tx, block, height)?;
+            }
+            Ok(())
+        });
+        result.map_err(|e| format!("Failed to connect block {}: {}", hash, e))?;
+
+        let mut coins = self.coins.lock().unwrap();
+        coins.merge(view.into_changes());
+        coins.set_best_block(hash);
+        if coins.memory_usage() > self.coins_cache_size {
+            self.write_coins(&mut coins)?;
+        }
+        drop(coins);
+
+        // The block is connected either way; a failed prune is retried on the next block
+        if let Err(e) = self.prune_block_files() {
+            log::error!("{}", e);
+        }
+        Ok(())
+    }
+
+    /// Write the coins cache back to the UTXO set and empty it
+    pub fn flush_coins(&self) -> Result<(), String> {
+        self.write_coins(&mut self.coins.lock().unwrap())
+    }
+
+    /// Limit the coins cache to about `bytes` (written back when it grows past it)
+    pub fn set_coins_cache_size(&mut self, bytes: usize) {
+        self.coins_cache_size = bytes;
+    }
+
+    /// Estimated memory use of the coins cache in bytes
+    pub fn coins_cache_usage(&self) -> usize {
+        self.coins.lock().unwrap().memory_usage()
+    }
+
+    /// Keep the block and undo files under about `bytes` by deleting the oldest ones
+    /// `None` keeps every block.
+    pub fn set_prune_target(&mut self, bytes: Option<u64>) {
+        self.prune_target = bytes;
+    }
+
+    /// Size the block files are pruned down to, if pruning is on
+    pub fn prune_target(&self) -> Option<u64> {
+        self.prune_target
+    }
+
+    /// Delete the oldest block files until the files fit the prune target
+    /// Files holding one of the last `MIN_BLOCKS_TO_KEEP` blocks, or a block
+    /// above the UTXO state on disk, are kept. Returns the deleted file numbers.
+    pub fn prune_block_files(&self) -> Result<Vec<u32>, String> {
+        let Some(target) = self.prune_target else {
+            return Ok(Vec::new());
+        };
+        let Some(utxo_tip) = self.blockchain.get_utxo_tip()? else {
+            return Ok(Vec::new());
+        };
+        let utxo_height = match self.blockchain.get_index_entry(&utxo_tip)? {
+            Some(entry) => entry.height,
+            None => return Ok(Vec::new()),
+        };
+        let tip_height = self.blockchain.get_chain_height()?.saturating_sub(1);
+
+        let files = self.blockchain.files();
+        let mut infos = Vec::new();
+        for file in files.block_file_numbers()? {
+            if let Some(info) = self.blockchain.get_file_info(file)? {
+                infos.push((file, info));
+            }
+        }
+        let mut usage: u64 = infos.iter().map(|(_, info)| info.size + info.undo_size).sum();
+
+        let mut pruned = Vec::new();
+        for (file, info) in infos {
+            if usage <= target {
+                break;
+            }
+            if file == files.current_file()
+                || info.height_last + MIN_BLOCKS_TO_KEEP > tip_height
+                || info.height_last >= utxo_height
+            {
+                continue;
+            }
+
+            let blocks = self.blockchain.prune_file(file)?;
+            log::info!(
+                "Pruned {} ({} blocks, heights {} to {})",
+                block_file_name(file),
+                blocks,
+                info.height_first,
+                info.height_last
+            );
+            usage -= info.size + info.undo_size;
+            pruned.push(file);
+        }
+
+        Ok(pruned)
+    }
+
+    /// Wipe the UTXO set and the indexes built from blocks, keeping the stored blocks
+    /// Returns the active chain from the height index, genesis first, for the
+    /// caller to revalidate and connect again; the height index stays until
+    /// then, so an interrupted rebuild can be restarted. Fails if blocks were pruned.
+    pub fn reset_chainstate(&self) -> Result<Vec<Hash256>, String> {
+        if let Some(pruned) = self.blockchain.get_prune_height()? {
+            return Err(format!(
+                "Blocks up to height {} were pruned; the chain state cannot be rebuilt without them",
+                pruned
+            ));
+        }
+
+        let chain = self.blockchain.get_active_chain()?;
+
+        // The tip goes first: a crash before the trees are cleared leaves an
+        // uninitialized chain, not a tip with a missing UTXO set
+        let mut coins = self.coins.lock().unwrap();
+        coins.clear();
+        let blocks = self.blockchain.tree();
+        let result = self.backend.transaction(|tx| {
+            for key in [TIP_KEY, HEIGHT_KEY, UTXO_TIP_KEY, ASSUME_VALID_KEY] {
+                tx.delete(blocks, key);
+            }
+            Ok(())
+        });
+        result.map_err(|e| format!("Failed to reset the chain tip: {}", e))?;
+
+        self.utxo_set.clear()?;
+        self.address_index.clear()?;
+        self.txindex
+            .tree()
+            .clear()
+            .map_err(|e| format!("Failed to clear transaction index: {}", e))?;
+        Ok(chain)
+    }
+
+    /// Rebuild the block index from the block files, then wipe the chain state
+    /// Every block record in the files is indexed again, so a lost or damaged
+    /// block index is repaired. Returns the longest chain found, genesis first.
+    pub fn reindex_blocks(&self) -> Result<Vec<Hash256>, String> {
+        self.reset_chainstate()?;
+        self.blockchain.rebuild_index()
+    }
+
+    /// Remove the height index from `height` up (where a rebuild stopped)
+    pub fn truncate_height_index(&self, height: u32) -> Result<(), String> {
+        let blocks = self.blockchain.tree();
+        let result = self.backend.transaction(|tx| {
+            let mut next = height;
+            while tx.get(blocks, &BlockchainDB::<B>::height_key(next))?.is_some() {
+                tx.delete(blocks, &BlockchainDB::<B>::height_key(next));
+                next += 1;
+            }
+            Ok(())
+        });
+        result.map_err(|e| format!("Failed to truncate the height index: {}", e))
+    }
+
+    /// Maintain the transaction index on connect and disconnect
+    /// Blocks connected while the index lags the tip are added by `build_txindex`.
+    pub fn set_txindex(&mut self, enabled: bool) {
+        self.txindex_enabled = enabled;
+    }
+
+    /// Whether the transaction index is maintained
+    pub fn txindex_enabled(&self) -> bool {
+        self.txindex_enabled
+    }
+
+    /// Index the existing chain in a background thread
+    /// The thread returns the number of blocks it indexed.
+    pub fn build_txindex(&self) -> JoinHandle<Result<u32, String>> {
+        let blocks = self.blockchain.clone();
+        let index = TxIndex::from_tree(self.backend.clone(), self.txindex.tree().clone());
+        std::thread::spawn(move || index.build(&blocks))
+    }
+
+    /// Roll back the tip block: restore the outputs it spent and remove the ones it created
+    /// Returns the disconnected block. Its data stays stored; only the undo record is dropped.
+    pub fn disconnect_block(&self) -> Result<Block, String> {
+        let tip = self.blockchain.get_tip()?.ok_or("Blockchain not initialized")?;
+        let chain_height = self.blockchain.get_chain_height()?;
+        if chain_height <= 1 {
+            return Err("Cannot disconnect the genesis block".to_string());
+        }
+        let height = chain_height - 1;
+
+        let block = self
+            .blockchain
+            .get_block(&tip)?
+            .ok_or_else(|| format!("Tip block {} is missing from storage", tip))?;
+        let undo = self
+            .blockchain
+            .get_undo(&tip)?
+            .ok_or_else(|| format!("No undo data for block {}", tip))?;
+        let inputs: usize = block.transactions.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.inputs.len()).sum();
+        if undo.spent.len() != inputs {
+            return Err(format!(
+                "Undo data for block {} has {} entries, block spends {} outputs",
+                tip,
+                undo.spent.len(),
+                inputs
+            ));
+        }
+
+        // Undo transactions last to first so outputs created and spent
+        // inside the block are restored before being removed
+        let mut view = self.coins_view();
+        let mut spent = undo.spent.iter().rev();
+        for tx in block.transactions.iter().rev() {
+            let txid = tx.txid();
+            for vout in 0..tx.outputs.len() {
+                view.spend_utxo(&OutPoint::new(txid, vout as u32))?;
+            }
+
+            if !tx.is_coinbase() {
+                for input in tx.inputs.iter().rev() {
+                    // Length was checked against the input count above
+                    let utxo = spent.next().unwrap().clone();
+                    view.add_utxo(&OutPoint::new(input.prev_tx_hash, input.prev_index), utxo, true);
+                }
+            }
+        }
+        let changes = view.into_changes();
+
+        // The UTXO state written here belongs to the parent block, so the
+        // cache is written back in the same transaction
+        let prev = block.header.prev_block_hash;
+        let mut coins = self.coins.lock().unwrap();
+        let blocks = self.blockchain.tree();
+        let result = self.backend.transaction(|tx| {
+            let mut batch = UtxoBatch::begin(&self.utxo_set, tx)?;
+            Self::write_entries(&mut batch, tx, coins.dirty())?;
+            Self::write_entries(&mut batch, tx, changes.iter())?;
+            batch.commit(tx);
+
+            if self.txindex_enabled {
+                self.txindex.disconnect(tx, &block, height)?;
+            }
+            self.address_index.disconnect(tx, &block, height, &undo.spent)?;
+            tx.delete(blocks, &BlockchainDB::<B>::height_key(height));
+            self.blockchain.stage_drop_undo(tx, &tip)?;
+            tx.put(blocks, TIP_KEY, prev.as_bytes());
+            tx.put(blocks, HEIGHT_KEY, &height.to_le_bytes());
+            tx.put(blocks, UTXO_TIP_KEY, prev.as_bytes());
+            Ok(())
+        });
+        result.map_err(|e| format!("Failed to disconnect block {}: {}", tip, e))?;
+        coins.clear();
+
+        Ok(block)
+    }
+
+    /// Detect and repair a block that was only partly written
+    /// Returns a description of the repair, or `None` if the chain was consistent.
+    pub fn recover(&self) -> Result<Option<String>, String> {
+        self.flush_coins()?;
+        let Some(tip) = self.blockchain.get_tip()? else {
+            return Ok(None);
+        };
+        let chain_height = self.blockchain.get_chain_height()?;
+
+        // The height index points at a block on top of the tip: the tip
+        // was never advanced to it
+        if let Some(next_hash) = self.blockchain.get_hash_by_height(chain_height)?
+            && next_hash != tip
+            && let Some(next) = self.blockchain.get_block(&next_hash)?
+            && next.header.prev_block_hash == tip
+        {
+            self.apply_block(&next, chain_height)?;
+            return Ok(Some(format!(
+                "Recovered half-applied block {} at height {}",
+                next_hash, chain_height
+            )));
+        }
+
+        let utxo_tip = self.blockchain.get_utxo_tip()?;
+        if utxo_tip == Some(tip) {
+            return Ok(None);
+        }
+
+        // Older versions could move the tip without the chain height
+        let tip_height = if self.blockchain.get_hash_by_height(chain_height)? == Some(tip) {
+            chain_height
+        } else {
+            chain_height.saturating_sub(1)
+        };
+
+        // The coins cache was not written back before the process stopped:
+        // replay the blocks above the UTXO state on disk
+        if let Some(utxo_tip) = utxo_tip {
+            let pending = self.blocks_above(&utxo_tip, &tip)?;
+            if !pending.is_empty() {
+                let first_height = tip_height + 1 - pending.len() as u32;
+                for (offset, block) in pending.iter().rev().enumerate() {
+                    self.apply_block(block, first_height + offset as u32)?;
+                }
+                return Ok(Some(format!(
+                    "Re-applied {} blocks up to tip {} at height {} to the UTXO set",
+                    pending.len(),
+                    tip,
+                    tip_height
+                )));
+            }
+        }
+
+        // An older version may have left the UTXO set partly updated. Re-applying
+        // a block is idempotent (spent inputs stay spent, outputs are rewritten),
+        // so replay the tip.
+        let block = self
+            .blockchain
+            .get_block(&tip)?
+            .ok_or_else(|| format!("Tip block {} is missing from storage", tip))?;
+        self.apply_block(&block, tip_height)?;
+
+        Ok(Some(format!("Re-applied tip block {} at height {} to the UTXO set", tip, tip_height)))
+    }
+
+    /// Write back the coins cache and flush the block files and all trees to disk
+    /// The files go first so the index never points at data lost in a crash.
+    pub fn flush(&self) -> Result<(), String> {
+        self.flush_coins()?;
+        self.prune_block_files()?;
+        self.blockchain.files().flush()?;
+        self.backend.flush()
+    }
+
+    // Helper: spend the inputs and add the outputs of `block` in a view
+    // Returns the undo data, or an error naming the first missing input.
+    fn apply_to_view(view: &mut CoinsViewCache<'_>, block: &Block, height: u32) -> Result<BlockUndo, String> {
+        let mut undo = BlockUndo::default();
+
+        for tx in &block.transactions {
+            if !tx.is_coinbase() {
+                for input in &tx.inputs {
+                    let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+                    let utxo = view
+                        .spend_utxo(&outpoint)?
+                        .ok_or_else(|| format!("Missing input {}:{}", outpoint.txid, outpoint.vout))?;
+                    undo.spent.push(utxo);
+                }
+            }
+
+            let txid = tx.txid();
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                let utxo = Utxo::new(output.clone(), height, tx.is_coinbase());
+                view.add_utxo(&OutPoint::new(txid, vout as u32), utxo, tx.is_coinbase());
+            }
+        }
+
+        Ok(undo)
+    }
+
+    // Helper: write the dirty coins and the block they reach in one transaction, then empty the cache
+    fn write_coins(&self, coins: &mut CoinsCache) -> Result<(), String> {
+        // Only clean entries are cached when no block was connected
+        let Some(best) = coins.best_block() else {
+            coins.clear();
+            return Ok(());
+        };
+
+        let result = self.backend.transaction(|tx| {
+            let mut batch = UtxoBatch::begin(&self.utxo_set, tx)?;
+            Self::write_entries(&mut batch, tx, coins.dirty())?;
+            batch.commit(tx);
+            tx.put(self.blockchain.tree(), UTXO_TIP_KEY, best.as_bytes());
+            Ok(())
+        });
+        result.map_err(|e| format!("Failed to write coins cache: {}", e))?;
+
+        coins.clear();
+        Ok(())
+    }
+
+    // Helper: apply cache entries to a UTXO batch
+    fn write_entries<'e>(
+        batch: &mut UtxoBatch<'_, B>,
+        tx: &mut KvBatch<B::Tree>,
+        entries: impl Iterator<Item = (&'e OutPoint, &'e CacheEntry)>,
+    ) -> Result<(), String> {
+        for (outpoint, entry) in entries {
+            match &entry.utxo {
+                Some(utxo) => batch.insert(tx, outpoint, utxo)?,
+                None => {
+                    batch.remove(tx, outpoint)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Helper: blocks from `tip` back to, not including, its ancestor `base`
+    // Empty if `base` is not on the chain below `tip`.
+    fn blocks_above(&self, base: &Hash256, tip: &Hash256) -> Result<Vec<Block>, String> {
+        let mut blocks = Vec::new();
+        let mut hash = *tip;
+
+        while hash != *base {
+            match self.blockchain.get_block(&hash)? {
+                Some(block) => {
+                    hash = block.header.prev_block_hash;
+                    blocks.push(block);
+                }
+                None => return Ok(Vec::new()),
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    // Helper: write a block, its undo data and its UTXO changes straight to disk
+    // Used for recovery, so it replays leniently: inputs that are already
+    // spent are skipped and the existing undo record is kept.
+    fn apply_block(&self, block: &Block, height: u32) -> Result<(), String> {
+        let hash = block.hash();
+        let chain_height = (height + 1).to_le_bytes();
+        let mut entry = self
+            .blockchain
+            .get_index_entry(&hash)?
+            .ok_or_else(|| format!("Block {} is missing from the block index", hash))?;
+
+        let blocks = self.blockchain.tree();
+        let result = self.backend.transaction(|batch_tx| {
+            batch_tx.put(blocks, &BlockchainDB::<B>::height_key(height), hash.as_bytes());
+            batch_tx.put(blocks, TIP_KEY, hash.as_bytes());
+            batch_tx.put(blocks, HEIGHT_KEY, &chain_height);
+            batch_tx.put(blocks, UTXO_TIP_KEY, hash.as_bytes());
+
+            // Spend the inputs and register the outputs of every transaction
+            let mut batch = UtxoBatch::begin(&self.utxo_set, batch_tx)?;
+            let mut undo = BlockUndo::default();
+            let mut complete = true;
+            for tx in &block.transactions {
+                if !tx.is_coinbase() {
+                    for input in &tx.inputs {
+                        let outpoint = OutPoint::new(input.prev_tx_hash, input.prev_index);
+                        match batch.remove(batch_tx, &outpoint)? {
+                            Some(utxo) => undo.spent.push(utxo),
+                            None => complete = false,
+                        }
+                    }
+                }
+
+                let txid = tx.txid();
+                for (vout, output) in tx.outputs.iter().enumerate() {
+                    let utxo = Utxo::new(output.clone(), height, tx.is_coinbase());
+                    batch.insert(batch_tx, &OutPoint::new(txid, vout as u32), &utxo)?;
+                }
+            }
+            batch.commit(batch_tx);
+
+            // A partial replay cannot describe what the block spent
+            if complete {
+                if entry.undo_pos().is_none() {
+                    self.blockchain.write_undo(&hash, &mut entry, &undo)?;
+                    self.blockchain.stage_undo(batch_tx, &hash, &entry)?;
+                }
+                self.address_index.connect(batch_tx, block, height, &undo.spent)?;
+            }
+            if self.txindex_enabled {
+                self.txindex.connect(batch_tx, block, height)?;
+            }
+            Ok(())
+        });
+
+        result.map_err(|e| format!("Cannot connect block {}: {}", hash, e))
+    }
+
+    // Helper: move blocks and undo data stored inside the database by older versions to the block files
+    // One transaction per block, so an interrupted move resumes where it stopped.
+    fn import_legacy_blocks(&self) -> Result<usize, String> {
+        let mut legacy = self.blockchain.legacy_hashes()?;
+        if legacy.is_empty() {
+            return Ok(0);
+        }
+
+        // Active-chain blocks go to the files in height order; others count as height 0
+        let mut heights = HashMap::new();
+        for height in 0..self.blockchain.get_chain_height()? {
+            if let Some(hash) = self.blockchain.get_hash_by_height(height)? {
+                heights.insert(hash, height);
+            }
+        }
+        legacy.sort_by_key(|hash| heights.get(hash).copied().unwrap_or(0));
+
+        for hash in &legacy {
+            let block = self
+                .blockchain
+                .legacy_block(hash)?
+                .ok_or_else(|| format!("Block {} disappeared while being moved", hash))?;
+            let mut entry = self.blockchain.write_block(&block, heights.get(hash).copied().unwrap_or(0))?;
+            if let Some(undo) = self.blockchain.legacy_undo(hash)? {
+                self.blockchain.write_undo(hash, &mut entry, &undo)?;
+            }
+
+            let result = self.backend.transaction(|tx| {
+                self.blockchain.stage_block(tx, hash, &entry)?;
+                self.blockchain.stage_drop_legacy(tx, hash);
+                Ok(())
+            });
+            result.map_err(|e| format!("Failed to move block {}: {}", hash, e))?;
+        }
+
+        self.flush()?;
+        Ok(legacy.len())
+    }
+
+    // Helper: build indexes missing from data written by older versions
+    fn build_indexes(&self) -> Result<(), String> {
+        if self.utxo_set.rebuild_derived()? {
+            log::info!("Rebuilt UTXO script index and statistics");
+        }
+
+        let tip = self.blockchain.get_tip()?;
+        let chain_height = self.blockchain.get_chain_height()?;
+        let history_tip = self.address_index.best_block()?;
+        if tip.is_some() && history_tip != tip.map(|hash| (hash, chain_height - 1)) {
+            let replayed = self.address_index.rebuild(&self.blockchain)?;
+            log::info!("Rebuilt address history for {} blocks", replayed);
+        }
+
+        Ok(())
+    }
+}
+
+impl<B: KvBackend> CoinsView for Storage<B> {
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, String> {
+        let mut coins = self.coins.lock().unwrap();
+        if let Some(entry) = coins.get(outpoint) {
+            return Ok(entry.utxo.clone());
+        }
+
+        let utxo = self.utxo_set.get_utxo(outpoint)?;
+        if let Some(utxo) = &utxo {
+            coins.insert_clean(outpoint, utxo);
+        }
+        Ok(utxo)
+    }
+}
+
+impl<B: KvBackend> Drop for Storage<B> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_coins() {
+            log::error!("{}", e);
+        }
+    }
+}
+
+// Helper: encode an index's best-block record
+fn encode_index_best(hash: &Hash256, height: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(36);
+    bytes.extend_from_slice(hash.as_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    bytes
+}
+
+// Helper: decode an index's best-block record
+fn decode_index_best(bytes: &[u8]) -> Result<(Hash256, u32), String> {
+    if bytes.len() != 36 {
+        return Err(format!("Invalid index tip length: {}", bytes.len()));
+    }
+    let hash = Hash256::new(bytes[0..32].try_into().unwrap());
+    Ok((hash, u32::from_le_bytes(bytes[32..36].try_into().unwrap())))
+}
+
+// Helper: whether an index tree stands at the parent of `block`
+fn index_extends<T: KvTree>(tx: &KvBatch<T>, index: &T, block: &Block, height: u32) -> Result<bool, String> {
+    let best = tx.get(index, INDEX_BEST_KEY)?.and_then(|data| decode_index_best(&data).ok());
+    Ok(match best {
+        Some((hash, best_height)) => hash == block.header.prev_block_hash && best_height + 1 == height,
+        None => height == 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Hash256, Serializable, Transaction, TxInput, TxOutput};
+
+    // Helper: block at `height` on `prev` with a coinbase and optional extra transactions
+    fn block(prev: Hash256, height: u32, extra: Vec<Transaction>) -> Block {
+        let mut transactions = vec![Transaction::coinbase(vec![height as u8], TxOutput::new(50, vec![1]), height)];
+        transactions.extend(extra);
+        let merkle_root = Block::calculate_merkle_root(&transactions);
+        Block::new(crate::core::BlockHeader::new(1, prev, merkle_root, height, 0x20ffffff, 0), transactions)
+    }
+
+    #[test]
+    fn test_connect_block_is_atomic() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+
+        // Spends one real output and one that does not exist
+        let coinbase_txid = genesis.transactions[0].txid();
+        let spend = Transaction::new(
+            vec![TxInput::new(coinbase_txid, 0, vec![]), TxInput::new(Hash256::new([9; 32]), 0, vec![])],
+            vec![TxOutput::new(40, vec![2])],
+        );
+        let bad = block(genesis.hash(), 1, vec![spend]);
+
+        let result = storage.connect_block_with(storage.coins_view(), &bad, 1, true);
+        assert!(result.unwrap_err().contains("Missing input"));
+        assert_eq!(storage.blockchain.get_tip().unwrap(), Some(genesis.hash()));
+        assert_eq!(storage.blockchain.get_chain_height().unwrap(), 1);
+        assert!(!storage.blockchain.has_block(&bad.hash()).unwrap());
+        assert!(storage.has_utxo(&OutPoint::new(coinbase_txid, 0)).unwrap());
+        assert_eq!(storage.blockchain.get_assumed_valid().unwrap(), None);
+        assert_eq!(storage.recover().unwrap(), None);
+
+        // The assume-valid count commits with the block
+        let good = block(genesis.hash(), 1, vec![]);
+        storage.connect_block_with(storage.coins_view(), &good, 1, true).unwrap();
+        assert_eq!(storage.blockchain.get_assumed_valid().unwrap(), Some((1, 1)));
+    }
+
+    #[test]
+    fn test_backends_store_the_same_chain() {
+        let on_disk = Storage::with_backend(SledBackend::temporary().unwrap(), BlockFiles::memory()).unwrap();
+        let in_memory = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+
+        on_disk.connect_block(&genesis, 0).unwrap();
+        in_memory.connect_block(&genesis, 0).unwrap();
+        let tip = extend(&on_disk, genesis.clone(), 1, 3);
+        assert_eq!(extend(&in_memory, genesis, 1, 3).hash(), tip.hash());
+        on_disk.disconnect_block().unwrap();
+        in_memory.disconnect_block().unwrap();
+        on_disk.flush().unwrap();
+        in_memory.flush().unwrap();
+
+        assert_eq!(utxo_bytes(&on_disk), utxo_bytes(&in_memory));
+        assert_eq!(on_disk.blockchain.get_tip().unwrap(), in_memory.blockchain.get_tip().unwrap());
+        assert_eq!(on_disk.utxo_set.stats().unwrap(), in_memory.utxo_set.stats().unwrap());
+    }
+
+    #[test]
+    fn test_recover_block_stored_without_tip() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+
+        // Crash after the block and height index were written
+        let next = block(genesis.hash(), 1, vec![]);
+        storage.blockchain.store_block(&next, 1).unwrap();
+        storage.blockchain.store_height(1, &next.hash()).unwrap();
+
+        assert!(storage.recover().unwrap().unwrap().contains("half-applied"));
+        assert_eq!(storage.blockchain.get_tip().unwrap(), Some(next.hash()));
+        assert_eq!(storage.blockchain.get_chain_height().unwrap(), 2);
+        assert!(storage.utxo_set.has_utxo(&OutPoint::new(next.transactions[0].txid(), 0)).unwrap());
+        assert_eq!(storage.recover().unwrap(), None);
+    }
+
+    #[test]
+    fn test_recover_utxo_set_behind_tip() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+
+        // Crash after the tip moved but before the UTXO set was updated
+        let spend = Transaction::new(
+            vec![TxInput::new(genesis.transactions[0].txid(), 0, vec![])],
+            vec![TxOutput::new(40, vec![2])],
+        );
+        let next = block(genesis.hash(), 1, vec![spend.clone()]);
+        storage.blockchain.store_block(&next, 1).unwrap();
+        storage.blockchain.store_height(1, &next.hash()).unwrap();
+        storage.blockchain.store_tip(&next.hash()).unwrap();
+
+        assert!(storage.recover().unwrap().unwrap().contains("Re-applied"));
+        assert_eq!(storage.blockchain.get_chain_height().unwrap(), 2);
+        assert!(!storage.utxo_set.has_utxo(&OutPoint::new(genesis.transactions[0].txid(), 0)).unwrap());
+        assert!(storage.utxo_set.has_utxo(&OutPoint::new(spend.txid(), 0)).unwrap());
+        assert_eq!(storage.utxo_set.count().unwrap(), 2);
+    }
+
+    // Helper: raw (key, value) pairs of the UTXO tree
+    fn utxo_bytes<B: KvBackend>(storage: &Storage<B>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        storage
+            .utxo_set
+            .tree()
+            .iter()
+            .map(|item| {
+                let (key, value) = item.unwrap();
+                (key.to_vec(), value.to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_disconnect_restores_utxo_set_bytes() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        storage.flush_coins().unwrap();
+        let before = utxo_bytes(&storage);
+        let before_stats = storage.utxo_set.stats().unwrap();
+
+        // Each block spends the previous coinbase, then spends its own new output
+        let mut prev = genesis.clone();
+        for height in 1..=5 {
+            let spend = Transaction::new(
+                vec![TxInput::new(prev.transactions[0].txid(), 0, vec![])],
+                vec![TxOutput::new(30, vec![2]), TxOutput::new(20, vec![3])],
+            );
+            let chained = Transaction::new(vec![TxInput::new(spend.txid(), 1, vec![])], vec![TxOutput::new(20, vec![4])]);
+            let next = block(prev.hash(), height, vec![spend, chained]);
+            storage.connect_block(&next, height).unwrap();
+            prev = next;
+        }
+        storage.flush_coins().unwrap();
+        assert_ne!(utxo_bytes(&storage), before);
+        assert_eq!(storage.utxo_set.stats().unwrap(), storage.utxo_set.compute_stats().unwrap());
+        assert_eq!(storage.utxo_set.stats().unwrap().total_amount, 6 * 50);
+
+        for height in (1..=5).rev() {
+            let block = storage.disconnect_block().unwrap();
+            assert_eq!(block.header.timestamp, height);
+            assert!(storage.blockchain.get_undo(&block.hash()).unwrap().is_none());
+        }
+
+        assert_eq!(utxo_bytes(&storage), before);
+        assert_eq!(storage.utxo_set.stats().unwrap(), before_stats);
+        assert_eq!(storage.utxo_set.stats().unwrap().hash(), before_stats.hash());
+        assert_eq!(storage.blockchain.get_tip().unwrap(), Some(genesis.hash()));
+        assert_eq!(storage.blockchain.get_chain_height().unwrap(), 1);
+        assert_eq!(storage.blockchain.get_hash_by_height(1).unwrap(), None);
+        assert_eq!(storage.recover().unwrap(), None);
+        assert!(storage.disconnect_block().unwrap_err().contains("genesis"));
+    }
+
+    #[test]
+    fn test_blocks_stored_in_the_database_move_to_files() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        let tip = extend(&storage, genesis.clone(), 1, 2);
+        let undo = storage.blockchain.get_undo(&tip.hash()).unwrap().unwrap();
+
+        // Lay the blocks out the way older versions did
+        let tree = storage.blockchain.tree();
+        for (stored, undo) in [(&genesis, None), (&tip, Some(&undo))] {
+            let hash = stored.hash();
+            tree.put(&[b"b".as_slice(), hash.as_bytes()].concat(), &stored.serialize()).unwrap();
+            if let Some(undo) = undo {
+                tree.put(&[b"u".as_slice(), hash.as_bytes()].concat(), &undo.to_bytes()).unwrap();
+            }
+            tree.delete(&[b"i".as_slice(), hash.as_bytes()].concat()).unwrap();
+        }
+        assert!(!storage.blockchain.has_block(&tip.hash()).unwrap());
+
+        storage.open_checks().unwrap();
+        assert_eq!(storage.blockchain.get_block(&tip.hash()).unwrap(), Some(tip.clone()));
+        assert_eq!(storage.blockchain.get_undo(&tip.hash()).unwrap().unwrap().to_bytes(), undo.to_bytes());
+        assert_eq!(storage.blockchain.get_index_entry(&tip.hash()).unwrap().unwrap().height, 2);
+        assert!(tree.scan_prefix(b"b").next().is_none());
+        assert!(!tree.contains(&[b"u".as_slice(), tip.hash().as_bytes()].concat()).unwrap());
+
+        // The moved tip can still be disconnected
+        assert_eq!(storage.disconnect_block().unwrap(), tip);
+    }
+
+    #[test]
+    fn test_prune_keeps_headers_utxos_and_recent_blocks() {
+        let mut storage = Storage::with_backend(MemoryBackend::new(), BlockFiles::memory().with_max_file_size(4096)).unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        let tip = extend(&storage, genesis.clone(), 1, MIN_BLOCKS_TO_KEEP + 40);
+        let tip_height = MIN_BLOCKS_TO_KEEP + 40;
+        storage.set_prune_target(Some(1));
+
+        // Nothing is pruned while the UTXO state on disk lags the tip
+        assert!(storage.prune_block_files().unwrap().is_empty());
+        storage.flush().unwrap();
+        let pruned_height = storage.blockchain.get_prune_height().unwrap().unwrap();
+        assert!(!storage.blockchain.files().block_file_numbers().unwrap().contains(&0));
+        assert!(pruned_height + MIN_BLOCKS_TO_KEEP <= tip_height);
+
+        // Pruned blocks keep their header and index entry but lose their data
+        let entry = storage.blockchain.get_index_entry(&genesis.hash()).unwrap().unwrap();
+        assert!(entry.is_pruned() && !entry.has_data());
+        assert_eq!(storage.blockchain.get_block(&genesis.hash()).unwrap(), None);
+        assert_eq!(storage.blockchain.get_header_by_height(0).unwrap(), Some(genesis.header.clone()));
+        assert!(storage.blockchain.is_ancestor(&genesis.hash(), &tip.hash()).unwrap());
+        for height in tip_height - MIN_BLOCKS_TO_KEEP..=tip_height {
+            assert!(storage.blockchain.get_block_by_height(height).unwrap().is_some());
+        }
+
+        assert_eq!(storage.utxo_set.stats().unwrap(), storage.utxo_set.compute_stats().unwrap());
+        assert_eq!(storage.disconnect_block().unwrap(), tip);
+    }
+
+    #[test]
+    fn test_reset_chainstate_rebuilds_the_same_state() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        let tip = extend(&storage, genesis, 1, 5);
+        storage.flush().unwrap();
+        let before = utxo_bytes(&storage);
+        let before_stats = storage.utxo_set.stats().unwrap();
+        let before_files = storage.blockchain.get_file_info(0).unwrap();
+        let before_history = storage.address_index.history(&[2]).unwrap();
+
+        let chain = storage.reset_chainstate().unwrap();
+        assert_eq!(chain.len(), 6);
+        assert_eq!(chain[5], tip.hash());
+        assert_eq!(storage.utxo_set.count().unwrap(), 0);
+        assert!(storage.address_index.history(&[2]).unwrap().is_empty());
+        assert_eq!(storage.blockchain.get_tip().unwrap(), None);
+
+        for (height, hash) in chain.iter().enumerate() {
+            let stored = storage.blockchain.get_block(hash).unwrap().unwrap();
+            storage.connect_block(&stored, height as u32).unwrap();
+        }
+        storage.flush().unwrap();
+
+        // The same UTXOs and history, and no block or undo record written twice
+        assert_eq!(utxo_bytes(&storage), before);
+        assert_eq!(storage.utxo_set.stats().unwrap(), before_stats);
+        assert_eq!(storage.blockchain.get_file_info(0).unwrap(), before_files);
+        assert_eq!(storage.address_index.history(&[2]).unwrap(), before_history);
+        assert_eq!(storage.disconnect_block().unwrap(), tip);
+    }
+
+    #[test]
+    fn test_reindex_blocks_rebuilds_the_index_from_files() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        extend(&storage, genesis.clone(), 1, 3);
+        let fork = block(genesis.hash(), 1, vec![]);
+        storage.blockchain.store_block(&fork, 1).unwrap();
+        storage.flush().unwrap();
+        let before = utxo_bytes(&storage);
+        let main: Vec<Hash256> = (0..4).map(|height| storage.blockchain.get_hash_by_height(height).unwrap().unwrap()).collect();
+
+        // Lose the whole block index
+        storage.blockchain.tree().clear().unwrap();
+        assert_eq!(storage.blockchain.get_block(&genesis.hash()).unwrap(), None);
+
+        let chain = storage.reindex_blocks().unwrap();
+        assert_eq!(chain, main);
+        assert_eq!(storage.blockchain.get_index_entry(&fork.hash()).unwrap().unwrap().height, 1);
+        assert_eq!(storage.blockchain.get_file_info(0).unwrap().unwrap().blocks, 5);
+        assert!(storage.blockchain.get_undo(&chain[3]).unwrap().is_none());
+
+        for (height, hash) in chain.iter().enumerate().take(3) {
+            let stored = storage.blockchain.get_block(hash).unwrap().unwrap();
+            storage.connect_block(&stored, height as u32).unwrap();
+        }
+        storage.truncate_height_index(3).unwrap();
+        assert_eq!(storage.blockchain.get_hash_by_height(3).unwrap(), None);
+        assert_eq!(storage.blockchain.get_tip().unwrap(), Some(chain[2]));
+
+        let stored = storage.blockchain.get_block(&chain[3]).unwrap().unwrap();
+        storage.connect_block(&stored, 3).unwrap();
+        storage.flush().unwrap();
+        assert_eq!(utxo_bytes(&storage), before);
+        assert!(storage.blockchain.get_undo(&chain[3]).unwrap().is_some());
+    }
+
+    // Helper: connect `count` blocks above `prev`, each spending the previous coinbase
+    fn extend<B: KvBackend>(storage: &Storage<B>, mut prev: Block, first_height: u32, count: u32) -> Block {
+        for height in first_height..first_height + count {
+            let spend = Transaction::new(
+                vec![TxInput::new(prev.transactions[0].txid(), 0, vec![])],
+                vec![TxOutput::new(30, vec![2]), TxOutput::new(20, vec![3])],
+            );
+            let next = block(prev.hash(), height, vec![spend]);
+            storage.connect_block(&next, height).unwrap();
+            prev = next;
+        }
+        prev
+    }
+
+    #[test]
+    fn test_coins_cache_writes_back_on_flush_or_when_full() {
+        let mut storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        let coinbase = OutPoint::new(genesis.transactions[0].txid(), 0);
+
+        // Only the cache has the output until it is written back
+        assert!(storage.has_utxo(&coinbase).unwrap());
+        assert!(!storage.utxo_set.has_utxo(&coinbase).unwrap());
+        assert_eq!(storage.blockchain.get_utxo_tip().unwrap(), None);
+        assert!(storage.coins_cache_usage() > 0);
+
+        storage.flush_coins().unwrap();
+        assert!(storage.utxo_set.has_utxo(&coinbase).unwrap());
+        assert_eq!(storage.blockchain.get_utxo_tip().unwrap(), Some(genesis.hash()));
+        assert_eq!(storage.coins_cache_usage(), 0);
+
+        // A full cache is written back at the end of the block
+        storage.set_coins_cache_size(0);
+        let tip = extend(&storage, genesis, 1, 1);
+        assert!(!storage.utxo_set.has_utxo(&coinbase).unwrap());
+        assert_eq!(storage.blockchain.get_utxo_tip().unwrap(), Some(tip.hash()));
+        assert_eq!(storage.utxo_set.stats().unwrap(), storage.utxo_set.compute_stats().unwrap());
+    }
+
+    #[test]
+    fn test_recover_replays_blocks_lost_with_the_cache() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        storage.flush_coins().unwrap();
+        let tip = extend(&storage, genesis.clone(), 1, 3);
+
+        // Crash: the cache is lost, the blocks are stored
+        storage.coins.lock().unwrap().clear();
+        assert_eq!(storage.utxo_set.count().unwrap(), 1);
+
+        assert!(storage.recover().unwrap().unwrap().contains("Re-applied 3 blocks"));
+        assert_eq!(storage.blockchain.get_utxo_tip().unwrap(), Some(tip.hash()));
+        assert_eq!(storage.blockchain.get_chain_height().unwrap(), 4);
+        assert_eq!(storage.utxo_set.count().unwrap(), 1 + 3 * 2);
+        assert_eq!(storage.utxo_set.stats().unwrap(), storage.utxo_set.compute_stats().unwrap());
+        assert_eq!(storage.recover().unwrap(), None);
+    }
+
+    #[test]
+    fn test_disconnect_writes_back_unflushed_blocks() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        let tip = extend(&storage, genesis, 1, 2);
+
+        let disconnected = storage.disconnect_block().unwrap();
+        assert_eq!(disconnected.hash(), tip.hash());
+        assert_eq!(storage.coins_cache_usage(), 0);
+        assert_eq!(storage.blockchain.get_utxo_tip().unwrap(), Some(tip.header.prev_block_hash));
+
+        // Block 1 spent the genesis coinbase: its own coinbase and two outputs remain
+        assert_eq!(storage.utxo_set.count().unwrap(), 3);
+        assert!(storage.utxo_set.has_utxo(&OutPoint::new(tip.transactions[1].inputs[0].prev_tx_hash, 0)).unwrap());
+        assert_eq!(storage.utxo_set.stats().unwrap(), storage.utxo_set.compute_stats().unwrap());
+    }
+
+    #[test]
+    fn test_txindex_follows_connect_and_disconnect() {
+        let mut storage = Storage::memory().unwrap();
+        storage.set_txindex(true);
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+
+        let spend = Transaction::new(
+            vec![TxInput::new(genesis.transactions[0].txid(), 0, vec![])],
+            vec![TxOutput::new(40, vec![2])],
+        );
+        let next = block(genesis.hash(), 1, vec![spend.clone()]);
+        storage.connect_block(&next, 1).unwrap();
+
+        let location = storage.txindex.get(&spend.txid()).unwrap().unwrap();
+        assert_eq!(location, TxLocation { block_hash: next.hash(), height: 1, position: 1 });
+        assert_eq!(storage.txindex.best_block().unwrap(), Some((next.hash(), 1)));
+
+        storage.disconnect_block().unwrap();
+        assert_eq!(storage.txindex.get(&spend.txid()).unwrap(), None);
+        assert_eq!(storage.txindex.best_block().unwrap(), Some((genesis.hash(), 0)));
+    }
+
+    #[test]
+    fn test_txindex_builds_existing_chain() {
+        let mut storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+        let second = block(genesis.hash(), 1, vec![]);
+        storage.connect_block(&second, 1).unwrap();
+        assert_eq!(storage.txindex.best_block().unwrap(), None);
+
+        // Connections while the index lags are left to the builder
+        storage.set_txindex(true);
+        let third = block(second.hash(), 2, vec![]);
+        storage.connect_block(&third, 2).unwrap();
+        assert_eq!(storage.txindex.get(&third.transactions[0].txid()).unwrap(), None);
+
+        assert_eq!(storage.build_txindex().join().unwrap().unwrap(), 3);
+        assert_eq!(storage.txindex.get(&second.transactions[0].txid()).unwrap().unwrap().height, 1);
+        assert_eq!(storage.txindex.best_block().unwrap(), Some((third.hash(), 2)));
+        assert_eq!(storage.build_txindex().join().unwrap().unwrap(), 0);
+
+        // Once caught up, new blocks are indexed on connect
+        let fourth = block(third.hash(), 3, vec![]);
+        storage.connect_block(&fourth, 3).unwrap();
+        assert!(storage.txindex.get(&fourth.transactions[0].txid()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_address_history_connect_disconnect_and_rebuild() {
+        let storage = Storage::memory().unwrap();
+        let genesis = block(Hash256::zero(), 0, vec![]);
+        storage.connect_block(&genesis, 0).unwrap();
+
+        // Coinbases pay script [1]; the spend sends 40 to [2] and 10 back to [1]
+        let spend = Transaction::new(
+            vec![TxInput::new(genesis.transactions[0].txid(), 0, vec![])],
+            vec![TxOutput::new(40, vec![2]), TxOutput::new(10, vec![1])],
+        );
+        let next = block(genesis.hash(), 1, vec![spend.clone()]);
+        storage.connect_block(&next, 1).unwrap();
+
+        let history = storage.address_index.history(&[1]).unwrap();
+        let summary: Vec<(u32, u32, u64, u64)> = history.iter().map(|e| (e.height, e.position, e.received, e.spent)).collect();
+        assert_eq!(summary, vec![(0, 0, 50, 0), (1, 0, 50, 0), (1, 1, 10, 50)]);
+        assert_eq!(history[2].txid, spend.txid());
+        assert_eq!(storage.address_index.history(&[2]).unwrap()[0].received, 40);
+
+        // Rebuilding from the blocks gives the same history
+        let incremental = history.clone();
+        assert_eq!(storage.address_index.rebuild(&storage.blockchain).unwrap(), 2);
+        assert_eq!(storage.address_index.history(&[1]).unwrap(), incremental);
+        assert_eq!(storage.address_index.best_block().unwrap(), Some((next.hash(), 1)));
+
+        storage.disconnect_block().unwrap();
+        assert_eq!(storage.address_index.history(&[1]).unwrap().len(), 1);
+        assert!(storage.address_index.history(&[2]).unwrap().is_empty());
+        assert_eq!(storage.utxo_set.get_balance(&[1]).unwrap(), 50);
+    }
 }
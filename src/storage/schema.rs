@@ -0,0 +1,234 @@
+// Database schema versions and migrations
+//
+// The chain database records the version of its layout under `version` in
+// the default tree. Opening a database runs the migrations from its version
+// up to `SCHEMA_VERSION` in order; each one stages its rewrite in the same
+// transaction that stores its version, so an interrupted upgrade resumes at
+// the step it stopped in. A database of a newer version is refused, since
+// this release would misread its records.
+//
+// Versions:
+// 1. Layout before versions were recorded
+// 2. Height index keys are big-endian, so they sort by height
+// 3. UTXOs are stored in the compact encoding of compress.rs
+
+use super::utxo_set::{STATS_KEY, STATS_TREE};
+use super::{KvBackend, KvBatch, KvTree, OutPoint, Utxo, UtxoStats, UTXO_TREE};
+
+/// Schema version this release reads and writes
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Key of the schema version in the default tree
+const VERSION_KEY: &[u8] = b"version";
+
+/// Version of a database that has data but no version record
+const UNVERSIONED: u32 = 1;
+
+/// One step of the schema history
+struct Migration<B: KvBackend> {
+    /// Version the database has after this step
+    version: u32,
+    /// What the step changes, for the log
+    description: &'static str,
+    /// Stage the rewrite in the batch that also stores `version`; returns a summary
+    apply: fn(&B, &mut KvBatch<B::Tree>) -> Result<String, String>,
+}
+
+// Helper: every migration, oldest first
+fn migrations<B: KvBackend>() -> Vec<Migration<B>> {
+    vec![
+        Migration {
+            version: 2,
+            description: "height index keys sorted by height",
+            apply: big_endian_height_keys,
+        },
+        Migration {
+            version: 3,
+            description: "compact UTXO encoding",
+            apply: compact_utxos,
+        },
+    ]
+}
+
+/// Schema version of the database behind `backend`
+/// A database without a version record is version 1 if it holds data, or
+/// `None` if it is empty.
+pub(super) fn stored_version<B: KvBackend>(backend: &B) -> Result<Option<u32>, String> {
+    let tree = backend.default_tree();
+    match tree.get(VERSION_KEY)? {
+        Some(data) => {
+            let bytes: [u8; 4] = data
+                .as_slice()
+                .try_into()
+                .map_err(|_| format!("Invalid schema version record: {} bytes", data.len()))?;
+            Ok(Some(u32::from_le_bytes(bytes)))
+        }
+        None if tree.is_empty() => Ok(None),
+        None => Ok(Some(UNVERSIONED)),
+    }
+}
+
+/// Bring the database behind `backend` to `SCHEMA_VERSION`
+/// An empty database is stamped with the current version. Returns the
+/// migrations applied, as "version N: description (summary)".
+pub(super) fn upgrade<B: KvBackend>(backend: &B) -> Result<Vec<String>, String> {
+    let tree = backend.default_tree();
+    let Some(version) = stored_version(backend)? else {
+        tree.put(VERSION_KEY, &SCHEMA_VERSION.to_le_bytes())
+            .map_err(|e| format!("Failed to store schema version: {}", e))?;
+        return Ok(Vec::new());
+    };
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "Database schema version {} is newer than this release supports ({}); use a newer release or another data directory",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    let mut applied = Vec::new();
+    for migration in migrations::<B>().into_iter().filter(|migration| migration.version > version) {
+        let result = backend.transaction(|tx| {
+            let summary = (migration.apply)(backend, tx)?;
+            tx.put(&tree, VERSION_KEY, &migration.version.to_le_bytes());
+            Ok(summary)
+        });
+        let summary = result
+            .map_err(|e| format!("Failed to upgrade the database to schema version {}: {}", migration.version, e))?;
+
+        applied.push(format!("version {}: {} ({})", migration.version, migration.description, summary));
+    }
+    Ok(applied)
+}
+
+// Helper: version 2, rewrite `h` + height (u32 LE) keys as `h` + height (u32 BE)
+fn big_endian_height_keys<B: KvBackend>(backend: &B, tx: &mut KvBatch<B::Tree>) -> Result<String, String> {
+    let tree = backend.default_tree();
+    let mut moved = Vec::new();
+    for item in tree.scan_prefix(b"h") {
+        let (key, hash) = item?;
+        // `height` (the chain height) shares the prefix
+        if key.len() == 5 {
+            moved.push((key, hash));
+        }
+    }
+
+    for (key, _) in &moved {
+        tx.delete(&tree, key);
+    }
+    for (key, hash) in &moved {
+        let height = u32::from_le_bytes(key[1..5].try_into().unwrap());
+        tx.put(&tree, &[b"h".as_slice(), &height.to_be_bytes()].concat(), hash);
+    }
+    Ok(format!("{} keys", moved.len()))
+}
+
+// Helper: version 3, re-encode every UTXO compactly and recompute the statistics
+// The commitment hash covers the plain encoding, so it stays the same.
+fn compact_utxos<B: KvBackend>(backend: &B, tx: &mut KvBatch<B::Tree>) -> Result<String, String> {
+    let utxos = backend.open_tree(UTXO_TREE)?;
+    let mut stats = UtxoStats::default();
+    let mut before = 0;
+    for item in utxos.iter() {
+        let (key, value) = item?;
+        let utxo = Utxo::from_bytes(&value).map_err(|e| {
+            let outpoint = OutPoint::from_bytes(&key).map(|o| format!("{}:{}", o.txid, o.vout)).unwrap_or_default();
+            format!("UTXO {}: {}", outpoint, e)
+        })?;
+        let compact = utxo.to_compact_bytes();
+        before += key.len() + value.len();
+        stats.add(&key, &utxo, compact.len());
+        tx.put(&utxos, &key, &compact);
+    }
+
+    tx.put(&backend.open_tree(STATS_TREE)?, STATS_KEY, &stats.to_bytes());
+    Ok(format!(
+        "{} UTXOs, {} bytes before, {} bytes after",
+        stats.count, before, stats.serialized_size
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Block, BlockHeader, Hash256, Script, Transaction, TxInput, TxOutput};
+    use crate::storage::{BlockFiles, MemoryBackend, Storage};
+
+    #[test]
+    fn test_unversioned_height_index_is_migrated() {
+        let backend = MemoryBackend::new();
+        let tree = backend.default_tree();
+        for height in [0u32, 1, 255, 256] {
+            tree.put(&[b"h".as_slice(), &height.to_le_bytes()].concat(), &[height as u8; 32]).unwrap();
+        }
+        tree.put(b"height", &257u32.to_le_bytes()).unwrap();
+        assert_eq!(stored_version(&backend).unwrap(), Some(1));
+
+        let storage = Storage::with_backend(backend.clone(), BlockFiles::memory()).unwrap();
+        assert_eq!(stored_version(&backend).unwrap(), Some(SCHEMA_VERSION));
+        for height in [0u32, 1, 255, 256] {
+            let hash = storage.blockchain.get_hash_by_height(height).unwrap().unwrap();
+            assert_eq!(hash.as_bytes(), &[height as u8; 32]);
+        }
+        assert_eq!(storage.blockchain.get_chain_height().unwrap(), 257);
+
+        // Upgrading again changes nothing
+        assert!(upgrade(&backend).unwrap().is_empty());
+        assert_eq!(tree.scan_prefix(b"h").count(), 5);
+    }
+
+    #[test]
+    fn test_utxos_are_compacted_with_the_same_hash() {
+        let backend = MemoryBackend::new();
+        let storage = Storage::with_backend(backend.clone(), BlockFiles::memory()).unwrap();
+        let script = Script::p2pkh_script_pubkey(&[3; 20]);
+        let mut prev = Hash256::zero();
+        let mut spendable = None;
+        for height in 0..20u32 {
+            let mut transactions =
+                vec![Transaction::coinbase(vec![height as u8], TxOutput::new(5_000_000_000, script.clone()), height)];
+            if let Some(txid) = spendable.take() {
+                let outputs = vec![TxOutput::new(1_000_000_000, script.clone()), TxOutput::new(3_999_990_000, vec![0x51])];
+                transactions.push(Transaction::new(vec![TxInput::new(txid, 0, vec![])], outputs));
+            }
+            spendable = Some(transactions[0].txid());
+            let merkle_root = Block::calculate_merkle_root(&transactions);
+            let block = Block::new(BlockHeader::new(1, prev, merkle_root, height, 0x20ffffff, 0), transactions);
+            storage.connect_block(&block, height).unwrap();
+            prev = block.hash();
+        }
+        storage.flush().unwrap();
+        let compact = storage.utxo_set.stats().unwrap();
+        drop(storage);
+
+        // Write the set back the way version 2 stored it
+        let utxos = backend.open_tree(UTXO_TREE).unwrap();
+        for item in utxos.iter() {
+            let (key, value) = item.unwrap();
+            utxos.put(&key, &Utxo::from_compact_bytes(&value).unwrap().to_bytes()).unwrap();
+        }
+        backend.default_tree().put(VERSION_KEY, &2u32.to_le_bytes()).unwrap();
+
+        let storage = Storage::with_backend(backend.clone(), BlockFiles::memory()).unwrap();
+        assert_eq!(stored_version(&backend).unwrap(), Some(SCHEMA_VERSION));
+        assert_eq!(storage.utxo_set.stats().unwrap(), compact);
+        assert_eq!(storage.utxo_set.compute_stats().unwrap(), compact);
+        assert_eq!(storage.utxo_set.get_balance(&script).unwrap(), 5_000_000_000 + 19 * 1_000_000_000);
+
+        // The last coinbase, 19 payments to P2PKH and 19 change outputs to a 1-byte script
+        let plain: usize = storage.utxo_set.get_all_utxos().unwrap().iter().map(|(_, utxo)| utxo.to_bytes().len()).sum();
+        assert_eq!(compact.count, 39);
+        assert_eq!((compact.serialized_size - 39 * 36, plain as u64), (593, 1065));
+    }
+
+    #[test]
+    fn test_new_databases_are_stamped_and_newer_ones_refused() {
+        let backend = MemoryBackend::new();
+        assert_eq!(stored_version(&backend).unwrap(), None);
+        Storage::with_backend(backend.clone(), BlockFiles::memory()).unwrap();
+        assert_eq!(stored_version(&backend).unwrap(), Some(SCHEMA_VERSION));
+
+        backend.default_tree().put(VERSION_KEY, &(SCHEMA_VERSION + 1).to_le_bytes()).unwrap();
+        let error = Storage::with_backend(backend, BlockFiles::memory()).err().unwrap();
+        assert!(error.contains("newer than this release supports"));
+    }
+}
@@ -0,0 +1,145 @@
+// sled implementation of the key-value backend
+//
+// Each tree is a sled tree of one database, and batches commit as one sled
+// transaction over the trees they touch.
+
+use super::{KvBackend, KvBatch, KvIter, KvTree};
+use sled::transaction::TransactionError;
+use sled::Transactional;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// sled database
+#[derive(Clone)]
+pub struct SledBackend {
+    db: sled::Db,
+    lock: Arc<Mutex<()>>,
+}
+
+impl SledBackend {
+    /// Open (or create) the database at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let db = sled::open(path).map_err(|e| format!("Failed to open database: {}", e))?;
+        Ok(Self::from_db(db))
+    }
+
+    /// Database removed when dropped
+    pub fn temporary() -> Result<Self, String> {
+        let db = sled::Config::new()
+            .temporary(true)
+            .open()
+            .map_err(|e| format!("Failed to create temporary db: {}", e))?;
+        Ok(Self::from_db(db))
+    }
+
+    // Helper: wrap an open database
+    fn from_db(db: sled::Db) -> Self {
+        Self {
+            db,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+}
+
+impl KvBackend for SledBackend {
+    type Tree = SledTree;
+
+    fn default_tree(&self) -> SledTree {
+        SledTree::new((*self.db).clone())
+    }
+
+    fn open_tree(&self, name: &str) -> Result<SledTree, String> {
+        let tree = self
+            .db
+            .open_tree(name)
+            .map_err(|e| format!("Failed to open tree {}: {}", name, e))?;
+        Ok(SledTree::new(tree))
+    }
+
+    fn write_batch(&self, batch: &KvBatch<SledTree>) -> Result<(), String> {
+        let (trees, writes): (Vec<&sled::Tree>, Vec<_>) = batch.trees().map(|(tree, writes)| (&tree.tree, writes)).unzip();
+
+        trees
+            .as_slice()
+            .transaction(|views| {
+                for (view, writes) in views.iter().zip(&writes) {
+                    for (key, value) in writes.iter() {
+                        match value {
+                            Some(value) => view.insert(key.as_slice(), value.as_slice())?,
+                            None => view.remove(key.as_slice())?,
+                        };
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e: TransactionError<String>| format!("Failed to write batch: {}", e))
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.db.flush().map_err(|e| format!("Failed to flush: {}", e))?;
+        Ok(())
+    }
+
+    fn transaction_lock(&self) -> &Mutex<()> {
+        &self.lock
+    }
+}
+
+/// One tree of a sled database
+#[derive(Clone)]
+pub struct SledTree {
+    tree: sled::Tree,
+    name: String,
+}
+
+impl SledTree {
+    // Helper: wrap a sled tree
+    fn new(tree: sled::Tree) -> Self {
+        let name = String::from_utf8_lossy(&tree.name()).into_owned();
+        Self { tree, name }
+    }
+}
+
+impl KvTree for SledTree {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let value = self.tree.get(key).map_err(|e| format!("Database error: {}", e))?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<(), String> {
+        self.tree.insert(key, value).map_err(|e| format!("Database error: {}", e))?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<(), String> {
+        self.tree.remove(key).map_err(|e| format!("Database error: {}", e))?;
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_> {
+        Box::new(self.tree.scan_prefix(prefix).map(|item| {
+            item.map(|(key, value)| (key.to_vec(), value.to_vec()))
+                .map_err(|e| format!("Iterator error: {}", e))
+        }))
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        self.tree.clear().map_err(|e| format!("Failed to clear tree {}: {}", self.name, e))
+    }
+
+    fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn contains(&self, key: &[u8]) -> Result<bool, String> {
+        self.tree.contains_key(key).map_err(|e| format!("Database error: {}", e))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
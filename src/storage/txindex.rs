@@ -0,0 +1,147 @@
+// Transaction index: txid → containing block, height and position
+//
+// The index is optional. While it is enabled and has caught up with the tip,
+// `Storage::connect_block` and `disconnect_block` update it inside the same
+// transaction as the chain. `build` indexes an existing chain one block per
+// transaction, so it can run in a background thread, and an interrupted build
+// resumes from the last indexed block. The `best` key records that block;
+// blocks connected while the index lags are picked up by the next build.
+
+use super::{decode_index_best, encode_index_best, index_extends, BlockchainDB, KvBackend, KvBatch, KvTree, SledBackend, INDEX_BEST_KEY};
+use crate::core::{Block, Hash256};
+
+/// Where a confirmed transaction is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxLocation {
+    pub block_hash: Hash256,
+    pub height: u32,
+    /// Index of the transaction inside the block
+    pub position: u32,
+}
+
+impl TxLocation {
+    /// Serialize to bytes (block hash, height LE, position LE)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(40);
+        bytes.extend_from_slice(self.block_hash.as_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.position.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != 40 {
+            return Err(format!("Invalid tx location length: {}", bytes.len()));
+        }
+
+        Ok(Self {
+            block_hash: Hash256::new(bytes[0..32].try_into().unwrap()),
+            height: u32::from_le_bytes(bytes[32..36].try_into().unwrap()),
+            position: u32::from_le_bytes(bytes[36..40].try_into().unwrap()),
+        })
+    }
+}
+
+/// Transaction index database
+pub struct TxIndex<B: KvBackend = SledBackend> {
+    backend: B,
+    db: B::Tree,
+}
+
+impl<B: KvBackend> TxIndex<B> {
+    /// Use one tree of a shared database
+    pub(super) fn from_tree(backend: B, db: B::Tree) -> Self {
+        Self { backend, db }
+    }
+
+    /// Underlying tree (for atomic batches spanning several trees)
+    pub(super) fn tree(&self) -> &B::Tree {
+        &self.db
+    }
+
+    /// Location of a confirmed transaction
+    pub fn get(&self, txid: &Hash256) -> Result<Option<TxLocation>, String> {
+        match self.db.get(txid.as_bytes())? {
+            Some(data) => Ok(Some(TxLocation::from_bytes(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Last indexed block as (hash, height)
+    pub fn best_block(&self) -> Result<Option<(Hash256, u32)>, String> {
+        match self.db.get(INDEX_BEST_KEY)? {
+            Some(data) => Ok(Some(decode_index_best(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Index every block of the active chain above the last indexed one
+    /// Returns the number of blocks indexed. An index that followed a chain
+    /// which has since been rolled back is rebuilt from genesis.
+    pub fn build(&self, blocks: &BlockchainDB<B>) -> Result<u32, String> {
+        let mut indexed = 0;
+
+        loop {
+            let next = match self.best_block()? {
+                Some((hash, height)) if blocks.get_hash_by_height(height)? == Some(hash) => height + 1,
+                Some(_) => {
+                    self.db.clear().map_err(|e| format!("Failed to clear tx index: {}", e))?;
+                    0
+                }
+                None => 0,
+            };
+            if next >= blocks.get_chain_height()? {
+                return Ok(indexed);
+            }
+
+            let block = blocks
+                .get_block_by_height(next)?
+                .ok_or_else(|| format!("Block at height {} is missing from storage", next))?;
+            let added = self
+                .backend
+                .transaction(|tx| self.connect(tx, &block, next))
+                .map_err(|e| format!("Failed to index block: {}", e))?;
+            if added {
+                indexed += 1;
+            }
+        }
+    }
+
+    /// Add the transactions of `block` if the index stands at its parent
+    /// Returns whether the block was indexed.
+    pub(super) fn connect(&self, tx: &mut KvBatch<B::Tree>, block: &Block, height: u32) -> Result<bool, String> {
+        if !index_extends(tx, &self.db, block, height)? {
+            return Ok(false);
+        }
+
+        let block_hash = block.hash();
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            let location = TxLocation {
+                block_hash,
+                height,
+                position: position as u32,
+            };
+            tx.put(&self.db, transaction.txid().as_bytes(), &location.to_bytes());
+        }
+        tx.put(&self.db, INDEX_BEST_KEY, &encode_index_best(&block_hash, height));
+
+        Ok(true)
+    }
+
+    /// Remove the transactions of `block` if it is the last indexed block
+    pub(super) fn disconnect(&self, tx: &mut KvBatch<B::Tree>, block: &Block, height: u32) -> Result<(), String> {
+        let block_hash = block.hash();
+        let best = tx.get(&self.db, INDEX_BEST_KEY)?.and_then(|data| decode_index_best(&data).ok());
+        if best != Some((block_hash, height)) {
+            return Ok(());
+        }
+
+        for transaction in &block.transactions {
+            tx.delete(&self.db, transaction.txid().as_bytes());
+        }
+        tx.put(&self.db, INDEX_BEST_KEY, &encode_index_best(&block.header.prev_block_hash, height - 1));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+// Block undo data
+//
+// Connecting a block deletes the outputs its inputs spend. The undo record
+// keeps those outputs (value, script, height, coinbase flag) in input order
+// so the block can be disconnected again during a reorg or rollback.
+
+use super::Utxo;
+use crate::core::{read_var_bytes, read_varint, write_var_bytes, write_varint};
+use std::io::Cursor;
+
+/// Outputs spent by one block, in the order of its non-coinbase inputs
+#[derive(Debug, Clone, Default)]
+pub struct BlockUndo {
+    pub spent: Vec<Utxo>,
+}
+
+impl BlockUndo {
+    /// Serialize as a count followed by length-prefixed UTXOs
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, self.spent.len() as u64).unwrap();
+        for utxo in &self.spent {
+            write_var_bytes(&mut bytes, &utxo.to_bytes()).unwrap();
+        }
+        bytes
+    }
+
+    /// Deserialize from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut cursor = Cursor::new(bytes);
+        let count = read_varint(&mut cursor).map_err(|e| format!("Invalid undo data: {}", e))?;
+
+        let mut spent = Vec::new();
+        for _ in 0..count {
+            let data = read_var_bytes(&mut cursor).map_err(|e| format!("Invalid undo data: {}", e))?;
+            spent.push(Utxo::from_bytes(&data)?);
+        }
+
+        if cursor.position() as usize != bytes.len() {
+            return Err("Trailing bytes in undo data".to_string());
+        }
+
+        Ok(Self { spent })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::TxOutput;
+
+    #[test]
+    fn test_undo_roundtrip() {
+        let undo = BlockUndo {
+            spent: vec![
+                Utxo::new(TxOutput::new(50, vec![1, 2, 3]), 7, true),
+                Utxo::new(TxOutput::new(1, vec![]), 9, false),
+            ],
+        };
+        let decoded = BlockUndo::from_bytes(&undo.to_bytes()).unwrap();
+
+        assert_eq!(decoded.spent.len(), 2);
+        assert_eq!(decoded.spent[0].to_bytes(), undo.spent[0].to_bytes());
+        assert_eq!(decoded.spent[1].height, 9);
+        assert!(!decoded.spent[1].is_coinbase);
+    }
+
+    #[test]
+    fn test_undo_rejects_truncated_data() {
+        let undo = BlockUndo {
+            spent: vec![Utxo::new(TxOutput::new(50, vec![1]), 0, true)],
+        };
+        let bytes = undo.to_bytes();
+
+        assert!(BlockUndo::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(BlockUndo::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+    }
+}
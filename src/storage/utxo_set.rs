@@ -1,9 +1,26 @@
 // UTXO (Unspent Transaction Output) set management
-
-use crate::core::{Hash256, TxOutput};
-use sled::Db;
+//
+// Besides the UTXOs keyed by outpoint, a second tree indexes them by
+// SHA-256(scriptPubKey) + outpoint, so balance and coin-selection lookups
+// read only the outputs of one script. A third tree holds the set's
+// statistics (see utxo_stats.rs). All three change in the same transaction.
+// UTXOs are stored in the compact encoding of compress.rs.
+
+use super::compress::{read_output, read_var_int, write_output, write_var_int};
+use super::{KvBackend, KvBatch, KvTree, MemoryBackend, SledBackend, UtxoStats};
+use crate::core::{sha256_hash, Hash256, TxOutput};
+use std::io::Cursor;
 use std::path::Path;
 
+/// Name of the script index tree
+pub(super) const SCRIPT_TREE: &str = "utxo_scripts";
+
+/// Name of the statistics tree
+pub(super) const STATS_TREE: &str = "utxo_stats";
+
+/// Key of the statistics record
+pub(super) const STATS_KEY: &[u8] = b"stats";
+
 /// UTXO identifier - transaction hash + output index
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct OutPoint {
@@ -43,7 +60,7 @@ impl OutPoint {
 }
 
 /// UTXO - contains the output and metadata
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Utxo {
     pub output: TxOutput,
     pub height: u32,      // Block height where this UTXO was created
@@ -99,46 +116,93 @@ impl Utxo {
             is_coinbase,
         })
     }
+
+    /// Serialize compactly, as stored in the UTXO tree
+    /// VARINT(height * 2 + coinbase flag), then the compressed amount and script.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32);
+        write_var_int(&mut bytes, (self.height as u64) << 1 | self.is_coinbase as u64);
+        write_output(&mut bytes, &self.output);
+        bytes
+    }
+
+    /// Deserialize from the compact encoding
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut cursor = Cursor::new(bytes);
+        let code = read_var_int(&mut cursor).map_err(|e| format!("Invalid UTXO data: {}", e))?;
+        let height = u32::try_from(code >> 1).map_err(|_| format!("Invalid UTXO height: {}", code >> 1))?;
+        let output = read_output(&mut cursor).map_err(|e| format!("Invalid UTXO data: {}", e))?;
+        if cursor.position() as usize != bytes.len() {
+            return Err("Trailing bytes in UTXO data".to_string());
+        }
+
+        Ok(Self {
+            output,
+            height,
+            is_coinbase: code & 1 == 1,
+        })
+    }
 }
 
 /// UTXO set database
-pub struct UtxoSet {
-    db: Db,
+pub struct UtxoSet<B: KvBackend = SledBackend> {
+    backend: B,
+    db: B::Tree,
+    scripts: B::Tree,
+    stats: B::Tree,
 }
 
-impl UtxoSet {
+impl UtxoSet<SledBackend> {
     /// Create a new UTXO set
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        let db = sled::open(path).map_err(|e| format!("Failed to open UTXO db: {}", e))?;
-        Ok(Self { db })
+        let utxo_set = Self::open(SledBackend::open(path)?)?;
+        utxo_set.rebuild_derived()?;
+        Ok(utxo_set)
     }
+}
 
+impl UtxoSet<MemoryBackend> {
     /// Create an in-memory UTXO set (for testing)
     pub fn memory() -> Result<Self, String> {
-        let config = sled::Config::new().temporary(true);
-        let db = config.open().map_err(|e| format!("Failed to create memory UTXO db: {}", e))?;
-        Ok(Self { db })
+        Self::open(MemoryBackend::new())
     }
+}
 
-    /// Add a UTXO
-    pub fn add_utxo(&self, outpoint: &OutPoint, utxo: &Utxo) -> Result<(), String> {
-        let key = outpoint.to_bytes();
-        let value = utxo.to_bytes();
+impl<B: KvBackend> UtxoSet<B> {
+    /// Use three trees of a shared database: UTXOs, script index and statistics
+    pub(super) fn from_trees(backend: B, db: B::Tree, scripts: B::Tree, stats: B::Tree) -> Self {
+        Self {
+            backend,
+            db,
+            scripts,
+            stats,
+        }
+    }
 
-        self.db
-            .insert(key, value)
-            .map_err(|e| format!("Failed to add UTXO: {}", e))?;
+    /// Underlying UTXO tree (for atomic batches spanning several trees)
+    pub(super) fn tree(&self) -> &B::Tree {
+        &self.db
+    }
 
-        Ok(())
+    /// Add a UTXO
+    pub fn add_utxo(&self, outpoint: &OutPoint, utxo: &Utxo) -> Result<(), String> {
+        self.backend
+            .transaction(|tx| {
+                let mut batch = UtxoBatch::begin(self, tx)?;
+                batch.insert(tx, outpoint, utxo)?;
+                batch.commit(tx);
+                Ok(())
+            })
+            .map_err(|e| format!("Failed to add UTXO: {}", e))
     }
 
     /// Get a UTXO
     pub fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<Utxo>, String> {
         let key = outpoint.to_bytes();
 
-        match self.db.get(&key).map_err(|e| format!("Database error: {}", e))? {
+        match self.db.get(&key)? {
             Some(data) => {
-                let utxo = Utxo::from_bytes(&data)?;
+                let utxo = Utxo::from_compact_bytes(&data)?;
                 Ok(Some(utxo))
             }
             None => Ok(None),
@@ -147,23 +211,91 @@ impl UtxoSet {
 
     /// Remove a UTXO (spent)
     pub fn remove_utxo(&self, outpoint: &OutPoint) -> Result<bool, String> {
-        let key = outpoint.to_bytes();
+        let removed = self
+            .backend
+            .transaction(|tx| {
+                let mut batch = UtxoBatch::begin(self, tx)?;
+                let removed = batch.remove(tx, outpoint)?;
+                batch.commit(tx);
+                Ok(removed)
+            })
+            .map_err(|e| format!("Failed to remove UTXO: {}", e))?;
+
+        Ok(removed.is_some())
+    }
+
+    /// Statistics and commitment hash, maintained with every change
+    pub fn stats(&self) -> Result<UtxoStats, String> {
+        match self.stats.get(STATS_KEY)? {
+            Some(data) => UtxoStats::from_bytes(&data),
+            None => Ok(UtxoStats::default()),
+        }
+    }
+
+    /// Recompute the statistics from every UTXO (to check the maintained ones)
+    pub fn compute_stats(&self) -> Result<UtxoStats, String> {
+        let mut stats = UtxoStats::default();
+
+        for item in self.db.iter() {
+            let (key, value) = item?;
+            let utxo = Utxo::from_compact_bytes(&value)?;
+            stats.add(&key, &utxo, value.len());
+        }
+
+        Ok(stats)
+    }
 
-        let existed = self
-            .db
-            .remove(&key)
-            .map_err(|e| format!("Failed to remove UTXO: {}", e))?
-            .is_some();
+    /// Remove every UTXO, the script index and the statistics
+    pub(super) fn clear(&self) -> Result<(), String> {
+        for tree in [&self.db, &self.scripts, &self.stats] {
+            tree.clear().map_err(|e| format!("Failed to clear UTXO set: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the script index and statistics if they are missing
+    /// (UTXO sets written before they existed). Returns whether anything was rebuilt.
+    pub fn rebuild_derived(&self) -> Result<bool, String> {
+        let missing_scripts = self.scripts.is_empty() && !self.db.is_empty();
+        let missing_stats = !self.stats.contains(STATS_KEY)?;
+        if !missing_scripts && !missing_stats {
+            return Ok(false);
+        }
+
+        self.reindex_scripts()?;
+        let stats = self.compute_stats()?;
+        self.stats
+            .put(STATS_KEY, &stats.to_bytes())
+            .map_err(|e| format!("Failed to store UTXO stats: {}", e))?;
+
+        Ok(true)
+    }
+
+    /// Rebuild the script index from the UTXO tree
+    /// Returns the number of UTXOs indexed.
+    pub fn reindex_scripts(&self) -> Result<usize, String> {
+        self.scripts
+            .clear()
+            .map_err(|e| format!("Failed to clear script index: {}", e))?;
+
+        let mut indexed = 0;
+        for item in self.db.iter() {
+            let (key, value) = item?;
+            let outpoint = OutPoint::from_bytes(&key)?;
+            let utxo = Utxo::from_compact_bytes(&value)?;
+            self.scripts
+                .put(&Self::script_key(&utxo.output.script_pubkey, &outpoint), &[])
+                .map_err(|e| format!("Failed to index UTXO: {}", e))?;
+            indexed += 1;
+        }
 
-        Ok(existed)
+        Ok(indexed)
     }
 
     /// Check if a UTXO exists
     pub fn has_utxo(&self, outpoint: &OutPoint) -> Result<bool, String> {
         let key = outpoint.to_bytes();
-        self.db
-            .contains_key(&key)
-            .map_err(|e| format!("Database error: {}", e))
+        self.db.contains(&key)
     }
 
     /// Get all UTXOs (for balance calculation)
@@ -171,10 +303,10 @@ impl UtxoSet {
         let mut utxos = Vec::new();
 
         for item in self.db.iter() {
-            let (key, value) = item.map_err(|e| format!("Iterator error: {}", e))?;
+            let (key, value) = item?;
 
             let outpoint = OutPoint::from_bytes(&key)?;
-            let utxo = Utxo::from_bytes(&value)?;
+            let utxo = Utxo::from_compact_bytes(&value)?;
 
             utxos.push((outpoint, utxo));
         }
@@ -182,45 +314,25 @@ impl UtxoSet {
         Ok(utxos)
     }
 
-    /// Get balance for a script pubkey
-    /// NOTE: This performs a full scan. For better performance with many UTXOs,
-    /// consider maintaining a separate address->balance index.
+    /// Get balance for a script pubkey (uses the script index)
     pub fn get_balance(&self, script_pubkey: &[u8]) -> Result<u64, String> {
-        let mut balance = 0u64;
-
-        for item in self.db.iter() {
-            let (_, value) = item.map_err(|e| format!("Iterator error: {}", e))?;
-
-            // Early exit optimization: check script length before full deserialization
-            if value.len() >= 13 {
-                let utxo = Utxo::from_bytes(&value)?;
-
-                if utxo.output.script_pubkey == script_pubkey {
-                    balance += utxo.output.value;
-                }
-            }
-        }
-
-        Ok(balance)
+        let utxos = self.get_utxos_for_script(script_pubkey)?;
+        Ok(utxos.iter().map(|(_, utxo)| utxo.output.value).sum())
     }
 
-    /// Get all UTXOs for a script pubkey
-    /// NOTE: This performs a full scan. For better performance with many UTXOs,
-    /// consider maintaining a separate script->outpoints index.
+    /// Get all UTXOs for a script pubkey (uses the script index)
     pub fn get_utxos_for_script(&self, script_pubkey: &[u8]) -> Result<Vec<(OutPoint, Utxo)>, String> {
         let mut utxos = Vec::new();
 
-        for item in self.db.iter() {
-            let (key, value) = item.map_err(|e| format!("Iterator error: {}", e))?;
-
-            // Early exit optimization: check minimum size before deserialization
-            if value.len() >= 13 {
-                let utxo = Utxo::from_bytes(&value)?;
+        for item in self.scripts.scan_prefix(&sha256_hash(script_pubkey)) {
+            let (key, _) = item?;
+            let outpoint = OutPoint::from_bytes(&key[32..])?;
 
-                if utxo.output.script_pubkey == script_pubkey {
-                    let outpoint = OutPoint::from_bytes(&key)?;
-                    utxos.push((outpoint, utxo));
-                }
+            // A SHA-256 collision would list another script's outputs
+            if let Some(utxo) = self.get_utxo(&outpoint)?
+                && utxo.output.script_pubkey == script_pubkey
+            {
+                utxos.push((outpoint, utxo));
             }
         }
 
@@ -229,19 +341,87 @@ impl UtxoSet {
 
     /// Count total UTXOs
     pub fn count(&self) -> Result<usize, String> {
-        self.db
-            .len()
-            .try_into()
-            .map_err(|e| format!("Failed to get UTXO count: {}", e))
+        Ok(self.db.len())
+    }
+
+    /// Open the trees of a standalone database
+    pub(super) fn open(backend: B) -> Result<Self, String> {
+        let db = backend.default_tree();
+        let scripts = backend.open_tree(SCRIPT_TREE)?;
+        let stats = backend.open_tree(STATS_TREE)?;
+        Ok(Self::from_trees(backend, db, scripts, stats))
+    }
+
+    // Helper: script index key (SHA-256 of the script, then the outpoint)
+    fn script_key(script_pubkey: &[u8], outpoint: &OutPoint) -> Vec<u8> {
+        let mut key = Vec::with_capacity(68);
+        key.extend_from_slice(&sha256_hash(script_pubkey));
+        key.extend_from_slice(&outpoint.to_bytes());
+        key
     }
 
     /// Manually flush database (call after batch operations)
     pub fn flush(&self) -> Result<(), String> {
-        self.db
-            .flush()
-            .map_err(|e| format!("Failed to flush: {}", e))?;
+        self.backend.flush()
+    }
+}
+
+/// UTXO changes staged in a backend transaction
+/// Keeps the script index and statistics in step with the UTXO tree.
+pub(super) struct UtxoBatch<'a, B: KvBackend> {
+    set: &'a UtxoSet<B>,
+    stats: UtxoStats,
+}
+
+impl<'a, B: KvBackend> UtxoBatch<'a, B> {
+    /// Start a batch, loading the current statistics
+    pub(super) fn begin(set: &'a UtxoSet<B>, tx: &KvBatch<B::Tree>) -> Result<Self, String> {
+        let stats = match tx.get(&set.stats, STATS_KEY)? {
+            Some(data) => UtxoStats::from_bytes(&data)?,
+            None => UtxoStats::default(),
+        };
+
+        Ok(Self { set, stats })
+    }
+
+    /// Insert a UTXO, replacing any previous one at the outpoint
+    pub(super) fn insert(&mut self, tx: &mut KvBatch<B::Tree>, outpoint: &OutPoint, utxo: &Utxo) -> Result<(), String> {
+        let key = outpoint.to_bytes();
+        let value = utxo.to_compact_bytes();
+
+        if let Some(old) = tx.get(&self.set.db, &key)? {
+            self.forget(tx, outpoint, &key, &old);
+        }
+        tx.put(&self.set.db, &key, &value);
+        tx.put(&self.set.scripts, &UtxoSet::<B>::script_key(&utxo.output.script_pubkey, outpoint), &[]);
+        self.stats.add(&key, utxo, value.len());
         Ok(())
     }
+
+    /// Remove a UTXO
+    /// Returns the UTXO that was removed.
+    pub(super) fn remove(&mut self, tx: &mut KvBatch<B::Tree>, outpoint: &OutPoint) -> Result<Option<Utxo>, String> {
+        let key = outpoint.to_bytes();
+        let Some(data) = tx.get(&self.set.db, &key)? else {
+            return Ok(None);
+        };
+        tx.delete(&self.set.db, &key);
+        Ok(self.forget(tx, outpoint, &key, &data))
+    }
+
+    /// Stage the updated statistics
+    pub(super) fn commit(self, tx: &mut KvBatch<B::Tree>) {
+        tx.put(&self.set.stats, STATS_KEY, &self.stats.to_bytes());
+    }
+
+    // Helper: drop the index entry and statistics of a UTXO leaving the set
+    // Returns the decoded UTXO, or `None` if its record was unreadable.
+    fn forget(&mut self, tx: &mut KvBatch<B::Tree>, outpoint: &OutPoint, key: &[u8], data: &[u8]) -> Option<Utxo> {
+        let utxo = Utxo::from_compact_bytes(data).ok()?;
+        tx.delete(&self.set.scripts, &UtxoSet::<B>::script_key(&utxo.output.script_pubkey, outpoint));
+        self.stats.remove(key, &utxo, data.len());
+        Some(utxo)
+    }
 }
 
 #[cfg(test)]
@@ -273,6 +453,22 @@ mod tests {
         assert_eq!(utxo.is_coinbase, decoded.is_coinbase);
     }
 
+    #[test]
+    fn test_utxo_compact_serialization() {
+        let script = crate::core::Script::p2pkh_script_pubkey(&[7; 20]);
+        let coinbase = Utxo::new(TxOutput::new(50 * 100_000_000, script), 1000, true);
+        let other = Utxo::new(TxOutput::new(123_456, vec![1, 2, 3]), 5, false);
+
+        // 25-byte script, 8-byte amount, 4-byte height and flag become 21 + 1 + 2 bytes
+        let bytes = coinbase.to_compact_bytes();
+        assert_eq!((coinbase.to_bytes().len(), bytes.len()), (39, 24));
+        assert_eq!(Utxo::from_compact_bytes(&bytes).unwrap(), coinbase);
+        assert_eq!(Utxo::from_compact_bytes(&other.to_compact_bytes()).unwrap(), other);
+
+        assert!(Utxo::from_compact_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Utxo::from_compact_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+    }
+
     #[test]
     fn test_add_and_get_utxo() {
         let utxo_set = UtxoSet::memory().unwrap();
@@ -346,4 +542,45 @@ mod tests {
 
         assert_eq!(utxo_set.count().unwrap(), 2);
     }
+
+    #[test]
+    fn test_script_index_follows_add_and_remove() {
+        let utxo_set = UtxoSet::memory().unwrap();
+        let script = vec![1, 2, 3];
+        let outpoint1 = OutPoint::new(Hash256::new([1; 32]), 0);
+        let outpoint2 = OutPoint::new(Hash256::new([2; 32]), 1);
+
+        utxo_set.add_utxo(&outpoint1, &Utxo::new(TxOutput::new(1000, script.clone()), 1, false)).unwrap();
+        utxo_set.add_utxo(&outpoint2, &Utxo::new(TxOutput::new(2000, script.clone()), 2, false)).unwrap();
+        utxo_set.add_utxo(&OutPoint::new(Hash256::new([3; 32]), 0), &Utxo::new(TxOutput::new(7, vec![9]), 2, false)).unwrap();
+
+        let found: Vec<OutPoint> = utxo_set.get_utxos_for_script(&script).unwrap().into_iter().map(|(o, _)| o).collect();
+        assert_eq!(found, vec![outpoint1.clone(), outpoint2.clone()]);
+
+        // Replacing an output with a different script moves its index entry
+        utxo_set.add_utxo(&outpoint2, &Utxo::new(TxOutput::new(5, vec![9]), 2, false)).unwrap();
+        utxo_set.remove_utxo(&outpoint1).unwrap();
+        assert_eq!(utxo_set.get_balance(&script).unwrap(), 0);
+        assert_eq!(utxo_set.get_balance(&[9]).unwrap(), 12);
+        assert_eq!(utxo_set.scripts.len(), 2);
+    }
+
+    #[test]
+    fn test_rebuild_derived() {
+        let utxo_set = UtxoSet::memory().unwrap();
+        let script = vec![4, 5];
+        utxo_set.add_utxo(&OutPoint::new(Hash256::new([1; 32]), 0), &Utxo::new(TxOutput::new(10, script.clone()), 1, false)).unwrap();
+
+        let stats = utxo_set.stats().unwrap();
+        assert!(!utxo_set.rebuild_derived().unwrap());
+
+        // Simulate a UTXO set written before the index and statistics existed
+        utxo_set.scripts.clear().unwrap();
+        utxo_set.stats.clear().unwrap();
+        assert_eq!(utxo_set.get_balance(&script).unwrap(), 0);
+
+        assert!(utxo_set.rebuild_derived().unwrap());
+        assert_eq!(utxo_set.get_balance(&script).unwrap(), 10);
+        assert_eq!(utxo_set.stats().unwrap(), stats);
+    }
 }
@@ -0,0 +1,168 @@
+// UTXO set statistics and commitment (gettxoutsetinfo)
+//
+// The statistics are kept up to date with every UTXO insert and removal, in
+// the same transaction. The commitment is a multiset hash in the spirit of
+// MuHash: each UTXO (outpoint + plain serialization, `Utxo::to_bytes`) is
+// mapped to a secp256k1 point, and the set hash is the sum of its points. Adding and removing a
+// UTXO are a point addition and subtraction, so the hash never needs a full
+// scan, and it depends only on the contents of the set, not on the order in
+// which blocks built it or how the database encodes it. Two nodes with the
+// same UTXO set get the same hash.
+
+use super::Utxo;
+use crate::core::{sha256_hash, Hash256};
+use secp256k1::{PublicKey, Secp256k1};
+
+/// Statistics of a UTXO set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UtxoStats {
+    /// Number of unspent outputs
+    pub count: u64,
+    /// Sum of their values in satoshis
+    pub total_amount: u64,
+    /// Bytes of all keys and values as stored (compact encoding)
+    pub serialized_size: u64,
+    /// Sum of the UTXO points (`None` for the empty set)
+    commitment: Option<PublicKey>,
+}
+
+impl UtxoStats {
+    /// Account for a UTXO entering the set
+    /// `key` is the serialized outpoint, `stored` the size of the value as stored.
+    pub fn add(&mut self, key: &[u8], utxo: &Utxo, stored: usize) {
+        self.count += 1;
+        self.total_amount += utxo.output.value;
+        self.serialized_size += (key.len() + stored) as u64;
+        self.combine(Self::element(key, &utxo.to_bytes()));
+    }
+
+    /// Account for a UTXO leaving the set
+    pub fn remove(&mut self, key: &[u8], utxo: &Utxo, stored: usize) {
+        self.count -= 1;
+        self.total_amount -= utxo.output.value;
+        self.serialized_size -= (key.len() + stored) as u64;
+        let element = Self::element(key, &utxo.to_bytes()).negate(&Secp256k1::verification_only());
+        self.combine(element);
+    }
+
+    /// Hash committing to the whole set
+    pub fn hash(&self) -> Hash256 {
+        Hash256::new(sha256_hash(&self.commitment_bytes()))
+    }
+
+    /// Serialize to bytes (count, amount, size, commitment point)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(57);
+        bytes.extend_from_slice(&self.count.to_le_bytes());
+        bytes.extend_from_slice(&self.total_amount.to_le_bytes());
+        bytes.extend_from_slice(&self.serialized_size.to_le_bytes());
+        bytes.extend_from_slice(&self.commitment_bytes());
+        bytes
+    }
+
+    /// Deserialize from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != 57 {
+            return Err(format!("Invalid UTXO stats length: {}", bytes.len()));
+        }
+
+        let commitment = if bytes[24..].iter().all(|b| *b == 0) {
+            None
+        } else {
+            Some(PublicKey::from_slice(&bytes[24..]).map_err(|e| format!("Invalid UTXO commitment: {}", e))?)
+        };
+
+        Ok(Self {
+            count: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            total_amount: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            serialized_size: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            commitment,
+        })
+    }
+
+    // Helper: add a point to the commitment (P + (-P) is the empty set)
+    fn combine(&mut self, element: PublicKey) {
+        self.commitment = match self.commitment {
+            Some(sum) => sum.combine(&element).ok(),
+            None => Some(element),
+        };
+    }
+
+    // Helper: compressed commitment point, 33 zero bytes for the empty set
+    fn commitment_bytes(&self) -> [u8; 33] {
+        self.commitment.map(|point| point.serialize()).unwrap_or([0; 33])
+    }
+
+    // Helper: map a UTXO to a curve point (try-and-increment on the x coordinate)
+    fn element(key: &[u8], value: &[u8]) -> PublicKey {
+        let mut data = Vec::with_capacity(8 + key.len() + value.len());
+        data.extend_from_slice(b"utxo");
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(key);
+        data.extend_from_slice(value);
+
+        for counter in 0u32.. {
+            data[4..8].copy_from_slice(&counter.to_le_bytes());
+            let mut point = [2u8; 33];
+            point[1..].copy_from_slice(&sha256_hash(&data));
+            if let Ok(element) = PublicKey::from_slice(&point) {
+                return element;
+            }
+        }
+        unreachable!("no curve point found for UTXO")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::TxOutput;
+
+    // Helper: UTXO worth `amount`
+    fn utxo(amount: u64) -> Utxo {
+        Utxo::new(TxOutput::new(amount, vec![1]), 1, false)
+    }
+
+    #[test]
+    fn test_hash_is_order_independent() {
+        let mut forward = UtxoStats::default();
+        forward.add(b"a", &utxo(10), 1);
+        forward.add(b"b", &utxo(20), 2);
+        forward.add(b"c", &utxo(30), 3);
+
+        let mut backward = UtxoStats::default();
+        backward.add(b"c", &utxo(30), 3);
+        backward.add(b"b", &utxo(20), 2);
+        backward.add(b"a", &utxo(10), 1);
+
+        assert_eq!(forward, backward);
+        assert_eq!(forward.hash(), backward.hash());
+        assert_eq!((forward.count, forward.total_amount, forward.serialized_size), (3, 60, 9));
+    }
+
+    #[test]
+    fn test_remove_undoes_add() {
+        let empty = UtxoStats::default();
+        let mut stats = UtxoStats::default();
+        stats.add(b"a", &utxo(10), 1);
+        let one = stats.clone();
+        stats.add(b"b", &utxo(5), 1);
+        assert_ne!(stats.hash(), one.hash());
+
+        stats.remove(b"b", &utxo(5), 1);
+        assert_eq!(stats, one);
+        stats.remove(b"a", &utxo(10), 1);
+        assert_eq!(stats, empty);
+        assert_eq!(stats.hash(), empty.hash());
+    }
+
+    #[test]
+    fn test_stats_roundtrip() {
+        let mut stats = UtxoStats::default();
+        assert_eq!(UtxoStats::from_bytes(&stats.to_bytes()).unwrap(), stats);
+
+        stats.add(b"key", &utxo(42), 5);
+        assert_eq!(UtxoStats::from_bytes(&stats.to_bytes()).unwrap(), stats);
+        assert!(UtxoStats::from_bytes(&[0; 10]).is_err());
+    }
+}
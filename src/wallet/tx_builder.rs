@@ -1,20 +1,28 @@
 // Transaction builder
 
+use crate::consensus::mempool::Mempool;
 use crate::core::{Transaction, TxInput, TxOutput, Script};
-use crate::storage::{UtxoSet, OutPoint, Utxo};
+use crate::storage::{KvBackend, SledBackend, UtxoSet, OutPoint, Utxo};
 use crate::wallet::{Keystore, Address};
 use secp256k1::{Secp256k1, Message};
 
 /// Transaction builder
-pub struct TransactionBuilder<'a> {
+pub struct TransactionBuilder<'a, B: KvBackend = SledBackend> {
     keystore: &'a Keystore,
-    utxo_set: &'a UtxoSet,
+    utxo_set: &'a UtxoSet<B>,
+    mempool: Option<&'a Mempool>,
 }
 
-impl<'a> TransactionBuilder<'a> {
+impl<'a, B: KvBackend> TransactionBuilder<'a, B> {
     /// Create a new transaction builder
-    pub fn new(keystore: &'a Keystore, utxo_set: &'a UtxoSet) -> Self {
-        Self { keystore, utxo_set }
+    pub fn new(keystore: &'a Keystore, utxo_set: &'a UtxoSet<B>) -> Self {
+        Self { keystore, utxo_set, mempool: None }
+    }
+
+    /// Skip coins that transactions in `mempool` already spend
+    pub fn with_mempool(mut self, mempool: &'a Mempool) -> Self {
+        self.mempool = Some(mempool);
+        self
     }
 
     /// Build a transaction to send amount to recipient
@@ -34,7 +42,10 @@ impl<'a> TransactionBuilder<'a> {
         let sender_script = keypair.script_pubkey();
 
         // Get UTXOs for sender
-        let utxos = self.utxo_set.get_utxos_for_script(&sender_script)?;
+        let mut utxos = self.utxo_set.get_utxos_for_script(&sender_script)?;
+        if let Some(mempool) = self.mempool {
+            utxos.retain(|(outpoint, _)| !mempool.is_spent(outpoint));
+        }
 
         if utxos.is_empty() {
             return Err("No UTXOs available for sender".to_string());
@@ -101,7 +112,7 @@ impl<'a> TransactionBuilder<'a> {
         keypair: &crate::wallet::KeyPair,
     ) -> Result<(), String> {
         let secp = Secp256k1::new();
-        let tx_hash = tx.txid();
+        let tx_hash = tx.signature_hash();
 
         for (i, (_, _utxo)) in utxos.iter().enumerate() {
             // Create message from tx hash
@@ -225,4 +236,47 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Insufficient funds"));
     }
+
+    #[test]
+    fn test_consecutive_sends_skip_pending_coins() {
+        use crate::consensus::block_assembly::BlockAssembler;
+        use crate::consensus::validation::BlockValidator;
+        use crate::core::Block;
+        use std::collections::HashSet;
+
+        let mut keystore = Keystore::new();
+        let utxo_set = UtxoSet::memory().unwrap();
+        let from = keystore.new_address();
+        let to = keystore.new_address();
+
+        let script = keystore.get_keypair(&from).unwrap().script_pubkey();
+        for i in 1..=2u8 {
+            let utxo = Utxo::new(TxOutput::new(100000, script.clone()), 1, false);
+            utxo_set.add_utxo(&OutPoint::new(Hash256::new([i; 32]), 0), &utxo).unwrap();
+        }
+
+        // Each send queues before the next is built, like `wallet send`
+        let mut mempool = Mempool::new();
+        let mut sent = Vec::new();
+        for _ in 0..2 {
+            let tx = TransactionBuilder::new(&keystore, &utxo_set)
+                .with_mempool(&mempool)
+                .build(&from, &to, 50000, 1000)
+                .unwrap();
+            sent.push(mempool.add(tx, &utxo_set).unwrap());
+        }
+        let third = TransactionBuilder::new(&keystore, &utxo_set).with_mempool(&mempool).build(&from, &to, 50000, 1000);
+        assert!(third.unwrap_err().contains("No UTXOs available"));
+
+        // Both fit in the next block and their scripts verify
+        let template = BlockAssembler::new().create_template(&mempool, Hash256::zero(), 2, 0x207fffff, 1_000);
+        let mined: HashSet<Hash256> = template.transactions.iter().map(|entry| entry.txid).collect();
+        assert_eq!(mined, sent.into_iter().collect());
+
+        let work = template.to_work(script).unwrap();
+        let block = Block::new(work.header, work.transactions);
+        BlockValidator::new(0x207fffff).verify_block_scripts(&block, &utxo_set).unwrap();
+        mempool.remove_for_block(&block);
+        assert!(mempool.is_empty());
+    }
 }